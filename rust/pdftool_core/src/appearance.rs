@@ -0,0 +1,583 @@
+// SPDX-License-Identifier: PMPL-1.0-or-later WITH Palimpsest
+
+//! Appearance stream generation for widgets that lack a usable `/AP`.
+
+use std::collections::HashSet;
+
+use lopdf::{dictionary, Dictionary, Document, Object, ObjectId, Stream};
+
+use crate::{
+    field_option_exports, get_dict_mut, object_to_name, object_to_number, object_to_text, rect_from_object,
+    resolve_object, root_catalog_id, widget_on_state, CoreResult, FieldDescriptor,
+};
+
+const FF_MULTILINE: i64 = 1 << 12;
+const DEFAULT_DA: &str = "/Helv 0 Tf 0 g";
+const DEFAULT_FONT_NAME: &str = "Helv";
+const AUTO_FONT_SIZE_MAX: f32 = 12.0;
+const PADDING: f32 = 2.0;
+
+/// Form-level defaults that widgets inherit when the field tree is silent.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct FormDefaults {
+    pub da: Option<String>,
+    pub quadding: Option<i64>,
+    pub fonts: Dictionary,
+}
+
+impl FormDefaults {
+    /// Defaults of the catalog's interactive form; empty when there is none.
+    pub(crate) fn from_document(doc: &Document) -> Self {
+        root_catalog_id(doc)
+            .ok()
+            .and_then(|catalog_id| doc.get_dictionary(catalog_id).ok())
+            .and_then(|catalog| catalog.get(b"AcroForm").ok())
+            .and_then(|form| resolve_object(doc, form).ok())
+            .and_then(|form| form.as_dict().ok().map(|form| Self::from_acroform(doc, form)))
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn from_acroform(doc: &Document, acroform: &Dictionary) -> Self {
+        let fonts = acroform
+            .get(b"DR")
+            .ok()
+            .and_then(|dr| resolve_object(doc, dr).ok())
+            .and_then(|dr| dr.as_dict().ok().and_then(|dr| dr.get(b"Font").ok().cloned()))
+            .and_then(|fonts| resolve_object(doc, &fonts).ok())
+            .and_then(|fonts| fonts.as_dict().ok().cloned())
+            .unwrap_or_default();
+        Self {
+            da: acroform.get(b"DA").ok().and_then(object_to_text),
+            quadding: acroform.get(b"Q").ok().and_then(|q| q.as_i64().ok()),
+            fonts,
+        }
+    }
+}
+
+/// Parsed default appearance string: font resource, size and the remaining
+/// (colour) operators, which are replayed verbatim.
+struct DefaultAppearance {
+    font: String,
+    size: f32,
+    colour: String,
+}
+
+fn parse_default_appearance(da: &str) -> DefaultAppearance {
+    let tokens: Vec<&str> = da.split_whitespace().collect();
+    let mut font = DEFAULT_FONT_NAME.to_string();
+    let mut size = 0.0;
+    let mut rest = Vec::new();
+    let mut index = 0;
+    while index < tokens.len() {
+        if index + 2 < tokens.len() && tokens[index + 2] == "Tf" && tokens[index].starts_with('/') {
+            font = tokens[index].trim_start_matches('/').to_string();
+            size = tokens[index + 1].parse().unwrap_or(0.0);
+            index += 3;
+            continue;
+        }
+        rest.push(tokens[index]);
+        index += 1;
+    }
+    DefaultAppearance {
+        font,
+        size,
+        colour: rest.join(" "),
+    }
+}
+
+/// Builds a `/Tx`-style appearance stream showing `value` for a widget of
+/// `field`, registering it as a new object.
+pub(crate) fn text_appearance(
+    doc: &mut Document,
+    field: &FieldDescriptor,
+    value: &str,
+    width: f32,
+    height: f32,
+    defaults: &FormDefaults,
+) -> ObjectId {
+    let da = field
+        .da
+        .clone()
+        .or_else(|| defaults.da.clone())
+        .unwrap_or_else(|| DEFAULT_DA.to_string());
+    let quadding = field.quadding.or(defaults.quadding).unwrap_or(0);
+    let multiline = field.flags & FF_MULTILINE != 0;
+    let appearance = parse_default_appearance(&da);
+
+    let lines: Vec<&str> = if multiline {
+        value.lines().collect()
+    } else {
+        vec![value.lines().next().unwrap_or("")]
+    };
+    let size = if appearance.size > 0.0 {
+        appearance.size
+    } else if multiline {
+        AUTO_FONT_SIZE_MAX.min(height - 2.0 * PADDING).max(4.0)
+    } else {
+        ((height - 2.0 * PADDING) * 0.8).clamp(4.0, AUTO_FONT_SIZE_MAX)
+    };
+    let leading = size * 1.15;
+
+    let mut content = String::from("/Tx BMC\nq\n");
+    content.push_str(&format!("{} {} {} {} re W n\n", PADDING / 2.0, PADDING / 2.0, width - PADDING, height - PADDING));
+    content.push_str("BT\n");
+    content.push_str(&format!("/{} {} Tf\n", appearance.font, fmt(size)));
+    if !appearance.colour.is_empty() {
+        content.push_str(&appearance.colour);
+        content.push('\n');
+    }
+    let first_baseline = if multiline {
+        height - PADDING - size
+    } else {
+        (height - size) / 2.0 + size * 0.22
+    };
+    for (index, line) in lines.iter().enumerate() {
+        let text_width = estimate_text_width(line, size);
+        let x = match quadding {
+            1 => (width - text_width) / 2.0,
+            2 => width - PADDING - text_width,
+            _ => PADDING,
+        };
+        let y = first_baseline - leading * index as f32;
+        content.push_str(&format!("1 0 0 1 {} {} Tm\n", fmt(x), fmt(y)));
+        content.push_str(&format!("({}) Tj\n", escape_text(line)));
+    }
+    content.push_str("ET\nQ\nEMC\n");
+
+    let font = defaults
+        .fonts
+        .get(appearance.font.as_bytes())
+        .ok()
+        .cloned()
+        .unwrap_or_else(|| Object::Reference(helvetica_font(doc)));
+    let stream = Stream::new(
+        dictionary! {
+            "Type" => "XObject",
+            "Subtype" => "Form",
+            "BBox" => Object::Array(vec![0.into(), 0.into(), Object::Real(width), Object::Real(height)]),
+            "Resources" => Object::Dictionary(dictionary! {
+                "Font" => Object::Dictionary(dictionary! {
+                    appearance.font.as_str() => font,
+                }),
+            }),
+        },
+        content.into_bytes(),
+    );
+    doc.add_object(Object::Stream(stream))
+}
+
+/// `/MK /CA` characters of the ZapfDingbats button styles with their width
+/// in em: check, circle, cross, diamond, square and star.
+const BUTTON_GLYPHS: [(char, f32); 6] = [('4', 0.76), ('l', 0.79), ('8', 0.76), ('u', 0.79), ('n', 0.76), ('H', 0.82)];
+const CHECK_GLYPH: char = '4';
+const CIRCLE_GLYPH: char = 'l';
+/// Bezier control distance for a quarter circle of radius 1.
+const KAPPA: f32 = 0.5523;
+
+/// How a check box or radio button is drawn, read from its widget's `/MK`
+/// (`CA` glyph, `BC` border and `BG` background colours, `R` rotation),
+/// `/BS` border width and `/DA` glyph size and colour.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ButtonStyle {
+    glyph: char,
+    /// Radio buttons get a round border and background.
+    round: bool,
+    border: Option<String>,
+    background: Option<String>,
+    border_width: f32,
+    rotation: i64,
+    size: f32,
+    colour: String,
+}
+
+impl ButtonStyle {
+    pub(crate) fn read(doc: &Document, widget: &Dictionary, da: Option<&str>, radio: bool) -> Self {
+        let entry = |dict: &Dictionary, key: &[u8]| dict.get(key).ok().and_then(|obj| resolve_object(doc, obj).ok());
+        let characteristics = entry(widget, b"MK").and_then(|mk| mk.as_dict().ok().cloned()).unwrap_or_default();
+        let glyph = entry(&characteristics, b"CA")
+            .and_then(|ca| object_to_text(&ca))
+            .and_then(|ca| ca.chars().next())
+            .unwrap_or(if radio { CIRCLE_GLYPH } else { CHECK_GLYPH });
+        let colour = |key: &[u8], stroke: bool| match entry(&characteristics, key) {
+            Some(Object::Array(components)) => colour_operator(&components, stroke),
+            _ => None,
+        };
+        let border_width = entry(widget, b"BS")
+            .and_then(|bs| bs.as_dict().ok().and_then(|bs| bs.get(b"W").ok().and_then(object_to_number)))
+            .unwrap_or(1.0);
+        let rotation = entry(&characteristics, b"R").and_then(|r| r.as_i64().ok()).unwrap_or(0).rem_euclid(360);
+        let own_da = widget.get(b"DA").ok().and_then(object_to_text);
+        let da = parse_default_appearance(own_da.as_deref().or(da).unwrap_or(""));
+        Self {
+            glyph,
+            round: radio,
+            border: colour(b"BC", true).filter(|_| border_width > 0.0),
+            background: colour(b"BG", false),
+            border_width,
+            rotation: if rotation % 90 == 0 { rotation } else { 0 },
+            size: da.size,
+            colour: if da.colour.is_empty() { "0 g".to_owned() } else { da.colour },
+        }
+    }
+}
+
+/// Fill (`g`, `rg`, `k`) or stroke operator for a 1-, 3- or 4-component
+/// colour array; none for an empty (transparent) one.
+fn colour_operator(components: &[Object], stroke: bool) -> Option<String> {
+    let values: Vec<String> = components.iter().map(|value| fmt(object_to_number(value).unwrap_or(0.0))).collect();
+    let operator = match (values.len(), stroke) {
+        (1, false) => "g",
+        (1, true) => "G",
+        (3, false) => "rg",
+        (3, true) => "RG",
+        (4, false) => "k",
+        (4, true) => "K",
+        _ => return None,
+    };
+    Some(format!("{} {}", values.join(" "), operator))
+}
+
+/// A closed circle path through four Bezier quarters.
+fn circle_path(cx: f32, cy: f32, r: f32) -> String {
+    let k = r * KAPPA;
+    let p = |values: &[f32]| values.iter().map(|value| fmt(*value)).collect::<Vec<_>>().join(" ");
+    format!(
+        "{} m\n{} c\n{} c\n{} c\n{} c\nh\n",
+        p(&[cx + r, cy]),
+        p(&[cx + r, cy + k, cx + k, cy + r, cx, cy + r]),
+        p(&[cx - k, cy + r, cx - r, cy + k, cx - r, cy]),
+        p(&[cx - r, cy - k, cx - k, cy - r, cx, cy - r]),
+        p(&[cx + k, cy - r, cx + r, cy - k, cx + r, cy]),
+    )
+}
+
+/// Builds the on and off appearances of a check box or radio button whose
+/// widget is `width` by `height`: background and border, plus the style's
+/// glyph in ZapfDingbats (`font`) when on. A rotated widget is drawn upright
+/// in its own space and turned by the stream's `/Matrix`.
+pub(crate) fn button_appearances(
+    doc: &mut Document,
+    style: &ButtonStyle,
+    width: f32,
+    height: f32,
+    font: Object,
+) -> (ObjectId, ObjectId) {
+    let (w, h) = if style.rotation % 180 == 0 { (width, height) } else { (height, width) };
+    let inset = style.border.as_ref().map_or(0.0, |_| style.border_width);
+    let (cx, cy, r) = (w / 2.0, h / 2.0, w.min(h) / 2.0);
+
+    let mut frame = String::new();
+    if let Some(background) = &style.background {
+        let path = if style.round { circle_path(cx, cy, r) } else { format!("0 0 {} {} re\n", fmt(w), fmt(h)) };
+        frame.push_str(&format!("q\n{}\n{}f\nQ\n", background, path));
+    }
+    if let Some(border) = &style.border {
+        let half = inset / 2.0;
+        let path = if style.round {
+            circle_path(cx, cy, r - half)
+        } else {
+            format!("{} {} {} {} re\n", fmt(half), fmt(half), fmt(w - inset), fmt(h - inset))
+        };
+        frame.push_str(&format!("q\n{}\n{} w\n{}S\nQ\n", border, fmt(inset), path));
+    }
+
+    let size = if style.size > 0.0 { style.size } else { (w.min(h) - 2.0 * inset) * 0.8 };
+    let advance = BUTTON_GLYPHS.iter().find(|(glyph, _)| *glyph == style.glyph).map_or(0.78, |(_, width)| *width);
+    // ZapfDingbats marks stand about 0.7 em tall on the baseline.
+    let on = format!(
+        "{}q\n{}\nBT\n/ZaDb {} Tf\n{} {} Td\n({}) Tj\nET\nQ\n",
+        frame,
+        style.colour,
+        fmt(size),
+        fmt((w - size * advance) / 2.0),
+        fmt((h - size * 0.7) / 2.0),
+        escape_text(&style.glyph.to_string())
+    );
+    let matrix: [f32; 6] = match style.rotation {
+        90 => [0.0, 1.0, -1.0, 0.0, width, 0.0],
+        180 => [-1.0, 0.0, 0.0, -1.0, width, height],
+        270 => [0.0, -1.0, 1.0, 0.0, 0.0, height],
+        _ => [1.0, 0.0, 0.0, 1.0, 0.0, 0.0],
+    };
+    let form = |content: String, resources: Dictionary| {
+        let mut dict = dictionary! {
+            "Type" => "XObject",
+            "Subtype" => "Form",
+            "BBox" => Object::Array(vec![0.into(), 0.into(), Object::Real(w), Object::Real(h)]),
+            "Resources" => resources,
+        };
+        if style.rotation != 0 {
+            dict.set("Matrix", matrix.iter().map(|value| Object::Real(*value)).collect::<Vec<_>>());
+        }
+        Object::Stream(Stream::new(dict, content.into_bytes()))
+    };
+    let on = doc.add_object(form(on, dictionary! { "Font" => dictionary! { "ZaDb" => font } }));
+    let off = doc.add_object(form(frame, Dictionary::new()));
+    (on, off)
+}
+
+/// Gives each widget of a check box or radio button field that has no
+/// on-state in `/AP /N` an on and off appearance, so checking it shows. The
+/// on-state is `Yes` for a check box (or the field's current value, if it
+/// names one). A radio button keeps an export name it already has: the
+/// state its `/AS` names, its `/Opt` export value, or the field's `/V` when
+/// it is the only button left without a state. Otherwise it is numbered:
+/// its option index when the field has `/Opt`, its 1-based position if not.
+pub(crate) fn ensure_button_appearances(
+    doc: &mut Document,
+    field: &FieldDescriptor,
+    defaults: &FormDefaults,
+) -> CoreResult<()> {
+    let kind = field.kind();
+    if !matches!(kind, "checkbox" | "radio") {
+        return Ok(());
+    }
+    let has_options = doc.get_dictionary(field.id).is_ok_and(|dict| dict.has(b"Opt"));
+    let exports = field_option_exports(doc, field.id);
+    let states: Vec<Option<Vec<u8>>> = field.widget_ids.iter().map(|id| widget_on_state(doc, *id)).collect();
+    let mut taken: HashSet<String> =
+        states.iter().flatten().map(|state| String::from_utf8_lossy(state).into_owned()).collect();
+    let stateless = states.iter().filter(|state| state.is_none()).count();
+    let current = doc
+        .get_dictionary(field.id)
+        .ok()
+        .and_then(|dict| dict.get(b"V").ok())
+        .and_then(object_to_name)
+        .filter(|value| value != "Off");
+    let mut font = None;
+    for (position, widget_id) in field.widget_ids.iter().enumerate() {
+        if widget_on_state(doc, *widget_id).is_some() {
+            continue;
+        }
+        let Some(widget) = doc.get_dictionary(*widget_id).ok() else {
+            continue;
+        };
+        let Some((_, _, width, height)) = widget.get(b"Rect").ok().and_then(rect_from_object) else {
+            continue;
+        };
+        let da = field.da.as_deref().or(defaults.da.as_deref());
+        let style = ButtonStyle::read(doc, widget, da, kind == "radio");
+        let state = match kind {
+            "radio" => {
+                let named = widget.get(b"AS").ok().and_then(object_to_name).filter(|state| state != "Off");
+                let exported = exports.get(position).filter(|export| !export.is_empty()).cloned();
+                let only = current.clone().filter(|_| stateless == 1);
+                let numbered = if has_options { position } else { position + 1 };
+                [named, exported, only]
+                    .into_iter()
+                    .flatten()
+                    .find(|state| !taken.contains(state))
+                    .unwrap_or_else(|| numbered.to_string())
+            }
+            _ => current.clone().unwrap_or_else(|| "Yes".to_owned()),
+        };
+        taken.insert(state.clone());
+        let font = font
+            .get_or_insert_with(|| defaults.fonts.get(b"ZaDb").ok().cloned().unwrap_or_else(|| zapf_dingbats_font(doc)))
+            .clone();
+        let (on, off) = button_appearances(doc, &style, width, height, font);
+        let mut normal = Dictionary::new();
+        normal.set(state.into_bytes(), Object::Reference(on));
+        normal.set("Off", Object::Reference(off));
+        let widget = get_dict_mut(doc, *widget_id, "BW_FILL_WIDGET_UPDATE_FAILED", &format!("widget {:?}", widget_id))?;
+        widget.set("AP", dictionary! { "N" => normal });
+        if !widget.has(b"AS") {
+            widget.set("AS", "Off");
+        }
+    }
+    Ok(())
+}
+
+fn zapf_dingbats_font(doc: &mut Document) -> Object {
+    Object::Reference(doc.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type1",
+        "BaseFont" => "ZapfDingbats",
+    }))
+}
+
+fn helvetica_font(doc: &mut Document) -> ObjectId {
+    doc.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type1",
+        "BaseFont" => "Helvetica",
+        "Encoding" => "WinAnsiEncoding",
+    })
+}
+
+/// Rough Helvetica advance: half an em per glyph is close enough for
+/// alignment of short field values.
+fn estimate_text_width(text: &str, size: f32) -> f32 {
+    text.chars().count() as f32 * size * 0.5
+}
+
+/// Escapes a literal string; characters outside Latin-1 become `?`.
+fn escape_text(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '(' | ')' | '\\' => {
+                out.push('\\');
+                out.push(ch);
+            }
+            '\r' | '\n' => out.push(' '),
+            ch if (ch as u32) < 0x20 => {}
+            ch if (ch as u32) < 0x80 => out.push(ch),
+            ch if (ch as u32) <= 0xFF => out.push_str(&format!("\\{:03o}", ch as u32)),
+            _ => out.push('?'),
+        }
+    }
+    out
+}
+
+fn fmt(value: f32) -> String {
+    let rounded = (value * 100.0).round() / 100.0;
+    if rounded.fract() == 0.0 {
+        format!("{}", rounded as i64)
+    } else {
+        format!("{}", rounded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::make_fixture_pdf;
+    use crate::{fill_blocks_impl, is_widget_dict, FieldValue};
+    use std::collections::HashMap;
+
+    #[test]
+    fn default_appearance_splits_font_and_colour() {
+        let da = parse_default_appearance("0.2 0.4 0.6 rg /TiRo 11 Tf");
+        assert_eq!(da.font, "TiRo");
+        assert_eq!(da.size, 11.0);
+        assert_eq!(da.colour, "0.2 0.4 0.6 rg");
+    }
+
+    #[test]
+    fn text_is_escaped_for_literal_strings() {
+        assert_eq!(escape_text("a(b)c\\"), "a\\(b\\)c\\\\");
+        assert_eq!(escape_text("é€"), "\\351?");
+    }
+
+    #[test]
+    fn colours_and_rotation_follow_the_widget() {
+        let operator = |values: &[f32], stroke| {
+            colour_operator(&values.iter().map(|value| Object::Real(*value)).collect::<Vec<_>>(), stroke)
+        };
+        assert_eq!(operator(&[0.5], false).as_deref(), Some("0.5 g"));
+        assert_eq!(operator(&[1.0, 0.0, 0.0], true).as_deref(), Some("1 0 0 RG"));
+        assert_eq!(operator(&[0.0, 0.0, 0.0, 1.0], false).as_deref(), Some("0 0 0 1 k"));
+        assert_eq!(operator(&[], true), None);
+
+        let mut doc = Document::with_version("1.7");
+        let widget = dictionary! {
+            "MK" => dictionary! { "CA" => Object::string_literal("8"), "BC" => vec![Object::Real(1.0)], "R" => 90 },
+        };
+        let style = ButtonStyle::read(&doc, &widget, Some("0 0 1 rg /ZaDb 0 Tf"), false);
+        assert_eq!((style.glyph, style.rotation, style.colour.as_str()), ('8', 90, "0 0 1 rg"));
+        let (on, off) = button_appearances(&mut doc, &style, 20.0, 10.0, Object::Null);
+        let on = doc.get_object(on).and_then(Object::as_stream).expect("on appearance");
+        let content = String::from_utf8_lossy(&on.content).into_owned();
+        assert!(content.contains("1 G") && content.contains("(8) Tj"), "{}", content);
+        let numbers = |key: &[u8]| -> Vec<f32> {
+            let values = on.dict.get(key).and_then(Object::as_array).expect("number array");
+            values.iter().filter_map(object_to_number).collect()
+        };
+        assert_eq!(numbers(b"BBox"), vec![0.0, 0.0, 10.0, 20.0]);
+        assert_eq!(numbers(b"Matrix"), vec![0.0, 1.0, -1.0, 0.0, 20.0, 0.0]);
+        let off = doc.get_object(off).and_then(Object::as_stream).expect("off appearance");
+        assert!(!String::from_utf8_lossy(&off.content).contains("Tj"));
+    }
+
+    #[test]
+    fn buttons_without_appearances_get_one_when_filled() {
+        let mut doc = Document::load_mem(&make_fixture_pdf()).expect("fixture parses");
+        for object in doc.objects.values_mut() {
+            if let Object::Dictionary(dict) = object {
+                if is_widget_dict(dict) {
+                    dict.remove(b"AP");
+                    dict.remove(b"AS");
+                }
+            }
+        }
+        let mut pdf = Vec::new();
+        doc.save_to(&mut pdf).expect("fixture saves");
+
+        let mut values = HashMap::new();
+        values.insert("Consent".to_string(), FieldValue::Bool(true));
+        values.insert("Choice".to_string(), FieldValue::from("2"));
+        let filled = fill_blocks_impl(&pdf, values).expect("buttons fill");
+        let doc = Document::load_mem(&filled).expect("filled output parses");
+
+        let mut states = Vec::new();
+        for dict in doc.objects.values().filter_map(|object| object.as_dict().ok()) {
+            if !is_widget_dict(dict) || dict.get(b"FT").ok().and_then(object_to_name).as_deref() == Some("Tx") {
+                continue;
+            }
+            let normal = dict.get(b"AP").and_then(Object::as_dict).and_then(|ap| ap.get(b"N"));
+            let normal = normal.and_then(Object::as_dict).expect("generated appearance");
+            let mut names = normal.iter().map(|(name, _)| String::from_utf8_lossy(name).into_owned());
+            let on = names.find(|name| name != "Off");
+            states.push((on, dict.get(b"AS").ok().and_then(object_to_name)));
+        }
+        states.sort();
+        let state = |on: &str, current: &str| (Some(on.to_owned()), Some(current.to_owned()));
+        assert_eq!(states, vec![state("1", "Off"), state("2", "2"), state("Yes", "Yes")]);
+    }
+
+    #[test]
+    fn generated_radio_states_keep_existing_export_names() {
+        // `edit` gets the Choice field and its widgets, the latter in order.
+        let on_states = |edit: &dyn Fn(&mut Dictionary, Option<usize>), value: &str| {
+            let mut doc = Document::load_mem(&make_fixture_pdf()).expect("fixture parses");
+            let mut widgets = 0;
+            for dict in doc.objects.values_mut().filter_map(|object| object.as_dict_mut().ok()) {
+                if dict.get(b"T").ok().and_then(object_to_text).as_deref() == Some("Choice") {
+                    edit(dict, None);
+                } else {
+                    let normal = dict.get(b"AP").and_then(Object::as_dict).and_then(|ap| ap.get(b"N"));
+                    if normal.and_then(Object::as_dict).is_ok_and(|normal| normal.has(b"A") || normal.has(b"B")) {
+                        edit(dict, Some(widgets));
+                        widgets += 1;
+                    }
+                }
+            }
+            let mut pdf = Vec::new();
+            doc.save_to(&mut pdf).expect("fixture saves");
+            let filled = fill_blocks_impl(&pdf, HashMap::from([("Choice".to_string(), FieldValue::from(value))]));
+            let doc = Document::load_mem(&filled.expect("radio fills")).expect("filled output parses");
+            let mut states: Vec<(String, String)> = doc
+                .objects
+                .iter()
+                .filter(|(_, object)| object.as_dict().is_ok_and(|dict| dict.has(b"Parent") && dict.has(b"AS")))
+                .filter_map(|(id, object)| {
+                    let state = widget_on_state(&doc, *id)?;
+                    let current = object.as_dict().ok()?.get(b"AS").ok().and_then(object_to_name)?;
+                    Some((String::from_utf8_lossy(&state).into_owned(), current))
+                })
+                .filter(|(state, _)| state != "Yes")
+                .collect();
+            states.sort();
+            states
+        };
+        let state = |on: &str, current: &str| (on.to_owned(), current.to_owned());
+
+        let with_options = |dict: &mut Dictionary, widget: Option<usize>| match widget {
+            None => dict.set("Opt", vec![Object::string_literal("Single"), Object::string_literal("Married")]),
+            Some(_) => {
+                dict.remove(b"AP");
+            }
+        };
+        assert_eq!(on_states(&with_options, "married"), vec![state("Married", "Married"), state("Single", "Off")]);
+
+        let with_value = |dict: &mut Dictionary, widget: Option<usize>| match widget {
+            None => dict.set("V", "B"),
+            Some(1) => {
+                dict.remove(b"AP");
+            }
+            Some(_) => {}
+        };
+        assert_eq!(on_states(&with_value, "a"), vec![state("A", "A"), state("B", "Off")]);
+    }
+}
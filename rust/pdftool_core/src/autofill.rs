@@ -0,0 +1,292 @@
+// SPDX-License-Identifier: PMPL-1.0-or-later WITH Palimpsest
+
+//! Profile-based autofill.
+//!
+//! A [`Profile`] holds what is known about a person. [`propose`] maps its
+//! attributes onto fields by their classification ([`crate::classify`]),
+//! deriving values where the form asks for something the profile stores
+//! differently: a given or family name split from `fullName`, a full name
+//! joined from its parts, or a date of birth rewritten in the field's
+//! `AFDate_FormatEx` pattern. Nothing is written: each [`Proposal`] carries
+//! its provenance so the user can confirm it, and the accepted values go
+//! through the ordinary fill.
+
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::classify::normalise;
+use crate::{FieldClassification, FieldSnapshot, FieldTag, FieldValue};
+
+/// Lowest classification confidence a field needs to be proposed a value.
+const MIN_CONFIDENCE: f64 = 0.5;
+/// Confidence of a match between an `ids` key and a field's name or label.
+const ID_CONFIDENCE: f64 = 0.6;
+/// How much a split full name is trusted relative to a stored part.
+const SPLIT_TRUST: f64 = 0.9;
+
+pub(crate) const MONTHS: [&str; 12] = [
+    "January", "February", "March", "April", "May", "June", "July", "August", "September", "October", "November",
+    "December",
+];
+
+/// What is known about the person filling forms. Dates are ISO
+/// `yyyy-mm-dd`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Profile {
+    pub given_name: Option<String>,
+    pub family_name: Option<String>,
+    pub full_name: Option<String>,
+    pub email: Option<String>,
+    pub phone: Option<String>,
+    pub date_of_birth: Option<String>,
+    pub street: Option<String>,
+    pub city: Option<String>,
+    pub region: Option<String>,
+    pub postcode: Option<String>,
+    pub country: Option<String>,
+    /// Identifiers such as `{ "passport number": "X1234567" }`, proposed for
+    /// fields whose name or printed label contains the key.
+    pub ids: BTreeMap<String, String>,
+}
+
+/// How a proposed value was obtained from the profile.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum Derivation {
+    /// The attribute as stored.
+    Copied,
+    /// The first or last word of `fullName`.
+    SplitName,
+    /// `givenName` and `familyName` joined with a space.
+    JoinedName,
+    /// An ISO date rewritten in the field's date format.
+    FormattedDate { format: String },
+}
+
+/// A value suggested for one field, with where it came from.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Proposal {
+    pub field: String,
+    pub value: String,
+    /// The field's classification; `None` for `ids` matches.
+    pub tag: Option<FieldTag>,
+    /// Profile attributes used, such as `givenName` or `ids.passport number`.
+    pub sources: Vec<String>,
+    pub derivation: Derivation,
+    pub confidence: f64,
+    /// The value the field holds now, when it is not empty.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current: Option<FieldValue>,
+}
+
+/// `(first, last)` words of a full name; `Family, Given` is read as written.
+fn split_name(full: &str) -> Option<(String, String)> {
+    if let Some((family, given)) = full.split_once(',') {
+        let given = given.split_whitespace().next()?;
+        return Some((given.to_owned(), family.trim().to_owned()));
+    }
+    let mut words = full.split_whitespace();
+    let first = words.next()?;
+    let last = words.last()?;
+    Some((first.to_owned(), last.to_owned()))
+}
+
+/// `iso` (`yyyy-mm-dd`) in an `AFDate_FormatEx` pattern: `yyyy`, `yy`,
+/// `mmmm` (January), `mmm` (Jan), `mm`, `m`, `dd` and `d`; other characters
+/// are copied.
+pub(crate) fn format_date(iso: &str, pattern: &str) -> Option<String> {
+    let mut parts = iso.trim().splitn(3, '-');
+    let year: u32 = parts.next()?.parse().ok()?;
+    let month: usize = parts.next()?.parse().ok()?;
+    let day: u32 = parts.next()?.parse().ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let mut out = String::new();
+    let mut rest = pattern;
+    while let Some(ch) = rest.chars().next() {
+        let run = rest.chars().take_while(|next| *next == ch).count();
+        let (text, used) = match (ch, run) {
+            ('y', 4..) => (format!("{:04}", year), 4),
+            ('y', 2..) => (format!("{:02}", year % 100), 2),
+            ('m', 4..) => (MONTHS[month - 1].to_owned(), 4),
+            ('m', 3) => (MONTHS[month - 1][..3].to_owned(), 3),
+            ('m', 2) => (format!("{:02}", month), 2),
+            ('m', _) => (month.to_string(), 1),
+            ('d', 2..) => (format!("{:02}", day), 2),
+            ('d', _) => (day.to_string(), 1),
+            _ => (ch.to_string(), 1),
+        };
+        out.push_str(&text);
+        rest = &rest[used * ch.len_utf8()..];
+    }
+    Some(out)
+}
+
+/// The profile's value for `tag`, with its sources and derivation.
+fn value_for(profile: &Profile, tag: FieldTag, date_format: Option<&str>) -> Option<(String, Vec<&'static str>, Derivation)> {
+    let copied = |value: &Option<String>, source: &'static str| {
+        value.clone().filter(|value| !value.trim().is_empty()).map(|value| (value, vec![source], Derivation::Copied))
+    };
+    let split = || profile.full_name.as_deref().and_then(split_name);
+    match tag {
+        FieldTag::GivenName => copied(&profile.given_name, "givenName")
+            .or_else(|| split().map(|(given, _)| (given, vec!["fullName"], Derivation::SplitName))),
+        FieldTag::FamilyName => copied(&profile.family_name, "familyName")
+            .or_else(|| split().map(|(_, family)| (family, vec!["fullName"], Derivation::SplitName))),
+        FieldTag::FullName => copied(&profile.full_name, "fullName").or_else(|| {
+            let (given, family) = (profile.given_name.as_ref()?, profile.family_name.as_ref()?);
+            Some((format!("{} {}", given, family), vec!["givenName", "familyName"], Derivation::JoinedName))
+        }),
+        FieldTag::DateOfBirth => {
+            let (iso, sources, _) = copied(&profile.date_of_birth, "dateOfBirth")?;
+            match date_format.and_then(|format| Some((format, format_date(&iso, format)?))) {
+                Some((format, value)) => Some((value, sources, Derivation::FormattedDate { format: format.to_owned() })),
+                None => Some((iso, sources, Derivation::Copied)),
+            }
+        }
+        FieldTag::Email => copied(&profile.email, "email"),
+        FieldTag::Phone => copied(&profile.phone, "phone"),
+        FieldTag::Street => copied(&profile.street, "street"),
+        FieldTag::City => copied(&profile.city, "city"),
+        FieldTag::Region => copied(&profile.region, "region"),
+        FieldTag::Postcode => copied(&profile.postcode, "postcode"),
+        FieldTag::Country => copied(&profile.country, "country"),
+        FieldTag::SignatureDate
+        | FieldTag::Date
+        | FieldTag::Amount
+        | FieldTag::Consent
+        | FieldTag::Signature => None,
+    }
+}
+
+/// Values proposed for the `classified` fields from `profile`, in field
+/// order. `current` supplies the values the fields hold now.
+pub fn propose(classified: &[FieldClassification], current: &[FieldSnapshot], profile: &Profile) -> Vec<Proposal> {
+    let current: HashMap<&str, &FieldValue> = current.iter().map(|field| (field.name.as_str(), &field.value)).collect();
+    let ids: Vec<(String, &String, &String)> =
+        profile.ids.iter().map(|(key, value)| (normalise(key), key, value)).filter(|(key, _, _)| key.trim() != "").collect();
+
+    let mut proposals = Vec::new();
+    for field in classified {
+        if !matches!(field.kind.as_str(), "text" | "combo") {
+            continue;
+        }
+        let best = field.best().filter(|score| score.confidence >= MIN_CONFIDENCE);
+        let proposal = match best.and_then(|score| Some((score, value_for(profile, score.tag, field.date_format.as_deref())?))) {
+            Some((score, (value, sources, derivation))) => {
+                let trust = if derivation == Derivation::SplitName { SPLIT_TRUST } else { 1.0 };
+                Some(Proposal {
+                    field: field.name.clone(),
+                    value,
+                    tag: Some(score.tag),
+                    sources: sources.into_iter().map(str::to_owned).collect(),
+                    derivation,
+                    confidence: (score.confidence * trust * 100.0).round() / 100.0,
+                    current: None,
+                })
+            }
+            None => {
+                let haystack = format!("{}{}", normalise(&field.name), normalise(field.nearby_label.as_deref().unwrap_or("")));
+                ids.iter().find(|(key, _, _)| haystack.contains(key.as_str())).map(|(_, key, value)| Proposal {
+                    field: field.name.clone(),
+                    value: (*value).clone(),
+                    tag: None,
+                    sources: vec![format!("ids.{}", key)],
+                    derivation: Derivation::Copied,
+                    confidence: ID_CONFIDENCE,
+                    current: None,
+                })
+            }
+        };
+        if let Some(mut proposal) = proposal {
+            proposal.current = current
+                .get(field.name.as_str())
+                .filter(|value| !matches!(value, FieldValue::Clear) && value.as_text().is_none_or(|text| !text.is_empty()))
+                .map(|value| (*value).clone());
+            proposals.push(proposal);
+        }
+    }
+    proposals
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TagScore;
+
+    fn classified(name: &str, tag: FieldTag, confidence: f64, date_format: Option<&str>) -> FieldClassification {
+        FieldClassification {
+            name: name.into(),
+            kind: "text".into(),
+            nearby_label: None,
+            date_format: date_format.map(Into::into),
+            tags: vec![TagScore { tag, confidence }],
+        }
+    }
+
+    #[test]
+    fn derived_values_carry_their_provenance() {
+        let fields = vec![
+            classified("First", FieldTag::GivenName, 0.8, None),
+            classified("Last", FieldTag::FamilyName, 0.8, None),
+            classified("Born", FieldTag::DateOfBirth, 0.9, Some("dd mmm yyyy")),
+            classified("Mail", FieldTag::Email, 0.4, None),
+            FieldClassification {
+                tags: Vec::new(),
+                nearby_label: Some("Passport No.".into()),
+                ..classified("f1_12", FieldTag::Email, 0.0, None)
+            },
+        ];
+        let current = vec![FieldSnapshot {
+            name: "Last".into(),
+            field_type: "Tx".into(),
+            value: FieldValue::from("Byron"),
+        }];
+        let profile = Profile {
+            full_name: Some("Ada King Lovelace".into()),
+            date_of_birth: Some("1815-12-10".into()),
+            email: Some("ada@example.org".into()),
+            ids: BTreeMap::from([("passport no".into(), "X1234567".into())]),
+            ..Profile::default()
+        };
+
+        let proposals = propose(&fields, &current, &profile);
+        let summary: Vec<(&str, &str, &Derivation, f64)> = proposals
+            .iter()
+            .map(|proposal| (proposal.field.as_str(), proposal.value.as_str(), &proposal.derivation, proposal.confidence))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("First", "Ada", &Derivation::SplitName, 0.72),
+                ("Last", "Lovelace", &Derivation::SplitName, 0.72),
+                ("Born", "10 Dec 1815", &Derivation::FormattedDate { format: "dd mmm yyyy".into() }, 0.9),
+                ("f1_12", "X1234567", &Derivation::Copied, ID_CONFIDENCE),
+            ],
+            "low-confidence email field skipped"
+        );
+        assert_eq!(proposals[1].current, Some(FieldValue::from("Byron")));
+        assert_eq!(proposals[3].sources, ["ids.passport no"]);
+    }
+
+    #[test]
+    fn names_join_and_dates_format() {
+        let profile = Profile {
+            given_name: Some("Grace".into()),
+            family_name: Some("Hopper".into()),
+            ..Profile::default()
+        };
+        assert_eq!(
+            value_for(&profile, FieldTag::FullName, None),
+            Some(("Grace Hopper".into(), vec!["givenName", "familyName"], Derivation::JoinedName))
+        );
+        assert_eq!(split_name("Hopper, Grace Brewster"), Some(("Grace".into(), "Hopper".into())));
+        assert_eq!(format_date("1906-12-09", "mm/dd/yyyy").as_deref(), Some("12/09/1906"));
+        assert_eq!(format_date("1906-12-09", "d mmmm yy").as_deref(), Some("9 December 06"));
+        assert_eq!(format_date("09/12/1906", "mm/dd/yyyy"), None);
+    }
+}
@@ -0,0 +1,167 @@
+// SPDX-License-Identifier: PMPL-1.0-or-later WITH Palimpsest
+
+//! Mail-merge: one template filled once per record.
+//!
+//! The template is parsed and indexed once; every record fills a copy of the
+//! loaded document, so nothing is re-read per row. Records either become one
+//! PDF each or are combined into a single PDF whose fields are renamed per
+//! record (`record1.Name`, `record2.Name`, ...). A record that cannot be
+//! filled is reported and skipped; the others still go through.
+
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use crate::csv::parse_csv;
+use crate::merge::{append_document, prefix_fields};
+use crate::{core_error, CoreError, CoreResult, FieldValue, FillReport, FormDocument, PartialNamePolicy};
+
+/// Outcome of one record of a batch fill.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchRecord {
+    /// 1-based position of the record in the input.
+    pub record: usize,
+    /// The record's filled PDF; absent in a combined batch or on failure.
+    #[serde(skip)]
+    pub output: Option<Vec<u8>>,
+    /// Field-name prefix of the record's copy in a combined batch.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub report: Option<FillReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<CoreError>,
+}
+
+/// Records of a CSV table: the header row gives the field keys, and every
+/// cell is filled as text.
+pub fn csv_records(text: &str) -> CoreResult<Vec<HashMap<String, FieldValue>>> {
+    let table = parse_csv(text)?;
+    Ok(table
+        .rows
+        .into_iter()
+        .map(|row| table.header.iter().cloned().zip(row.into_iter().map(FieldValue::Text)).collect())
+        .collect())
+}
+
+/// The template filled with `values`, or why it could not be.
+fn fill_copy(
+    template: &FormDocument,
+    values: &HashMap<String, FieldValue>,
+    policy: PartialNamePolicy,
+) -> CoreResult<(FormDocument, FillReport)> {
+    let mut copy = template.clone();
+    let report = copy.fill_with_policy(values, policy)?;
+    Ok((copy, report))
+}
+
+pub(crate) fn fill_separately(
+    template: &FormDocument,
+    records: &[HashMap<String, FieldValue>],
+    policy: PartialNamePolicy,
+) -> CoreResult<Vec<BatchRecord>> {
+    template.index()?;
+    Ok(records
+        .iter()
+        .enumerate()
+        .map(|(position, values)| {
+            let filled = fill_copy(template, values, policy).and_then(|(copy, report)| Ok((copy.save()?, report)));
+            let (output, report, error) = match filled {
+                Ok((output, report)) => (Some(output), Some(report), None),
+                Err(error) => (None, None, Some(error)),
+            };
+            BatchRecord {
+                record: position + 1,
+                output,
+                prefix: None,
+                report,
+                error,
+            }
+        })
+        .collect())
+}
+
+pub(crate) fn fill_combined(
+    template: &FormDocument,
+    records: &[HashMap<String, FieldValue>],
+    policy: PartialNamePolicy,
+) -> CoreResult<(Vec<u8>, Vec<BatchRecord>)> {
+    template.index()?;
+    let mut combined: Option<FormDocument> = None;
+    let mut outcomes = Vec::with_capacity(records.len());
+    for (position, values) in records.iter().enumerate() {
+        let prefix = format!("record{}", position + 1);
+        let filled = fill_copy(template, values, policy).and_then(|(mut copy, report)| {
+            prefix_fields(&mut copy.doc, &prefix)?;
+            match combined.as_mut() {
+                Some(combined) => append_document(&mut combined.doc, copy.doc)?,
+                None => combined = Some(copy),
+            }
+            Ok(report)
+        });
+        outcomes.push(match filled {
+            Ok(report) => BatchRecord {
+                record: position + 1,
+                output: None,
+                prefix: Some(prefix),
+                report: Some(report),
+                error: None,
+            },
+            Err(error) => BatchRecord {
+                record: position + 1,
+                output: None,
+                prefix: None,
+                report: None,
+                error: Some(error),
+            },
+        });
+    }
+    let Some(mut combined) = combined else {
+        return Err(core_error(
+            "BW_BATCH_NOTHING_FILLED",
+            format!("none of the {} records could be filled", records.len()),
+        ));
+    };
+    combined.invalidate_index();
+    Ok((combined.save()?, outcomes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::make_fixture_pdf;
+
+    #[test]
+    fn every_record_gets_its_own_pdf_and_report() {
+        let template = FormDocument::load(&make_fixture_pdf()).expect("loads");
+        let records = csv_records("Name,Choice\nAda,A\nGrace,C\n").expect("valid CSV");
+        let outcomes = template.fill_batch(&records, Default::default()).expect("batch");
+        assert_eq!(outcomes.len(), 2);
+        assert_eq!(outcomes[0].report.as_ref().map(|report| report.filled), Some(2));
+        assert_eq!(outcomes[1].error.as_ref().map(CoreError::code), Some("BW_FILL_BUTTON_VALUE_INVALID"));
+        assert!(outcomes[1].output.is_none());
+
+        let first = FormDocument::load(outcomes[0].output.as_deref().expect("output")).expect("output loads");
+        let values: HashMap<String, FieldValue> =
+            first.extract().expect("extract").into_iter().map(|field| (field.name, field.value)).collect();
+        assert_eq!(values["Name"], FieldValue::from("Ada"));
+    }
+
+    #[test]
+    fn a_combined_batch_renames_fields_per_record() {
+        let template = FormDocument::load(&make_fixture_pdf()).expect("loads");
+        let records = csv_records("Name\nAda\nGrace\nEdsger\n").expect("valid CSV");
+        let (combined, outcomes) = template.fill_batch_combined(&records, Default::default()).expect("batch");
+        assert_eq!(outcomes[2].prefix.as_deref(), Some("record3"));
+
+        let combined = FormDocument::load(&combined).expect("combined loads");
+        assert_eq!(combined.doc.get_pages().len(), 3);
+        let values: HashMap<String, FieldValue> =
+            combined.extract().expect("extract").into_iter().map(|field| (field.name, field.value)).collect();
+        assert_eq!(values.len(), 9);
+        assert_eq!(values["record1.Name"], FieldValue::from("Ada"));
+        assert_eq!(values["record3.Name"], FieldValue::from("Edsger"));
+        assert_eq!(values["record2.Consent"], FieldValue::from("Off"));
+    }
+}
@@ -0,0 +1,7 @@
+// SPDX-License-Identifier: PMPL-1.0-or-later WITH Palimpsest
+
+#![forbid(unsafe_code)]
+
+fn main() -> std::process::ExitCode {
+    pdftool_core::cli::run(std::env::args().skip(1))
+}
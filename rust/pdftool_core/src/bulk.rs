@@ -0,0 +1,160 @@
+// SPDX-License-Identifier: PMPL-1.0-or-later WITH Palimpsest
+
+//! Bulk extraction: the values of many completed copies of one form as a
+//! single table.
+//!
+//! The blank template fixes the columns, its terminal fields in tree order,
+//! so every run over the same form yields the same layout whatever order
+//! the fields appear in within each copy. A document that cannot be read,
+//! or that lacks any of the template's fields, gets a row carrying its error
+//! instead of values.
+
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use crate::csv::write_csv;
+use crate::value::format_number;
+use crate::{core_error_with_context, CoreError, CoreResult, FieldValue, FormDocument};
+
+/// Template fields listed by name in a mismatch error before it is cut short.
+const LISTED_MISSING: usize = 5;
+
+/// Values of many documents under one template's columns.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BulkExtraction {
+    /// Full names of the template's terminal fields, in tree order.
+    pub columns: Vec<String>,
+    /// One row per document, in input order.
+    pub rows: Vec<ExtractedRow>,
+}
+
+/// One document's values, aligned with [`BulkExtraction::columns`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ExtractedRow {
+    /// The name the document was given (usually its path).
+    pub source: String,
+    /// Empty when `error` is set.
+    pub values: Vec<FieldValue>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<CoreError>,
+}
+
+impl BulkExtraction {
+    /// The table as CSV: a `source` column, the template's columns, then an
+    /// `error` column holding `CODE: message` for documents that failed.
+    /// Booleans are written `true`/`false` and list items joined with `;`.
+    pub fn to_csv(&self) -> String {
+        let mut rows = Vec::with_capacity(self.rows.len() + 1);
+        rows.push(
+            std::iter::once("source".to_owned())
+                .chain(self.columns.iter().cloned())
+                .chain(std::iter::once("error".to_owned()))
+                .collect(),
+        );
+        for row in &self.rows {
+            let mut cells = vec![row.source.clone()];
+            match &row.error {
+                Some(error) => {
+                    cells.extend(self.columns.iter().map(|_| String::new()));
+                    cells.push(format!("{}: {}", error.code(), error.message()));
+                }
+                None => {
+                    cells.extend(row.values.iter().map(cell_text));
+                    cells.push(String::new());
+                }
+            }
+            rows.push(cells);
+        }
+        write_csv(&rows)
+    }
+}
+
+fn cell_text(value: &FieldValue) -> String {
+    match value {
+        FieldValue::Clear => String::new(),
+        FieldValue::Bool(flag) => flag.to_string(),
+        FieldValue::Number(number) => format_number(*number).unwrap_or_default(),
+        FieldValue::Text(text) => text.clone(),
+        FieldValue::List(items) => items.join(";"),
+    }
+}
+
+pub(crate) fn extract_documents<'a>(
+    template: &FormDocument,
+    documents: impl IntoIterator<Item = (&'a str, &'a [u8])>,
+    password: Option<&str>,
+) -> CoreResult<BulkExtraction> {
+    let columns: Vec<String> = template.extract()?.into_iter().map(|field| field.name).collect();
+    let rows = documents
+        .into_iter()
+        .map(|(source, data)| {
+            let values = FormDocument::load_with_limits(data, password, template.limits().clone())
+                .and_then(|document| document.extract())
+                .and_then(|fields| {
+                    let mut found: HashMap<String, FieldValue> =
+                        fields.into_iter().map(|field| (field.name, field.value)).collect();
+                    let missing: Vec<&str> =
+                        columns.iter().filter(|column| !found.contains_key(*column)).map(String::as_str).collect();
+                    if !missing.is_empty() {
+                        let listed = missing.iter().take(LISTED_MISSING).copied().collect::<Vec<_>>().join(", ");
+                        let more = if missing.len() > LISTED_MISSING { ", ..." } else { "" };
+                        return Err(core_error_with_context(
+                            "BW_EXTRACT_FORM_MISMATCH",
+                            format!("{} of the template's fields are missing: {}{}", missing.len(), listed, more),
+                            Some(source.to_owned()),
+                        ));
+                    }
+                    Ok(columns.iter().map(|column| found.remove(column).unwrap_or(FieldValue::Clear)).collect())
+                });
+            match values {
+                Ok(values) => ExtractedRow {
+                    source: source.to_owned(),
+                    values,
+                    error: None,
+                },
+                Err(error) => ExtractedRow {
+                    source: source.to_owned(),
+                    values: Vec::new(),
+                    error: Some(error),
+                },
+            }
+        })
+        .collect();
+    Ok(BulkExtraction { columns, rows })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::make_fixture_pdf;
+
+    #[test]
+    fn one_row_per_document_in_template_order() {
+        let template = FormDocument::load(&make_fixture_pdf()).expect("loads");
+        let mut form = template.clone();
+        form.fill(&HashMap::from([
+            ("Name".to_string(), FieldValue::from("Lovelace, Ada")),
+            ("Choice".to_string(), FieldValue::from("B")),
+        ]))
+        .expect("fill");
+        let filled = form.save().expect("save");
+        let mut renamed = template.clone();
+        crate::merge::prefix_fields(&mut renamed.doc, "Other").expect("prefix");
+        let other = renamed.save().expect("save");
+
+        let documents = [("a.pdf", filled.as_slice()), ("b.pdf", other.as_slice()), ("c.pdf", b"not a pdf".as_slice())];
+        let table = template.extract_bulk(documents, None).expect("extract");
+        assert_eq!(table.columns, ["Name", "Consent", "Choice"]);
+        assert_eq!(table.rows[0].values, [FieldValue::from("Lovelace, Ada"), FieldValue::from("Off"), FieldValue::from("B")]);
+        let mismatch = table.rows[1].error.as_ref().expect("other form");
+        assert_eq!((mismatch.code(), mismatch.context()), ("BW_EXTRACT_FORM_MISMATCH", Some("b.pdf")));
+        assert!(table.rows[2].error.is_some());
+
+        let csv = table.to_csv();
+        let mut lines = csv.lines();
+        assert_eq!(lines.next(), Some("source,Name,Consent,Choice,error"));
+        assert_eq!(lines.next(), Some("a.pdf,\"Lovelace, Ada\",Off,B,"));
+        assert!(lines.next().is_some_and(|line| line.starts_with("b.pdf,,,,\"BW_EXTRACT_FORM_MISMATCH: 3 of")));
+    }
+}
@@ -0,0 +1,628 @@
+// SPDX-License-Identifier: PMPL-1.0-or-later WITH Palimpsest
+
+//! Heuristic semantic classification of fields for autofill.
+//!
+//! Each terminal field is scored against a fixed set of [`FieldTag`]s from
+//! four sources of evidence: its full name, its `/TU` label, the page text
+//! nearest to its first widget (the label printed on the form), and its
+//! `/AA` format and keystroke scripts (`AFDate_FormatEx`, `AFSpecial_Format`,
+//! `AFNumber_Format` with a currency symbol). Each source that supports a tag
+//! contributes its own weight, and the contributions are combined as
+//! independent evidence (`1 - (1 - a)(1 - b)...`) into the tag's confidence.
+
+use std::collections::HashMap;
+
+use lopdf::content::Content;
+use lopdf::{Document, Object, ObjectId};
+use serde::{Deserialize, Serialize};
+
+use crate::limits::stream_content;
+use crate::{dict_text, Budget, CoreResult, FieldDescriptor, FieldIndex, Limits};
+
+/// What a field asks for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum FieldTag {
+    GivenName,
+    FamilyName,
+    FullName,
+    Email,
+    Phone,
+    DateOfBirth,
+    Street,
+    City,
+    Region,
+    Postcode,
+    Country,
+    SignatureDate,
+    /// Any other date.
+    Date,
+    Amount,
+    /// A checkbox agreeing to terms or confirming a statement.
+    Consent,
+    Signature,
+}
+
+/// A tag and how sure the classifier is of it, in `0..=1`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TagScore {
+    pub tag: FieldTag,
+    pub confidence: f64,
+}
+
+/// The tags found for one terminal field, most likely first.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FieldClassification {
+    pub name: String,
+    pub kind: String,
+    /// Page text read as the field's printed label, when one was found.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nearby_label: Option<String>,
+    /// The `AFDate_FormatEx` pattern (`mm/dd/yyyy`) the field formats dates
+    /// with, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub date_format: Option<String>,
+    pub tags: Vec<TagScore>,
+}
+
+impl FieldClassification {
+    /// The most likely tag, if any evidence was found.
+    pub fn best(&self) -> Option<&TagScore> {
+        self.tags.first()
+    }
+}
+
+/// How much each source of evidence counts.
+const NAME_WEIGHT: f64 = 0.55;
+const TOOLTIP_WEIGHT: f64 = 0.7;
+const LABEL_WEIGHT: f64 = 0.6;
+const FORMAT_WEIGHT: f64 = 0.75;
+
+/// Phrases per tag, matched against whole lowercase words, longest first; a
+/// matched phrase is consumed, so `email address` does not also count as
+/// `address`. The last value scales the source weight: generic words like
+/// `name` or `date` count less.
+const RULES: &[(FieldTag, &[&str], f64)] = &[
+    (FieldTag::GivenName, &["first name", "firstname", "given name", "given names", "forename", "fname"], 1.0),
+    (FieldTag::FamilyName, &["last name", "lastname", "surname", "family name", "lname"], 1.0),
+    (FieldTag::FullName, &["full name", "fullname", "your name", "print name", "name of applicant"], 1.0),
+    (FieldTag::FullName, &["name"], 0.5),
+    (FieldTag::Email, &["email", "e mail", "email address", "mail address"], 1.0),
+    (FieldTag::Phone, &["phone", "telephone", "tel", "mobile", "cell", "phone number", "daytime number"], 1.0),
+    (FieldTag::DateOfBirth, &["date of birth", "birth date", "birthdate", "dob", "born"], 1.0),
+    (FieldTag::Street, &["street", "street address", "address line", "address 1", "address"], 1.0),
+    (FieldTag::City, &["city", "town", "suburb", "municipality"], 1.0),
+    (FieldTag::Region, &["state", "province", "county", "region"], 1.0),
+    (FieldTag::Postcode, &["postcode", "post code", "postal code", "zip", "zip code", "zipcode"], 1.0),
+    (FieldTag::Country, &["country", "nation"], 1.0),
+    (FieldTag::SignatureDate, &["date signed", "signature date", "date of signature", "signed date", "signed on"], 1.0),
+    (FieldTag::Date, &["date"], 0.5),
+    (FieldTag::Amount, &["amount", "total", "sum", "price", "fee", "cost", "balance", "payment"], 1.0),
+    (FieldTag::Consent, &["agree", "consent", "accept", "confirm", "acknowledge", "certify", "declare"], 1.0),
+    (FieldTag::Signature, &["signature", "sign here"], 1.0),
+];
+
+/// Tags a field of `kind` can carry.
+fn fits(kind: &str, tag: FieldTag) -> bool {
+    match kind {
+        "checkbox" => tag == FieldTag::Consent,
+        "signature" => tag == FieldTag::Signature,
+        "text" | "combo" | "list" => !matches!(tag, FieldTag::Consent),
+        _ => false,
+    }
+}
+
+/// Lowercase words split at punctuation, digits-to-letters and camel case,
+/// joined with single spaces and padded so phrases match whole words.
+pub(crate) fn normalise(text: &str) -> String {
+    let mut out = String::from(" ");
+    let mut previous: Option<char> = None;
+    for ch in text.chars() {
+        let boundary = match previous {
+            Some(prev) => {
+                !ch.is_alphanumeric()
+                    || (ch.is_uppercase() && prev.is_lowercase())
+                    || (ch.is_alphabetic() != prev.is_alphabetic() && prev.is_alphanumeric())
+            }
+            None => false,
+        };
+        if boundary && !out.ends_with(' ') {
+            out.push(' ');
+        }
+        if ch.is_alphanumeric() {
+            out.extend(ch.to_lowercase());
+        }
+        previous = Some(ch);
+    }
+    if !out.ends_with(' ') {
+        out.push(' ');
+    }
+    out
+}
+
+/// Adds `weight` of evidence for every rule `text` matches.
+fn score_text(text: &str, weight: f64, scores: &mut HashMap<FieldTag, f64>) {
+    let mut text = normalise(text);
+    let mut phrases: Vec<(&str, FieldTag, f64)> = RULES
+        .iter()
+        .flat_map(|(tag, phrases, strength)| phrases.iter().map(move |phrase| (*phrase, *tag, *strength)))
+        .collect();
+    phrases.sort_by_key(|(phrase, _, _)| std::cmp::Reverse(phrase.len()));
+    let mut best: HashMap<FieldTag, f64> = HashMap::new();
+    for (phrase, tag, strength) in phrases {
+        let phrase = format!(" {} ", phrase);
+        if text.contains(&phrase) {
+            text = text.replace(&phrase, " | ");
+            let entry = best.entry(tag).or_default();
+            *entry = entry.max(weight * strength);
+        }
+    }
+    for (tag, evidence) in best {
+        add_evidence(scores, tag, evidence);
+    }
+}
+
+fn add_evidence(scores: &mut HashMap<FieldTag, f64>, tag: FieldTag, evidence: f64) {
+    let score = scores.entry(tag).or_default();
+    *score = 1.0 - (1.0 - *score) * (1.0 - evidence);
+}
+
+/// The field's `/AA` format (`F`) and keystroke (`K`) scripts, concatenated;
+/// script streams are decoded under the stream size cap.
+fn format_script(doc: &Document, field: &FieldDescriptor, limits: &Limits) -> CoreResult<String> {
+    let mut script = String::new();
+    for id in std::iter::once(field.id).chain(field.widget_ids.iter().copied()) {
+        let Some(actions) = doc
+            .get_dictionary(id)
+            .ok()
+            .and_then(|dict| dict.get(b"AA").ok())
+            .and_then(|aa| doc.dereference(aa).ok())
+            .and_then(|(_, aa)| aa.as_dict().ok())
+        else {
+            continue;
+        };
+        for key in [b"F".as_slice(), b"K".as_slice()] {
+            let Some(action) =
+                actions.get(key).ok().and_then(|action| doc.dereference(action).ok()).and_then(|(_, a)| a.as_dict().ok())
+            else {
+                continue;
+            };
+            match action.get(b"JS").ok().and_then(|js| doc.dereference(js).ok()).map(|(_, js)| js) {
+                Some(Object::String(bytes, _)) => script.push_str(&String::from_utf8_lossy(bytes)),
+                Some(Object::Stream(stream)) => {
+                    let content = stream_content(stream, limits, &format!("{} script", String::from_utf8_lossy(key)))?;
+                    script.push_str(&String::from_utf8_lossy(&content));
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(script)
+}
+
+/// The pattern of an `AFDate_FormatEx("mm/dd/yyyy")` call, if any.
+fn date_format(script: &str) -> Option<String> {
+    let start = script.find("AFDate_FormatEx(")?;
+    let pattern = script[start..].split('"').nth(1)?;
+    (!pattern.is_empty()).then(|| pattern.to_owned())
+}
+
+/// Evidence from the field's format scripts.
+fn score_format(script: &str, scores: &mut HashMap<FieldTag, f64>) {
+    if script.contains("AFDate_Format") {
+        add_evidence(scores, FieldTag::Date, FORMAT_WEIGHT);
+        // A date format confirms a date tag the name or label suggested.
+        for tag in [FieldTag::DateOfBirth, FieldTag::SignatureDate] {
+            if scores.contains_key(&tag) {
+                add_evidence(scores, tag, FORMAT_WEIGHT / 2.0);
+            }
+        }
+    }
+    let compact: String = script.chars().filter(|ch| !ch.is_whitespace()).collect();
+    if compact.contains("AFSpecial_Format(0)") || compact.contains("AFSpecial_Format(1)") {
+        add_evidence(scores, FieldTag::Postcode, FORMAT_WEIGHT);
+    }
+    if compact.contains("AFSpecial_Format(2)") {
+        add_evidence(scores, FieldTag::Phone, FORMAT_WEIGHT);
+    }
+    if let Some(start) = compact.find("AFNumber_Format(") {
+        let arguments = &compact[start..compact[start..].find(')').map_or(compact.len(), |end| start + end)];
+        let currency = arguments
+            .split('"')
+            .nth(1)
+            .is_some_and(|symbol| symbol.chars().any(|ch| matches!(ch, '$' | '€' | '£' | '¥') || ch.is_alphabetic()));
+        add_evidence(scores, FieldTag::Amount, if currency { FORMAT_WEIGHT } else { FORMAT_WEIGHT / 2.0 });
+    }
+}
+
+/// A run of text shown on a page, positioned at its start in default user
+/// space.
+#[derive(Debug, Clone)]
+struct TextRun {
+    text: String,
+    x: f32,
+    y: f32,
+    /// Estimated from the glyph count; fonts are not measured.
+    width: f32,
+}
+
+type Matrix = [f32; 6];
+/// `x, y, width, height`, as `rect_from_object` returns it.
+type Rect = (f32, f32, f32, f32);
+
+fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+    [
+        a[0] * b[0] + a[1] * b[2],
+        a[0] * b[1] + a[1] * b[3],
+        a[2] * b[0] + a[3] * b[2],
+        a[2] * b[1] + a[3] * b[3],
+        a[4] * b[0] + a[5] * b[2] + b[4],
+        a[4] * b[1] + a[5] * b[3] + b[5],
+    ]
+}
+
+const IDENTITY: Matrix = [1.0, 0.0, 0.0, 1.0, 0.0, 0.0];
+
+/// Bytes of a string operand read as Latin-1, which is right for the simple
+/// fonts forms print their labels in.
+fn shown_text(operand: &Object) -> String {
+    match operand {
+        Object::String(bytes, _) => bytes.iter().map(|byte| char::from(*byte)).filter(|ch| !ch.is_control()).collect(),
+        Object::Array(parts) => {
+            let mut text = String::new();
+            for part in parts {
+                match part {
+                    Object::Integer(_) | Object::Real(_) => {
+                        // A large negative adjustment stands for a space.
+                        if crate::object_to_number(part).is_some_and(|shift| shift < -200.0) {
+                            text.push(' ');
+                        }
+                    }
+                    other => text.push_str(&shown_text(other)),
+                }
+            }
+            text
+        }
+        _ => String::new(),
+    }
+}
+
+/// Moves to the start of the next line, offset by `(tx, ty)`.
+fn next_line(tm: &mut Matrix, tlm: &mut Matrix, tx: f32, ty: f32) {
+    *tlm = multiply(&[1.0, 0.0, 0.0, 1.0, tx, ty], tlm);
+    *tm = *tlm;
+}
+
+/// The page's content streams, decoded under the stream size cap and joined.
+fn page_content(doc: &Document, page_id: ObjectId, limits: &Limits) -> CoreResult<Vec<u8>> {
+    let mut content = Vec::new();
+    for id in doc.get_page_contents(page_id) {
+        if let Ok(stream) = doc.get_object(id).and_then(Object::as_stream) {
+            content.extend(stream_content(stream, limits, &format!("content stream {} {}", id.0, id.1))?);
+        }
+    }
+    Ok(content)
+}
+
+/// Text runs of a page's content stream. Positions follow `cm`, `q`/`Q` and
+/// the text positioning operators; each run advances by an estimated width.
+fn page_text_runs(doc: &Document, page_id: ObjectId, budget: &mut Budget) -> CoreResult<Vec<TextRun>> {
+    let content = page_content(doc, page_id, budget.limits())?;
+    let Ok(content) = Content::decode(&content) else {
+        return Ok(Vec::new());
+    };
+    let mut runs = Vec::new();
+    let mut ctm = IDENTITY;
+    let mut stack = Vec::new();
+    let (mut tm, mut tlm) = (IDENTITY, IDENTITY);
+    let (mut size, mut leading) = (12.0_f32, 0.0_f32);
+    for operation in &content.operations {
+        budget.tick("classify")?;
+        let numbers: Vec<f32> = operation.operands.iter().filter_map(crate::object_to_number).collect();
+        let shown = match operation.operator.as_str() {
+            "q" => {
+                stack.push(ctm);
+                None
+            }
+            "Q" => {
+                ctm = stack.pop().unwrap_or(IDENTITY);
+                None
+            }
+            "cm" if numbers.len() == 6 => {
+                ctm = multiply(&[numbers[0], numbers[1], numbers[2], numbers[3], numbers[4], numbers[5]], &ctm);
+                None
+            }
+            "BT" => {
+                tm = IDENTITY;
+                tlm = IDENTITY;
+                None
+            }
+            "Tf" => {
+                size = numbers.first().copied().unwrap_or(size);
+                None
+            }
+            "TL" => {
+                leading = numbers.first().copied().unwrap_or(leading);
+                None
+            }
+            "Td" | "TD" if numbers.len() == 2 => {
+                if operation.operator == "TD" {
+                    leading = -numbers[1];
+                }
+                next_line(&mut tm, &mut tlm, numbers[0], numbers[1]);
+                None
+            }
+            "Tm" if numbers.len() == 6 => {
+                tlm = [numbers[0], numbers[1], numbers[2], numbers[3], numbers[4], numbers[5]];
+                tm = tlm;
+                None
+            }
+            "T*" => {
+                next_line(&mut tm, &mut tlm, 0.0, -leading);
+                None
+            }
+            "Tj" | "TJ" => operation.operands.first().map(shown_text),
+            "'" | "\"" => {
+                next_line(&mut tm, &mut tlm, 0.0, -leading);
+                operation.operands.last().map(shown_text)
+            }
+            _ => None,
+        };
+        let Some(text) = shown else {
+            continue;
+        };
+        let origin = multiply(&tm, &ctm);
+        let scale = (origin[0] * origin[0] + origin[1] * origin[1]).sqrt().max(f32::EPSILON);
+        let width = text.chars().count() as f32 * size * 0.5;
+        tm = multiply(&[1.0, 0.0, 0.0, 1.0, width, 0.0], &tm);
+        if !text.trim().is_empty() {
+            runs.push(TextRun {
+                text: text.trim().to_owned(),
+                x: origin[4],
+                y: origin[5],
+                width: width * scale,
+            });
+        }
+    }
+    Ok(runs)
+}
+
+/// How far to look for a label: left of the widget on its line, or above it.
+const LABEL_REACH: f32 = 150.0;
+const LABEL_ABOVE: f32 = 24.0;
+
+/// The run most likely printed as the label of a widget at `rect`: the
+/// nearest run ending left of it on the same line, else the nearest run just
+/// above it.
+fn nearby_label(runs: &[TextRun], rect: Rect) -> Option<String> {
+    let (x, y, width, height) = rect;
+    let same_line = runs
+        .iter()
+        .filter(|run| run.y >= y - 2.0 && run.y <= y + height && run.x < x)
+        .map(|run| ((x - (run.x + run.width)).max(0.0), run))
+        .filter(|(gap, _)| *gap <= LABEL_REACH);
+    let above = runs
+        .iter()
+        .filter(|run| run.y > y + height && run.y <= y + height + LABEL_ABOVE)
+        .filter(|run| run.x + run.width >= x - 10.0 && run.x <= x + width)
+        .map(|run| (run.y - (y + height) + LABEL_REACH, run));
+    same_line
+        .chain(above)
+        .min_by(|a, b| a.0.total_cmp(&b.0))
+        .map(|(_, run)| run.text.trim_end_matches(':').trim().to_owned())
+}
+
+pub(crate) fn classify_document(
+    doc: &Document,
+    index: &FieldIndex,
+    limits: &Limits,
+) -> CoreResult<Vec<FieldClassification>> {
+    let mut budget = Budget::new(limits);
+    let mut placed: HashMap<ObjectId, (ObjectId, Rect)> = HashMap::new();
+    for page in index.pages.iter().flatten() {
+        for widget in &page.widgets {
+            if let (Some(id), Some(rect)) = (widget.id(), widget.rect) {
+                placed.entry(id).or_insert((page.page_id, rect));
+            }
+        }
+    }
+    let mut runs_by_page: HashMap<ObjectId, Vec<TextRun>> = HashMap::new();
+
+    let mut classified = Vec::new();
+    for field in index.terminal_fields() {
+        budget.tick("classify")?;
+        let Some(name) = &field.full_name else {
+            continue;
+        };
+        let kind = field.kind();
+        let mut scores: HashMap<FieldTag, f64> = HashMap::new();
+        score_text(name, NAME_WEIGHT, &mut scores);
+        if let Some(tooltip) = doc.get_dictionary(field.id).ok().and_then(|dict| dict_text(doc, dict, b"TU")) {
+            score_text(&tooltip, TOOLTIP_WEIGHT, &mut scores);
+        }
+        let mut label = None;
+        if let Some((page_id, rect)) = field.widget_ids.iter().find_map(|id| placed.get(id)) {
+            if !runs_by_page.contains_key(page_id) {
+                runs_by_page.insert(*page_id, page_text_runs(doc, *page_id, &mut budget)?);
+            }
+            label = nearby_label(&runs_by_page[page_id], *rect);
+            if let Some(label) = &label {
+                score_text(label, LABEL_WEIGHT, &mut scores);
+            }
+        }
+        let script = format_script(doc, field, limits)?;
+        score_format(&script, &mut scores);
+        if kind == "signature" {
+            add_evidence(&mut scores, FieldTag::Signature, 0.95);
+        }
+
+        let mut tags: Vec<TagScore> = scores
+            .into_iter()
+            .filter(|(tag, _)| fits(kind, *tag))
+            .map(|(tag, confidence)| TagScore {
+                tag,
+                confidence: (confidence * 100.0).round() / 100.0,
+            })
+            .collect();
+        tags.sort_by(|a, b| b.confidence.total_cmp(&a.confidence).then(a.tag.cmp(&b.tag)));
+        classified.push(FieldClassification {
+            name: name.clone(),
+            kind: kind.to_owned(),
+            nearby_label: label,
+            date_format: date_format(&script),
+            tags,
+        });
+    }
+    Ok(classified)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FormDocument;
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+    use lopdf::{dictionary, Stream};
+    use std::io::Write;
+
+    /// One page with a printed label left of one box and above another, and
+    /// fields identified by name, `/TU` and format scripts.
+    fn application_pdf() -> Vec<u8> {
+        let mut doc = Document::with_version("1.7");
+        let pages_id = doc.new_object_id();
+        let page_id = doc.new_object_id();
+        let content = b"BT /F1 10 Tf 1 0 0 1 40 705 Tm (Email address:) Tj ET\n\
+                        q 1 0 0 1 300 0 cm BT /F1 9 Tf 0 640 Td (Town or city) Tj ET Q"
+            .to_vec();
+        let content_id = doc.add_object(Stream::new(dictionary! {}, content));
+        let script = |js: &str| dictionary! { "F" => dictionary! { "S" => "JavaScript", "JS" => Object::string_literal(js) } };
+        let fields = [
+            dictionary! { "T" => Object::string_literal("Text1"), "FT" => "Tx", "Rect" => vec![130.into(), 700.into(), 280.into(), 718.into()] },
+            dictionary! { "T" => Object::string_literal("Text2"), "FT" => "Tx", "Rect" => vec![300.into(), 612.into(), 450.into(), 630.into()] },
+            dictionary! { "T" => Object::string_literal("applicant.firstName"), "FT" => "Tx", "Rect" => vec![40.into(), 560.into(), 190.into(), 578.into()] },
+            dictionary! { "T" => Object::string_literal("f1_07"), "TU" => Object::string_literal("Date of birth"), "FT" => "Tx",
+                "AA" => script("AFDate_FormatEx(\"mm/dd/yyyy\");"), "Rect" => vec![40.into(), 520.into(), 190.into(), 538.into()] },
+            dictionary! { "T" => Object::string_literal("f1_08"), "FT" => "Tx", "AA" => script("AFSpecial_Format(2);"),
+                "Rect" => vec![40.into(), 480.into(), 190.into(), 498.into()] },
+            dictionary! { "T" => Object::string_literal("f1_09"), "FT" => "Tx", "AA" => script("AFNumber_Format(2, 0, 0, 0, \"$\", true);"),
+                "Rect" => vec![40.into(), 440.into(), 190.into(), 458.into()] },
+            dictionary! { "T" => Object::string_literal("agree_terms"), "FT" => "Btn", "Rect" => vec![40.into(), 400.into(), 52.into(), 412.into()] },
+            dictionary! { "T" => Object::string_literal("Sig"), "FT" => "Sig", "Rect" => vec![40.into(), 340.into(), 240.into(), 380.into()] },
+        ];
+        let mut refs = Vec::new();
+        for mut field in fields {
+            field.set("Type", "Annot");
+            field.set("Subtype", "Widget");
+            field.set("P", Object::Reference(page_id));
+            refs.push(Object::Reference(doc.add_object(field)));
+        }
+        doc.objects.insert(page_id, Object::Dictionary(dictionary! {
+            "Type" => "Page",
+            "Parent" => Object::Reference(pages_id),
+            "MediaBox" => vec![0.into(), 0.into(), 612.into(), 792.into()],
+            "Contents" => Object::Reference(content_id),
+            "Annots" => refs.clone(),
+        }));
+        doc.objects.insert(pages_id, Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Kids" => vec![Object::Reference(page_id)],
+            "Count" => 1,
+        }));
+        let acroform_id = doc.add_object(dictionary! { "Fields" => refs });
+        let catalog_id = doc.add_object(dictionary! {
+            "Type" => "Catalog",
+            "Pages" => Object::Reference(pages_id),
+            "AcroForm" => Object::Reference(acroform_id),
+        });
+        doc.trailer.set("Root", Object::Reference(catalog_id));
+        let mut pdf = Vec::new();
+        doc.save_to(&mut pdf).expect("application save");
+        pdf
+    }
+
+    #[test]
+    fn every_source_of_evidence_tags_a_field() {
+        let classified = FormDocument::load(&application_pdf()).expect("loads").classify().expect("classify");
+        let best = |name: &str| {
+            let field = classified.iter().find(|field| field.name == name).expect("field classified");
+            field.best().map(|score| (score.tag, score.confidence))
+        };
+        let label = |name: &str| classified.iter().find(|field| field.name == name).and_then(|field| field.nearby_label.clone());
+
+        assert_eq!(label("Text1").as_deref(), Some("Email address"), "label left of the box");
+        assert_eq!(best("Text1"), Some((FieldTag::Email, 0.6)));
+        assert_eq!(label("Text2").as_deref(), Some("Town or city"), "label above the box, placed with cm");
+        assert_eq!(best("Text2").map(|(tag, _)| tag), Some(FieldTag::City));
+        assert_eq!(best("applicant.firstName"), Some((FieldTag::GivenName, 0.55)));
+        let (tag, confidence) = best("f1_07").expect("tagged");
+        assert_eq!(tag, FieldTag::DateOfBirth);
+        assert!(confidence > 0.7, "tooltip plus date format: {}", confidence);
+        let dob = classified.iter().find(|field| field.name == "f1_07").expect("dob");
+        assert_eq!(dob.date_format.as_deref(), Some("mm/dd/yyyy"));
+        assert_eq!(best("f1_08"), Some((FieldTag::Phone, 0.75)));
+        assert_eq!(best("f1_09"), Some((FieldTag::Amount, 0.75)));
+        assert_eq!(best("agree_terms"), Some((FieldTag::Consent, 0.55)));
+        assert_eq!(best("Sig"), Some((FieldTag::Signature, 0.95)));
+    }
+
+    #[test]
+    fn page_content_inflation_is_capped() {
+        let mut doc = Document::load_mem(&application_pdf()).expect("application parses");
+        let (_, page_id) = doc.get_pages().into_iter().next().expect("one page");
+        let content_id = doc.get_page_contents(page_id)[0];
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(&vec![b' '; 4 * 1024 * 1024]).expect("compress");
+        let bomb = Stream::new(dictionary! { "Filter" => "FlateDecode" }, encoder.finish().expect("compress"));
+        doc.objects.insert(content_id, Object::Stream(bomb));
+        let mut pdf = Vec::new();
+        doc.save_to(&mut pdf).expect("bomb save");
+
+        let limits = Limits {
+            max_stream_bytes: 64 * 1024,
+            ..Limits::default()
+        };
+        let form = FormDocument::load_with_limits(&pdf, None, limits).expect("bomb loads lazily");
+        let error = form.classify().expect_err("content stream past the cap");
+        assert_eq!(error.code(), "BW_LIMIT_STREAM_SIZE");
+        assert_eq!(error.context(), Some(format!("content stream {} {}", content_id.0, content_id.1).as_str()));
+        FormDocument::load(&pdf).expect("loads").classify().expect("default cap allows 4 MiB");
+    }
+
+    #[test]
+    fn script_stream_inflation_is_capped() {
+        let mut doc = Document::load_mem(&application_pdf()).expect("application parses");
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(&vec![b' '; 4 * 1024 * 1024]).expect("compress");
+        let packed = encoder.finish().expect("compress");
+        let bomb = doc.add_object(Stream::new(dictionary! { "Filter" => "FlateDecode" }, packed));
+        for dict in doc.objects.values_mut().filter_map(|object| object.as_dict_mut().ok()) {
+            if let Ok(Object::Dictionary(actions)) = dict.get_mut(b"AA") {
+                actions.set("K", dictionary! { "S" => "JavaScript", "JS" => Object::Reference(bomb) });
+            }
+        }
+        let mut pdf = Vec::new();
+        doc.save_to(&mut pdf).expect("bomb save");
+
+        let limits = Limits {
+            max_stream_bytes: 64 * 1024,
+            ..Limits::default()
+        };
+        let form = FormDocument::load_with_limits(&pdf, None, limits).expect("bomb loads lazily");
+        let error = form.classify().expect_err("script stream past the cap");
+        assert_eq!(error.code(), "BW_LIMIT_STREAM_SIZE");
+        assert_eq!(error.context(), Some("K script"));
+    }
+
+    #[test]
+    fn names_split_into_words() {
+        assert_eq!(normalise("form1[0].Page1[0].LastName[0]"), " form 1 0 page 1 0 last name 0 ");
+        assert_eq!(normalise("DOB"), " dob ");
+        let mut scores = HashMap::new();
+        score_text("Date signed", 1.0, &mut scores);
+        assert_eq!(scores.get(&FieldTag::SignatureDate), Some(&1.0));
+        assert_eq!(scores.get(&FieldTag::Date), None, "`date` was consumed by `date signed`");
+        score_text("Start date", 1.0, &mut scores);
+        assert_eq!(scores.get(&FieldTag::Date), Some(&0.5), "generic words count half");
+    }
+}
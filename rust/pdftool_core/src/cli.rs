@@ -0,0 +1,797 @@
+// SPDX-License-Identifier: PMPL-1.0-or-later WITH Palimpsest
+
+//! Native `pdftool` command line, built with the `cli` feature.
+//!
+//! Results are printed to stdout as JSON. Failures are printed to stderr as
+//! the same `{ code, message, context }` payload the WASM API throws, with
+//! exit status 2 for usage errors and 1 for everything else. Repairs made
+//! while loading a damaged input are printed to stderr in the same shape.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use std::process::ExitCode;
+
+use serde::ser::SerializeMap;
+use serde::Serialize;
+
+use crate::csv::parse_csv;
+use crate::xfdf::parse_xfdf;
+use crate::{
+    core_error, core_error_with_context, csv_records, AmbiguousKey, BatchRecord, Block, CoreResult, DataValue,
+    EditReport, EncryptionOptions, ExtractedRow, FieldEdit, FieldValue, FillReport, FormDocument, Mapping, MergeReport,
+    PartialNamePolicy, Permissions, Profile,
+};
+
+const USAGE: &str = "\
+usage: pdftool <command> <input.pdf> [options]
+
+commands:
+  autofill <input.pdf> --profile <profile.json>
+                                               propose values from a profile, with provenance
+  batch   <template.pdf> --values <records.csv|records.jsonl>
+          --output <dir|combined.pdf> [--format csv|jsonl]
+          [--partial-names error|first|all]
+          [--combine]                          fill once per record, or into one combined PDF
+  classify <input.pdf>                         tag fields as name, email, date of birth, ...
+  collect <template.pdf> <completed.pdf>... [--format csv|json] [--output <file>]
+                                               one row of values per completed copy
+  create  <input.pdf> --blocks <blocks.json> --output <output.pdf>
+                                               add text, checkbox, radio and choice fields
+  detect  <input.pdf> [--rows]                 list widget blocks, or repeating rows
+  diff    <old.pdf> --revised <new.pdf>        list fields added, removed, renamed, moved or retyped
+  edit    <input.pdf> --edits <edits.json> --output <output.pdf>
+                                               delete, rename, move or split fields
+  extract <input.pdf>                          print field values as a JSON object
+  fingerprint <input.pdf>                      print the form's structural fingerprint,
+          [--compare <other.pdf>]              or its similarity to another form
+  fill    <input.pdf> --values <file> --output <output.pdf>
+          [--format json|csv|xfdf] [--row <n>] [--reapply-encryption]
+          [--partial-names error|first|all] [--mapping <mapping.json>]
+          [--owner-password <pw> [--user-password <pw>]
+          [--permissions print,copy,modify,fill-in|none]]
+  flatten <input.pdf> --output <output.pdf>
+  merge   <first.pdf> <more.pdf>... --output <merged.pdf>
+                                               append documents, prefixing colliding fields
+
+options:
+  --password <pw>   password for an encrypted input
+";
+
+const VALUE_OPTIONS: &[&str] = &[
+    "values",
+    "format",
+    "row",
+    "output",
+    "password",
+    "user-password",
+    "owner-password",
+    "partial-names",
+    "compare",
+    "revised",
+    "profile",
+    "mapping",
+    "blocks",
+    "edits",
+    "permissions",
+];
+const SWITCHES: &[&str] = &["reapply-encryption", "rows", "combine"];
+
+/// Runs the CLI with `args` (excluding the program name) and returns the
+/// process exit status.
+pub fn run<I: IntoIterator<Item = String>>(args: I) -> ExitCode {
+    let args: Vec<String> = args.into_iter().collect();
+    if args.is_empty() || matches!(args[0].as_str(), "help" | "-h" | "--help") {
+        print!("{}", USAGE);
+        return ExitCode::SUCCESS;
+    }
+    match execute(&args) {
+        Ok(output) => {
+            println!("{}", output);
+            ExitCode::SUCCESS
+        }
+        Err(payload) => {
+            let usage = payload.code == "BW_CLI_USAGE";
+            eprintln!(
+                "{}",
+                serde_json::to_string(&payload).unwrap_or_else(|_| format!("{}: {}", payload.code, payload.message))
+            );
+            if usage {
+                eprint!("{}", USAGE);
+                ExitCode::from(2)
+            } else {
+                ExitCode::from(1)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Arguments {
+    positional: Vec<String>,
+    options: HashMap<String, String>,
+    switches: HashSet<String>,
+}
+
+impl Arguments {
+    fn parse(args: &[String]) -> CoreResult<Self> {
+        let mut parsed = Arguments::default();
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            let Some(name) = arg.strip_prefix("--") else {
+                parsed.positional.push(arg.clone());
+                continue;
+            };
+            if SWITCHES.contains(&name) {
+                parsed.switches.insert(name.to_owned());
+            } else if VALUE_OPTIONS.contains(&name) {
+                let value = iter.next().ok_or_else(|| usage_error(format!("--{} expects a value", name)))?;
+                parsed.options.insert(name.to_owned(), value.clone());
+            } else {
+                return Err(usage_error(format!("unknown option '{}'", arg)));
+            }
+        }
+        Ok(parsed)
+    }
+
+    fn option(&self, name: &str) -> Option<&str> {
+        self.options.get(name).map(String::as_str)
+    }
+
+    fn required(&self, name: &str) -> CoreResult<&str> {
+        self.option(name).ok_or_else(|| usage_error(format!("--{} is required", name)))
+    }
+
+    fn only(&self, allowed: &[&str]) -> CoreResult<()> {
+        let unexpected = self
+            .options
+            .keys()
+            .map(String::as_str)
+            .chain(self.switches.iter().map(String::as_str))
+            .find(|name| !allowed.contains(name));
+        match unexpected {
+            Some(name) => Err(usage_error(format!("--{} is not valid for this command", name))),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Comparison {
+    same: bool,
+    similarity: f64,
+}
+
+#[derive(Serialize)]
+struct BatchEntry {
+    #[serde(flatten)]
+    record: BatchRecord,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    output: Option<String>,
+}
+
+#[derive(Serialize)]
+struct CombinedBatch<'a> {
+    output: &'a str,
+    bytes: usize,
+    records: Vec<BatchRecord>,
+}
+
+/// A bulk extraction row as `{ source, values: { column: value } }` with
+/// the columns in template order, or `{ source, error }`.
+struct CollectedRow<'a> {
+    columns: &'a [String],
+    row: &'a ExtractedRow,
+}
+
+struct OrderedValues<'a>(&'a [String], &'a [FieldValue]);
+
+impl Serialize for CollectedRow<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(2))?;
+        map.serialize_entry("source", &self.row.source)?;
+        match &self.row.error {
+            Some(error) => map.serialize_entry("error", error)?,
+            None => map.serialize_entry("values", &OrderedValues(self.columns, &self.row.values))?,
+        }
+        map.end()
+    }
+}
+
+impl Serialize for OrderedValues<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (column, value) in self.0.iter().zip(self.1) {
+            map.serialize_entry(column, value)?;
+        }
+        map.end()
+    }
+}
+
+#[derive(Serialize)]
+struct CollectSummary<'a> {
+    output: &'a str,
+    documents: usize,
+    failed: usize,
+}
+
+#[derive(Serialize)]
+struct CreateOutput<'a> {
+    output: &'a str,
+    bytes: usize,
+    created: usize,
+}
+
+#[derive(Serialize)]
+struct EditOutput<'a> {
+    output: &'a str,
+    bytes: usize,
+    #[serde(flatten)]
+    report: EditReport,
+}
+
+#[derive(Serialize)]
+struct MergeOutput<'a> {
+    output: &'a str,
+    bytes: usize,
+    appended: Vec<MergeReport>,
+}
+
+#[derive(Serialize)]
+struct WriteReport<'a> {
+    output: &'a str,
+    bytes: usize,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    ambiguous: Vec<AmbiguousKey>,
+    #[serde(rename = "missingKeys", skip_serializing_if = "Vec::is_empty")]
+    missing_keys: Vec<String>,
+    #[serde(rename = "fieldErrors", skip_serializing_if = "Vec::is_empty")]
+    field_errors: Vec<crate::CoreError>,
+}
+
+fn usage_error(message: impl Into<String>) -> crate::CoreError {
+    core_error("BW_CLI_USAGE", message)
+}
+
+fn to_json<T: Serialize>(value: &T) -> CoreResult<String> {
+    serde_json::to_string_pretty(value)
+        .map_err(|err| core_error_with_context("BW_SERIALIZATION_ERROR", err.to_string(), Some("cli output".into())))
+}
+
+fn read_file(path: &str) -> CoreResult<Vec<u8>> {
+    fs::read(path).map_err(|err| core_error_with_context("BW_CLI_IO_READ", err.to_string(), Some(path.to_owned())))
+}
+
+fn read_text(path: &str) -> CoreResult<String> {
+    String::from_utf8(read_file(path)?)
+        .map_err(|err| core_error_with_context("BW_CLI_IO_READ", err.to_string(), Some(path.to_owned())))
+}
+
+fn write_file(path: &str, data: &[u8], report: FillReport) -> CoreResult<String> {
+    write_output(path, data)?;
+    to_json(&WriteReport {
+        output: path,
+        bytes: data.len(),
+        ambiguous: report.ambiguous,
+        missing_keys: report.missing_keys,
+        field_errors: report.field_errors,
+    })
+}
+
+fn load(path: &str, password: Option<&str>) -> CoreResult<FormDocument> {
+    let document = FormDocument::load_with(&read_file(path)?, password)?;
+    for warning in document.warnings() {
+        if let Ok(line) = serde_json::to_string(warning) {
+            eprintln!("{}", line);
+        }
+    }
+    Ok(document)
+}
+
+type Command = fn(&Arguments) -> CoreResult<String>;
+
+fn execute(args: &[String]) -> CoreResult<String> {
+    let command: Command = match args[0].as_str() {
+        "autofill" => autofill,
+        "batch" => batch,
+        "classify" => classify,
+        "collect" => collect,
+        "create" => create,
+        "detect" => detect,
+        "diff" => diff,
+        "edit" => edit,
+        "extract" => extract,
+        "fingerprint" => fingerprint,
+        "fill" => fill,
+        "flatten" => flatten,
+        "merge" => merge,
+        other => return Err(usage_error(format!("unknown command '{}'", other))),
+    };
+    command(&Arguments::parse(&args[1..])?)
+}
+
+/// The one input PDF of a single-document command.
+fn single_input(arguments: &Arguments) -> CoreResult<&str> {
+    match arguments.positional.as_slice() {
+        [input] => Ok(input.as_str()),
+        [] => Err(usage_error("missing input PDF")),
+        _ => Err(usage_error("expected exactly one input PDF")),
+    }
+}
+
+fn write_output(path: &str, data: &[u8]) -> CoreResult<()> {
+    fs::write(path, data)
+        .map_err(|err| core_error_with_context("BW_CLI_IO_WRITE", err.to_string(), Some(path.to_owned())))
+}
+
+fn autofill(arguments: &Arguments) -> CoreResult<String> {
+    arguments.only(&["password", "profile"])?;
+    let input = single_input(arguments)?;
+    let path = arguments.required("profile")?;
+    let profile: Profile = serde_json::from_str(&read_text(path)?)
+        .map_err(|err| core_error_with_context("BW_PROFILE_PAYLOAD_INVALID", err.to_string(), Some(path.to_owned())))?;
+    to_json(&load(input, arguments.option("password"))?.propose_autofill(&profile)?)
+}
+
+fn batch(arguments: &Arguments) -> CoreResult<String> {
+    arguments.only(&["password", "values", "format", "output", "partial-names", "combine"])?;
+    let input = single_input(arguments)?;
+    let output = arguments.required("output")?;
+    let policy = partial_name_policy(arguments)?;
+    let records = read_records(arguments)?;
+    let template = load(input, arguments.option("password"))?;
+    if arguments.switches.contains("combine") {
+        let (combined, records) = template.fill_batch_combined(&records, policy)?;
+        write_output(output, &combined)?;
+        return to_json(&CombinedBatch {
+            output,
+            bytes: combined.len(),
+            records,
+        });
+    }
+    fs::create_dir_all(output)
+        .map_err(|err| core_error_with_context("BW_CLI_IO_WRITE", err.to_string(), Some(output.to_owned())))?;
+    let stem = Path::new(input).file_stem().and_then(|stem| stem.to_str()).unwrap_or("record");
+    let mut entries = Vec::new();
+    for record in template.fill_batch(&records, policy)? {
+        let path = match &record.output {
+            Some(pdf) => {
+                let path = Path::new(output).join(format!("{}-{}.pdf", stem, record.record));
+                let path = path.to_string_lossy().into_owned();
+                write_output(&path, pdf)?;
+                Some(path)
+            }
+            None => None,
+        };
+        entries.push(BatchEntry { output: path, record });
+    }
+    to_json(&entries)
+}
+
+fn classify(arguments: &Arguments) -> CoreResult<String> {
+    arguments.only(&["password"])?;
+    to_json(&load(single_input(arguments)?, arguments.option("password"))?.classify()?)
+}
+
+fn collect(arguments: &Arguments) -> CoreResult<String> {
+    arguments.only(&["password", "format", "output"])?;
+    let password = arguments.option("password");
+    let [template, documents @ ..] = arguments.positional.as_slice() else {
+        return Err(usage_error("missing template PDF"));
+    };
+    if documents.is_empty() {
+        return Err(usage_error("collect expects completed PDFs after the template"));
+    }
+    let template = load(template, password)?;
+    let mut contents = Vec::with_capacity(documents.len());
+    for path in documents {
+        contents.push((path.as_str(), read_file(path)?));
+    }
+    let table = template.extract_bulk(contents.iter().map(|(path, data)| (*path, data.as_slice())), password)?;
+    let text = match arguments.option("format").map(str::to_ascii_lowercase).as_deref() {
+        Some("csv") => table.to_csv(),
+        None | Some("json") => {
+            let rows: Vec<CollectedRow> =
+                table.rows.iter().map(|row| CollectedRow { columns: &table.columns, row }).collect();
+            to_json(&rows)?
+        }
+        Some(other) => return Err(usage_error(format!("unknown collect format '{}'", other))),
+    };
+    let Some(output) = arguments.option("output") else {
+        return Ok(text);
+    };
+    write_output(output, text.as_bytes())?;
+    to_json(&CollectSummary {
+        output,
+        documents: table.rows.len(),
+        failed: table.rows.iter().filter(|row| row.error.is_some()).count(),
+    })
+}
+
+fn create(arguments: &Arguments) -> CoreResult<String> {
+    arguments.only(&["password", "blocks", "output"])?;
+    let input = single_input(arguments)?;
+    let output = arguments.required("output")?;
+    let path = arguments.required("blocks")?;
+    let blocks: Vec<Block> = serde_json::from_str(&read_text(path)?)
+        .map_err(|err| core_error_with_context("BW_BLOCKS_PAYLOAD_INVALID", err.to_string(), Some(path.to_owned())))?;
+    let mut document = load(input, arguments.option("password"))?;
+    let created = document.create_fields(&blocks)?;
+    let data = document.save_with_code("BW_CREATE_SAVE_FAILED")?;
+    write_output(output, &data)?;
+    to_json(&CreateOutput {
+        output,
+        bytes: data.len(),
+        created,
+    })
+}
+
+fn detect(arguments: &Arguments) -> CoreResult<String> {
+    arguments.only(&["password", "rows"])?;
+    let document = load(single_input(arguments)?, arguments.option("password"))?;
+    if arguments.switches.contains("rows") {
+        return to_json(&document.repeating_rows()?);
+    }
+    to_json(&document.detect()?)
+}
+
+fn diff(arguments: &Arguments) -> CoreResult<String> {
+    arguments.only(&["password", "revised"])?;
+    let password = arguments.option("password");
+    let old = load(single_input(arguments)?, password)?;
+    let revised = load(arguments.required("revised")?, password)?;
+    to_json(&old.diff(&revised)?)
+}
+
+fn edit(arguments: &Arguments) -> CoreResult<String> {
+    arguments.only(&["password", "edits", "output"])?;
+    let input = single_input(arguments)?;
+    let output = arguments.required("output")?;
+    let path = arguments.required("edits")?;
+    let edits: Vec<FieldEdit> = serde_json::from_str(&read_text(path)?)
+        .map_err(|err| core_error_with_context("BW_EDITS_PAYLOAD_INVALID", err.to_string(), Some(path.to_owned())))?;
+    let mut document = load(input, arguments.option("password"))?;
+    let report = document.edit_fields(&edits)?;
+    let data = document.save_with_code("BW_EDIT_SAVE_FAILED")?;
+    write_output(output, &data)?;
+    to_json(&EditOutput {
+        output,
+        bytes: data.len(),
+        report,
+    })
+}
+
+fn extract(arguments: &Arguments) -> CoreResult<String> {
+    arguments.only(&["password"])?;
+    let fields = load(single_input(arguments)?, arguments.option("password"))?.extract()?;
+    let values: BTreeMap<String, FieldValue> = fields.into_iter().map(|field| (field.name, field.value)).collect();
+    to_json(&values)
+}
+
+fn fingerprint(arguments: &Arguments) -> CoreResult<String> {
+    arguments.only(&["password", "compare"])?;
+    let password = arguments.option("password");
+    let fingerprint = load(single_input(arguments)?, password)?.fingerprint()?;
+    match arguments.option("compare") {
+        Some(other) => {
+            let other = load(other, password)?.fingerprint()?;
+            to_json(&Comparison {
+                same: fingerprint.hash == other.hash,
+                similarity: fingerprint.similarity(&other),
+            })
+        }
+        None => to_json(&fingerprint),
+    }
+}
+
+fn fill(arguments: &Arguments) -> CoreResult<String> {
+    arguments.only(&[
+        "password",
+        "values",
+        "format",
+        "row",
+        "output",
+        "reapply-encryption",
+        "user-password",
+        "owner-password",
+        "permissions",
+        "partial-names",
+        "mapping",
+    ])?;
+    let input = single_input(arguments)?;
+    let output = arguments.required("output")?;
+    let policy = partial_name_policy(arguments)?;
+    let mut document = load(input, arguments.option("password"))?;
+    let report = match arguments.option("mapping") {
+        Some(path) => {
+            let mapping: Mapping = serde_json::from_str(&read_text(path)?).map_err(|err| {
+                core_error_with_context("BW_MAPPING_PAYLOAD_INVALID", err.to_string(), Some(path.to_owned()))
+            })?;
+            document.fill_mapped(&read_semantic_data(arguments)?, &mapping, policy)?
+        }
+        None => document.fill_with_policy(&read_values(arguments)?, policy)?,
+    };
+    match (arguments.option("owner-password"), arguments.option("user-password")) {
+        (Some(owner), user) => document.encrypt(&EncryptionOptions {
+            owner_password: owner.to_owned(),
+            user_password: user.unwrap_or_default().to_owned(),
+            permissions: permissions(arguments)?,
+        })?,
+        (None, Some(_)) => return Err(usage_error("--user-password requires --owner-password")),
+        (None, None) if arguments.option("permissions").is_some() => {
+            return Err(usage_error("--permissions requires --owner-password"))
+        }
+        (None, None) if arguments.switches.contains("reapply-encryption") => document.reapply_input_encryption(),
+        (None, None) => {}
+    }
+    write_file(output, &document.save_with_code("BW_FILL_SAVE_FAILED")?, report)
+}
+
+fn flatten(arguments: &Arguments) -> CoreResult<String> {
+    arguments.only(&["password", "output"])?;
+    let input = single_input(arguments)?;
+    let output = arguments.required("output")?;
+    let mut document = load(input, arguments.option("password"))?;
+    document.flatten()?;
+    write_file(output, &document.save_with_code("BW_FLATTEN_SAVE_FAILED")?, FillReport::default())
+}
+
+fn merge(arguments: &Arguments) -> CoreResult<String> {
+    arguments.only(&["password", "output"])?;
+    let password = arguments.option("password");
+    let output = arguments.required("output")?;
+    let [first, rest @ ..] = arguments.positional.as_slice() else {
+        return Err(usage_error("missing input PDF"));
+    };
+    if rest.is_empty() {
+        return Err(usage_error("merge expects at least two input PDFs"));
+    }
+    let mut merged = load(first, password)?;
+    let mut appended = Vec::new();
+    for path in rest {
+        appended.push(merged.append(&load(path, password)?)?);
+    }
+    let data = merged.save_with_code("BW_MERGE_SAVE_FAILED")?;
+    write_output(output, &data)?;
+    to_json(&MergeOutput {
+        output,
+        bytes: data.len(),
+        appended,
+    })
+}
+
+/// `--permissions`: the comma-separated rights (`print`, `copy`, `modify`,
+/// `fill-in`) granted to user-password holders, or `none`; all of them when
+/// the option is absent.
+fn permissions(arguments: &Arguments) -> CoreResult<Permissions> {
+    let Some(list) = arguments.option("permissions") else {
+        return Ok(Permissions::default());
+    };
+    let mut granted = Permissions {
+        print: false,
+        copy: false,
+        modify: false,
+        fill_in: false,
+    };
+    if list.trim().eq_ignore_ascii_case("none") {
+        return Ok(granted);
+    }
+    for name in list.split(',').map(|name| name.trim().to_ascii_lowercase()) {
+        match name.as_str() {
+            "print" => granted.print = true,
+            "copy" => granted.copy = true,
+            "modify" => granted.modify = true,
+            "fill-in" => granted.fill_in = true,
+            other => return Err(usage_error(format!("unknown permission '{}'", other))),
+        }
+    }
+    Ok(granted)
+}
+
+fn partial_name_policy(arguments: &Arguments) -> CoreResult<PartialNamePolicy> {
+    match arguments.option("partial-names") {
+        Some(policy) => PartialNamePolicy::parse(policy)
+            .ok_or_else(|| usage_error(format!("unknown --partial-names policy '{}'", policy))),
+        None => Ok(PartialNamePolicy::default()),
+    }
+}
+
+/// Loads `--values` as batch records: CSV (header row of field keys) or
+/// JSON lines (one object of field values per line).
+fn read_records(arguments: &Arguments) -> CoreResult<Vec<HashMap<String, FieldValue>>> {
+    let path = arguments.required("values")?;
+    let extension = path.rsplit_once('.').map(|(_, ext)| ext.to_ascii_lowercase()).unwrap_or_default();
+    let format = match arguments.option("format") {
+        Some(format) => format.to_ascii_lowercase(),
+        None if extension == "csv" => "csv".into(),
+        None => "jsonl".into(),
+    };
+    let text = read_text(path)?;
+    match format.as_str() {
+        "csv" => csv_records(&text),
+        "jsonl" => text
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(number, line)| {
+                serde_json::from_str(line).map_err(|err| {
+                    core_error_with_context("BW_INPUT_JSONL_INVALID", err.to_string(), Some(format!("line {}", number + 1)))
+                })
+            })
+            .collect(),
+        other => Err(usage_error(format!("unknown records format '{}'", other))),
+    }
+}
+
+/// Loads `--values` as semantic JSON data for a `--mapping` fill.
+fn read_semantic_data(arguments: &Arguments) -> CoreResult<BTreeMap<String, DataValue>> {
+    if arguments.option("format").is_some_and(|format| !format.eq_ignore_ascii_case("json"))
+        || arguments.option("row").is_some()
+    {
+        return Err(usage_error("--mapping reads --values as JSON"));
+    }
+    let path = arguments.required("values")?;
+    serde_json::from_str(&read_text(path)?)
+        .map_err(|err| core_error_with_context("BW_FIELDS_PAYLOAD_INVALID", err.to_string(), Some(path.to_owned())))
+}
+
+/// Loads `--values` as JSON (an object of field name to value), CSV (header
+/// row of field names, `--row` selects the 1-based record) or XFDF.
+fn read_values(arguments: &Arguments) -> CoreResult<HashMap<String, FieldValue>> {
+    let path = arguments.required("values")?;
+    let extension = path.rsplit_once('.').map(|(_, ext)| ext.to_ascii_lowercase()).unwrap_or_default();
+    let format = match arguments.option("format") {
+        Some(format) => format.to_ascii_lowercase(),
+        None if extension == "csv" => "csv".into(),
+        None if extension == "xfdf" || extension == "xml" => "xfdf".into(),
+        None => "json".into(),
+    };
+    if arguments.option("row").is_some() && format != "csv" {
+        return Err(usage_error("--row only applies to CSV values"));
+    }
+    let text = read_text(path)?;
+
+    match format.as_str() {
+        "json" => serde_json::from_str(&text)
+            .map_err(|err| core_error_with_context("BW_FIELDS_PAYLOAD_INVALID", err.to_string(), Some(path.to_owned()))),
+        "csv" => {
+            let row = match arguments.option("row") {
+                Some(row) => row
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|row| *row > 0)
+                    .ok_or_else(|| usage_error("--row expects a positive record number"))?,
+                None => 1,
+            };
+            let mut table = parse_csv(&text)?;
+            if row > table.rows.len() {
+                return Err(core_error_with_context(
+                    "BW_INPUT_CSV_INVALID",
+                    format!("record {} requested but the table has {}", row, table.rows.len()),
+                    Some(path.to_owned()),
+                ));
+            }
+            let record = table.rows.swap_remove(row - 1);
+            Ok(table.header.into_iter().zip(record.into_iter().map(FieldValue::Text)).collect())
+        }
+        "xfdf" => Ok(parse_xfdf(&text)?.into_iter().collect()),
+        other => Err(usage_error(format!("unknown values format '{}'", other))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::make_fixture_pdf;
+
+    fn args(items: &[&str]) -> Vec<String> {
+        items.iter().map(|item| item.to_string()).collect()
+    }
+
+    fn scratch(name: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("pdftool-cli-{}", std::process::id()));
+        fs::create_dir_all(&dir).expect("create scratch dir");
+        dir.join(name).to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn fill_from_csv_then_extract_round_trips() {
+        let input = scratch("form.pdf");
+        let values = scratch("values.csv");
+        let output = scratch("filled.pdf");
+        fs::write(&input, make_fixture_pdf()).expect("write fixture");
+        fs::write(&values, "Name,Consent,Choice\nGrace Hopper,false,A\nAda Lovelace,true,B\n").expect("write values");
+
+        execute(&args(&["fill", &input, "--values", &values, "--row", "2", "--output", &output])).expect("fill succeeds");
+        let extracted: BTreeMap<String, FieldValue> =
+            serde_json::from_str(&execute(&args(&["extract", &output])).expect("extract succeeds")).expect("JSON output");
+
+        assert_eq!(extracted.get("Name"), Some(&FieldValue::Text("Ada Lovelace".into())));
+        assert_eq!(extracted.get("Consent"), Some(&FieldValue::Text("Yes".into())));
+        assert_eq!(extracted.get("Choice"), Some(&FieldValue::Text("B".into())));
+
+        let comparison: serde_json::Value =
+            serde_json::from_str(&execute(&args(&["fingerprint", &input, "--compare", &output])).expect("fingerprint"))
+                .expect("JSON output");
+        assert_eq!(comparison["same"], true, "filling does not change the fingerprint");
+        let diff: serde_json::Value =
+            serde_json::from_str(&execute(&args(&["diff", &input, "--revised", &output])).expect("diff")).expect("JSON");
+        assert_eq!(diff["changes"], serde_json::json!([]));
+        for path in [input, values, output] {
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    #[test]
+    fn batch_writes_one_pdf_per_jsonl_record_and_collect_reads_them_back() {
+        let input = scratch("onboarding.pdf");
+        let values = scratch("people.jsonl");
+        let output = scratch("batch");
+        fs::write(&input, make_fixture_pdf()).expect("write fixture");
+        fs::write(&values, "{\"Name\": \"Grace Hopper\"}\n\n{\"Choice\": \"C\"}\n").expect("write records");
+
+        let entries: serde_json::Value =
+            serde_json::from_str(&execute(&args(&["batch", &input, "--values", &values, "--output", &output])).expect("batch"))
+                .expect("JSON output");
+        assert_eq!(entries[0]["report"]["filled"], 1);
+        assert_eq!(entries[1]["error"]["code"], "BW_FILL_BUTTON_VALUE_INVALID");
+        let first = entries[0]["output"].as_str().expect("output path").to_owned();
+        let extracted: BTreeMap<String, FieldValue> =
+            serde_json::from_str(&execute(&args(&["extract", &first])).expect("extract succeeds")).expect("JSON output");
+        assert_eq!(extracted.get("Name"), Some(&FieldValue::Text("Grace Hopper".into())));
+
+        let collected = execute(&args(&["collect", &input, &first, &values])).expect("collect");
+        let rows: serde_json::Value = serde_json::from_str(&collected).expect("JSON output");
+        assert_eq!(rows[0]["values"]["Name"], "Grace Hopper");
+        assert!(collected.find("\"Name\"") < collected.find("\"Consent\""), "template column order");
+        assert_eq!(rows[1]["source"], values.as_str());
+        assert!(rows[1]["error"]["code"].is_string());
+        let _ = fs::remove_dir_all(output);
+        for path in [input, values] {
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    #[test]
+    fn encrypted_fill_restricts_permissions() {
+        let input = scratch("locked-form.pdf");
+        let values = scratch("locked-values.json");
+        let output = scratch("locked.pdf");
+        fs::write(&input, make_fixture_pdf()).expect("write fixture");
+        fs::write(&values, "{\"Name\": \"Ada\"}").expect("write values");
+        let fill = |permissions: &str| {
+            execute(&args(&[
+                "fill", &input, "--values", &values, "--output", &output, "--owner-password", "owner", "--permissions",
+                permissions,
+            ]))
+        };
+
+        fill("print,fill-in").expect("encrypted fill");
+        let doc = lopdf::Document::load(&output).expect("encrypted output parses");
+        let encrypt = doc.trailer.get(b"Encrypt").and_then(|encrypt| doc.dereference(encrypt)).expect("Encrypt");
+        let p = encrypt.1.as_dict().and_then(|encrypt| encrypt.get(b"P")).and_then(lopdf::Object::as_i64).expect("P");
+        let granted = |bits: i64| p & bits == bits;
+        assert!(granted((1 << 2) | (1 << 8)), "print and fill-in granted");
+        assert!(!granted(1 << 3) && !granted(1 << 4), "modify and copy withheld");
+
+        assert_eq!(fill("print,share").expect_err("unknown permission").code, "BW_CLI_USAGE");
+        let unencrypted = args(&["fill", &input, "--values", &values, "--output", &output, "--permissions", "none"]);
+        let payload = execute(&unencrypted).expect_err("permissions without encryption");
+        assert_eq!(payload.code, "BW_CLI_USAGE");
+        for path in [input, values, output] {
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    #[test]
+    fn usage_and_io_errors_carry_codes() {
+        let payload = execute(&args(&["fill", "form.pdf"])).expect_err("missing --output");
+        assert_eq!(payload.code, "BW_CLI_USAGE");
+
+        let payload = execute(&args(&["detect", &scratch("missing.pdf")])).expect_err("missing input");
+        assert_eq!(payload.code, "BW_CLI_IO_READ");
+
+        let payload = execute(&args(&["detect", "form.pdf", "--output", "x.pdf"])).expect_err("stray option");
+        assert_eq!(payload.code, "BW_CLI_USAGE");
+    }
+}
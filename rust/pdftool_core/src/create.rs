@@ -0,0 +1,489 @@
+// SPDX-License-Identifier: PMPL-1.0-or-later WITH Palimpsest
+
+//! Field creation: [`Block`]s turned into AcroForm fields, so a flat PDF
+//! becomes a fillable form.
+//!
+//! Each block becomes a widget on its page, listed in the page's `/Annots`
+//! and reachable from `AcroForm.Fields`, under the parents a dotted name
+//! implies. Text and choice fields draw with Helvetica (`/Helv`), buttons
+//! with ZapfDingbats (`/ZaDb`); both fonts are added to the form's `/DR`.
+//! Buttons get on and off appearances so their states can be selected by
+//! name, while text appearances are left to the viewer (`NeedAppearances`).
+
+use std::collections::{BTreeMap, HashMap};
+
+use lopdf::{dictionary, Dictionary, Document, Object, ObjectId};
+
+use crate::appearance::{button_appearances, ButtonStyle};
+use crate::{
+    append_root_fields, core_error_with_context, get_dict, get_dict_mut, object_as_reference, resolve_object, Block,
+    BlockKind, CoreResult, FieldIndex,
+};
+
+const FF_NO_TOGGLE_TO_OFF: i64 = 1 << 14;
+const FF_RADIO: i64 = 1 << 15;
+const FF_COMBO: i64 = 1 << 17;
+/// Annotation flag: print the widget with the page.
+const ANNOT_PRINT: i64 = 4;
+const TEXT_DA: &str = "/Helv 0 Tf 0 g";
+const BUTTON_DA: &str = "/ZaDb 0 Tf 0 g";
+/// ZapfDingbats check mark and filled circle.
+const CHECK_GLYPH: &str = "4";
+const RADIO_GLYPH: &str = "l";
+
+impl Block {
+    /// Full name of the field the block describes.
+    pub(crate) fn field_name(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.label).trim()
+    }
+}
+
+/// One field to create: a single block, or every radio block sharing a name.
+struct NewField<'a> {
+    name: String,
+    kind: BlockKind,
+    /// Blocks with their page and on-state (buttons only).
+    widgets: Vec<(&'a Block, ObjectId, Option<String>)>,
+}
+
+fn invalid(message: impl Into<String>, name: &str) -> crate::CoreError {
+    core_error_with_context("BW_CREATE_BLOCK_INVALID", message, Some(name.to_owned()))
+}
+
+fn name_taken(message: impl Into<String>, name: &str) -> crate::CoreError {
+    core_error_with_context("BW_CREATE_NAME_TAKEN", message, Some(name.to_owned()))
+}
+
+/// Groups and checks `blocks` against each other and the existing fields.
+fn plan<'a>(doc: &Document, index: &FieldIndex, blocks: &'a [Block]) -> CoreResult<Vec<NewField<'a>>> {
+    let pages = doc.get_pages();
+    let mut fields: Vec<NewField> = Vec::new();
+    let mut by_name = HashMap::new();
+    for block in blocks {
+        let name = block.field_name();
+        let kind = block.kind.ok_or_else(|| invalid("block has no kind", name))?;
+        if name.is_empty() || name.split('.').any(|segment| segment.trim().is_empty()) {
+            return Err(invalid(format!("'{}' is not a valid field name", name), &block.label));
+        }
+        if !(block.width > 0.0 && block.height > 0.0 && block.x.is_finite() && block.y.is_finite()) {
+            return Err(invalid("block must have a positive width and height", name));
+        }
+        let number = block.page.unwrap_or(1);
+        let page_id = *pages.get(&number).ok_or_else(|| {
+            core_error_with_context(
+                "BW_CREATE_PAGE_INVALID",
+                format!("page {} does not exist (the document has {} pages)", number, pages.len()),
+                Some(name.to_owned()),
+            )
+        })?;
+        match by_name.get(name) {
+            Some(&position) => {
+                let field: &mut NewField = &mut fields[position];
+                if kind != BlockKind::Radio || field.kind != BlockKind::Radio {
+                    return Err(name_taken(format!("more than one block is named '{}'", name), name));
+                }
+                field.widgets.push((block, page_id, None));
+            }
+            None => {
+                by_name.insert(name.to_owned(), fields.len());
+                fields.push(NewField {
+                    name: name.to_owned(),
+                    kind,
+                    widgets: vec![(block, page_id, None)],
+                });
+            }
+        }
+    }
+
+    let existing: HashMap<&str, bool> = index
+        .fields
+        .iter()
+        .filter_map(|field| Some((field.full_name.as_deref()?, field.terminal)))
+        .collect();
+    for field in &mut fields {
+        if existing.contains_key(field.name.as_str()) {
+            return Err(name_taken(format!("a field named '{}' already exists", field.name), &field.name));
+        }
+        for (end, _) in field.name.match_indices('.') {
+            let parent = &field.name[..end];
+            if existing.get(parent) == Some(&true) || by_name.contains_key(parent) {
+                return Err(name_taken(format!("field '{}' cannot hold other fields", parent), &field.name));
+            }
+        }
+        assign_states(field)?;
+    }
+    Ok(fields)
+}
+
+/// On-states of a button field's widgets: `Yes` for a checkbox, and each
+/// radio button's position in the group unless the block names one.
+fn assign_states(field: &mut NewField) -> CoreResult<()> {
+    let default_state = |position: usize| match field.kind {
+        BlockKind::Checkbox => Some("Yes".to_owned()),
+        BlockKind::Radio => Some((position + 1).to_string()),
+        BlockKind::Text | BlockKind::Choice => None,
+    };
+    let states: Vec<Option<String>> = field
+        .widgets
+        .iter()
+        .enumerate()
+        .map(|(position, (block, _, _))| default_state(position).map(|state| block.export.clone().unwrap_or(state)))
+        .collect();
+    let mut seen = Vec::new();
+    for state in states.iter().flatten() {
+        if state.is_empty() || state == "Off" {
+            return Err(invalid(format!("'{}' cannot be a button's on-state", state), &field.name));
+        }
+        if seen.contains(&state) {
+            return Err(invalid(format!("two radio buttons share the on-state '{}'", state), &field.name));
+        }
+        seen.push(state);
+    }
+    for (widget, state) in field.widgets.iter_mut().zip(states) {
+        widget.2 = state;
+    }
+    Ok(())
+}
+
+/// Creates a field for each of `blocks` (radio blocks sharing a name become
+/// one group) and returns the number of fields created. Fails before
+/// changing anything when a block lacks a kind or a valid name or size
+/// (`BW_CREATE_BLOCK_INVALID`), names a missing page
+/// (`BW_CREATE_PAGE_INVALID`), or would reuse a field's full name
+/// (`BW_CREATE_NAME_TAKEN`).
+pub(crate) fn create_fields(doc: &mut Document, index: &FieldIndex, blocks: &[Block]) -> CoreResult<usize> {
+    let fields = plan(doc, index, blocks)?;
+    if fields.is_empty() {
+        return Ok(0);
+    }
+    let acroform_id = append_root_fields(doc, &[])?;
+    let button_font = prepare_form(doc, acroform_id)?;
+
+    let parents: HashMap<&str, ObjectId> = index
+        .fields
+        .iter()
+        .filter(|field| !field.terminal)
+        .filter_map(|field| Some((field.full_name.as_deref()?, field.id)))
+        .collect();
+    let mut created_parents = HashMap::new();
+    let mut roots = Vec::new();
+    for field in &fields {
+        let (prefix, partial) = match field.name.rsplit_once('.') {
+            Some((prefix, partial)) => (Some(prefix), partial),
+            None => (None, field.name.as_str()),
+        };
+        let parent = match prefix {
+            Some(prefix) => Some(parent_node(doc, &parents, &mut created_parents, &mut roots, prefix)?),
+            None => None,
+        };
+        let field_id = add_field(doc, field, partial, parent, &button_font)?;
+        match parent {
+            Some(parent) => push_reference(doc, parent, b"Kids", field_id, "BW_FORM_FIELD_INVALID")?,
+            None => roots.push(field_id),
+        }
+    }
+    append_root_fields(doc, &roots)?;
+    Ok(fields.len())
+}
+
+/// The non-terminal field named `path`, created (with its own parents) when
+/// the form has none.
+pub(crate) fn parent_node(
+    doc: &mut Document,
+    existing: &HashMap<&str, ObjectId>,
+    created: &mut HashMap<String, ObjectId>,
+    roots: &mut Vec<ObjectId>,
+    path: &str,
+) -> CoreResult<ObjectId> {
+    if let Some(id) = existing.get(path).or_else(|| created.get(path)) {
+        return Ok(*id);
+    }
+    let (parent, partial) = match path.rsplit_once('.') {
+        Some((prefix, partial)) => (Some(parent_node(doc, existing, created, roots, prefix)?), partial),
+        None => (None, path),
+    };
+    let mut node = dictionary! {
+        "T" => Object::string_literal(partial),
+        "Kids" => Vec::<Object>::new(),
+    };
+    if let Some(parent) = parent {
+        node.set("Parent", Object::Reference(parent));
+    }
+    let id = doc.add_object(node);
+    match parent {
+        Some(parent) => push_reference(doc, parent, b"Kids", id, "BW_FORM_FIELD_INVALID")?,
+        None => roots.push(id),
+    }
+    created.insert(path.to_owned(), id);
+    Ok(id)
+}
+
+/// Writes one field and its widgets; returns the field's object id.
+fn add_field(
+    doc: &mut Document,
+    field: &NewField,
+    partial: &str,
+    parent: Option<ObjectId>,
+    button_font: &Object,
+) -> CoreResult<ObjectId> {
+    let mut dict = match field.kind {
+        BlockKind::Text => dictionary! { "FT" => "Tx", "DA" => Object::string_literal(TEXT_DA) },
+        BlockKind::Checkbox => dictionary! { "FT" => "Btn", "V" => "Off" },
+        BlockKind::Radio => dictionary! { "FT" => "Btn", "Ff" => FF_RADIO | FF_NO_TOGGLE_TO_OFF, "V" => "Off" },
+        BlockKind::Choice => {
+            let options = field.widgets[0].0.options.iter().map(|option| Object::string_literal(option.as_str()));
+            dictionary! {
+                "FT" => "Ch",
+                "Ff" => FF_COMBO,
+                "DA" => Object::string_literal(TEXT_DA),
+                "Opt" => options.collect::<Vec<_>>(),
+            }
+        }
+    };
+    dict.set("T", Object::string_literal(partial));
+    if let Some(parent) = parent {
+        dict.set("Parent", Object::Reference(parent));
+    }
+
+    if field.kind != BlockKind::Radio {
+        let (block, page_id, state) = &field.widgets[0];
+        let mut merged = widget(doc, block, *page_id, state.as_deref(), CHECK_GLYPH, button_font);
+        for (key, value) in dict.iter() {
+            merged.set(key.clone(), value.clone());
+        }
+        let id = doc.add_object(merged);
+        push_reference(doc, *page_id, b"Annots", id, "BW_PDF_PAGE_INVALID")?;
+        return Ok(id);
+    }
+
+    let field_id = doc.add_object(dict);
+    let mut kids = Vec::with_capacity(field.widgets.len());
+    for (block, page_id, state) in &field.widgets {
+        let mut kid = widget(doc, block, *page_id, state.as_deref(), RADIO_GLYPH, button_font);
+        kid.set("Parent", Object::Reference(field_id));
+        let kid_id = doc.add_object(kid);
+        push_reference(doc, *page_id, b"Annots", kid_id, "BW_PDF_PAGE_INVALID")?;
+        kids.push(Object::Reference(kid_id));
+    }
+    get_dict_mut(doc, field_id, "BW_FORM_FIELD_INVALID", &field.name)?.set("Kids", kids);
+    Ok(field_id)
+}
+
+/// Widget annotation entries for `block`; a button's (`state` set) also get
+/// its `/DA`, `/MK` glyph and on/off appearances.
+fn widget(
+    doc: &mut Document,
+    block: &Block,
+    page_id: ObjectId,
+    state: Option<&str>,
+    glyph: &str,
+    button_font: &Object,
+) -> Dictionary {
+    let corners = [block.x, block.y, block.x + block.width, block.y + block.height];
+    let mut widget = dictionary! {
+        "Type" => "Annot",
+        "Subtype" => "Widget",
+        "Rect" => corners.iter().map(|value| Object::Real(*value)).collect::<Vec<_>>(),
+        "P" => Object::Reference(page_id),
+        "F" => ANNOT_PRINT,
+    };
+    if let Some(state) = state {
+        widget.set("DA", Object::string_literal(BUTTON_DA));
+        widget.set("MK", dictionary! { "CA" => Object::string_literal(glyph) });
+        let style = ButtonStyle::read(doc, &widget, None, glyph == RADIO_GLYPH);
+        let (on, off) = button_appearances(doc, &style, block.width, block.height, button_font.clone());
+        let mut normal = Dictionary::new();
+        normal.set(state.as_bytes().to_vec(), Object::Reference(on));
+        normal.set("Off", Object::Reference(off));
+        widget.set("AS", "Off");
+        widget.set("AP", dictionary! { "N" => normal });
+    }
+    widget
+}
+
+/// Appends a reference to `item` to the array under `key` of `holder`,
+/// whether the array is inline, referenced or not there yet.
+pub(crate) fn push_reference(
+    doc: &mut Document,
+    holder: ObjectId,
+    key: &[u8],
+    item: ObjectId,
+    code: &'static str,
+) -> CoreResult<()> {
+    let context = format!("object {} {}", holder.0, holder.1);
+    let array_id = get_dict(doc, holder, code, &context)?.get(key).ok().and_then(object_as_reference);
+    if let Some(Ok(items)) = array_id.map(|id| doc.get_object_mut(id).and_then(Object::as_array_mut)) {
+        items.push(Object::Reference(item));
+        return Ok(());
+    }
+    let holder = get_dict_mut(doc, holder, code, &context)?;
+    match holder.get_mut(key) {
+        Ok(Object::Array(items)) => items.push(Object::Reference(item)),
+        _ => holder.set(key.to_vec(), vec![Object::Reference(item)]),
+    }
+    Ok(())
+}
+
+/// Adds `/Helv` and `/ZaDb` to the form's `/DR` fonts unless present, gives
+/// the form a default `/DA` and asks viewers to draw text appearances;
+/// returns the `/ZaDb` font entry for button appearances.
+fn prepare_form(doc: &mut Document, acroform_id: ObjectId) -> CoreResult<Object> {
+    let owned_dict = |doc: &Document, entry: Option<&Object>| {
+        entry
+            .and_then(|entry| resolve_object(doc, entry).ok())
+            .and_then(|entry| entry.as_dict().ok().cloned())
+            .unwrap_or_default()
+    };
+    let acroform = get_dict(doc, acroform_id, "BW_FORM_ACROFORM_INVALID", "AcroForm dictionary")?;
+    let resources_ref = acroform.get(b"DR").ok().and_then(object_as_reference);
+    let mut resources = owned_dict(doc, acroform.get(b"DR").ok());
+    let fonts_ref = resources.get(b"Font").ok().and_then(object_as_reference);
+    let mut fonts = owned_dict(doc, resources.get(b"Font").ok());
+
+    let standard: BTreeMap<&str, Dictionary> = BTreeMap::from([
+        (
+            "Helv",
+            dictionary! {
+                "Type" => "Font",
+                "Subtype" => "Type1",
+                "BaseFont" => "Helvetica",
+                "Encoding" => "WinAnsiEncoding",
+            },
+        ),
+        ("ZaDb", dictionary! { "Type" => "Font", "Subtype" => "Type1", "BaseFont" => "ZapfDingbats" }),
+    ]);
+    for (name, font) in standard {
+        if !fonts.has(name.as_bytes()) {
+            fonts.set(name, Object::Reference(doc.add_object(font)));
+        }
+    }
+    let button_font = fonts.get(b"ZaDb").cloned().map_err(|_| {
+        core_error_with_context("BW_FORM_ACROFORM_INVALID", "no /ZaDb font in the form's /DR", Some("AcroForm.DR".into()))
+    })?;
+
+    match fonts_ref {
+        Some(id) => {
+            doc.objects.insert(id, Object::Dictionary(fonts));
+        }
+        None => resources.set("Font", fonts),
+    }
+    let acroform = get_dict_mut(doc, acroform_id, "BW_FORM_ACROFORM_INVALID", "AcroForm dictionary")?;
+    if !acroform.has(b"DA") {
+        acroform.set("DA", Object::string_literal(TEXT_DA));
+    }
+    acroform.set("NeedAppearances", true);
+    match resources_ref {
+        Some(id) => {
+            doc.objects.insert(id, Object::Dictionary(resources));
+        }
+        None => acroform.set("DR", resources),
+    }
+    Ok(button_font)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::tests::make_fixture_pdf;
+    use crate::{FieldValue, FormDocument};
+
+    fn block(kind: BlockKind, name: &str, x: f32, export: Option<&str>) -> Block {
+        Block {
+            label: name.to_owned(),
+            x,
+            y: 500.0,
+            width: 20.0,
+            height: 20.0,
+            kind: Some(kind),
+            page: Some(1),
+            name: Some(name.to_owned()),
+            export: export.map(str::to_owned),
+            options: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn created_fields_are_detected_and_filled_natively() {
+        let mut flat = Document::with_version("1.7");
+        let pages_id = flat.new_object_id();
+        let page_id = flat.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => Object::Reference(pages_id),
+            "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()],
+        });
+        flat.objects.insert(pages_id, Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Kids" => vec![Object::Reference(page_id)],
+            "Count" => 1,
+        }));
+        let catalog_id = flat.add_object(dictionary! { "Type" => "Catalog", "Pages" => Object::Reference(pages_id) });
+        flat.trailer.set("Root", Object::Reference(catalog_id));
+        let mut output = Vec::new();
+        flat.save_to(&mut output).expect("save");
+
+        let mut form = FormDocument::load(&output).expect("flat PDF loads");
+        let mut country = block(BlockKind::Choice, "Applicant.Country", 200.0, None);
+        country.options = vec!["NL".into(), "UK".into()];
+        let blocks = [
+            Block {
+                width: 150.0,
+                ..block(BlockKind::Text, "Applicant.Name", 40.0, None)
+            },
+            country,
+            block(BlockKind::Checkbox, "Consent", 40.0, None),
+            block(BlockKind::Radio, "Status", 100.0, Some("Single")),
+            block(BlockKind::Radio, "Status", 140.0, Some("Married")),
+        ];
+        assert_eq!(form.create_fields(&blocks).expect("create"), 4);
+
+        let reloaded = FormDocument::load(&form.save().expect("save")).expect("form loads");
+        let detected = reloaded.detect().expect("detect");
+        let names: Vec<Option<&str>> = detected.iter().map(|block| block.name.as_deref()).collect();
+        assert_eq!(
+            names,
+            [Some("Applicant.Name"), Some("Applicant.Country"), Some("Consent"), Some("Status"), Some("Status")]
+        );
+        assert_eq!(detected[3].kind, Some(BlockKind::Radio));
+
+        let mut filled = reloaded.clone();
+        let values = HashMap::from([
+            ("Name".to_owned(), FieldValue::from("Ada")),
+            ("Country".to_owned(), FieldValue::from("UK")),
+            ("Consent".to_owned(), FieldValue::Bool(true)),
+            ("Status".to_owned(), FieldValue::from("Married")),
+        ]);
+        assert_eq!(filled.fill(&values).expect("fill"), 4);
+        let values: HashMap<String, FieldValue> =
+            filled.extract().expect("extract").into_iter().map(|field| (field.name, field.value)).collect();
+        assert_eq!(values["Applicant.Name"], FieldValue::from("Ada"));
+        assert_eq!(values["Status"], FieldValue::from("Married"));
+        assert_eq!(values["Consent"], FieldValue::from("Yes"));
+
+        // fill_blocks creates only the blocks no field matches yet.
+        let more = [blocks[2].clone(), block(BlockKind::Text, "Notes", 300.0, None)];
+        let values = HashMap::from([("Notes".to_owned(), FieldValue::from("late"))]);
+        let created = form.save().expect("save");
+        let refilled = crate::fill_blocks_with_options_impl(&created, &more, values, &Default::default());
+        let fields = FormDocument::load(&refilled.expect("fill_blocks")).expect("loads").extract().expect("extract");
+        assert_eq!(fields.len(), 5);
+    }
+
+    #[test]
+    fn names_must_be_new_and_pages_must_exist() {
+        let mut form = FormDocument::load(&make_fixture_pdf()).expect("fixture loads");
+        let taken = form.create_fields(&[block(BlockKind::Text, "Name", 0.0, None)]).expect_err("Name exists");
+        assert_eq!((taken.code(), taken.context()), ("BW_CREATE_NAME_TAKEN", Some("Name")));
+        let under_terminal = form.create_fields(&[block(BlockKind::Text, "Consent.Note", 0.0, None)]);
+        assert_eq!(under_terminal.expect_err("Consent is terminal").code(), "BW_CREATE_NAME_TAKEN");
+        let far = Block {
+            page: Some(3),
+            ..block(BlockKind::Checkbox, "Extra", 0.0, None)
+        };
+        assert_eq!(form.create_fields(&[far]).expect_err("one page").code(), "BW_CREATE_PAGE_INVALID");
+        let pair = [block(BlockKind::Radio, "Pick", 0.0, Some("A")), block(BlockKind::Radio, "Pick", 30.0, Some("A"))];
+        assert_eq!(form.create_fields(&pair).expect_err("same state").code(), "BW_CREATE_BLOCK_INVALID");
+        assert_eq!(form.extract().expect("unchanged").len(), 3);
+    }
+}
@@ -0,0 +1,139 @@
+// SPDX-License-Identifier: PMPL-1.0-or-later WITH Palimpsest
+
+//! Minimal RFC 4180 reader and writer for field value tables.
+//!
+//! The first row is the header (field keys); each following row is one
+//! record. Quoted cells may contain separators, doubled quotes and newlines.
+//! Blank lines are skipped; errors name the source line of the bad record.
+
+use crate::{core_error_with_context, CoreResult};
+
+/// A parsed table: header cells and data rows of the same width.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct CsvTable {
+    pub header: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+}
+
+pub(crate) fn parse_csv(text: &str) -> CoreResult<CsvTable> {
+    let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+    // Each record with the source line it starts on, for error context.
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut cell = String::new();
+    let mut in_quotes = false;
+    let mut line = 1;
+    let mut record_line = 1;
+    let mut chars = text.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        if in_quotes {
+            match ch {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    cell.push('"');
+                }
+                '"' => in_quotes = false,
+                other => {
+                    if other == '\n' || (other == '\r' && chars.peek() != Some(&'\n')) {
+                        line += 1;
+                    }
+                    cell.push(other);
+                }
+            }
+            continue;
+        }
+        match ch {
+            '"' if cell.is_empty() => in_quotes = true,
+            ',' => record.push(std::mem::take(&mut cell)),
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' | '\r' => {
+                record.push(std::mem::take(&mut cell));
+                records.push((record_line, std::mem::take(&mut record)));
+                line += 1;
+                record_line = line;
+            }
+            other => cell.push(other),
+        }
+    }
+    if in_quotes {
+        return Err(core_error_with_context(
+            "BW_INPUT_CSV_INVALID",
+            "unterminated quoted cell",
+            Some(format!("line {}", record_line)),
+        ));
+    }
+    if !cell.is_empty() || !record.is_empty() {
+        record.push(cell);
+        records.push((record_line, record));
+    }
+    records.retain(|(_, record)| !(record.len() == 1 && record[0].is_empty()));
+
+    let mut records = records.into_iter();
+    let (_, header) = records.next().ok_or_else(|| {
+        core_error_with_context("BW_INPUT_CSV_INVALID", "CSV input has no header row", Some("line 1".into()))
+    })?;
+    let mut rows = Vec::with_capacity(records.len());
+    for (line, row) in records {
+        if row.len() != header.len() {
+            return Err(core_error_with_context(
+                "BW_INPUT_CSV_INVALID",
+                format!("expected {} cells but found {}", header.len(), row.len()),
+                Some(format!("line {}", line)),
+            ));
+        }
+        rows.push(row);
+    }
+    Ok(CsvTable { header, rows })
+}
+
+/// Writes `rows` as CSV with CRLF line endings, quoting cells that contain
+/// a separator, a quote or a line break.
+pub(crate) fn write_csv(rows: &[Vec<String>]) -> String {
+    let mut out = String::new();
+    for row in rows {
+        for (position, cell) in row.iter().enumerate() {
+            if position > 0 {
+                out.push(',');
+            }
+            if cell.contains([',', '"', '\n', '\r']) {
+                out.push('"');
+                out.push_str(&cell.replace('"', "\"\""));
+                out.push('"');
+            } else {
+                out.push_str(cell);
+            }
+        }
+        out.push_str("\r\n");
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_quoted_cells_and_crlf() {
+        let table = parse_csv("Name,Notes\r\n\"Lovelace, Ada\",\"said \"\"hi\"\"\nthen left\"\r\n").expect("valid CSV");
+        assert_eq!(table.header, vec!["Name", "Notes"]);
+        assert_eq!(table.rows, vec![vec!["Lovelace, Ada".to_string(), "said \"hi\"\nthen left".to_string()]]);
+
+        let written = write_csv(&[table.header.clone(), table.rows[0].clone()]);
+        assert_eq!(parse_csv(&written).expect("round trips"), table);
+    }
+
+    #[test]
+    fn rejects_ragged_rows() {
+        let payload = parse_csv("a,b\n1\n").expect_err("ragged row");
+        assert_eq!(payload.code, "BW_INPUT_CSV_INVALID");
+        assert_eq!(payload.context.as_deref(), Some("line 2"));
+    }
+
+    #[test]
+    fn errors_name_the_source_line_past_blank_lines() {
+        let payload = parse_csv("a,b\n\n1,2\n\"x\ny\",3\n\n4\n").expect_err("ragged row");
+        assert_eq!(payload.code, "BW_INPUT_CSV_INVALID");
+        assert_eq!(payload.context.as_deref(), Some("line 7"));
+    }
+}
@@ -0,0 +1,327 @@
+// SPDX-License-Identifier: PMPL-1.0-or-later WITH Palimpsest
+
+//! Structural diff between two revisions of a form.
+//!
+//! The field models compared are the [`FieldSignature`]s of two fingerprints,
+//! paired by full name. Fields present on one side only are first offered to
+//! the rename heuristic, which pairs a removed field with an added one when
+//! they sit in about the same place, share a label or name words, and have
+//! the same kind; whatever stays unpaired is reported as added or removed.
+
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{FieldSignature, FormFingerprint};
+
+/// Lowest rename score at which a removed and an added field are paired.
+const RENAME_THRESHOLD: f64 = 0.6;
+
+/// Where a field's first widget sits.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Placement {
+    pub page: u32,
+    pub rect: Option<[i32; 4]>,
+}
+
+/// One difference between the old and the revised form.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "change", rename_all = "camelCase")]
+pub enum FieldChange {
+    Added {
+        name: String,
+        kind: String,
+    },
+    Removed {
+        name: String,
+        kind: String,
+    },
+    /// A field that probably carries a new name; `confidence` is the rename
+    /// score in `0.6..=1`.
+    Renamed {
+        from: String,
+        to: String,
+        confidence: f64,
+    },
+    Moved {
+        name: String,
+        from: Placement,
+        to: Placement,
+    },
+    Retyped {
+        name: String,
+        from: String,
+        to: String,
+    },
+    OptionsChanged {
+        name: String,
+        added: Vec<String>,
+        removed: Vec<String>,
+    },
+}
+
+impl FieldChange {
+    /// The field name the change is listed under (the old name for renames).
+    fn name(&self) -> &str {
+        match self {
+            FieldChange::Renamed { from, .. } => from,
+            FieldChange::Added { name, .. }
+            | FieldChange::Removed { name, .. }
+            | FieldChange::Moved { name, .. }
+            | FieldChange::Retyped { name, .. }
+            | FieldChange::OptionsChanged { name, .. } => name,
+        }
+    }
+}
+
+/// Changes from one revision of a form to the next, ordered by field name.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FormDiff {
+    /// Whether the two revisions have the same fingerprint.
+    pub identical: bool,
+    pub changes: Vec<FieldChange>,
+}
+
+/// Compares two fingerprints; `old` is the earlier revision.
+pub fn diff_fingerprints(old: &FormFingerprint, new: &FormFingerprint) -> FormDiff {
+    let old_fields: HashMap<&str, &FieldSignature> = old.fields.iter().map(|field| (field.name.as_str(), field)).collect();
+    let new_fields: HashMap<&str, &FieldSignature> = new.fields.iter().map(|field| (field.name.as_str(), field)).collect();
+    let mut changes = Vec::new();
+
+    for before in &old.fields {
+        if let Some(after) = new_fields.get(before.name.as_str()) {
+            compare_kept(before, after, &mut changes);
+        }
+    }
+
+    let removed: Vec<&FieldSignature> =
+        old.fields.iter().filter(|field| !new_fields.contains_key(field.name.as_str())).collect();
+    let added: Vec<&FieldSignature> =
+        new.fields.iter().filter(|field| !old_fields.contains_key(field.name.as_str())).collect();
+    let mut candidates: Vec<(f64, usize, usize)> = Vec::new();
+    for (r, before) in removed.iter().enumerate() {
+        for (a, after) in added.iter().enumerate() {
+            let score = rename_score(before, after);
+            if score >= RENAME_THRESHOLD {
+                candidates.push((score, r, a));
+            }
+        }
+    }
+    // Best pairs first; ties keep document order.
+    candidates.sort_by(|x, y| y.0.total_cmp(&x.0).then((x.1, x.2).cmp(&(y.1, y.2))));
+    let mut paired_removed = HashSet::new();
+    let mut paired_added = HashSet::new();
+    for (score, r, a) in candidates {
+        if paired_removed.contains(&r) || paired_added.contains(&a) {
+            continue;
+        }
+        paired_removed.insert(r);
+        paired_added.insert(a);
+        changes.push(FieldChange::Renamed {
+            from: removed[r].name.clone(),
+            to: added[a].name.clone(),
+            confidence: (score * 100.0).round() / 100.0,
+        });
+    }
+    for (_, field) in removed.iter().enumerate().filter(|(r, _)| !paired_removed.contains(r)) {
+        changes.push(FieldChange::Removed {
+            name: field.name.clone(),
+            kind: field.kind.clone(),
+        });
+    }
+    for (_, field) in added.iter().enumerate().filter(|(a, _)| !paired_added.contains(a)) {
+        changes.push(FieldChange::Added {
+            name: field.name.clone(),
+            kind: field.kind.clone(),
+        });
+    }
+
+    changes.sort_by(|x, y| x.name().cmp(y.name()));
+    FormDiff {
+        identical: old.hash == new.hash,
+        changes,
+    }
+}
+
+/// Changes to a field that kept its name.
+fn compare_kept(before: &FieldSignature, after: &FieldSignature, changes: &mut Vec<FieldChange>) {
+    if before.kind != after.kind {
+        changes.push(FieldChange::Retyped {
+            name: before.name.clone(),
+            from: before.kind.clone(),
+            to: after.kind.clone(),
+        });
+    }
+    if before.closeness(after) < 1.0 {
+        changes.push(FieldChange::Moved {
+            name: before.name.clone(),
+            from: Placement {
+                page: before.page,
+                rect: before.rect,
+            },
+            to: Placement {
+                page: after.page,
+                rect: after.rect,
+            },
+        });
+    }
+    if before.options != after.options {
+        changes.push(FieldChange::OptionsChanged {
+            name: before.name.clone(),
+            added: after.options.iter().filter(|option| !before.options.contains(option)).cloned().collect(),
+            removed: before.options.iter().filter(|option| !after.options.contains(option)).cloned().collect(),
+        });
+    }
+}
+
+/// How likely `after` is `before` under a new name: geometry counts 0.4, the
+/// label (or, without one, the words of the partial name) 0.4 and the kind
+/// 0.2, so a field of the same kind pairs on either geometry or label alone.
+fn rename_score(before: &FieldSignature, after: &FieldSignature) -> f64 {
+    let kind = if before.kind == after.kind { 1.0 } else { 0.0 };
+    (4.0 * before.closeness(after) + 4.0 * label_similarity(before, after) + 2.0 * kind) / 10.0
+}
+
+fn label_similarity(before: &FieldSignature, after: &FieldSignature) -> f64 {
+    let ours = words(before);
+    let theirs = words(after);
+    if ours.is_empty() && theirs.is_empty() {
+        return 0.0;
+    }
+    ours.intersection(&theirs).count() as f64 / ours.union(&theirs).count() as f64
+}
+
+/// Lowercase words of the label, or of the last name segment split at
+/// punctuation and camel case (`dateOfBirth` -> date, of, birth).
+fn words(field: &FieldSignature) -> HashSet<String> {
+    let text = match &field.label {
+        Some(label) => label.as_str(),
+        None => field.name.rsplit('.').next().unwrap_or_default(),
+    };
+    let mut words = HashSet::new();
+    let mut current = String::new();
+    let mut previous_lower = false;
+    for ch in text.chars() {
+        let boundary = !ch.is_alphanumeric() || (ch.is_uppercase() && previous_lower);
+        if boundary && !current.is_empty() {
+            words.insert(std::mem::take(&mut current));
+        }
+        if ch.is_alphanumeric() {
+            current.extend(ch.to_lowercase());
+        }
+        previous_lower = ch.is_lowercase() || ch.is_ascii_digit();
+    }
+    if !current.is_empty() {
+        words.insert(current);
+    }
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(name: &str, kind: &str, rect: [i32; 4], label: Option<&str>) -> FieldSignature {
+        FieldSignature {
+            name: name.into(),
+            kind: kind.into(),
+            page: 1,
+            rect: Some(rect),
+            options: Vec::new(),
+            label: label.map(Into::into),
+        }
+    }
+
+    fn fingerprint(hash: &str, fields: Vec<FieldSignature>) -> FormFingerprint {
+        FormFingerprint {
+            version: crate::FINGERPRINT_VERSION,
+            hash: hash.into(),
+            pages: 1,
+            fields,
+        }
+    }
+
+    #[test]
+    fn revisions_list_every_kind_of_change() {
+        let mut colour = field("Colour", "combo", [50, 500, 100, 20], None);
+        colour.options = vec!["Red".into(), "Green".into()];
+        let old = fingerprint(
+            "old",
+            vec![
+                field("DOB", "text", [50, 700, 120, 20], Some("Date of birth")),
+                field("Fax", "text", [50, 650, 120, 20], None),
+                field("Consent", "checkbox", [50, 600, 12, 12], None),
+                field("Phone", "text", [50, 550, 120, 20], None),
+                colour.clone(),
+            ],
+        );
+        colour.options = vec!["Red".into(), "Blue".into()];
+        let new = fingerprint(
+            "new",
+            vec![
+                field("Applicant.BirthDate", "text", [52, 701, 120, 20], Some("Date of birth")),
+                field("Consent", "radio", [50, 600, 12, 12], None),
+                field("Phone", "text", [300, 550, 120, 20], None),
+                field("Email", "text", [50, 450, 120, 20], None),
+                colour,
+            ],
+        );
+
+        let diff = diff_fingerprints(&old, &new);
+        assert!(!diff.identical);
+        assert_eq!(
+            diff.changes,
+            vec![
+                FieldChange::OptionsChanged {
+                    name: "Colour".into(),
+                    added: vec!["Blue".into()],
+                    removed: vec!["Green".into()],
+                },
+                FieldChange::Retyped {
+                    name: "Consent".into(),
+                    from: "checkbox".into(),
+                    to: "radio".into(),
+                },
+                FieldChange::Renamed {
+                    from: "DOB".into(),
+                    to: "Applicant.BirthDate".into(),
+                    confidence: 1.0,
+                },
+                FieldChange::Added {
+                    name: "Email".into(),
+                    kind: "text".into(),
+                },
+                FieldChange::Removed {
+                    name: "Fax".into(),
+                    kind: "text".into(),
+                },
+                FieldChange::Moved {
+                    name: "Phone".into(),
+                    from: Placement {
+                        page: 1,
+                        rect: Some([50, 550, 120, 20]),
+                    },
+                    to: Placement {
+                        page: 1,
+                        rect: Some([300, 550, 120, 20]),
+                    },
+                },
+            ]
+        );
+        assert!(diff_fingerprints(&old, &old).changes.is_empty());
+    }
+
+    #[test]
+    fn renames_need_geometry_or_shared_words() {
+        let before = field("HomePhone", "text", [50, 550, 120, 20], None);
+        let nearby = field("Telephone", "text", [50, 552, 120, 20], None);
+        let far_same_label = field("Contact.Line1", "text", [300, 100, 120, 20], Some("Home phone"));
+        let far_other_words = field("Employer", "text", [300, 100, 120, 20], None);
+        let nearby_other_kind = field("Telephone", "checkbox", [50, 552, 120, 20], None);
+        assert!(rename_score(&before, &nearby) >= RENAME_THRESHOLD);
+        assert!(rename_score(&before, &far_same_label) >= RENAME_THRESHOLD);
+        assert!(rename_score(&before, &far_other_words) < RENAME_THRESHOLD);
+        assert!(rename_score(&before, &nearby_other_kind) < RENAME_THRESHOLD);
+        assert_eq!(words(&field("A.HomePhone2", "text", [0; 4], None)), HashSet::from(["home".into(), "phone2".into()]));
+    }
+}
@@ -0,0 +1,445 @@
+// SPDX-License-Identifier: PMPL-1.0-or-later WITH Palimpsest
+
+//! `FormDocument`: the native Rust entry point to the form pipeline.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::OnceLock;
+
+use lopdf::Document;
+
+use crate::batch::{fill_combined, fill_separately};
+use crate::bulk::extract_documents;
+use crate::classify::classify_document;
+use crate::create::create_fields;
+use crate::edit::edit_fields;
+use crate::encryption::SecurityHandler;
+use crate::extract::extract_document_fields;
+use crate::fingerprint::fingerprint_document;
+use crate::flatten::flatten_document;
+use crate::merge::append_form;
+use crate::paths::repeating_groups;
+use crate::repair::{adopt_orphan_widgets, promote_inline_dictionaries};
+use crate::{
+    core_error_with_context, detect_document_blocks, diff_fingerprints, fill_document, load_document, propose,
+    resolve_mapping, BatchRecord, Block, BulkExtraction, CoreError, CoreResult, DataValue, EditReport, EncryptionOptions,
+    FieldClassification, FieldEdit, FieldIndex, FieldSnapshot, FieldValue, FillReport, FormDiff, FormFingerprint, Limits,
+    Mapping, MergeReport, PartialNamePolicy, Profile, Proposal, RepeatingGroup, Resolution,
+};
+
+/// A parsed (and, if needed, decrypted) PDF form.
+///
+/// ```no_run
+/// use std::collections::HashMap;
+/// use pdftool_core::{FieldValue, FormDocument};
+///
+/// # fn main() -> Result<(), pdftool_core::CoreError> {
+/// let mut form = FormDocument::load(&std::fs::read("form.pdf").unwrap())?;
+/// let mut values = HashMap::new();
+/// values.insert("Name".to_string(), FieldValue::from("Ada Lovelace"));
+/// form.fill(&values)?;
+/// let filled: Vec<u8> = form.save()?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct FormDocument {
+    pub(crate) doc: Document,
+    pub(crate) input_encryption: Option<SecurityHandler>,
+    pub(crate) output_encryption: Option<SecurityHandler>,
+    pub(crate) limits: Limits,
+    pub(crate) warnings: Vec<CoreError>,
+    /// Field tree index, built on load and rebuilt on first use after the tree
+    /// changes. A build that exceeds the limits keeps its error.
+    pub(crate) index: OnceLock<CoreResult<FieldIndex>>,
+}
+
+impl FormDocument {
+    /// Parses `pdf_data`; encrypted inputs are opened with the empty user
+    /// password.
+    pub fn load(pdf_data: &[u8]) -> CoreResult<Self> {
+        Self::load_with(pdf_data, None)
+    }
+
+    /// Parses `pdf_data`, opening an encrypted input with `password` (user or
+    /// owner password).
+    pub fn load_with_password(pdf_data: &[u8], password: &str) -> CoreResult<Self> {
+        Self::load_with(pdf_data, Some(password))
+    }
+
+    /// Parses `pdf_data` under `limits` instead of [`Limits::default`]; they
+    /// also apply to every later operation on the document.
+    pub fn load_with_limits(pdf_data: &[u8], password: Option<&str>, limits: Limits) -> CoreResult<Self> {
+        let (mut doc, input_encryption, mut warnings) = load_document(pdf_data, password, &limits)?;
+        warnings.extend(promote_inline_dictionaries(&mut doc, &limits));
+        // An index that exceeds the limits is kept and reported on first use.
+        let index = match FieldIndex::build(&doc, &limits) {
+            Ok(index) => match adopt_orphan_widgets(&mut doc, &index, &limits)? {
+                Some(adopted) => {
+                    warnings.push(adopted);
+                    FieldIndex::build(&doc, &limits)
+                }
+                None => Ok(index),
+            },
+            Err(error) => Err(error),
+        };
+        Ok(Self {
+            doc,
+            input_encryption,
+            output_encryption: None,
+            limits,
+            warnings,
+            index: OnceLock::from(index),
+        })
+    }
+
+    pub(crate) fn load_with(pdf_data: &[u8], password: Option<&str>) -> CoreResult<Self> {
+        Self::load_with_limits(pdf_data, password, Limits::default())
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    /// Repairs made while loading (for example `BW_PDF_XREF_REBUILT` when the
+    /// cross-reference table had to be reconstructed); empty for sound files.
+    pub fn warnings(&self) -> &[CoreError] {
+        &self.warnings
+    }
+
+    pub(crate) fn index(&self) -> CoreResult<&FieldIndex> {
+        cached_index(&self.index, &self.doc, &self.limits)
+    }
+
+    /// Drops the cached index after edits to the field or page structure.
+    pub(crate) fn invalidate_index(&mut self) {
+        self.index = OnceLock::new();
+    }
+
+    /// Whether the input carried an `/Encrypt` dictionary.
+    pub fn is_encrypted(&self) -> bool {
+        self.input_encryption.is_some()
+    }
+
+    /// Widget rectangles and labels, page by page.
+    pub fn detect(&self) -> CoreResult<Vec<Block>> {
+        detect_document_blocks(self.index()?)
+    }
+
+    /// Creates a text, checkbox, radio or choice field for each of `blocks`
+    /// (radio blocks sharing a name become one group), with widgets on the
+    /// blocks' pages; returns the number of fields created. A block without a
+    /// kind or valid size fails with `BW_CREATE_BLOCK_INVALID`, one on a
+    /// missing page with `BW_CREATE_PAGE_INVALID`, and one reusing a field
+    /// name with `BW_CREATE_NAME_TAKEN`, leaving the form unchanged.
+    pub fn create_fields(&mut self, blocks: &[Block]) -> CoreResult<usize> {
+        let index = cached_index(&self.index, &self.doc, &self.limits)?;
+        let created = create_fields(&mut self.doc, index, blocks)?;
+        if created > 0 {
+            self.invalidate_index();
+        }
+        Ok(created)
+    }
+
+    /// Deletes, renames, moves or splits fields, in order, keeping `/Kids`,
+    /// `/Parent`, the pages' `/Annots` and `AcroForm.CO` consistent. Either
+    /// every edit applies or the form is left unchanged; an edit that would
+    /// reuse a full name fails with `BW_EDIT_NAME_TAKEN`. The report lists
+    /// the fields removed and renamed.
+    pub fn edit_fields(&mut self, edits: &[FieldEdit]) -> CoreResult<EditReport> {
+        let mut doc = self.doc.clone();
+        let report = edit_fields(&mut doc, &self.limits, edits)?;
+        self.doc = doc;
+        self.invalidate_index();
+        Ok(report)
+    }
+
+    /// Fields that repeat per row (`Items[0].Qty`, `Items[1].Qty`, ...),
+    /// grouped with their row count and column names.
+    pub fn repeating_rows(&self) -> CoreResult<Vec<RepeatingGroup>> {
+        Ok(repeating_groups(self.index()?))
+    }
+
+    /// Structural fingerprint of the form, independent of its values.
+    pub fn fingerprint(&self) -> CoreResult<FormFingerprint> {
+        fingerprint_document(&self.doc, self.index()?, &self.limits)
+    }
+
+    /// Field changes from this form to `revised`, a later edition of it.
+    pub fn diff(&self, revised: &FormDocument) -> CoreResult<FormDiff> {
+        Ok(diff_fingerprints(&self.fingerprint()?, &revised.fingerprint()?))
+    }
+
+    /// Semantic tags (given name, email, date of birth, ...) for every
+    /// terminal field, each with a confidence score.
+    pub fn classify(&self) -> CoreResult<Vec<FieldClassification>> {
+        classify_document(&self.doc, self.index()?, &self.limits)
+    }
+
+    /// Values for the fields this form asks about that `profile` can answer,
+    /// each with its provenance. Nothing is written until the accepted values
+    /// are passed to [`FormDocument::fill`].
+    pub fn propose_autofill(&self, profile: &Profile) -> CoreResult<Vec<Proposal>> {
+        Ok(propose(&self.classify()?, &self.extract()?, profile))
+    }
+
+    /// Current value of every terminal field.
+    pub fn extract(&self) -> CoreResult<Vec<FieldSnapshot>> {
+        extract_document_fields(&self.doc, self.index()?)
+    }
+
+    /// Values of each of `documents` (a name, usually the path, and the PDF
+    /// bytes) under this form's fields as columns, one row per document. A
+    /// document that cannot be opened with `password` or lacks any of the
+    /// columns gets a row with its error (`BW_EXTRACT_FORM_MISMATCH` for a
+    /// different form).
+    pub fn extract_bulk<'a>(
+        &self,
+        documents: impl IntoIterator<Item = (&'a str, &'a [u8])>,
+        password: Option<&str>,
+    ) -> CoreResult<BulkExtraction> {
+        extract_documents(self, documents, password)
+    }
+
+    /// Writes `values` (keyed by full or partial field name) into the form and
+    /// returns the number of fields updated. A key matching several fields by
+    /// partial name fails with `BW_FILL_AMBIGUOUS_KEY`; a failed fill leaves
+    /// the form unchanged.
+    pub fn fill(&mut self, values: &HashMap<String, FieldValue>) -> CoreResult<usize> {
+        Ok(self.fill_with_policy(values, PartialNamePolicy::default())?.filled)
+    }
+
+    /// Like [`fill`](Self::fill), resolving ambiguous partial names under
+    /// `policy`; the report lists the keys that were. A fill that fails
+    /// leaves the form unchanged. Clears refused by NoToggleToOff radio groups
+    /// do not fail it: those groups keep their selection and are listed in the
+    /// report's `field_errors`.
+    pub fn fill_with_policy(
+        &mut self,
+        values: &HashMap<String, FieldValue>,
+        policy: PartialNamePolicy,
+    ) -> CoreResult<FillReport> {
+        let cached = self.index.take().unwrap_or_else(|| FieldIndex::build(&self.doc, &self.limits));
+        let mut index = match cached {
+            Ok(index) => index,
+            Err(error) => {
+                self.index = OnceLock::from(Err(error.clone()));
+                return Err(error);
+            }
+        };
+        // A failed fill restores the objects it wrote and leaves the index as
+        // it was, so the index stays valid either way.
+        let report = fill_document(&mut self.doc, &mut index, values, policy, &self.limits);
+        self.index = OnceLock::from(Ok(index));
+        report
+    }
+
+    /// Fills semantic `data` through `mapping`, which must have been written
+    /// for this form (`BW_MAPPING_FINGERPRINT_MISMATCH` otherwise). Bound keys
+    /// absent from `data` are listed in the report's `missing_keys`, and
+    /// fields whose transforms failed in its `field_errors`; when no binding
+    /// yields a value, the first such error is returned instead.
+    pub fn fill_mapped(
+        &mut self,
+        data: &BTreeMap<String, DataValue>,
+        mapping: &Mapping,
+        policy: PartialNamePolicy,
+    ) -> CoreResult<FillReport> {
+        let fingerprint = self.fingerprint()?;
+        if mapping.fingerprint != fingerprint.hash {
+            return Err(core_error_with_context(
+                "BW_MAPPING_FINGERPRINT_MISMATCH",
+                format!("mapping was written for form {}, this form is {}", mapping.fingerprint, fingerprint.hash),
+                mapping.name.clone(),
+            ));
+        }
+        let Resolution {
+            values,
+            missing_keys,
+            mut field_errors,
+        } = resolve_mapping(mapping, data)?;
+        if values.is_empty() && !field_errors.is_empty() {
+            return Err(field_errors.swap_remove(0));
+        }
+        let mut report = self.fill_with_policy(&values, policy)?;
+        field_errors.append(&mut report.field_errors);
+        Ok(FillReport {
+            missing_keys,
+            field_errors,
+            ..report
+        })
+    }
+
+    /// Fills a copy of the form for each of `records`, reusing this parsed
+    /// template; each outcome holds that record's PDF or its error.
+    pub fn fill_batch(
+        &self,
+        records: &[HashMap<String, FieldValue>],
+        policy: PartialNamePolicy,
+    ) -> CoreResult<Vec<BatchRecord>> {
+        fill_separately(self, records, policy)
+    }
+
+    /// Like [`fill_batch`](Self::fill_batch), combining the filled copies
+    /// into one PDF whose fields are prefixed `record<n>.`; fails with
+    /// `BW_BATCH_NOTHING_FILLED` when no record could be filled.
+    pub fn fill_batch_combined(
+        &self,
+        records: &[HashMap<String, FieldValue>],
+        policy: PartialNamePolicy,
+    ) -> CoreResult<(Vec<u8>, Vec<BatchRecord>)> {
+        fill_combined(self, records, policy)
+    }
+
+    /// Appends the pages and fields of `other`, merging the two forms'
+    /// `/DR` resources. When a top-level field name of `other` is already
+    /// used here, its fields are moved under a `copy<n>.` prefix so each copy
+    /// keeps its own values.
+    pub fn append(&mut self, other: &FormDocument) -> CoreResult<MergeReport> {
+        append_form(self, other)
+    }
+
+    /// Bytes of the document with `values` applied, leaving `self` unchanged.
+    pub fn preview(&self, values: &HashMap<String, FieldValue>) -> CoreResult<Vec<u8>> {
+        let mut preview = self.clone();
+        preview.fill(values)?;
+        preview.save()
+    }
+
+    /// Paints widget appearances into the page content and removes the form;
+    /// returns the number of widgets flattened.
+    pub fn flatten(&mut self) -> CoreResult<usize> {
+        let index = cached_index(&self.index, &self.doc, &self.limits)?;
+        let flattened = flatten_document(&mut self.doc, index, &self.limits)?;
+        self.invalidate_index();
+        Ok(flattened)
+    }
+
+    /// Encrypts the output of subsequent [`save`](Self::save) calls with
+    /// AES-256.
+    pub fn encrypt(&mut self, options: &EncryptionOptions) -> CoreResult<()> {
+        self.output_encryption = Some(SecurityHandler::aes256(options)?);
+        Ok(())
+    }
+
+    /// Saves with the input's original encryption (a no-op for unencrypted
+    /// inputs).
+    pub fn reapply_input_encryption(&mut self) {
+        self.output_encryption = self.input_encryption.clone();
+    }
+
+    /// Serialises the document, encrypting it if requested.
+    pub fn save(&self) -> CoreResult<Vec<u8>> {
+        self.save_with_code("BW_PDF_SAVE_FAILED")
+    }
+
+    pub(crate) fn save_with_code(&self, code: &'static str) -> CoreResult<Vec<u8>> {
+        let mut doc = self.doc.clone();
+        if let Some(handler) = &self.output_encryption {
+            handler.encrypt_document(&mut doc)?;
+        }
+        let mut output = Vec::new();
+        doc.save_to(&mut output)
+            .map_err(|err| core_error_with_context(code, err.to_string(), Some("Document::save_to".into())))?;
+        Ok(output)
+    }
+}
+
+/// The index in `cache`, built from `doc` on first use. Borrows only the
+/// cache, so callers can still edit `doc` with the index in hand.
+fn cached_index<'a>(
+    cache: &'a OnceLock<CoreResult<FieldIndex>>,
+    doc: &Document,
+    limits: &Limits,
+) -> CoreResult<&'a FieldIndex> {
+    cache.get_or_init(|| FieldIndex::build(doc, limits)).as_ref().map_err(Clone::clone)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::make_fixture_pdf;
+
+    #[test]
+    fn fill_save_and_reload_through_the_rust_api() {
+        let mut form = FormDocument::load(&make_fixture_pdf()).expect("fixture loads");
+        assert!(!form.is_encrypted());
+        assert_eq!(form.detect().expect("detect").len(), 4);
+
+        let mut values = HashMap::new();
+        values.insert("Name".to_string(), FieldValue::from("Ada Lovelace"));
+        values.insert("Consent".to_string(), FieldValue::Bool(true));
+        assert_eq!(form.fill(&values).expect("fill"), 2);
+
+        let reloaded = FormDocument::load(&form.save().expect("save")).expect("output loads");
+        let fields = reloaded.extract().expect("extract");
+        let name = fields.iter().find(|field| field.name == "Name").expect("Name field");
+        assert_eq!(name.value, FieldValue::Text("Ada Lovelace".into()));
+    }
+
+    #[test]
+    fn failed_fill_leaves_the_document_untouched() {
+        let mut form = FormDocument::load(&make_fixture_pdf()).expect("fixture loads");
+        let before = form.extract().expect("extract");
+        let objects = form.doc.objects.clone();
+
+        let mut values = HashMap::new();
+        values.insert("Name".to_string(), FieldValue::from("Ada Lovelace"));
+        values.insert("Consent".to_string(), FieldValue::Bool(true));
+        values.insert("Choice".to_string(), FieldValue::from("Z"));
+        let error = form.fill(&values).expect_err("no radio button is Z");
+        assert_eq!(error.code(), "BW_FILL_BUTTON_VALUE_INVALID");
+
+        assert_eq!(form.doc.objects, objects, "written objects are restored and added ones dropped");
+        assert_eq!(form.extract().expect("extract"), before);
+        let reloaded = FormDocument::load(&form.save().expect("save")).expect("output loads");
+        assert_eq!(reloaded.extract().expect("extract"), before, "nothing half-written reaches save");
+    }
+
+    #[test]
+    fn preview_leaves_the_document_untouched() {
+        let form = FormDocument::load(&make_fixture_pdf()).expect("fixture loads");
+        let mut values = HashMap::new();
+        values.insert("Name".to_string(), FieldValue::from("Preview only"));
+
+        let preview = FormDocument::load(&form.preview(&values).expect("preview")).expect("preview loads");
+        let value_of = |form: &FormDocument| {
+            form.extract()
+                .expect("extract")
+                .into_iter()
+                .find(|field| field.name == "Name")
+                .map(|field| field.value)
+        };
+        assert_eq!(value_of(&preview), Some(FieldValue::Text("Preview only".into())));
+        assert_ne!(value_of(&form), Some(FieldValue::Text("Preview only".into())));
+    }
+
+    #[test]
+    fn errors_expose_stable_codes() {
+        let error = FormDocument::load(b"not a pdf").expect_err("invalid input");
+        assert_eq!(error.code(), "BW_PDF_INVALID");
+        let boxed: Box<dyn std::error::Error> = Box::new(error);
+        assert!(boxed.to_string().starts_with("BW_PDF_INVALID: "));
+    }
+
+    #[test]
+    fn limits_travel_with_the_document() {
+        let pdf = make_fixture_pdf();
+        let tiny = Limits {
+            max_objects: 3,
+            ..Limits::default()
+        };
+        let error = FormDocument::load_with_limits(&pdf, None, tiny).expect_err("fixture has more objects");
+        assert_eq!(error.code(), "BW_LIMIT_OBJECTS");
+
+        let form = FormDocument::load_with_limits(
+            &pdf,
+            None,
+            Limits {
+                max_steps: 2,
+                ..Limits::default()
+            },
+        )
+        .expect("index errors surface on use");
+        assert_eq!(form.detect().expect_err("index build runs out").code(), "BW_LIMIT_STEPS");
+    }
+}
@@ -0,0 +1,498 @@
+// SPDX-License-Identifier: PMPL-1.0-or-later WITH Palimpsest
+
+//! Structural edits of the field tree: delete, rename, move and split.
+//!
+//! Edits run in order, each on a fresh index of the tree the previous one
+//! left, and [`FormDocument::edit_fields`](crate::FormDocument::edit_fields)
+//! keeps the document unchanged unless all of them succeed. Every edit keeps
+//! the tree's links in step: `/Kids` and `/Parent` between fields,
+//! `AcroForm.Fields` for top-level fields, the pages' `/Annots` for widgets
+//! and `AcroForm.CO` (calculation order). A group left without kids is
+//! removed with them. Edits are written as one-entry objects:
+//!
+//! ```json
+//! [
+//!   { "delete": { "field": "OldNotes" } },
+//!   { "rename": { "field": "f1_01[0]", "to": "FirstName" } },
+//!   { "move": { "field": "FirstName", "parent": "Applicant" } },
+//!   { "split": { "field": "Date", "names": ["Start", "End"] } }
+//! ]
+//! ```
+
+use std::collections::{HashMap, HashSet};
+
+use lopdf::{Document, Object, ObjectId};
+use serde::{Deserialize, Serialize};
+
+use crate::create::{parent_node, push_reference};
+use crate::{
+    append_root_fields, core_error_with_context, ensure_acroform_object, get_dict, get_dict_mut, object_as_reference,
+    root_catalog_id, CoreError, CoreResult, FieldIndex, Limits,
+};
+
+/// One change to the field tree; fields are named by full name.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldEdit {
+    /// Removes the field with its kids and widgets.
+    Delete { field: String },
+    /// Gives the field a new partial name (`/T`); `to` has no dots.
+    Rename { field: String, to: String },
+    /// Moves the field under `parent` (created when no field has that
+    /// name), or to the top level. Attributes the field inherited are copied
+    /// onto it first, so it keeps its type, flags, appearance and value.
+    Move {
+        field: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        parent: Option<String>,
+    },
+    /// Makes each widget of a field shared by several widgets a field of its
+    /// own, named by `names` (`1`, `2`, ... by default) under the original.
+    Split {
+        field: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        names: Option<Vec<String>>,
+    },
+}
+
+/// What a list of edits did to the form's terminal fields.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct EditReport {
+    /// Full names of the fields deleted.
+    pub removed: Vec<String>,
+    /// Fields whose full name changed, in edit order.
+    pub renamed: Vec<NameChange>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct NameChange {
+    pub from: String,
+    pub to: String,
+}
+
+fn invalid(message: impl Into<String>, field: &str) -> CoreError {
+    core_error_with_context("BW_EDIT_INVALID", message, Some(field.to_owned()))
+}
+
+/// Checks a partial name given to a field: not empty and without dots.
+fn check_partial_name(name: &str, field: &str) -> CoreResult<()> {
+    if name.trim().is_empty() || name.contains('.') {
+        return Err(invalid(format!("'{}' is not a valid partial field name", name), field));
+    }
+    Ok(())
+}
+
+pub(crate) fn edit_fields(doc: &mut Document, limits: &Limits, edits: &[FieldEdit]) -> CoreResult<EditReport> {
+    let mut report = EditReport::default();
+    for edit in edits {
+        let index = FieldIndex::build(doc, limits)?;
+        match edit {
+            FieldEdit::Delete { field } => {
+                let position = find(&index, field)?;
+                delete(doc, &index, position, &mut report)?;
+            }
+            FieldEdit::Rename { field, to } => {
+                let position = find(&index, field)?;
+                check_partial_name(to, field)?;
+                let parent = index.fields[position].parent;
+                let renamed = match parent.and_then(|parent| index.fields[parent].full_name.as_ref()) {
+                    Some(prefix) => format!("{}.{}", prefix, to),
+                    None => to.clone(),
+                };
+                report.renamed.extend(check_names(&index, position, &renamed)?);
+                get_dict_mut(doc, index.fields[position].id, "BW_FORM_FIELD_INVALID", field)?
+                    .set("T", Object::string_literal(to.as_str()));
+            }
+            FieldEdit::Move { field, parent } => {
+                let position = find(&index, field)?;
+                report.renamed.extend(move_field(doc, &index, position, parent.as_deref())?);
+            }
+            FieldEdit::Split { field, names } => {
+                let position = find(&index, field)?;
+                report.renamed.extend(split(doc, &index, position, names.as_deref())?);
+            }
+        }
+    }
+    doc.prune_objects();
+    Ok(report)
+}
+
+fn find(index: &FieldIndex, name: &str) -> CoreResult<usize> {
+    index.fields.iter().position(|field| field.full_name.as_deref() == Some(name)).ok_or_else(|| {
+        core_error_with_context(
+            "BW_EDIT_FIELD_NOT_FOUND",
+            format!("no field is named '{}'", name),
+            Some(name.to_owned()),
+        )
+    })
+}
+
+/// Which fields lie in the subtree rooted at `position` (itself included).
+fn subtree(index: &FieldIndex, position: usize) -> Vec<bool> {
+    let mut inside = vec![false; index.fields.len()];
+    for (current, field) in index.fields.iter().enumerate() {
+        inside[current] = current == position || field.parent.is_some_and(|parent| inside[parent]);
+    }
+    inside
+}
+
+/// Renames of the subtree's terminal fields when the field at `position`
+/// takes the full name `renamed`; fails with `BW_EDIT_NAME_TAKEN` when a
+/// field outside the subtree already has one of the new names.
+fn check_names(index: &FieldIndex, position: usize, renamed: &str) -> CoreResult<Vec<NameChange>> {
+    let inside = subtree(index, position);
+    let old = index.fields[position].full_name.as_deref().unwrap_or_default();
+    let others: HashSet<&str> = index
+        .fields
+        .iter()
+        .zip(&inside)
+        .filter(|(_, inside)| !**inside)
+        .filter_map(|(field, _)| field.full_name.as_deref())
+        .collect();
+    let mut changes = Vec::new();
+    for (field, _) in index.fields.iter().zip(&inside).filter(|(_, inside)| **inside) {
+        let Some(name) = field.full_name.as_deref() else {
+            continue;
+        };
+        let new_name = format!("{}{}", renamed, name.strip_prefix(old).unwrap_or_default());
+        if others.contains(new_name.as_str()) {
+            return Err(core_error_with_context(
+                "BW_EDIT_NAME_TAKEN",
+                format!("a field named '{}' already exists", new_name),
+                Some(new_name),
+            ));
+        }
+        if field.terminal && new_name != name {
+            changes.push(NameChange {
+                from: name.to_owned(),
+                to: new_name,
+            });
+        }
+    }
+    Ok(changes)
+}
+
+fn delete(doc: &mut Document, index: &FieldIndex, position: usize, report: &mut EditReport) -> CoreResult<()> {
+    let inside = subtree(index, position);
+    let mut removed = HashSet::new();
+    for (field, _) in index.fields.iter().zip(&inside).filter(|(_, inside)| **inside) {
+        removed.insert(field.id);
+        removed.extend(field.widget_ids.iter().copied());
+        if field.terminal {
+            report.removed.extend(field.full_name.clone());
+        }
+    }
+    detach(doc, index, position)?;
+    for page in index.pages.iter().filter_map(|page| page.as_ref().ok()) {
+        with_array(doc, page.page_id, b"Annots", "BW_PDF_PAGE_INVALID", |annots| {
+            annots.retain(|annot| !object_as_reference(annot).is_some_and(|id| removed.contains(&id)))
+        })?;
+    }
+    let acroform_id = ensure_acroform_object(doc, root_catalog_id(doc)?)?;
+    with_array(doc, acroform_id, b"CO", "BW_FORM_ACROFORM_INVALID", |order| {
+        order.retain(|field| !object_as_reference(field).is_some_and(|id| removed.contains(&id)))
+    })?;
+    Ok(())
+}
+
+/// Unlinks the field at `position` from its parent's `/Kids` (or from
+/// `AcroForm.Fields`), then unlinks parents left without kids.
+fn detach(doc: &mut Document, index: &FieldIndex, position: usize) -> CoreResult<()> {
+    let id = index.fields[position].id;
+    let Some(parent) = index.fields[position].parent else {
+        let acroform_id = ensure_acroform_object(doc, root_catalog_id(doc)?)?;
+        with_array(doc, acroform_id, b"Fields", "BW_FORM_ACROFORM_INVALID", |fields| {
+            fields.retain(|field| object_as_reference(field) != Some(id))
+        })?;
+        return Ok(());
+    };
+    let parent_id = index.fields[parent].id;
+    let left = with_array(doc, parent_id, b"Kids", "BW_FORM_FIELD_INVALID", |kids| {
+        kids.retain(|kid| object_as_reference(kid) != Some(id));
+        kids.len()
+    })?;
+    if left == Some(0) && !index.fields[parent].widget_ids.contains(&parent_id) {
+        detach(doc, index, parent)?;
+    }
+    Ok(())
+}
+
+fn move_field(
+    doc: &mut Document,
+    index: &FieldIndex,
+    position: usize,
+    target: Option<&str>,
+) -> CoreResult<Vec<NameChange>> {
+    let field = &index.fields[position];
+    let name = field.full_name.clone().unwrap_or_default();
+    let partial = field
+        .partial_name
+        .clone()
+        .ok_or_else(|| invalid("a field without a partial name cannot be moved", &name))?;
+    let renamed = match target {
+        Some(target) => {
+            if target.split('.').any(|segment| segment.trim().is_empty()) {
+                return Err(invalid(format!("'{}' is not a valid field name", target), &name));
+            }
+            if target == name || target.starts_with(&format!("{}.", name)) {
+                return Err(invalid("a field cannot be moved under itself", &name));
+            }
+            let terminals = index.terminal_fields().filter_map(|field| field.full_name.as_deref());
+            if let Some(terminal) = terminals
+                .into_iter()
+                .find(|terminal| target == *terminal || target.starts_with(&format!("{}.", terminal)))
+            {
+                return Err(invalid(format!("field '{}' cannot hold other fields", terminal), &name));
+            }
+            format!("{}.{}", target, partial)
+        }
+        None => partial,
+    };
+    let changes = check_names(index, position, &renamed)?;
+    let current_parent = field.parent.map(|parent| index.fields[parent].id);
+
+    let groups: HashMap<&str, ObjectId> = index
+        .fields
+        .iter()
+        .filter(|field| !field.terminal)
+        .filter_map(|field| Some((field.full_name.as_deref()?, field.id)))
+        .collect();
+    let mut roots = Vec::new();
+    let new_parent = match target {
+        Some(target) => Some(parent_node(doc, &groups, &mut HashMap::new(), &mut roots, target)?),
+        None => None,
+    };
+    if new_parent == current_parent {
+        return Ok(changes);
+    }
+
+    let value = index.inherited_value(doc, field);
+    let dict = get_dict_mut(doc, field.id, "BW_FORM_FIELD_INVALID", &name)?;
+    let inherited = [
+        ("FT", field.field_type.as_deref().map(|field_type| Object::Name(field_type.as_bytes().to_vec()))),
+        ("Ff", (field.flags != 0).then_some(Object::Integer(field.flags))),
+        ("DA", field.da.as_deref().map(Object::string_literal)),
+        ("Q", field.quadding.map(Object::Integer)),
+        ("DV", field.default_value.clone()),
+        ("V", value),
+    ];
+    for (key, value) in inherited {
+        if let (false, Some(value)) = (dict.has(key.as_bytes()), value) {
+            dict.set(key, value);
+        }
+    }
+    match new_parent {
+        Some(parent) => {
+            dict.set("Parent", Object::Reference(parent));
+            push_reference(doc, parent, b"Kids", field.id, "BW_FORM_FIELD_INVALID")?;
+        }
+        None => {
+            dict.remove(b"Parent");
+            roots.push(field.id);
+        }
+    }
+    if !roots.is_empty() {
+        append_root_fields(doc, &roots)?;
+    }
+    detach(doc, index, position)?;
+    Ok(changes)
+}
+
+fn split(
+    doc: &mut Document,
+    index: &FieldIndex,
+    position: usize,
+    names: Option<&[String]>,
+) -> CoreResult<Vec<NameChange>> {
+    let field = &index.fields[position];
+    let name = field.full_name.clone().unwrap_or_default();
+    if !field.terminal {
+        return Err(invalid("only a field without kid fields can be split", &name));
+    }
+    if field.kind() == "radio" {
+        return Err(invalid("radio buttons share one field by design", &name));
+    }
+    if field.widget_ids.contains(&field.id) || field.widget_ids.len() < 2 {
+        return Err(invalid("field does not have several widgets to split", &name));
+    }
+    let names: Vec<String> = match names {
+        Some(names) if names.len() != field.widget_ids.len() => {
+            return Err(invalid(
+                format!("{} names given for {} widgets", names.len(), field.widget_ids.len()),
+                &name,
+            ))
+        }
+        Some(names) => names.to_vec(),
+        None => (1..=field.widget_ids.len()).map(|number| number.to_string()).collect(),
+    };
+    let mut seen = HashSet::new();
+    for partial in &names {
+        check_partial_name(partial, &name)?;
+        if !seen.insert(partial) {
+            return Err(invalid(format!("'{}' is given to two widgets", partial), &name));
+        }
+    }
+
+    let others: HashSet<&str> = index.fields.iter().filter_map(|field| field.full_name.as_deref()).collect();
+    let mut changes = Vec::with_capacity(names.len());
+    for (widget_id, partial) in field.widget_ids.iter().zip(&names) {
+        let new_name = format!("{}.{}", name, partial);
+        if others.contains(new_name.as_str()) {
+            return Err(core_error_with_context(
+                "BW_EDIT_NAME_TAKEN",
+                format!("a field named '{}' already exists", new_name),
+                Some(new_name),
+            ));
+        }
+        get_dict_mut(doc, *widget_id, "BW_FORM_FIELD_INVALID", &new_name)?
+            .set("T", Object::string_literal(partial.as_str()));
+        changes.push(NameChange {
+            from: name.clone(),
+            to: new_name,
+        });
+    }
+    let acroform_id = ensure_acroform_object(doc, root_catalog_id(doc)?)?;
+    with_array(doc, acroform_id, b"CO", "BW_FORM_ACROFORM_INVALID", |order| {
+        *order = order
+            .drain(..)
+            .flat_map(|entry| match object_as_reference(&entry) {
+                Some(id) if id == field.id => field.widget_ids.iter().map(|id| Object::Reference(*id)).collect(),
+                _ => vec![entry],
+            })
+            .collect();
+    })?;
+    Ok(changes)
+}
+
+/// Runs `edit` on the array under `key` of `holder`, inline or referenced;
+/// `None` when there is no such array.
+fn with_array<R>(
+    doc: &mut Document,
+    holder: ObjectId,
+    key: &[u8],
+    code: &'static str,
+    edit: impl FnOnce(&mut Vec<Object>) -> R,
+) -> CoreResult<Option<R>> {
+    let context = format!("object {} {}", holder.0, holder.1);
+    let referenced = get_dict(doc, holder, code, &context)?
+        .get(key)
+        .ok()
+        .and_then(object_as_reference)
+        .filter(|id| matches!(doc.get_object(*id), Ok(Object::Array(_))));
+    let items = match referenced {
+        Some(id) => doc.get_object_mut(id).and_then(Object::as_array_mut).ok(),
+        None => match get_dict_mut(doc, holder, code, &context)?.get_mut(key) {
+            Ok(Object::Array(items)) => Some(items),
+            _ => None,
+        },
+    };
+    Ok(items.map(edit))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::make_fixture_pdf;
+    use crate::{FieldValue, FormDocument};
+
+    fn values(form: &FormDocument) -> HashMap<String, FieldValue> {
+        form.extract().expect("extract").into_iter().map(|field| (field.name, field.value)).collect()
+    }
+
+    #[test]
+    fn edits_keep_names_values_and_links_consistent() {
+        let mut form = FormDocument::load(&make_fixture_pdf()).expect("fixture loads");
+        form.fill(&HashMap::from([("Name".to_owned(), FieldValue::from("Ada"))])).expect("fill");
+        let edits: Vec<FieldEdit> = serde_json::from_str(
+            r#"[
+                { "rename": { "field": "Name", "to": "FullName" } },
+                { "move": { "field": "FullName", "parent": "Applicant" } },
+                { "move": { "field": "Consent", "parent": "Applicant" } },
+                { "delete": { "field": "Choice" } }
+            ]"#,
+        )
+        .expect("edits parse");
+        let report = form.edit_fields(&edits).expect("edits apply");
+        assert_eq!(report.removed, ["Choice"]);
+        let moves: Vec<(&str, &str)> =
+            report.renamed.iter().map(|change| (change.from.as_str(), change.to.as_str())).collect();
+        assert_eq!(moves, [("Name", "FullName"), ("FullName", "Applicant.FullName"), ("Consent", "Applicant.Consent")]);
+
+        let reloaded = FormDocument::load(&form.save().expect("save")).expect("output loads");
+        let extracted = values(&reloaded);
+        assert_eq!(extracted.len(), 2);
+        assert_eq!(extracted["Applicant.FullName"], FieldValue::from("Ada"));
+        let page = reloaded.doc.get_pages()[&1];
+        let annots = reloaded.doc.get_dictionary(page).and_then(|page| page.get(b"Annots")).expect("annots");
+        assert_eq!(annots.as_array().map(Vec::len).ok(), Some(2), "radio widgets left the page");
+
+        let mut back = reloaded.clone();
+        let edit = [FieldEdit::Move {
+            field: "Applicant.FullName".into(),
+            parent: None,
+        }];
+        back.edit_fields(&edit).expect("move to the top");
+        let edit = [FieldEdit::Move {
+            field: "Applicant.Consent".into(),
+            parent: None,
+        }];
+        back.edit_fields(&edit).expect("move to the top");
+        let names: Vec<String> = back.extract().expect("extract").into_iter().map(|field| field.name).collect();
+        assert_eq!(names, ["FullName", "Consent"], "the emptied group is removed");
+        assert_eq!(back.index().expect("index").fields.len(), 2);
+    }
+
+    #[test]
+    fn conflicting_edits_leave_the_form_unchanged() {
+        let mut form = FormDocument::load(&make_fixture_pdf()).expect("fixture loads");
+        let edits = [
+            FieldEdit::Delete { field: "Choice".into() },
+            FieldEdit::Rename {
+                field: "Name".into(),
+                to: "Consent".into(),
+            },
+        ];
+        let error = form.edit_fields(&edits).expect_err("Consent exists");
+        assert_eq!((error.code(), error.context()), ("BW_EDIT_NAME_TAKEN", Some("Consent")));
+        assert_eq!(values(&form).len(), 3);
+        let missing = form.edit_fields(&[FieldEdit::Delete { field: "Nope".into() }]).expect_err("no such field");
+        assert_eq!(missing.code(), "BW_EDIT_FIELD_NOT_FOUND");
+        let radio = form.edit_fields(&[FieldEdit::Split {
+            field: "Choice".into(),
+            names: None,
+        }]);
+        assert_eq!(radio.expect_err("radio group").code(), "BW_EDIT_INVALID");
+    }
+
+    #[test]
+    fn splitting_names_each_widget_and_updates_calculation_order() {
+        let mut form = FormDocument::load(&make_fixture_pdf()).expect("fixture loads");
+        // Turn the radio group into a text field shared by two widgets.
+        let fields = &form.index().expect("index").fields;
+        let choice_id = fields.iter().find(|field| field.partial_name.as_deref() == Some("Choice")).expect("Choice").id;
+        let dict = form.doc.get_dictionary_mut(choice_id).expect("Choice");
+        dict.set("FT", "Tx");
+        dict.remove(b"Ff");
+        let catalog_id = root_catalog_id(&form.doc).expect("root");
+        let acroform_id = ensure_acroform_object(&mut form.doc, catalog_id).expect("form");
+        form.doc
+            .get_dictionary_mut(acroform_id)
+            .expect("form")
+            .set("CO", vec![Object::Reference(choice_id)]);
+        form.invalidate_index();
+
+        let edit = [FieldEdit::Split {
+            field: "Choice".into(),
+            names: Some(vec!["Start".into(), "End".into()]),
+        }];
+        let report = form.edit_fields(&edit).expect("split");
+        assert_eq!(report.renamed.len(), 2);
+        let mut form = form.clone();
+        form.fill(&HashMap::from([("Choice.End".to_owned(), FieldValue::from("Friday"))])).expect("fill");
+        let extracted = values(&form);
+        assert_eq!(extracted["Choice.End"], FieldValue::from("Friday"));
+        assert!(extracted.contains_key("Choice.Start"));
+        let order = form.doc.get_dictionary(acroform_id).and_then(|form| form.get(b"CO")).expect("CO");
+        assert_eq!(order.as_array().map(Vec::len).ok(), Some(2));
+    }
+}
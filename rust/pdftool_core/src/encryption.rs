@@ -0,0 +1,912 @@
+// SPDX-License-Identifier: PMPL-1.0-or-later WITH Palimpsest
+
+//! Standard security handler support.
+//!
+//! Encrypted inputs (revisions 2-6: RC4, AES-128 and AES-256) are decrypted in
+//! place while loading, before their object streams are unpacked, so the rest
+//! of the pipeline only ever sees plain objects. On save, a document can either
+//! be encrypted with fresh AES-256 (revision 6) credentials or have its
+//! original handler re-applied.
+
+use aes::cipher::block_padding::{NoPadding, Pkcs7};
+use aes::cipher::{BlockDecryptMut, BlockEncryptMut, KeyIvInit};
+use lopdf::{dictionary, Dictionary, Document, Object, ObjectId, StringFormat};
+use md5::Md5;
+use serde::Deserialize;
+use sha2::{Digest, Sha256, Sha384, Sha512};
+
+use crate::{core_error, core_error_with_context, resolve_object, CoreResult};
+
+type Aes128CbcEnc = cbc::Encryptor<aes::Aes128>;
+type Aes128CbcDec = cbc::Decryptor<aes::Aes128>;
+type Aes256CbcEnc = cbc::Encryptor<aes::Aes256>;
+type Aes256CbcDec = cbc::Decryptor<aes::Aes256>;
+
+const PASSWORD_PADDING: [u8; 32] = [
+    0x28, 0xBF, 0x4E, 0x5E, 0x4E, 0x75, 0x8A, 0x41, 0x64, 0x00, 0x4E, 0x56, 0xFF, 0xFA, 0x01, 0x08, 0x2E, 0x2E, 0x00,
+    0xB6, 0xD0, 0x68, 0x3E, 0x80, 0x2F, 0x0C, 0xA9, 0xFE, 0x64, 0x53, 0x69, 0x7A,
+];
+
+/// Permission bits granted to holders of the user password.
+///
+/// Every flag defaults to `true`; set a flag to `false` to withhold it.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Permissions {
+    pub print: bool,
+    pub copy: bool,
+    pub modify: bool,
+    pub fill_in: bool,
+}
+
+impl Default for Permissions {
+    fn default() -> Self {
+        Self {
+            print: true,
+            copy: true,
+            modify: true,
+            fill_in: true,
+        }
+    }
+}
+
+impl Permissions {
+    /// Packs the flags into the signed 32-bit `/P` value (ISO 32000-2, table 22).
+    fn bits(self) -> i32 {
+        // Bits 7-8 and 13-32 are reserved and must be set; bit 10 (text
+        // extraction for accessibility) is always granted.
+        let mut bits: u32 = 0xFFFF_F0C0 | (1 << 9);
+        if self.print {
+            bits |= (1 << 2) | (1 << 11);
+        }
+        if self.modify {
+            bits |= (1 << 3) | (1 << 5) | (1 << 10);
+        }
+        if self.copy {
+            bits |= 1 << 4;
+        }
+        if self.fill_in {
+            bits |= 1 << 8;
+        }
+        bits as i32
+    }
+}
+
+/// Credentials and permissions for AES-256 (revision 6) output encryption.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EncryptionOptions {
+    #[serde(default)]
+    pub user_password: String,
+    pub owner_password: String,
+    #[serde(default)]
+    pub permissions: Permissions,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CryptMethod {
+    Identity,
+    Rc4,
+    AesV2,
+    AesV3,
+}
+
+/// An authenticated security handler holding the file encryption key.
+#[derive(Debug, Clone)]
+pub(crate) struct SecurityHandler {
+    encrypt: Dictionary,
+    file_id: Option<Object>,
+    key: Vec<u8>,
+    string_method: CryptMethod,
+    stream_method: CryptMethod,
+    encrypt_metadata: bool,
+}
+
+impl SecurityHandler {
+    /// Authenticates `password` (as user or owner) against the document's
+    /// `/Encrypt` dictionary and derives the file key.
+    pub(crate) fn open(doc: &Document, password: &str) -> CoreResult<Self> {
+        let encrypt = match doc.trailer.get(b"Encrypt") {
+            Ok(obj) => match resolve_object(doc, obj) {
+                Ok(Object::Dictionary(dict)) => dict,
+                _ => return Err(dict_error("trailer.Encrypt is not a dictionary", "trailer.Encrypt")),
+            },
+            Err(_) => return Err(dict_error("trailer.Encrypt is missing", "trailer.Encrypt")),
+        };
+
+        let filter = encrypt.get(b"Filter").ok().and_then(|obj| obj.as_name().ok());
+        if filter != Some(b"Standard".as_slice()) {
+            return Err(core_error_with_context(
+                "BW_CRYPT_UNSUPPORTED",
+                "only the Standard security handler is supported",
+                Some("Encrypt.Filter".into()),
+            ));
+        }
+
+        let version = dict_integer(&encrypt, b"V").unwrap_or(0);
+        let revision =
+            dict_integer(&encrypt, b"R").ok_or_else(|| dict_error("Encrypt.R is missing", "Encrypt.R"))?;
+        let encrypt_metadata = encrypt
+            .get(b"EncryptMetadata")
+            .and_then(Object::as_bool)
+            .unwrap_or(true);
+        let file_id = doc.trailer.get(b"ID").ok().cloned();
+
+        let (string_method, stream_method) = match version {
+            1 | 2 => (CryptMethod::Rc4, CryptMethod::Rc4),
+            4 | 5 => (
+                crypt_filter_method(doc, &encrypt, b"StrF")?,
+                crypt_filter_method(doc, &encrypt, b"StmF")?,
+            ),
+            other => {
+                return Err(core_error_with_context(
+                    "BW_CRYPT_UNSUPPORTED",
+                    format!("unsupported encryption algorithm V {}", other),
+                    Some("Encrypt.V".into()),
+                ))
+            }
+        };
+
+        let key = match revision {
+            2..=4 => {
+                let params = LegacyParams::from_dict(&encrypt, revision, version, file_id.as_ref(), encrypt_metadata)?;
+                params.authenticate(password.as_bytes())
+            }
+            5 | 6 => aes256_file_key(&encrypt, revision, password.as_bytes())?,
+            other => {
+                return Err(core_error_with_context(
+                    "BW_CRYPT_UNSUPPORTED",
+                    format!("unsupported security handler revision {}", other),
+                    Some("Encrypt.R".into()),
+                ))
+            }
+        }
+        .ok_or_else(|| {
+            core_error_with_context(
+                "BW_CRYPT_PASSWORD_INVALID",
+                "the supplied password does not open this document",
+                Some("Encrypt".into()),
+            )
+        })?;
+
+        Ok(Self {
+            encrypt,
+            file_id,
+            key,
+            string_method,
+            stream_method,
+            encrypt_metadata,
+        })
+    }
+
+    /// Builds a fresh AES-256 revision 6 handler from user-supplied options.
+    pub(crate) fn aes256(options: &EncryptionOptions) -> CoreResult<Self> {
+        if options.owner_password.is_empty() {
+            return Err(core_error_with_context(
+                "BW_CRYPT_OPTIONS_INVALID",
+                "an owner password is required to encrypt output",
+                Some("encryption.ownerPassword".into()),
+            ));
+        }
+
+        let key = random_bytes::<32>()?;
+        let user = truncate_password(options.user_password.as_bytes());
+        let owner = truncate_password(options.owner_password.as_bytes());
+
+        let user_salts = random_bytes::<16>()?;
+        let mut u = hash_2b(user, &user_salts[..8], &[], 6).to_vec();
+        u.extend_from_slice(&user_salts);
+        let ue = aes256_encrypt_block_chain(&hash_2b(user, &user_salts[8..], &[], 6), &key);
+
+        let owner_salts = random_bytes::<16>()?;
+        let mut o = hash_2b(owner, &owner_salts[..8], &u, 6).to_vec();
+        o.extend_from_slice(&owner_salts);
+        let oe = aes256_encrypt_block_chain(&hash_2b(owner, &owner_salts[8..], &u, 6), &key);
+
+        let p = options.permissions.bits();
+        let mut perms = [0u8; 16];
+        perms[..4].copy_from_slice(&p.to_le_bytes());
+        perms[4..8].copy_from_slice(&[0xFF; 4]);
+        perms[8] = b'T';
+        perms[9..12].copy_from_slice(b"adb");
+        perms[12..].copy_from_slice(&random_bytes::<4>()?);
+        let perms = aes256_encrypt_block_chain(&key, &perms);
+
+        let encrypt = dictionary! {
+            "Filter" => Object::Name(b"Standard".to_vec()),
+            "V" => Object::Integer(5),
+            "R" => Object::Integer(6),
+            "Length" => Object::Integer(256),
+            "CF" => Object::Dictionary(dictionary! {
+                "StdCF" => Object::Dictionary(dictionary! {
+                    "CFM" => Object::Name(b"AESV3".to_vec()),
+                    "AuthEvent" => Object::Name(b"DocOpen".to_vec()),
+                    "Length" => Object::Integer(32),
+                }),
+            }),
+            "StmF" => Object::Name(b"StdCF".to_vec()),
+            "StrF" => Object::Name(b"StdCF".to_vec()),
+            "O" => hex_string(o),
+            "U" => hex_string(u),
+            "OE" => hex_string(oe),
+            "UE" => hex_string(ue),
+            "P" => Object::Integer(i64::from(p)),
+            "Perms" => hex_string(perms),
+            "EncryptMetadata" => Object::Boolean(true),
+        };
+
+        Ok(Self {
+            encrypt,
+            file_id: None,
+            key: key.to_vec(),
+            string_method: CryptMethod::AesV3,
+            stream_method: CryptMethod::AesV3,
+            encrypt_metadata: true,
+        })
+    }
+
+    /// Decrypts every string and stream in place and drops `/Encrypt`.
+    pub(crate) fn decrypt_document(&self, doc: &mut Document) -> CoreResult<()> {
+        let encrypt_id = doc.trailer.get(b"Encrypt").ok().and_then(|obj| obj.as_reference().ok());
+        if let Some(id) = encrypt_id {
+            doc.objects.remove(&id);
+        }
+        doc.trailer.remove(b"Encrypt");
+        self.transform_document(doc, false)
+    }
+
+    /// Encrypts every string and stream in place and installs `/Encrypt`.
+    ///
+    /// Must be the last mutation before the document is serialised.
+    pub(crate) fn encrypt_document(&self, doc: &mut Document) -> CoreResult<()> {
+        let file_id = match self.file_id.clone().or_else(|| doc.trailer.get(b"ID").ok().cloned()) {
+            Some(id) => id,
+            None => {
+                let id = random_bytes::<16>()?.to_vec();
+                Object::Array(vec![hex_string(id.clone()), hex_string(id)])
+            }
+        };
+
+        if self.string_method == CryptMethod::AesV3 || self.stream_method == CryptMethod::AesV3 {
+            declare_aes256_support(doc);
+        }
+
+        self.transform_document(doc, true)?;
+
+        let encrypt_id = doc.add_object(Object::Dictionary(self.encrypt.clone()));
+        doc.trailer.set(b"Encrypt", Object::Reference(encrypt_id));
+        doc.trailer.set(b"ID", file_id);
+        Ok(())
+    }
+
+    fn transform_document(&self, doc: &mut Document, encrypt: bool) -> CoreResult<()> {
+        for (&id, object) in doc.objects.iter_mut() {
+            self.transform_object(id, object, encrypt)?;
+        }
+        Ok(())
+    }
+
+    fn transform_object(&self, id: ObjectId, object: &mut Object, encrypt: bool) -> CoreResult<()> {
+        match object {
+            Object::String(bytes, format) => {
+                *bytes = self.crypt(self.string_method, id, bytes, encrypt)?;
+                if encrypt {
+                    *format = StringFormat::Hexadecimal;
+                }
+            }
+            Object::Array(items) => {
+                for item in items.iter_mut() {
+                    self.transform_object(id, item, encrypt)?;
+                }
+            }
+            Object::Dictionary(dict) => {
+                for (_, value) in dict.iter_mut() {
+                    self.transform_object(id, value, encrypt)?;
+                }
+            }
+            // Cross-reference streams are never encrypted, dictionary included.
+            Object::Stream(stream) if stream.dict.type_is(b"XRef") => {}
+            Object::Stream(stream) => {
+                for (_, value) in stream.dict.iter_mut() {
+                    self.transform_object(id, value, encrypt)?;
+                }
+                if self.encrypt_metadata || !stream.dict.type_is(b"Metadata") {
+                    let content = self.crypt(self.stream_method, id, &stream.content, encrypt)?;
+                    stream.set_content(content);
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn crypt(&self, method: CryptMethod, id: ObjectId, data: &[u8], encrypt: bool) -> CoreResult<Vec<u8>> {
+        match method {
+            CryptMethod::Identity => Ok(data.to_vec()),
+            CryptMethod::Rc4 => Ok(rc4(&self.object_key(method, id), data)),
+            CryptMethod::AesV2 | CryptMethod::AesV3 => {
+                let key = self.object_key(method, id);
+                if encrypt {
+                    aes_encrypt(&key, data)
+                } else {
+                    aes_decrypt(&key, data).ok_or_else(|| {
+                        core_error_with_context(
+                            "BW_CRYPT_DECRYPT_FAILED",
+                            "encrypted payload has an invalid length or padding",
+                            Some(format!("object {:?}", id)),
+                        )
+                    })
+                }
+            }
+        }
+    }
+
+    /// Algorithm 1: per-object keys for RC4 and AES-128; AES-256 uses the file key.
+    fn object_key(&self, method: CryptMethod, id: ObjectId) -> Vec<u8> {
+        if method == CryptMethod::AesV3 {
+            return self.key.clone();
+        }
+        let mut hasher = Md5::new();
+        hasher.update(&self.key);
+        hasher.update(&id.0.to_le_bytes()[..3]);
+        hasher.update(id.1.to_le_bytes());
+        if method == CryptMethod::AesV2 {
+            hasher.update(b"sAlT");
+        }
+        let digest = hasher.finalize();
+        digest[..(self.key.len() + 5).min(16)].to_vec()
+    }
+}
+
+/// Parameters of the revision 2-4 (MD5/RC4 based) password algorithms.
+struct LegacyParams {
+    revision: i64,
+    key_len: usize,
+    owner: Vec<u8>,
+    user: Vec<u8>,
+    permissions: i32,
+    id0: Vec<u8>,
+    encrypt_metadata: bool,
+}
+
+impl LegacyParams {
+    fn from_dict(
+        encrypt: &Dictionary,
+        revision: i64,
+        version: i64,
+        file_id: Option<&Object>,
+        encrypt_metadata: bool,
+    ) -> CoreResult<Self> {
+        let key_len = match version {
+            1 => 5,
+            4 => 16,
+            _ => (dict_integer(encrypt, b"Length").unwrap_or(40) / 8) as usize,
+        };
+        if !(5..=16).contains(&key_len) {
+            return Err(dict_error("Encrypt.Length must be between 40 and 128 bits", "Encrypt.Length"));
+        }
+        let id0 = match file_id {
+            Some(Object::Array(items)) => items.first().and_then(|obj| obj.as_str().ok()).map(<[u8]>::to_vec),
+            _ => None,
+        }
+        .unwrap_or_default();
+
+        Ok(Self {
+            revision,
+            key_len,
+            owner: dict_bytes(encrypt, b"O").ok_or_else(|| dict_error("Encrypt.O is missing", "Encrypt.O"))?,
+            user: dict_bytes(encrypt, b"U").ok_or_else(|| dict_error("Encrypt.U is missing", "Encrypt.U"))?,
+            permissions: dict_integer(encrypt, b"P").ok_or_else(|| dict_error("Encrypt.P is missing", "Encrypt.P"))?
+                as i32,
+            id0,
+            encrypt_metadata,
+        })
+    }
+
+    /// Tries `password` as the user password, then as the owner password.
+    fn authenticate(&self, password: &[u8]) -> Option<Vec<u8>> {
+        let key = self.file_key(password);
+        if self.user_password_matches(&key) {
+            return Some(key);
+        }
+        let recovered_user = self.user_password_from_owner(password);
+        let key = self.file_key(&recovered_user);
+        self.user_password_matches(&key).then_some(key)
+    }
+
+    /// Algorithm 2.
+    fn file_key(&self, password: &[u8]) -> Vec<u8> {
+        let mut hasher = Md5::new();
+        hasher.update(pad_password(password));
+        hasher.update(&self.owner);
+        hasher.update((self.permissions as u32).to_le_bytes());
+        hasher.update(&self.id0);
+        if self.revision >= 4 && !self.encrypt_metadata {
+            hasher.update([0xFF; 4]);
+        }
+        let mut hash = hasher.finalize().to_vec();
+        if self.revision >= 3 {
+            for _ in 0..50 {
+                hash = Md5::digest(&hash[..self.key_len]).to_vec();
+            }
+        }
+        hash.truncate(self.key_len);
+        hash
+    }
+
+    /// Algorithms 4 and 5, compared against `/U`.
+    fn user_password_matches(&self, key: &[u8]) -> bool {
+        if self.revision == 2 {
+            return rc4(key, &PASSWORD_PADDING) == self.user;
+        }
+        let mut hasher = Md5::new();
+        hasher.update(PASSWORD_PADDING);
+        hasher.update(&self.id0);
+        let mut value = rc4(key, &hasher.finalize());
+        for round in 1..=19u8 {
+            value = rc4(&xor_key(key, round), &value);
+        }
+        self.user.len() >= 16 && value[..16] == self.user[..16]
+    }
+
+    /// Algorithm 7: recovers the user password from `/O` with the owner password.
+    fn user_password_from_owner(&self, owner_password: &[u8]) -> Vec<u8> {
+        let mut hash = Md5::digest(pad_password(owner_password)).to_vec();
+        if self.revision >= 3 {
+            for _ in 0..50 {
+                hash = Md5::digest(&hash).to_vec();
+            }
+        }
+        let key = &hash[..self.key_len];
+        if self.revision == 2 {
+            return rc4(key, &self.owner);
+        }
+        let mut value = self.owner.clone();
+        for round in (0..=19u8).rev() {
+            value = rc4(&xor_key(key, round), &value);
+        }
+        value
+    }
+}
+
+/// Algorithms 2.A / 2.B: validates `password` and unwraps the AES-256 file key.
+fn aes256_file_key(encrypt: &Dictionary, revision: i64, password: &[u8]) -> CoreResult<Option<Vec<u8>>> {
+    let field = |key: &[u8], len: usize, context: &str| -> CoreResult<Vec<u8>> {
+        match dict_bytes(encrypt, key) {
+            Some(bytes) if bytes.len() >= len => Ok(bytes),
+            _ => Err(dict_error(format!("{} must be at least {} bytes", context, len), context)),
+        }
+    };
+    let owner = field(b"O", 48, "Encrypt.O")?;
+    let user = field(b"U", 48, "Encrypt.U")?;
+    let owner_key = field(b"OE", 32, "Encrypt.OE")?;
+    let user_key = field(b"UE", 32, "Encrypt.UE")?;
+    let password = truncate_password(password);
+
+    let (intermediate, wrapped) = if hash_2b(password, &owner[32..40], &user[..48], revision)[..] == owner[..32] {
+        (hash_2b(password, &owner[40..48], &user[..48], revision), owner_key)
+    } else if hash_2b(password, &user[32..40], &[], revision)[..] == user[..32] {
+        (hash_2b(password, &user[40..48], &[], revision), user_key)
+    } else {
+        return Ok(None);
+    };
+
+    let key = Aes256CbcDec::new(intermediate.as_slice().into(), &[0u8; 16].into())
+        .decrypt_padded_vec_mut::<NoPadding>(&wrapped[..32])
+        .ok();
+    Ok(key)
+}
+
+/// Algorithm 2.B (revision 6); revision 5 uses a single SHA-256 round.
+fn hash_2b(password: &[u8], salt: &[u8], udata: &[u8], revision: i64) -> [u8; 32] {
+    let mut k = Sha256::new()
+        .chain_update(password)
+        .chain_update(salt)
+        .chain_update(udata)
+        .finalize()
+        .to_vec();
+
+    if revision >= 6 {
+        let mut round = 0u32;
+        loop {
+            let mut k1 = Vec::with_capacity(64 * (password.len() + k.len() + udata.len()));
+            for _ in 0..64 {
+                k1.extend_from_slice(password);
+                k1.extend_from_slice(&k);
+                k1.extend_from_slice(udata);
+            }
+            let e = Aes128CbcEnc::new(k[..16].into(), k[16..32].into()).encrypt_padded_vec_mut::<NoPadding>(&k1);
+            let selector: u32 = e[..16].iter().map(|byte| u32::from(*byte)).sum();
+            k = match selector % 3 {
+                0 => Sha256::digest(&e).to_vec(),
+                1 => Sha384::digest(&e).to_vec(),
+                _ => Sha512::digest(&e).to_vec(),
+            };
+            round += 1;
+            let last = e.last().copied().map(u32::from).unwrap_or(0);
+            if round >= 64 && last + 32 <= round {
+                break;
+            }
+        }
+    }
+
+    let mut out = [0u8; 32];
+    out.copy_from_slice(&k[..32]);
+    out
+}
+
+fn aes256_encrypt_block_chain(key: &[u8; 32], data: &[u8]) -> Vec<u8> {
+    Aes256CbcEnc::new(key.into(), &[0u8; 16].into()).encrypt_padded_vec_mut::<NoPadding>(data)
+}
+
+fn aes_encrypt(key: &[u8], data: &[u8]) -> CoreResult<Vec<u8>> {
+    let iv = random_bytes::<16>()?;
+    let body = match key.len() {
+        16 => Aes128CbcEnc::new(key.into(), &iv.into()).encrypt_padded_vec_mut::<Pkcs7>(data),
+        _ => Aes256CbcEnc::new(key.into(), &iv.into()).encrypt_padded_vec_mut::<Pkcs7>(data),
+    };
+    let mut out = iv.to_vec();
+    out.extend_from_slice(&body);
+    Ok(out)
+}
+
+fn aes_decrypt(key: &[u8], data: &[u8]) -> Option<Vec<u8>> {
+    if data.is_empty() {
+        return Some(Vec::new());
+    }
+    if data.len() < 16 || !data.len().is_multiple_of(16) {
+        return None;
+    }
+    let (iv, body) = data.split_at(16);
+    match key.len() {
+        16 => Aes128CbcDec::new(key.into(), iv.into()).decrypt_padded_vec_mut::<Pkcs7>(body).ok(),
+        _ => Aes256CbcDec::new(key.into(), iv.into()).decrypt_padded_vec_mut::<Pkcs7>(body).ok(),
+    }
+}
+
+fn rc4(key: &[u8], data: &[u8]) -> Vec<u8> {
+    if key.is_empty() {
+        return data.to_vec();
+    }
+    let mut state: [u8; 256] = std::array::from_fn(|i| i as u8);
+    let mut j = 0u8;
+    for i in 0..256 {
+        j = j.wrapping_add(state[i]).wrapping_add(key[i % key.len()]);
+        state.swap(i, usize::from(j));
+    }
+    let (mut i, mut j) = (0u8, 0u8);
+    data.iter()
+        .map(|byte| {
+            i = i.wrapping_add(1);
+            j = j.wrapping_add(state[usize::from(i)]);
+            state.swap(usize::from(i), usize::from(j));
+            byte ^ state[usize::from(state[usize::from(i)].wrapping_add(state[usize::from(j)]))]
+        })
+        .collect()
+}
+
+fn xor_key(key: &[u8], round: u8) -> Vec<u8> {
+    key.iter().map(|byte| byte ^ round).collect()
+}
+
+fn pad_password(password: &[u8]) -> [u8; 32] {
+    let len = password.len().min(32);
+    let mut padded = [0u8; 32];
+    padded[..len].copy_from_slice(&password[..len]);
+    padded[len..].copy_from_slice(&PASSWORD_PADDING[..32 - len]);
+    padded
+}
+
+fn truncate_password(password: &[u8]) -> &[u8] {
+    &password[..password.len().min(127)]
+}
+
+fn crypt_filter_method(doc: &Document, encrypt: &Dictionary, key: &[u8]) -> CoreResult<CryptMethod> {
+    let filter_name = match encrypt.get(key).ok().and_then(|obj| obj.as_name().ok()) {
+        Some(name) => name.to_vec(),
+        None => return Ok(CryptMethod::Identity),
+    };
+    if filter_name == b"Identity" {
+        return Ok(CryptMethod::Identity);
+    }
+
+    let context = format!("Encrypt.CF.{}", String::from_utf8_lossy(&filter_name));
+    let filter = encrypt
+        .get(b"CF")
+        .ok()
+        .and_then(|obj| resolve_object(doc, obj).ok())
+        .and_then(|obj| obj.as_dict().ok().cloned())
+        .and_then(|cf| cf.get(&filter_name).ok().and_then(|obj| resolve_object(doc, obj).ok()))
+        .and_then(|obj| obj.as_dict().ok().cloned())
+        .ok_or_else(|| dict_error("crypt filter is not defined", &context))?;
+
+    match filter.get(b"CFM").ok().and_then(|obj| obj.as_name().ok()) {
+        None | Some(b"None") => Ok(CryptMethod::Identity),
+        Some(b"V2") => Ok(CryptMethod::Rc4),
+        Some(b"AESV2") => Ok(CryptMethod::AesV2),
+        Some(b"AESV3") => Ok(CryptMethod::AesV3),
+        Some(other) => Err(core_error_with_context(
+            "BW_CRYPT_UNSUPPORTED",
+            format!("unsupported crypt filter method '{}'", String::from_utf8_lossy(other)),
+            Some(context),
+        )),
+    }
+}
+
+/// AES-256 is a PDF 2.0 feature; older headers advertise it via the ADBE
+/// extension level 8 in the catalog.
+fn declare_aes256_support(doc: &mut Document) {
+    if !doc.version.starts_with("1.") {
+        return;
+    }
+    if doc.version.as_str() < "1.7" {
+        doc.version = "1.7".to_string();
+    }
+    if let Ok(catalog) = doc.catalog_mut() {
+        let mut extensions = match catalog.get(b"Extensions") {
+            Ok(Object::Dictionary(dict)) => dict.clone(),
+            _ => Dictionary::new(),
+        };
+        extensions.set(
+            "ADBE",
+            Object::Dictionary(dictionary! {
+                "BaseVersion" => Object::Name(b"1.7".to_vec()),
+                "ExtensionLevel" => Object::Integer(8),
+            }),
+        );
+        catalog.set("Extensions", Object::Dictionary(extensions));
+    }
+}
+
+fn random_bytes<const N: usize>() -> CoreResult<[u8; N]> {
+    let mut buf = [0u8; N];
+    getrandom::getrandom(&mut buf)
+        .map_err(|err| core_error("BW_CRYPT_RANDOM_FAILED", format!("secure random source unavailable: {}", err)))?;
+    Ok(buf)
+}
+
+fn hex_string(bytes: impl Into<Vec<u8>>) -> Object {
+    Object::String(bytes.into(), StringFormat::Hexadecimal)
+}
+
+fn dict_integer(dict: &Dictionary, key: &[u8]) -> Option<i64> {
+    dict.get(key).ok().and_then(|obj| obj.as_i64().ok())
+}
+
+fn dict_bytes(dict: &Dictionary, key: &[u8]) -> Option<Vec<u8>> {
+    dict.get(key).ok().and_then(|obj| obj.as_str().ok()).map(<[u8]>::to_vec)
+}
+
+fn dict_error(message: impl Into<String>, context: &str) -> crate::CoreError {
+    core_error_with_context("BW_CRYPT_DICT_INVALID", message, Some(context.to_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_doc() -> Document {
+        let mut doc = Document::with_version("1.5");
+        let pages_id = doc.new_object_id();
+        let catalog_id = doc.add_object(dictionary! {
+            "Type" => "Catalog",
+            "Pages" => Object::Reference(pages_id),
+        });
+        doc.objects.insert(
+            pages_id,
+            Object::Dictionary(dictionary! {
+                "Type" => "Pages",
+                "Kids" => Object::Array(vec![]),
+                "Count" => Object::Integer(0),
+                "Title" => Object::string_literal("Confidential"),
+            }),
+        );
+        doc.trailer.set(b"Root", Object::Reference(catalog_id));
+        doc
+    }
+
+    fn options(user: &str, owner: &str) -> EncryptionOptions {
+        EncryptionOptions {
+            user_password: user.to_string(),
+            owner_password: owner.to_string(),
+            permissions: Permissions::default(),
+        }
+    }
+
+    fn roundtrip(doc: &mut Document) -> Document {
+        let mut bytes = Vec::new();
+        doc.save_to(&mut bytes).expect("serialize encrypted document");
+        Document::load_mem(&bytes).expect("encrypted output should parse")
+    }
+
+    fn pages_title(doc: &Document) -> Vec<u8> {
+        let pages_id = doc
+            .catalog()
+            .and_then(|catalog| catalog.get(b"Pages"))
+            .and_then(Object::as_reference)
+            .expect("pages reference");
+        doc.get_dictionary(pages_id)
+            .and_then(|pages| pages.get(b"Title"))
+            .and_then(Object::as_str)
+            .expect("pages title")
+            .to_vec()
+    }
+
+    #[test]
+    fn permissions_pack_reserved_and_requested_bits() {
+        let none = Permissions {
+            print: false,
+            copy: false,
+            modify: false,
+            fill_in: false,
+        };
+        assert_eq!(none.bits() as u32, 0xFFFF_F2C0);
+        assert_eq!(Permissions::default().bits() as u32, 0xFFFF_FFFC);
+    }
+
+    #[test]
+    fn aes256_roundtrip_opens_with_user_and_owner_passwords() {
+        let mut doc = sample_doc();
+        let handler = SecurityHandler::aes256(&options("reader", "admin")).expect("create handler");
+        handler.encrypt_document(&mut doc).expect("encrypt");
+        assert_eq!(doc.version, "1.7");
+
+        let loaded = roundtrip(&mut doc);
+        assert_ne!(pages_title(&loaded), b"Confidential");
+
+        for password in ["reader", "admin"] {
+            let mut copy = loaded.clone();
+            let opened = SecurityHandler::open(&copy, password).expect("password should authenticate");
+            opened.decrypt_document(&mut copy).expect("decrypt");
+            assert_eq!(pages_title(&copy), b"Confidential");
+            assert!(copy.trailer.get(b"Encrypt").is_err());
+        }
+
+        let payload = SecurityHandler::open(&loaded, "wrong").expect_err("wrong password should fail");
+        assert_eq!(payload.code, "BW_CRYPT_PASSWORD_INVALID");
+    }
+
+    #[test]
+    fn reopened_handler_reapplies_original_encryption() {
+        let mut doc = sample_doc();
+        SecurityHandler::aes256(&options("", "admin"))
+            .expect("create handler")
+            .encrypt_document(&mut doc)
+            .expect("encrypt");
+        let mut loaded = roundtrip(&mut doc);
+        let original_encrypt = SecurityHandler::open(&loaded, "").expect("empty user password").encrypt;
+
+        let handler = SecurityHandler::open(&loaded, "").expect("empty user password");
+        handler.decrypt_document(&mut loaded).expect("decrypt");
+        handler.encrypt_document(&mut loaded).expect("re-encrypt");
+
+        let mut reloaded = roundtrip(&mut loaded);
+        let reopened = SecurityHandler::open(&reloaded, "").expect("reapplied handler opens");
+        assert_eq!(reopened.encrypt.get(b"U").ok(), original_encrypt.get(b"U").ok());
+        reopened.decrypt_document(&mut reloaded).expect("decrypt");
+        assert_eq!(pages_title(&reloaded), b"Confidential");
+    }
+
+    #[test]
+    fn legacy_rc4_handler_authenticates_user_and_owner() {
+        let params = LegacyParams {
+            revision: 3,
+            key_len: 16,
+            owner: Vec::new(),
+            user: Vec::new(),
+            permissions: Permissions::default().bits(),
+            id0: b"0123456789abcdef".to_vec(),
+            encrypt_metadata: true,
+        };
+        // Build /O (algorithm 3) and /U (algorithm 5) for user "u" / owner "o".
+        let mut owner_hash = Md5::digest(pad_password(b"o")).to_vec();
+        for _ in 0..50 {
+            owner_hash = Md5::digest(&owner_hash).to_vec();
+        }
+        let mut owner = rc4(&owner_hash[..16], &pad_password(b"u"));
+        for round in 1..=19u8 {
+            owner = rc4(&xor_key(&owner_hash[..16], round), &owner);
+        }
+        let params = LegacyParams { owner, ..params };
+        let key = params.file_key(b"u");
+        let mut hasher = Md5::new();
+        hasher.update(PASSWORD_PADDING);
+        hasher.update(&params.id0);
+        let mut user = rc4(&key, &hasher.finalize());
+        for round in 1..=19u8 {
+            user = rc4(&xor_key(&key, round), &user);
+        }
+        user.resize(32, 0);
+        let params = LegacyParams { user, ..params };
+
+        assert_eq!(params.authenticate(b"u").as_deref(), Some(key.as_slice()));
+        assert_eq!(params.authenticate(b"o").as_deref(), Some(key.as_slice()));
+        assert!(params.authenticate(b"x").is_none());
+    }
+
+    /// A one-field form whose objects all sit in an AES-256 encrypted object
+    /// stream, indexed by a cross-reference stream.
+    fn encrypted_object_stream_pdf(handler: &SecurityHandler) -> Vec<u8> {
+        let members = [
+            "<< /Type /Catalog /Pages 2 0 R /AcroForm 4 0 R >>",
+            "<< /Type /Pages /Kids [3 0 R] /Count 1 >>",
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 612 792] /Annots [5 0 R] >>",
+            "<< /Fields [5 0 R] >>",
+            "<< /Type /Annot /Subtype /Widget /FT /Tx /T (Name) /V (Ada) /Rect [10 10 110 30] /P 3 0 R >>",
+        ];
+        let mut index = String::new();
+        let mut body = String::new();
+        for (position, member) in members.iter().enumerate() {
+            index.push_str(&format!("{} {} ", position + 1, body.len()));
+            body.push_str(member);
+            body.push('\n');
+        }
+        let packed = handler.crypt(CryptMethod::AesV3, (6, 0), format!("{}\n{}", index, body).as_bytes(), true);
+        let packed = packed.expect("encrypt object stream");
+
+        let hex = |key: &[u8]| {
+            let bytes = dict_bytes(&handler.encrypt, key).expect("encrypt key");
+            bytes.iter().map(|byte| format!("{:02X}", byte)).collect::<String>()
+        };
+        let mut pdf = b"%PDF-1.7\n".to_vec();
+        let stream_offset = pdf.len();
+        pdf.extend_from_slice(
+            format!("6 0 obj\n<< /Type /ObjStm /N 5 /First {} /Length {} >>\nstream\n", index.len() + 1, packed.len())
+                .as_bytes(),
+        );
+        pdf.extend_from_slice(&packed);
+        pdf.extend_from_slice(b"\nendstream\nendobj\n");
+        let encrypt_offset = pdf.len();
+        pdf.extend_from_slice(
+            format!(
+                "7 0 obj\n<< /Filter /Standard /V 5 /R 6 /Length 256 /CF << /StdCF << /CFM /AESV3 /Length 32 >> >> \
+                 /StmF /StdCF /StrF /StdCF /O <{}> /U <{}> /OE <{}> /UE <{}> /P {} /Perms <{}> >>\nendobj\n",
+                hex(b"O"),
+                hex(b"U"),
+                hex(b"OE"),
+                hex(b"UE"),
+                dict_integer(&handler.encrypt, b"P").expect("P"),
+                hex(b"Perms"),
+            )
+            .as_bytes(),
+        );
+        let xref_offset = pdf.len();
+        let mut entries = vec![0u8, 0, 0, 0, 0, 0xFF, 0xFF];
+        for member in 0..5u16 {
+            entries.extend([2, 0, 0, 0, 6]);
+            entries.extend(member.to_be_bytes());
+        }
+        for offset in [stream_offset, encrypt_offset, xref_offset] {
+            entries.push(1);
+            entries.extend((offset as u32).to_be_bytes());
+            entries.extend([0, 0]);
+        }
+        pdf.extend_from_slice(
+            format!(
+                "8 0 obj\n<< /Type /XRef /Size 9 /W [1 4 2] /Root 1 0 R /Encrypt 7 0 R /Length {} >>\nstream\n",
+                entries.len()
+            )
+            .as_bytes(),
+        );
+        pdf.extend_from_slice(&entries);
+        pdf.extend_from_slice(format!("\nendstream\nendobj\nstartxref\n{}\n%%EOF\n", xref_offset).as_bytes());
+        pdf
+    }
+
+    #[test]
+    fn encrypted_object_streams_are_decrypted_before_unpacking() {
+        let handler = SecurityHandler::aes256(&options("reader", "admin")).expect("create handler");
+        let pdf = encrypted_object_stream_pdf(&handler);
+        let form = crate::FormDocument::load_with_password(&pdf, "reader").expect("opens with the user password");
+        let fields = form.extract().expect("extract");
+        assert_eq!(fields.len(), 1);
+        assert_eq!(fields[0].name, "Name");
+        assert_eq!(fields[0].value, crate::FieldValue::from("Ada"));
+    }
+
+    #[test]
+    fn aes256_requires_owner_password() {
+        let payload = SecurityHandler::aes256(&options("user", "")).expect_err("empty owner password");
+        assert_eq!(payload.code, "BW_CRYPT_OPTIONS_INVALID");
+    }
+}
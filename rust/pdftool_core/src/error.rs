@@ -0,0 +1,73 @@
+// SPDX-License-Identifier: PMPL-1.0-or-later WITH Palimpsest
+
+//! Error type shared by the Rust API, the WASM bindings and the CLI.
+
+use std::fmt;
+
+use serde::Serialize;
+
+/// A failure with a stable machine-readable `BW_*` code, a human-readable
+/// message and optional context (usually the field, object or input involved).
+///
+/// Serialises as `{ code, message, context }`, which is the payload thrown to
+/// JavaScript and printed by the CLI.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CoreError {
+    pub(crate) code: &'static str,
+    pub(crate) message: String,
+    pub(crate) context: Option<String>,
+}
+
+pub type CoreResult<T> = Result<T, CoreError>;
+
+impl CoreError {
+    /// Stable `BW_*` error code.
+    pub fn code(&self) -> &'static str {
+        self.code
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn context(&self) -> Option<&str> {
+        self.context.as_deref()
+    }
+}
+
+impl fmt::Display for CoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code, self.message)?;
+        if let Some(context) = &self.context {
+            write!(f, " ({})", context)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for CoreError {}
+
+pub(crate) fn core_error(code: &'static str, message: impl Into<String>) -> CoreError {
+    core_error_with_context(code, message, None)
+}
+
+pub(crate) fn core_error_with_context(code: &'static str, message: impl Into<String>, context: Option<String>) -> CoreError {
+    CoreError {
+        code,
+        message: message.into(),
+        context,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_includes_code_and_context() {
+        let error = core_error_with_context("BW_PDF_INVALID", "bad header", Some("Document::load_mem".into()));
+        assert_eq!(error.to_string(), "BW_PDF_INVALID: bad header (Document::load_mem)");
+        assert_eq!(error.code(), "BW_PDF_INVALID");
+        assert_eq!(core_error("BW_PDF_EMPTY", "empty PDF payload").context(), None);
+    }
+}
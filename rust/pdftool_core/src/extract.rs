@@ -0,0 +1,77 @@
+// SPDX-License-Identifier: PMPL-1.0-or-later WITH Palimpsest
+
+//! Reads the current values of terminal form fields.
+
+use lopdf::{decode_text_string, Document, Object};
+use serde::Serialize;
+
+use crate::{acroform_field_roots, object_to_name, CoreResult, FieldIndex, FieldValue};
+
+/// One terminal field and the value it currently holds.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FieldSnapshot {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub field_type: String,
+    pub value: FieldValue,
+}
+
+#[cfg(any(feature = "wasm", test))]
+pub(crate) fn extract_fields_impl(pdf_data: &[u8], password: Option<&str>) -> CoreResult<Vec<FieldSnapshot>> {
+    crate::FormDocument::load_with(pdf_data, password)?.extract()
+}
+
+pub(crate) fn extract_document_fields(doc: &Document, index: &FieldIndex) -> CoreResult<Vec<FieldSnapshot>> {
+    acroform_field_roots(doc)?;
+
+    Ok(index
+        .terminal_fields()
+        .filter_map(|field| {
+            let name = field.full_name.clone()?;
+            let value = index
+                .inherited_value(doc, field)
+                .map(|value| object_to_field_value(&value))
+                .unwrap_or(FieldValue::Clear);
+            Some(FieldSnapshot {
+                name,
+                field_type: field.field_type.clone().unwrap_or_default(),
+                value,
+            })
+        })
+        .collect())
+}
+
+fn object_to_field_value(obj: &Object) -> FieldValue {
+    match obj {
+        Object::String(..) => decode_text_string(obj).map(FieldValue::Text).unwrap_or(FieldValue::Clear),
+        Object::Name(_) => object_to_name(obj).map(FieldValue::Text).unwrap_or(FieldValue::Clear),
+        Object::Integer(value) => FieldValue::Number(*value as f64),
+        Object::Real(value) => FieldValue::Number(f64::from(*value)),
+        Object::Array(items) => FieldValue::List(items.iter().filter_map(|item| decode_text_string(item).ok()).collect()),
+        _ => FieldValue::Clear,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::make_fixture_pdf;
+    use crate::fill_blocks_impl;
+    use std::collections::HashMap;
+
+    #[test]
+    fn extract_reports_filled_values_for_terminal_fields() {
+        let mut values = HashMap::new();
+        values.insert("Name".to_string(), FieldValue::from("Ada Lovelace"));
+        values.insert("Choice".to_string(), FieldValue::from("B"));
+        let filled = fill_blocks_impl(&make_fixture_pdf(), values).expect("fixture fills");
+
+        let fields = extract_fields_impl(&filled, None).expect("extract succeeds");
+        let value_of = |name: &str| fields.iter().find(|field| field.name == name).map(|field| field.value.clone());
+
+        assert_eq!(value_of("Name"), Some(FieldValue::Text("Ada Lovelace".into())));
+        assert_eq!(value_of("Choice"), Some(FieldValue::Text("B".into())));
+        assert_eq!(value_of("Consent"), Some(FieldValue::Text("Off".into())));
+        assert!(fields.iter().all(|field| !field.field_type.is_empty()));
+    }
+}
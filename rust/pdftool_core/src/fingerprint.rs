@@ -0,0 +1,295 @@
+// SPDX-License-Identifier: PMPL-1.0-or-later WITH Palimpsest
+
+//! Form fingerprints, for recognising a known template.
+//!
+//! A fingerprint is built from the form's structure only: the page count and,
+//! for every terminal field, its full name, kind, page, first widget rectangle
+//! rounded to whole points, its option export values (choice fields) or
+//! on-states (buttons), and its `/TU` label. Field values, appearance
+//! streams, object numbering and the byte layout of the file play no part, so
+//! a filled or re-saved copy hashes like the blank original.
+//! [`FormFingerprint::similarity`] scores two fingerprints so that a revision
+//! of a form (a moved box, an added field) is still recognised.
+//!
+//! The hash depends on what goes into it, so its inputs are versioned by
+//! [`FINGERPRINT_VERSION`]. Version 2 added the `/TU` label, which changed
+//! every hash; fingerprints and mapping `fingerprint` hashes stored from
+//! version 1 must be regenerated.
+
+use std::collections::{HashMap, HashSet};
+
+use lopdf::{Document, ObjectId};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{dict_text, field_option_exports, widget_on_state, Budget, CoreResult, FieldIndex, Limits};
+
+/// Rectangles whose coordinates all differ by at most this many points are
+/// considered in the same place.
+const SAME_PLACE: f64 = 2.0;
+/// Rectangles moved by this many points or more no longer count as close.
+const FAR_APART: f64 = 50.0;
+
+/// The fingerprint format this build computes: 1 hashed names, kinds,
+/// pages, rectangles and options; 2 also hashes `/TU` labels.
+pub const FINGERPRINT_VERSION: u32 = 2;
+
+/// Structural identity of a form.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FormFingerprint {
+    /// [`FINGERPRINT_VERSION`] the hash was computed under; stored
+    /// fingerprints without one are version 1.
+    #[serde(default = "first_version")]
+    pub version: u32,
+    /// Lowercase hex SHA-256 of the normalised structure.
+    pub hash: String,
+    pub pages: usize,
+    /// Terminal fields, sorted by name.
+    pub fields: Vec<FieldSignature>,
+}
+
+/// The normalised structure of one terminal field.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FieldSignature {
+    pub name: String,
+    /// `text`, `checkbox`, `radio`, `pushbutton`, `combo`, `list`,
+    /// `signature` or `unknown`.
+    pub kind: String,
+    /// 1-based page of the first placed widget; 0 when none is on a page.
+    pub page: u32,
+    /// `[x, y, width, height]` of that widget, rounded to whole points.
+    pub rect: Option<[i32; 4]>,
+    /// Choice export values or button on-states, in document order.
+    pub options: Vec<String>,
+    /// The user-facing name (`/TU`), when the form gives one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+}
+
+fn first_version() -> u32 {
+    1
+}
+
+impl FormFingerprint {
+    /// Score in `0.0..=1.0`: 1 for the same structure, falling as fields are
+    /// added, removed, retyped or moved. Fields are paired by name.
+    pub fn similarity(&self, other: &FormFingerprint) -> f64 {
+        if self.hash == other.hash {
+            return 1.0;
+        }
+        let theirs: HashMap<&str, &FieldSignature> =
+            other.fields.iter().map(|field| (field.name.as_str(), field)).collect();
+        let mut paired = 0;
+        let mut score = 0.0;
+        for field in &self.fields {
+            if let Some(their) = theirs.get(field.name.as_str()) {
+                paired += 1;
+                score += field.resemblance(their);
+            }
+        }
+        let union = self.fields.len() + other.fields.len() - paired;
+        let fields = if union == 0 { 1.0 } else { score / union as f64 };
+        let pages = match self.pages.max(other.pages) {
+            0 => 1.0,
+            most => self.pages.min(other.pages) as f64 / most as f64,
+        };
+        (9.0 * fields + pages) / 10.0
+    }
+}
+
+impl FieldSignature {
+    /// How alike two fields of the same name are: the name counts 0.4, the
+    /// kind 0.2, the position 0.3 and the options 0.1.
+    fn resemblance(&self, other: &FieldSignature) -> f64 {
+        let kind = if self.kind == other.kind { 1.0 } else { 0.0 };
+        let position = self.closeness(other);
+        let options = if self.options == other.options {
+            1.0
+        } else {
+            let ours: HashSet<&String> = self.options.iter().collect();
+            let theirs: HashSet<&String> = other.options.iter().collect();
+            ours.intersection(&theirs).count() as f64 / ours.union(&theirs).count() as f64
+        };
+        (4.0 + 2.0 * kind + 3.0 * position + options) / 10.0
+    }
+
+    /// 1 when both fields sit in the same place (within a couple of points),
+    /// falling to 0 once they are far apart or on different pages.
+    pub(crate) fn closeness(&self, other: &FieldSignature) -> f64 {
+        match (self.rect, other.rect) {
+            _ if self.page != other.page => 0.0,
+            (None, None) => 1.0,
+            (Some(ours), Some(theirs)) => {
+                let moved = ours
+                    .iter()
+                    .zip(theirs)
+                    .map(|(ours, theirs)| f64::from((ours - theirs).abs()))
+                    .fold(0.0, f64::max);
+                ((FAR_APART - moved) / (FAR_APART - SAME_PLACE)).clamp(0.0, 1.0)
+            }
+            _ => 0.0,
+        }
+    }
+}
+
+pub(crate) fn fingerprint_document(doc: &Document, index: &FieldIndex, limits: &Limits) -> CoreResult<FormFingerprint> {
+    let mut budget = Budget::new(limits);
+    let mut placed: HashMap<ObjectId, (u32, [i32; 4])> = HashMap::new();
+    for page in index.pages.iter().flatten() {
+        for widget in &page.widgets {
+            if let (Some(id), Some(rect)) = (widget.id(), widget.rect) {
+                let rounded = [rect.0, rect.1, rect.2, rect.3].map(|value| value.round() as i32);
+                placed.entry(id).or_insert((page.number, rounded));
+            }
+        }
+    }
+
+    let mut fields = Vec::new();
+    for field in index.terminal_fields() {
+        budget.tick("fingerprint")?;
+        let Some(name) = &field.full_name else {
+            continue;
+        };
+        let location = field.widget_ids.iter().find_map(|id| placed.get(id));
+        let options = match field.field_type.as_deref() {
+            Some("Ch") => field_option_exports(doc, field.id),
+            Some("Btn") => {
+                let mut states: Vec<String> = Vec::new();
+                for state in field.widget_ids.iter().filter_map(|id| widget_on_state(doc, *id)) {
+                    let state = String::from_utf8_lossy(&state).into_owned();
+                    if !states.contains(&state) {
+                        states.push(state);
+                    }
+                }
+                states
+            }
+            _ => Vec::new(),
+        };
+        fields.push(FieldSignature {
+            name: name.clone(),
+            kind: field.kind().to_owned(),
+            page: location.map_or(0, |(page, _)| *page),
+            rect: location.map(|(_, rect)| *rect),
+            options,
+            label: doc.get_dictionary(field.id).ok().and_then(|dict| dict_text(doc, dict, b"TU")),
+        });
+    }
+    fields.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let pages = index.pages.len();
+    Ok(FormFingerprint {
+        version: FINGERPRINT_VERSION,
+        hash: structure_hash(pages, &fields),
+        pages,
+        fields,
+    })
+}
+
+/// SHA-256 over a length-prefixed encoding of the structure, so no choice of
+/// names can make two different structures encode alike.
+fn structure_hash(pages: usize, fields: &[FieldSignature]) -> String {
+    let mut hasher = Sha256::new();
+    let mut put = |text: &str| {
+        hasher.update((text.len() as u64).to_be_bytes());
+        hasher.update(text.as_bytes());
+    };
+    put(&pages.to_string());
+    for field in fields {
+        put(&field.name);
+        put(&field.kind);
+        put(&field.page.to_string());
+        match field.rect {
+            Some(rect) => put(&format!("{} {} {} {}", rect[0], rect[1], rect[2], rect[3])),
+            None => put("-"),
+        }
+        put(&field.options.len().to_string());
+        for option in &field.options {
+            put(option);
+        }
+        put(field.label.as_deref().unwrap_or(""));
+    }
+    hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::make_fixture_pdf;
+    use crate::{FieldValue, FormDocument};
+    use lopdf::Object;
+
+    /// The fixture with `edit` applied to the object of the field named `name`.
+    fn revised(name: &str, edit: impl FnOnce(&mut lopdf::Dictionary)) -> Vec<u8> {
+        let mut doc = Document::load_mem(&make_fixture_pdf()).expect("fixture parses");
+        let id = doc
+            .objects
+            .iter()
+            .find(|(_, object)| {
+                object
+                    .as_dict()
+                    .ok()
+                    .and_then(|dict| dict.get(b"T").ok())
+                    .and_then(|title| title.as_str().ok())
+                    == Some(name.as_bytes())
+            })
+            .map(|(id, _)| *id)
+            .expect("field present");
+        edit(doc.get_dictionary_mut(id).expect("field dictionary"));
+        let mut pdf = Vec::new();
+        doc.save_to(&mut pdf).expect("save");
+        pdf
+    }
+
+    fn fingerprint(pdf: &[u8]) -> FormFingerprint {
+        FormDocument::load(pdf).expect("loads").fingerprint().expect("fingerprint")
+    }
+
+    #[test]
+    fn values_and_byte_layout_do_not_change_the_hash() {
+        let blank = fingerprint(&make_fixture_pdf());
+        assert_eq!(blank.pages, 1);
+        assert_eq!(
+            blank.fields.iter().map(|field| (field.name.as_str(), field.kind.as_str())).collect::<Vec<_>>(),
+            [("Choice", "radio"), ("Consent", "checkbox"), ("Name", "text")]
+        );
+        assert_eq!(blank.fields[0].options, ["A", "B"]);
+
+        let mut form = FormDocument::load(&make_fixture_pdf()).expect("loads");
+        let values = HashMap::from([
+            ("Name".to_string(), FieldValue::from("Ada")),
+            ("Consent".to_string(), FieldValue::Bool(true)),
+        ]);
+        form.fill(&values).expect("fill");
+        let filled = fingerprint(&form.save().expect("save"));
+        assert_eq!(filled, blank);
+        assert_eq!(blank.similarity(&filled), 1.0);
+
+        assert_eq!(blank.version, FINGERPRINT_VERSION);
+        let mut stored = serde_json::to_value(&blank).expect("serialize");
+        stored.as_object_mut().expect("object").remove("version");
+        let stored: FormFingerprint = serde_json::from_value(stored).expect("version 1 fingerprint reads");
+        assert_eq!(stored.version, 1, "fingerprints stored before versioning are version 1");
+    }
+
+    #[test]
+    fn revisions_score_by_how_much_changed() {
+        let blank = fingerprint(&make_fixture_pdf());
+        let nudged = fingerprint(&revised("Name", |dict| {
+            dict.set("Rect", vec![51.into(), 701.into(), 251.into(), 725.into()]);
+        }));
+        let moved = fingerprint(&revised("Name", |dict| {
+            dict.set("Rect", vec![300.into(), 100.into(), 500.into(), 124.into()]);
+        }));
+        let renamed = fingerprint(&revised("Name", |dict| {
+            dict.set("T", Object::string_literal("FullName"));
+        }));
+        assert_ne!(nudged.hash, blank.hash);
+
+        let nudged = blank.similarity(&nudged);
+        let moved = blank.similarity(&moved);
+        let renamed = blank.similarity(&renamed);
+        assert_eq!(nudged, 1.0, "rounding and tolerance absorb a one-point shift");
+        assert!(moved < nudged && renamed < moved, "{} {} {}", nudged, moved, renamed);
+        assert!(renamed > 0.5, "two of three fields still agree: {}", renamed);
+    }
+}
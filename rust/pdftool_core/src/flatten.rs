@@ -0,0 +1,345 @@
+// SPDX-License-Identifier: PMPL-1.0-or-later WITH Palimpsest
+
+//! Flattening: paints widget appearances into page content and removes the
+//! interactive form.
+
+use std::collections::HashSet;
+
+use lopdf::{dictionary, Dictionary, Document, Object, ObjectId, Stream};
+
+use crate::appearance::{text_appearance, FormDefaults};
+use crate::{
+    core_error_with_context, get_dict_mut, is_widget_dict, object_as_reference, rect_from_object, resolve_object,
+    root_catalog_id, Budget, CoreResult, FieldIndex, Limits,
+};
+
+const ANNOT_FLAG_HIDDEN: i64 = 1 << 1;
+
+/// An appearance stream and the widget rectangle it is painted into.
+type Placement = (ObjectId, (f32, f32, f32, f32));
+
+#[cfg(any(feature = "wasm", test))]
+pub(crate) fn flatten_impl(pdf_data: &[u8], password: Option<&str>) -> CoreResult<Vec<u8>> {
+    let mut document = crate::FormDocument::load_with(pdf_data, password)?;
+    document.flatten()?;
+    document.save_with_code("BW_FLATTEN_SAVE_FAILED")
+}
+
+/// Flattens every widget on every page; returns the number of widgets removed.
+pub(crate) fn flatten_document(doc: &mut Document, index: &FieldIndex, limits: &Limits) -> CoreResult<usize> {
+    let mut budget = Budget::new(limits);
+    let catalog_id = root_catalog_id(doc)?;
+    let defaults = FormDefaults::from_document(doc);
+
+    let mut flattened = 0usize;
+    for page in index.pages.iter().filter_map(|page| page.as_ref().ok()) {
+        if page.widgets.is_empty() {
+            continue;
+        }
+        let kept: Vec<Object> = match doc
+            .get_dictionary(page.page_id)
+            .ok()
+            .and_then(|dict| dict.get(b"Annots").ok())
+            .and_then(|annots| resolve_object(doc, annots).ok())
+        {
+            Some(Object::Array(annots)) => annots
+                .into_iter()
+                .filter(|annot| !matches!(resolve_object(doc, annot), Ok(Object::Dictionary(dict)) if is_widget_dict(&dict)))
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        let mut placements = Vec::new();
+        for widget in &page.widgets {
+            budget.tick("flatten")?;
+            flattened += 1;
+            let Ok(Object::Dictionary(dict)) = resolve_object(doc, &widget.annot) else {
+                continue;
+            };
+            let hidden = dict.get(b"F").and_then(Object::as_i64).unwrap_or(0) & ANNOT_FLAG_HIDDEN != 0;
+            let Some(rect) = widget.rect else {
+                continue;
+            };
+            if hidden {
+                continue;
+            }
+            let appearance = match widget_appearance(doc, &dict) {
+                Some(id) => Some(id),
+                None => widget.id().and_then(|id| generate_appearance(doc, index, id, rect, &defaults)),
+            };
+            if let Some(appearance_id) = appearance {
+                placements.push((appearance_id, rect));
+            }
+        }
+
+        if !placements.is_empty() {
+            paint_appearances(doc, page.page_id, page.number, &placements)?;
+        }
+
+        let page_dict = get_dict_mut(doc, page.page_id, "BW_PDF_PAGE_INVALID", &format!("page {}", page.number))?;
+        if kept.is_empty() {
+            page_dict.remove(b"Annots");
+        } else {
+            page_dict.set("Annots", Object::Array(kept));
+        }
+    }
+
+    let catalog = get_dict_mut(doc, catalog_id, "BW_FORM_CATALOG_INVALID", "catalog dictionary")?;
+    catalog.remove(b"AcroForm");
+    doc.prune_objects();
+    Ok(flattened)
+}
+
+/// The normal appearance selected by `/AS`, promoted to an indirect object.
+fn widget_appearance(doc: &mut Document, widget: &Dictionary) -> Option<ObjectId> {
+    let normal = {
+        let ap = resolve_object(doc, widget.get(b"AP").ok()?).ok()?;
+        ap.as_dict().ok()?.get(b"N").ok()?.clone()
+    };
+    let selected = match &normal {
+        Object::Reference(id) => match doc.get_object(*id).ok()? {
+            Object::Stream(_) => return Some(*id),
+            Object::Dictionary(states) => select_state(widget, states)?,
+            _ => return None,
+        },
+        Object::Stream(stream) => return Some(doc.add_object(Object::Stream(stream.clone()))),
+        Object::Dictionary(states) => select_state(widget, states)?,
+        _ => return None,
+    };
+    match selected {
+        Object::Reference(id) if matches!(doc.get_object(id), Ok(Object::Stream(_))) => Some(id),
+        Object::Stream(stream) => Some(doc.add_object(Object::Stream(stream))),
+        _ => None,
+    }
+}
+
+fn select_state(widget: &Dictionary, states: &Dictionary) -> Option<Object> {
+    let state = widget.get(b"AS").ok()?.as_name().ok()?;
+    states.get(state).ok().cloned()
+}
+
+fn generate_appearance(
+    doc: &mut Document,
+    index: &FieldIndex,
+    widget_id: ObjectId,
+    rect: (f32, f32, f32, f32),
+    defaults: &FormDefaults,
+) -> Option<ObjectId> {
+    let field = index.field_for_widget(widget_id)?;
+    let value = match index.inherited_value(doc, field)? {
+        value @ Object::String(..) => lopdf::decode_text_string(&value).ok()?,
+        Object::Array(items) => items
+            .iter()
+            .filter_map(|item| lopdf::decode_text_string(item).ok())
+            .collect::<Vec<_>>()
+            .join(", "),
+        _ => return None,
+    };
+    match field.field_type.as_deref() {
+        Some("Tx") | Some("Ch") if !value.is_empty() => Some(text_appearance(doc, field, &value, rect.2, rect.3, defaults)),
+        _ => None,
+    }
+}
+
+/// Maps the appearance's transformed bounding box onto the widget rectangle
+/// (ISO 32000-2, 12.5.5) and appends the drawing to the page content.
+fn paint_appearances(
+    doc: &mut Document,
+    page_id: ObjectId,
+    page_number: u32,
+    placements: &[Placement],
+) -> CoreResult<()> {
+    let mut xobjects = Vec::new();
+    let mut drawing = String::from("Q\n");
+    for (index, (appearance_id, (x, y, width, height))) in placements.iter().enumerate() {
+        let Ok(Object::Stream(stream)) = doc.get_object_mut(*appearance_id) else {
+            continue;
+        };
+        stream.dict.set("Type", "XObject");
+        stream.dict.set("Subtype", "Form");
+        let (bx0, by0, bx1, by1) = transformed_bbox(&stream.dict);
+        let sx = if bx1 - bx0 > 0.0 { width / (bx1 - bx0) } else { 1.0 };
+        let sy = if by1 - by0 > 0.0 { height / (by1 - by0) } else { 1.0 };
+        let name = format!("BWFlat{}_{}", page_number, index + 1);
+        drawing.push_str(&format!(
+            "q {} 0 0 {} {} {} cm /{} Do Q\n",
+            sx,
+            sy,
+            x - bx0 * sx,
+            y - by0 * sy,
+            name
+        ));
+        xobjects.push((name, *appearance_id));
+    }
+
+    register_xobjects(doc, page_id, page_number, xobjects)?;
+
+    let save_id = doc.add_object(Stream::new(dictionary! {}, b"q\n".to_vec()));
+    let draw_id = doc.add_object(Stream::new(dictionary! {}, drawing.into_bytes()));
+    let page = get_dict_mut(doc, page_id, "BW_PDF_PAGE_INVALID", &format!("page {}", page_number))?;
+    let mut contents = vec![Object::Reference(save_id)];
+    match page.get(b"Contents") {
+        Ok(Object::Array(items)) => contents.extend(items.iter().cloned()),
+        Ok(other) => contents.push(other.clone()),
+        Err(_) => {}
+    }
+    contents.push(Object::Reference(draw_id));
+    page.set("Contents", Object::Array(contents));
+    Ok(())
+}
+
+fn transformed_bbox(dict: &Dictionary) -> (f32, f32, f32, f32) {
+    let (x, y, width, height) = dict
+        .get(b"BBox")
+        .ok()
+        .and_then(rect_from_object)
+        .unwrap_or((0.0, 0.0, 0.0, 0.0));
+    let matrix: Vec<f32> = match dict.get(b"Matrix") {
+        Ok(Object::Array(values)) if values.len() == 6 => values.iter().filter_map(|value| value.as_float().ok()).collect(),
+        _ => Vec::new(),
+    };
+    if matrix.len() != 6 {
+        return (x, y, x + width, y + height);
+    }
+    let corners = [(x, y), (x + width, y), (x, y + height), (x + width, y + height)];
+    let mapped: Vec<(f32, f32)> = corners
+        .iter()
+        .map(|(cx, cy)| {
+            (
+                matrix[0] * cx + matrix[2] * cy + matrix[4],
+                matrix[1] * cx + matrix[3] * cy + matrix[5],
+            )
+        })
+        .collect();
+    let xs = mapped.iter().map(|point| point.0);
+    let ys = mapped.iter().map(|point| point.1);
+    (
+        xs.clone().fold(f32::INFINITY, f32::min),
+        ys.clone().fold(f32::INFINITY, f32::min),
+        xs.fold(f32::NEG_INFINITY, f32::max),
+        ys.fold(f32::NEG_INFINITY, f32::max),
+    )
+}
+
+/// Adds the flattened appearances to the page's `/Resources /XObject`.
+fn register_xobjects(
+    doc: &mut Document,
+    page_id: ObjectId,
+    page_number: u32,
+    xobjects: Vec<(String, ObjectId)>,
+) -> CoreResult<()> {
+    let context = format!("page {}", page_number);
+    let xobject_id = match page_resources_mut(doc, page_id, &context)?.get(b"XObject") {
+        Ok(Object::Reference(id)) => Some(*id),
+        _ => None,
+    };
+    let target = match xobject_id {
+        Some(id) => get_dict_mut(doc, id, "BW_PDF_PAGE_INVALID", &context)?,
+        None => {
+            let resources = page_resources_mut(doc, page_id, &context)?;
+            if !matches!(resources.get(b"XObject"), Ok(Object::Dictionary(_))) {
+                resources.set("XObject", Dictionary::new());
+            }
+            resources
+                .get_mut(b"XObject")
+                .and_then(Object::as_dict_mut)
+                .map_err(|err| core_error_with_context("BW_PDF_PAGE_INVALID", err.to_string(), Some(context.clone())))?
+        }
+    };
+    for (name, id) in xobjects {
+        target.set(name, Object::Reference(id));
+    }
+    Ok(())
+}
+
+/// The page's own resource dictionary, copying inherited resources onto the
+/// page first so additions do not leak to sibling pages.
+fn page_resources_mut<'a>(doc: &'a mut Document, page_id: ObjectId, context: &str) -> CoreResult<&'a mut Dictionary> {
+    let resources_id = match doc.get_dictionary(page_id).map(|page| page.get(b"Resources")) {
+        Ok(Ok(Object::Reference(id))) => Some(*id),
+        Ok(Ok(_)) => None,
+        _ => {
+            let inherited = inherited_resources(doc, page_id);
+            get_dict_mut(doc, page_id, "BW_PDF_PAGE_INVALID", context)?.set("Resources", Object::Dictionary(inherited));
+            None
+        }
+    };
+    match resources_id {
+        Some(id) => get_dict_mut(doc, id, "BW_PDF_PAGE_INVALID", context),
+        None => get_dict_mut(doc, page_id, "BW_PDF_PAGE_INVALID", context)?
+            .get_mut(b"Resources")
+            .and_then(Object::as_dict_mut)
+            .map_err(|err| core_error_with_context("BW_PDF_PAGE_INVALID", err.to_string(), Some(context.to_owned()))),
+    }
+}
+
+/// The nearest `/Resources` of the page's `/Pages` ancestors, inline or
+/// referenced, or an empty dictionary.
+fn inherited_resources(doc: &Document, page_id: ObjectId) -> Dictionary {
+    let mut seen = HashSet::new();
+    let mut parent =
+        doc.get_dictionary(page_id).ok().and_then(|page| page.get(b"Parent").ok()).and_then(object_as_reference);
+    while let Some(id) = parent.filter(|id| seen.insert(*id)) {
+        let Ok(node) = doc.get_dictionary(id) else {
+            break;
+        };
+        if let Some(resources) = node.get(b"Resources").ok().and_then(|object| resolve_object(doc, object).ok()) {
+            return resources.as_dict().cloned().unwrap_or_default();
+        }
+        parent = node.get(b"Parent").ok().and_then(object_as_reference);
+    }
+    Dictionary::new()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::make_fixture_pdf;
+    use crate::{fill_blocks_impl, FieldValue};
+    use std::collections::HashMap;
+
+    #[test]
+    fn flatten_removes_form_and_paints_values() {
+        let mut values = HashMap::new();
+        values.insert("Name".to_string(), FieldValue::from("Ada Lovelace"));
+        let filled = fill_blocks_impl(&make_fixture_pdf(), values).expect("fixture fills");
+
+        let flattened = flatten_impl(&filled, None).expect("flatten succeeds");
+        let doc = Document::load_mem(&flattened).expect("flattened output parses");
+
+        let catalog = doc.catalog().expect("catalog");
+        assert!(catalog.get(b"AcroForm").is_err(), "AcroForm should be removed");
+        let (_, page_id) = doc.get_pages().into_iter().next().expect("one page");
+        let page = doc.get_dictionary(page_id).expect("page dictionary");
+        assert!(page.get(b"Annots").is_err(), "widget annotations should be removed");
+
+        let content = String::from_utf8_lossy(&doc.get_page_content(page_id).expect("page content")).into_owned();
+        assert!(content.contains("/BWFlat1_1 Do"), "flattened appearance should be painted: {}", content);
+    }
+    #[test]
+    fn flatten_keeps_resources_inherited_inline_from_the_page_tree() {
+        let mut doc = Document::load_mem(&make_fixture_pdf()).expect("fixture parses");
+        let (_, page_id) = doc.get_pages().into_iter().next().expect("one page");
+        let page = doc.get_dictionary_mut(page_id).expect("page dictionary");
+        page.remove(b"Resources");
+        let pages_id = page.get(b"Parent").and_then(Object::as_reference).expect("parent");
+        let font_id = doc.add_object(dictionary! { "Type" => "Font", "Subtype" => "Type1", "BaseFont" => "Courier" });
+        doc.get_dictionary_mut(pages_id).expect("pages dictionary").set(
+            "Resources",
+            dictionary! { "Font" => dictionary! { "F9" => Object::Reference(font_id) } },
+        );
+        let mut inherited = Vec::new();
+        doc.save_to(&mut inherited).expect("save");
+
+        let mut values = HashMap::new();
+        values.insert("Name".to_string(), FieldValue::from("Ada Lovelace"));
+        let filled = fill_blocks_impl(&inherited, values).expect("fills");
+        let doc = Document::load_mem(&flatten_impl(&filled, None).expect("flatten succeeds")).expect("parses");
+        let (_, page_id) = doc.get_pages().into_iter().next().expect("one page");
+        let resources = doc.get_dictionary(page_id).and_then(|page| page.get(b"Resources")).expect("page resources");
+        let resources = resolve_object(&doc, resources).expect("resources resolve");
+        let resources = resources.as_dict().expect("resources dictionary");
+        assert!(resources.get(b"Font").and_then(Object::as_dict).is_ok_and(|fonts| fonts.has(b"F9")));
+        assert!(resources.get(b"XObject").and_then(Object::as_dict).is_ok_and(|xobjects| xobjects.has(b"BWFlat1_1")));
+    }
+}
@@ -0,0 +1,462 @@
+// SPDX-License-Identifier: PMPL-1.0-or-later WITH Palimpsest
+
+//! One-pass index of the field tree and page widgets.
+//!
+//! The tree under `AcroForm.Fields` is walked once, top-down, carrying the
+//! inheritable attributes (`FT`, `Ff`, `DA`, `Q`, `DV`) and the dotted name
+//! prefix along, so no per-field `/Parent` walks are needed afterwards.
+//! Detection, fill and extraction all read from the same index.
+
+use std::collections::{HashMap, HashSet};
+
+use lopdf::{Dictionary, Document, Object, ObjectId};
+
+use crate::{
+    acroform_field_roots, core_error_with_context, is_widget_dict, object_as_reference, object_to_name,
+    object_to_text, rect_from_object, resolve_object, widget_label, Budget, CoreResult, Limits,
+};
+
+/// Follows a reference without cloning, unlike `resolve_object`.
+fn deref<'a>(doc: &'a Document, obj: &'a Object) -> Option<&'a Object> {
+    match obj {
+        Object::Reference(id) => doc.get_object(*id).ok(),
+        other => Some(other),
+    }
+}
+
+/// A field node (a dictionary with `/T`, or a root of `AcroForm.Fields`) with
+/// its inherited attributes resolved.
+#[derive(Debug, Clone)]
+pub(crate) struct FieldDescriptor {
+    pub id: ObjectId,
+    pub parent: Option<usize>,
+    pub partial_name: Option<String>,
+    pub full_name: Option<String>,
+    pub field_type: Option<String>,
+    pub flags: i64,
+    pub da: Option<String>,
+    pub quadding: Option<i64>,
+    pub default_value: Option<Object>,
+    /// Widget annotations of this field: itself when merged, plus kid widgets.
+    pub widget_ids: Vec<ObjectId>,
+    /// No kid is a field of its own.
+    pub terminal: bool,
+}
+
+impl FieldDescriptor {
+    /// The field type refined by its flags: `text`, `checkbox`, `radio`,
+    /// `pushbutton`, `combo`, `list`, `signature`, or `unknown`.
+    pub(crate) fn kind(&self) -> &'static str {
+        match self.field_type.as_deref() {
+            Some("Tx") => "text",
+            Some("Btn") if self.flags & (1 << 16) != 0 => "pushbutton",
+            Some("Btn") if self.flags & (1 << 15) != 0 => "radio",
+            Some("Btn") => "checkbox",
+            Some("Ch") if self.flags & (1 << 17) != 0 => "combo",
+            Some("Ch") => "list",
+            Some("Sig") => "signature",
+            _ => "unknown",
+        }
+    }
+}
+
+/// A widget annotation listed in a page's `/Annots`.
+#[derive(Debug, Clone)]
+pub(crate) struct PageWidget {
+    /// The `/Annots` entry: a reference, or the dictionary itself for widgets
+    /// written inline.
+    pub annot: Object,
+    pub rect: Option<(f32, f32, f32, f32)>,
+    pub label: String,
+}
+
+impl PageWidget {
+    pub(crate) fn id(&self) -> Option<ObjectId> {
+        object_as_reference(&self.annot)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct PageWidgets {
+    pub number: u32,
+    pub page_id: ObjectId,
+    pub widgets: Vec<PageWidget>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct FieldIndex {
+    /// Field nodes in tree order (parents before their kids).
+    pub fields: Vec<FieldDescriptor>,
+    widget_field: HashMap<ObjectId, usize>,
+    /// Pages in document order; a page that cannot be read keeps its error so
+    /// callers that need every page (detection) can report it.
+    pub pages: Vec<CoreResult<PageWidgets>>,
+}
+
+/// Inheritable state handed from a field to its kids.
+#[derive(Clone, Default)]
+struct Inherited {
+    full_name: Option<String>,
+    field_type: Option<String>,
+    flags: Option<i64>,
+    da: Option<String>,
+    quadding: Option<i64>,
+    default_value: Option<Object>,
+}
+
+impl FieldIndex {
+    /// Indexes `doc`. A document without an interactive form yields an index
+    /// with pages but no fields; a tree deeper or larger than `limits` allow
+    /// fails with `BW_LIMIT_DEPTH` or `BW_LIMIT_FIELDS`.
+    pub(crate) fn build(doc: &Document, limits: &Limits) -> CoreResult<Self> {
+        let mut budget = Budget::new(limits);
+        let mut index = FieldIndex::default();
+        if let Ok(roots) = acroform_field_roots(doc) {
+            let mut seen = HashSet::new();
+            index.walk(doc, &roots, None, &Inherited::default(), 0, &mut seen, &mut budget)?;
+        }
+        for (number, page_id) in doc.get_pages() {
+            let page = index.page_widgets(doc, number, page_id, &mut budget)?;
+            index.pages.push(page);
+        }
+        Ok(index)
+    }
+
+    /// The field a widget annotation belongs to.
+    pub(crate) fn field_for_widget(&self, widget_id: ObjectId) -> Option<&FieldDescriptor> {
+        self.widget_field.get(&widget_id).map(|index| &self.fields[*index])
+    }
+
+    /// Re-reads `/DV` after a fill for the touched fields and everything
+    /// that inherits from them.
+    pub(crate) fn refresh_defaults(&mut self, doc: &Document, touched: &HashSet<ObjectId>) {
+        let mut refreshed = vec![false; self.fields.len()];
+        for position in 0..self.fields.len() {
+            let parent = self.fields[position].parent;
+            if !touched.contains(&self.fields[position].id) && !parent.is_some_and(|parent| refreshed[parent]) {
+                continue;
+            }
+            let own = doc
+                .get_dictionary(self.fields[position].id)
+                .ok()
+                .and_then(|dict| dict.get(b"DV").ok())
+                .and_then(|dv| resolve_object(doc, dv).ok());
+            self.fields[position].default_value =
+                own.or_else(|| parent.and_then(|parent| self.fields[parent].default_value.clone()));
+            refreshed[position] = true;
+        }
+    }
+
+    /// The field's `/V`, or the nearest ancestor's.
+    pub(crate) fn inherited_value(&self, doc: &Document, field: &FieldDescriptor) -> Option<Object> {
+        let mut current = Some(field);
+        while let Some(field) = current {
+            if let Some(value) = doc
+                .get_dictionary(field.id)
+                .ok()
+                .and_then(|dict| dict.get(b"V").ok())
+                .and_then(|value| resolve_object(doc, value).ok())
+            {
+                return Some(value);
+            }
+            current = field.parent.map(|parent| &self.fields[parent]);
+        }
+        None
+    }
+
+    pub(crate) fn terminal_fields(&self) -> impl Iterator<Item = &FieldDescriptor> {
+        self.fields.iter().filter(|field| field.terminal)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn walk(
+        &mut self,
+        doc: &Document,
+        source: &Object,
+        parent: Option<usize>,
+        inherited: &Inherited,
+        depth: usize,
+        seen: &mut HashSet<ObjectId>,
+        budget: &mut Budget,
+    ) -> CoreResult<()> {
+        budget.tick("field tree")?;
+        match source {
+            Object::Array(items) => {
+                for item in items {
+                    self.walk(doc, item, parent, inherited, depth, seen, budget)?;
+                }
+            }
+            Object::Reference(id) => {
+                if !seen.insert(*id) {
+                    return Ok(());
+                }
+                let Ok(Object::Dictionary(dict)) = doc.get_object(*id) else {
+                    return Ok(());
+                };
+                if let (Some(parent), false) = (parent, dict.has(b"T")) {
+                    // A kid without /T is a widget of the enclosing field.
+                    if is_widget_dict(dict) {
+                        self.fields[parent].widget_ids.push(*id);
+                        self.widget_field.insert(*id, parent);
+                    }
+                    return Ok(());
+                }
+                if depth >= budget.limits().max_depth {
+                    return Err(core_error_with_context(
+                        "BW_LIMIT_DEPTH",
+                        format!("field tree is nested deeper than {} levels", budget.limits().max_depth),
+                        Some(format!("object {} {}", id.0, id.1)),
+                    ));
+                }
+                if self.fields.len() >= budget.limits().max_fields {
+                    return Err(core_error_with_context(
+                        "BW_LIMIT_FIELDS",
+                        format!("form has more than {} fields", budget.limits().max_fields),
+                        Some(format!("object {} {}", id.0, id.1)),
+                    ));
+                }
+                self.add_field(doc, *id, dict, parent, inherited, depth, seen, budget)?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn add_field(
+        &mut self,
+        doc: &Document,
+        id: ObjectId,
+        dict: &Dictionary,
+        parent: Option<usize>,
+        inherited: &Inherited,
+        depth: usize,
+        seen: &mut HashSet<ObjectId>,
+        budget: &mut Budget,
+    ) -> CoreResult<()> {
+        let partial_name = dict.get(b"T").ok().and_then(|name| deref(doc, name)).and_then(object_to_text);
+        let full_name = match (&inherited.full_name, &partial_name) {
+            (Some(prefix), Some(name)) => Some(format!("{}.{}", prefix, name)),
+            (prefix, name) => name.clone().or_else(|| prefix.clone()),
+        };
+        let entry = |key: &[u8]| dict.get(key).ok().and_then(|obj| deref(doc, obj));
+        let state = Inherited {
+            full_name: full_name.clone(),
+            field_type: entry(b"FT").and_then(object_to_name).or_else(|| inherited.field_type.clone()),
+            flags: entry(b"Ff").and_then(|flags| flags.as_i64().ok()).or(inherited.flags),
+            da: entry(b"DA").and_then(object_to_text).or_else(|| inherited.da.clone()),
+            quadding: entry(b"Q").and_then(|q| q.as_i64().ok()).or(inherited.quadding),
+            default_value: entry(b"DV").cloned().or_else(|| inherited.default_value.clone()),
+        };
+
+        let position = self.fields.len();
+        self.fields.push(FieldDescriptor {
+            id,
+            parent,
+            partial_name,
+            full_name,
+            field_type: state.field_type.clone(),
+            flags: state.flags.unwrap_or(0),
+            da: state.da.clone(),
+            quadding: state.quadding,
+            default_value: state.default_value.clone(),
+            widget_ids: Vec::new(),
+            terminal: true,
+        });
+        if let Some(parent) = parent {
+            self.fields[parent].terminal = false;
+        }
+        if is_widget_dict(dict) {
+            self.fields[position].widget_ids.push(id);
+            self.widget_field.insert(id, position);
+        }
+        if let Ok(kids) = dict.get(b"Kids") {
+            if let Some(kids) = deref(doc, kids) {
+                self.walk(doc, kids, Some(position), &state, depth + 1, seen, budget)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Widgets of one page. The outer result fails only when the budget runs
+    /// out; an unreadable page is kept as the inner error.
+    fn page_widgets(
+        &self,
+        doc: &Document,
+        number: u32,
+        page_id: ObjectId,
+        budget: &mut Budget,
+    ) -> CoreResult<CoreResult<PageWidgets>> {
+        let context = || Some(format!("page {}", number));
+        let page = match doc.get_object(page_id) {
+            Err(err) => return Ok(Err(core_error_with_context("BW_PDF_PAGE_READ_FAILED", err.to_string(), context()))),
+            Ok(page) => match page.as_dict() {
+                Err(err) => return Ok(Err(core_error_with_context("BW_PDF_PAGE_INVALID", err.to_string(), context()))),
+                Ok(page) => page,
+            },
+        };
+
+        let mut widgets = Vec::new();
+        let annots = match page.get(b"Annots").ok().and_then(|annots| deref(doc, annots)) {
+            Some(Object::Array(annots)) => annots.as_slice(),
+            _ => &[],
+        };
+        for (annot_index, annot) in annots.iter().enumerate() {
+            budget.tick("page annotations")?;
+            let Some(Object::Dictionary(widget)) = deref(doc, annot) else {
+                continue;
+            };
+            if !is_widget_dict(widget) {
+                continue;
+            }
+            let id = object_as_reference(annot);
+            let fallback = format!("field_{}_{}", number, annot_index + 1);
+            let label = match id.and_then(|id| self.field_for_widget(id)) {
+                Some(field) => field.partial_name.clone().unwrap_or(fallback),
+                None => widget_label(doc, widget, fallback),
+            };
+            widgets.push(PageWidget {
+                annot: annot.clone(),
+                rect: widget.get(b"Rect").ok().and_then(rect_from_object),
+                label,
+            });
+        }
+        Ok(Ok(PageWidgets {
+            number,
+            page_id,
+            widgets,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::make_fixture_pdf;
+    use lopdf::dictionary;
+
+    /// `Section{s}.Row{r}.Cell{c}` text fields, one merged widget each, all on
+    /// a single page.
+    fn nested_form(sections: usize, rows: usize, cells: usize) -> Document {
+        let mut doc = Document::with_version("1.7");
+        let pages_id = doc.new_object_id();
+        let page_id = doc.new_object_id();
+        let mut annots = Vec::new();
+        let mut roots = Vec::new();
+        for section in 0..sections {
+            let section_id = doc.new_object_id();
+            let mut section_kids = Vec::new();
+            for row in 0..rows {
+                let row_id = doc.new_object_id();
+                let mut row_kids = Vec::new();
+                for cell in 0..cells {
+                    let cell_id = doc.add_object(dictionary! {
+                        "Type" => "Annot",
+                        "Subtype" => "Widget",
+                        "T" => Object::string_literal(format!("Cell{}", cell)),
+                        "Parent" => Object::Reference(row_id),
+                        "Rect" => Object::Array(vec![0.into(), 0.into(), 10.into(), 10.into()]),
+                    });
+                    row_kids.push(Object::Reference(cell_id));
+                    annots.push(Object::Reference(cell_id));
+                }
+                doc.objects.insert(row_id, Object::Dictionary(dictionary! {
+                    "T" => Object::string_literal(format!("Row{}", row)),
+                    "Parent" => Object::Reference(section_id),
+                    "Kids" => Object::Array(row_kids),
+                }));
+                section_kids.push(Object::Reference(row_id));
+            }
+            doc.objects.insert(section_id, Object::Dictionary(dictionary! {
+                "T" => Object::string_literal(format!("Section{}", section)),
+                "FT" => "Tx",
+                "DA" => Object::string_literal("/Helv 9 Tf 0 g"),
+                "Kids" => Object::Array(section_kids),
+            }));
+            roots.push(Object::Reference(section_id));
+        }
+        doc.objects.insert(page_id, Object::Dictionary(dictionary! {
+            "Type" => "Page",
+            "Parent" => Object::Reference(pages_id),
+            "MediaBox" => Object::Array(vec![0.into(), 0.into(), 612.into(), 792.into()]),
+            "Annots" => Object::Array(annots),
+        }));
+        doc.objects.insert(pages_id, Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Kids" => vec![Object::Reference(page_id)],
+            "Count" => 1,
+        }));
+        let acroform_id = doc.add_object(dictionary! { "Fields" => Object::Array(roots) });
+        let catalog_id = doc.add_object(dictionary! {
+            "Type" => "Catalog",
+            "Pages" => Object::Reference(pages_id),
+            "AcroForm" => Object::Reference(acroform_id),
+        });
+        doc.trailer.set("Root", Object::Reference(catalog_id));
+        doc
+    }
+
+    #[test]
+    fn index_resolves_names_inheritance_and_widgets() {
+        let doc = Document::load_mem(&make_fixture_pdf()).expect("fixture parses");
+        let index = FieldIndex::build(&doc, &Limits::default()).expect("within limits");
+
+        let choice = index
+            .fields
+            .iter()
+            .find(|field| field.full_name.as_deref() == Some("Choice"))
+            .expect("radio parent indexed");
+        assert_eq!(choice.field_type.as_deref(), Some("Btn"));
+        assert_eq!(choice.widget_ids.len(), 2);
+        assert!(choice.terminal);
+        for widget_id in &choice.widget_ids {
+            assert_eq!(index.field_for_widget(*widget_id).map(|field| field.id), Some(choice.id));
+        }
+
+        assert_eq!(index.terminal_fields().count(), 3);
+        let page = index.pages[0].as_ref().expect("page indexed");
+        let labels: Vec<&str> = page.widgets.iter().map(|widget| widget.label.as_str()).collect();
+        assert_eq!(labels, vec!["Name", "Consent", "Choice", "Choice"]);
+    }
+
+    #[test]
+    fn large_nested_form_is_indexed_in_one_pass() {
+        let doc = nested_form(10, 50, 10);
+        let index = FieldIndex::build(&doc, &Limits::default()).expect("within limits");
+
+        let terminals: Vec<&FieldDescriptor> = index.terminal_fields().collect();
+        assert_eq!(terminals.len(), 5_000);
+        assert_eq!(index.fields.len(), 5_000 + 500 + 10);
+
+        let last = terminals.last().expect("terminal fields");
+        assert_eq!(last.full_name.as_deref(), Some("Section9.Row49.Cell9"));
+        assert_eq!(last.field_type.as_deref(), Some("Tx"), "FT inherited from the section");
+        assert_eq!(last.da.as_deref(), Some("/Helv 9 Tf 0 g"), "DA inherited from the section");
+        assert_eq!(index.field_for_widget(last.id).map(|field| field.id), Some(last.id));
+        assert_eq!(index.pages[0].as_ref().expect("page").widgets.len(), 5_000);
+    }
+
+    #[test]
+    fn tree_limits_fail_with_limit_codes() {
+        let doc = nested_form(2, 3, 4);
+        let shallow = Limits {
+            max_depth: 2,
+            ..Limits::default()
+        };
+        let error = FieldIndex::build(&doc, &shallow).expect_err("three levels deep");
+        assert_eq!(error.code(), "BW_LIMIT_DEPTH");
+
+        let few = Limits {
+            max_fields: 10,
+            ..Limits::default()
+        };
+        assert_eq!(FieldIndex::build(&doc, &few).expect_err("32 fields").code(), "BW_LIMIT_FIELDS");
+
+        let exact = Limits {
+            max_depth: 3,
+            max_fields: 2 + 6 + 24,
+            ..Limits::default()
+        };
+        assert_eq!(FieldIndex::build(&doc, &exact).expect("at the limits").fields.len(), 32);
+    }
+}
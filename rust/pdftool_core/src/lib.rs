@@ -3,9 +3,68 @@
 #![forbid(unsafe_code)]
 use std::collections::{HashMap, HashSet};
 
-use lopdf::{Dictionary, Document, Object, ObjectId};
+use lopdf::{dictionary, Dictionary, Document, Object, ObjectId};
 use serde::{Deserialize, Serialize};
-use wasm_bindgen::prelude::*;
+
+mod appearance;
+mod autofill;
+mod batch;
+mod bulk;
+mod classify;
+#[cfg(feature = "cli")]
+pub mod cli;
+mod create;
+mod csv;
+mod diff;
+mod document;
+mod edit;
+mod encryption;
+mod error;
+mod extract;
+mod fingerprint;
+mod flatten;
+mod index;
+mod limits;
+mod mapping;
+mod matching;
+mod merge;
+mod paths;
+mod repair;
+mod transform;
+mod value;
+#[cfg(feature = "wasm")]
+mod wasm;
+#[cfg(feature = "cli")]
+mod xfdf;
+
+pub use autofill::{propose, Derivation, Profile, Proposal};
+pub use batch::{csv_records, BatchRecord};
+pub use bulk::{BulkExtraction, ExtractedRow};
+pub use classify::{FieldClassification, FieldTag, TagScore};
+pub use diff::{diff_fingerprints, FieldChange, FormDiff, Placement};
+pub use document::FormDocument;
+pub use edit::{EditReport, FieldEdit, NameChange};
+use encryption::SecurityHandler;
+pub use encryption::{EncryptionOptions, Permissions};
+use error::{core_error, core_error_with_context};
+use index::{FieldDescriptor, FieldIndex};
+use limits::Budget;
+pub use limits::Limits;
+pub use mapping::{find_mapping, resolve_mapping, Binding, DataValue, Mapping, Resolution, MAPPING_VERSION};
+use matching::match_input_keys;
+pub use matching::{AmbiguousKey, FillReport, PartialNamePolicy};
+pub use merge::MergeReport;
+pub use paths::RepeatingGroup;
+pub use transform::Transform;
+pub use error::{CoreError, CoreResult};
+pub use extract::FieldSnapshot;
+pub use fingerprint::{FieldSignature, FormFingerprint, FINGERPRINT_VERSION};
+pub use value::FieldValue;
+#[cfg(feature = "wasm")]
+pub use wasm::{
+    detect_blocks, diff_form_fingerprints, extract_fields, fill_blocks, fill_blocks_with_options, fingerprint_similarity,
+    flatten_form, PdfSession,
+};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Block {
@@ -14,41 +73,55 @@ pub struct Block {
     pub y: f32,
     pub width: f32,
     pub height: f32,
+    /// Field type of the block; blocks given a kind can be turned into
+    /// fields by [`FormDocument::create_fields`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<BlockKind>,
+    /// 1-based page number (page 1 when absent).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub page: Option<u32>,
+    /// Full field name (`Applicant.Date`); `label` when absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// On-state of a checkbox (`Yes` by default) or of one radio button
+    /// (its 1-based position in the group by default).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub export: Option<String>,
+    /// Options of a choice field.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub options: Vec<String>,
 }
 
-#[derive(Debug, Serialize)]
-struct CoreErrorPayload {
-    code: &'static str,
-    message: String,
-    context: Option<String>,
-}
-
-type CoreResult<T> = Result<T, CoreErrorPayload>;
-
-fn core_error(code: &'static str, message: impl Into<String>) -> CoreErrorPayload {
-    core_error_with_context(code, message, None)
-}
-
-fn core_error_with_context(
-    code: &'static str,
-    message: impl Into<String>,
-    context: Option<String>,
-) -> CoreErrorPayload {
-    CoreErrorPayload {
-        code,
-        message: message.into(),
-        context,
-    }
+/// Field type of a [`Block`]. Radio blocks sharing a name form one group;
+/// choices are combo boxes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BlockKind {
+    Text,
+    Checkbox,
+    Radio,
+    Choice,
 }
 
-fn core_error_to_js(payload: CoreErrorPayload) -> JsValue {
-    serde_wasm_bindgen::to_value(&payload)
-        .unwrap_or_else(|_| JsValue::from_str(&format!("{}: {}", payload.code, payload.message)))
+/// Options for the fill pipeline beyond the field values themselves.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct FillOptions {
+    /// Password used to open an encrypted input; the empty password is tried when absent.
+    pub password: Option<String>,
+    /// Encrypt the output with AES-256 using these credentials.
+    pub encryption: Option<EncryptionOptions>,
+    /// Re-apply the input's original encryption when no `encryption` is given.
+    pub reapply_encryption: bool,
+    /// Resource limits for parsing and filling.
+    pub limits: Limits,
+    /// What a key matching several fields by partial name fills.
+    pub partial_names: PartialNamePolicy,
 }
 
 fn resolve_object(doc: &Document, obj: &Object) -> Result<Object, lopdf::Error> {
     match obj {
-        Object::Reference(id) => doc.get_object(*id).map(Clone::clone),
+        Object::Reference(id) => doc.get_object(*id).cloned(),
         _ => Ok(obj.clone()),
     }
 }
@@ -56,7 +129,7 @@ fn resolve_object(doc: &Document, obj: &Object) -> Result<Object, lopdf::Error>
 fn object_to_number(obj: &Object) -> Option<f32> {
     match obj {
         Object::Integer(v) => Some(*v as f32),
-        Object::Real(v) => Some(*v as f32),
+        Object::Real(v) => Some(*v),
         _ => None,
     }
 }
@@ -173,10 +246,6 @@ fn is_widget_dict(dict: &Dictionary) -> bool {
     )
 }
 
-fn is_widget_object(doc: &Document, id: ObjectId) -> bool {
-    matches!(doc.get_object(id), Ok(Object::Dictionary(dict)) if is_widget_dict(dict))
-}
-
 fn root_catalog_id(doc: &Document) -> CoreResult<ObjectId> {
     let root = doc
         .trailer
@@ -230,163 +299,78 @@ enum FormSource {
     }
 }
 
-fn collect_field_ids(doc: &Document, source: &Object, out: &mut Vec<ObjectId>, seen: &mut HashSet<ObjectId>) {
-    match source {
-        Object::Reference(id) => {
-            if !seen.insert(*id) {
-                return;
-            }
-            out.push(*id);
-            if let Ok(Object::Dictionary(dict)) = doc.get_object(*id) {
-                if let Ok(kids) = dict.get(b"Kids") {
-                    collect_field_ids(doc, kids, out, seen);
-                }
-            }
-        }
-        Object::Array(items) => {
-            for item in items {
-                collect_field_ids(doc, item, out, seen);
-            }
-        }
-        Object::Dictionary(dict) => {
-            if let Ok(kids) = dict.get(b"Kids") {
-                collect_field_ids(doc, kids, out, seen);
-            }
-        }
-        _ => {}
-    }
-}
-
-fn field_parent_id(doc: &Document, field_id: ObjectId) -> Option<ObjectId> {
-    let dict = match doc.get_object(field_id).ok()?.as_dict() {
-        Ok(dict) => dict,
-        Err(_) => return None,
+/// Appends `roots` to `AcroForm.Fields`, creating the form if the catalog
+/// has none; returns the form's object id.
+fn append_root_fields(doc: &mut Document, roots: &[ObjectId]) -> CoreResult<ObjectId> {
+    let catalog_id = root_catalog_id(doc)?;
+    let has_form = doc.get_dictionary(catalog_id).is_ok_and(|catalog| catalog.has(b"AcroForm"));
+    let acroform_id = if has_form {
+        ensure_acroform_object(doc, catalog_id)?
+    } else {
+        let acroform_id = doc.add_object(dictionary! { "Fields" => Vec::<Object>::new() });
+        get_dict_mut(doc, catalog_id, "BW_FORM_CATALOG_INVALID", "catalog dictionary")?
+            .set("AcroForm", Object::Reference(acroform_id));
+        acroform_id
     };
-    dict.get(b"Parent").ok().and_then(object_as_reference)
-}
-
-fn field_partial_name(doc: &Document, field_id: ObjectId) -> Option<String> {
-    let dict = doc.get_object(field_id).ok()?.as_dict().ok()?;
-    dict_text(doc, dict, b"T")
-}
-
-fn field_full_name(doc: &Document, field_id: ObjectId, depth: usize) -> Option<String> {
-    if depth > 48 {
-        return None;
-    }
-    let partial = field_partial_name(doc, field_id);
-    match (field_parent_id(doc, field_id), partial) {
-        (Some(parent_id), Some(name)) => match field_full_name(doc, parent_id, depth + 1) {
-            Some(parent_name) => Some(format!("{}.{}", parent_name, name)),
-            None => Some(name),
-        },
-        (Some(parent_id), None) => field_full_name(doc, parent_id, depth + 1),
-        (None, Some(name)) => Some(name),
-        (None, None) => None,
-    }
-}
-
-fn field_type(doc: &Document, field_id: ObjectId, depth: usize) -> Option<String> {
-    if depth > 48 {
-        return None;
-    }
-    let dict = doc.get_object(field_id).ok()?.as_dict().ok()?;
-    if let Ok(ft_obj) = dict.get(b"FT") {
-        if let Ok(resolved) = resolve_object(doc, ft_obj) {
-            if let Some(name) = object_to_name(&resolved) {
-                return Some(name);
-            }
-        }
-        if let Some(name) = object_to_name(ft_obj) {
-            return Some(name);
-        }
-    }
-
-    match field_parent_id(doc, field_id) {
-        Some(parent_id) => field_type(doc, parent_id, depth + 1),
-        None => None,
-    }
-}
-
-fn collect_widget_ids_for_field(doc: &Document, source: &Object, out: &mut Vec<ObjectId>, seen: &mut HashSet<ObjectId>) {
-    match source {
-        Object::Reference(id) => {
-            if !seen.insert(*id) {
-                return;
-            }
-            if is_widget_object(doc, *id) {
-                out.push(*id);
-            }
-            if let Ok(Object::Dictionary(dict)) = doc.get_object(*id) {
-                if let Ok(kids) = dict.get(b"Kids") {
-                    collect_widget_ids_for_field(doc, kids, out, seen);
-                }
-            }
-        }
-        Object::Array(items) => {
-            for item in items {
-                collect_widget_ids_for_field(doc, item, out, seen);
-            }
-        }
-        Object::Dictionary(dict) => {
-            if is_widget_dict(dict) {
-                // Inline widgets are rare; no stable ObjectId to push.
-            }
-            if let Ok(kids) = dict.get(b"Kids") {
-                collect_widget_ids_for_field(doc, kids, out, seen);
+    let fields_ref = doc
+        .get_dictionary(acroform_id)
+        .ok()
+        .and_then(|form| form.get(b"Fields").ok())
+        .and_then(object_as_reference);
+    let additions = roots.iter().map(|id| Object::Reference(*id));
+    match fields_ref.map(|id| doc.get_object_mut(id).and_then(Object::as_array_mut)) {
+        Some(Ok(fields)) => fields.extend(additions),
+        _ => {
+            let acroform = get_dict_mut(doc, acroform_id, "BW_FORM_ACROFORM_INVALID", "AcroForm dictionary")?;
+            match acroform.get_mut(b"Fields") {
+                Ok(Object::Array(fields)) => fields.extend(additions),
+                _ => acroform.set("Fields", additions.collect::<Vec<_>>()),
             }
         }
-        _ => {}
     }
+    Ok(acroform_id)
 }
 
-#[derive(Debug)]
-struct FieldDescriptor {
-    id: ObjectId,
-    partial_name: Option<String>,
-    full_name: Option<String>,
-    field_type: Option<String>,
-    widget_ids: Vec<ObjectId>,
-}
-
-fn describe_field(doc: &Document, field_id: ObjectId) -> FieldDescriptor {
-    let partial_name = field_partial_name(doc, field_id);
-    let full_name = field_full_name(doc, field_id, 0);
-    let field_type = field_type(doc, field_id, 0);
-
-    let mut widget_ids = Vec::new();
-    let mut seen = HashSet::new();
-    if is_widget_object(doc, field_id) {
-        widget_ids.push(field_id);
-        seen.insert(field_id);
-    }
-    if let Ok(Object::Dictionary(dict)) = doc.get_object(field_id) {
-        if let Ok(kids) = dict.get(b"Kids") {
-            collect_widget_ids_for_field(doc, kids, &mut widget_ids, &mut seen);
-        }
-    }
-
-    FieldDescriptor {
-        id: field_id,
-        partial_name,
-        full_name,
-        field_type,
-        widget_ids,
-    }
+/// The `/Fields` array of the catalog's interactive form, resolved.
+fn acroform_field_roots(doc: &Document) -> CoreResult<Object> {
+    let catalog = get_dict(doc, root_catalog_id(doc)?, "BW_FORM_CATALOG_INVALID", "catalog dictionary")?;
+    let acroform = catalog
+        .get(b"AcroForm")
+        .map_err(|err| core_error_with_context("BW_FORM_MISSING_ACROFORM", err.to_string(), Some("catalog.AcroForm".into())))
+        .and_then(|form| {
+            resolve_object(doc, form).map_err(|err| {
+                core_error_with_context("BW_FORM_ACROFORM_INVALID", err.to_string(), Some("catalog.AcroForm".into()))
+            })
+        })?;
+    let acroform = acroform.as_dict().map_err(|_| {
+        core_error_with_context(
+            "BW_FORM_ACROFORM_INVALID",
+            "catalog.AcroForm must be a dictionary or reference",
+            Some("catalog.AcroForm".into()),
+        )
+    })?;
+    acroform
+        .get(b"Fields")
+        .cloned()
+        .map_err(|err| core_error_with_context("BW_FORM_FIELDS_MISSING", err.to_string(), Some("AcroForm.Fields".into())))
 }
 
-fn field_input_value(descriptor: &FieldDescriptor, fields: &HashMap<String, String>) -> Option<String> {
-    if let Some(full_name) = &descriptor.full_name {
-        if let Some(value) = fields.get(full_name) {
-            return Some(value.clone());
-        }
-    }
-    if let Some(partial_name) = &descriptor.partial_name {
-        if let Some(value) = fields.get(partial_name) {
-            return Some(value.clone());
-        }
-    }
-    None
+/// Export values of a choice field's `/Opt` array, in option order.
+fn field_option_exports(doc: &Document, field_id: ObjectId) -> Vec<String> {
+    let Some(dict) = doc.get_object(field_id).ok().and_then(|obj| obj.as_dict().ok()) else {
+        return Vec::new();
+    };
+    let Some(Object::Array(options)) = dict.get(b"Opt").ok().and_then(|obj| resolve_object(doc, obj).ok()) else {
+        return Vec::new();
+    };
+    options
+        .iter()
+        .map(|option| match resolve_object(doc, option) {
+            Ok(Object::Array(pair)) => pair.first().and_then(object_to_text).unwrap_or_default(),
+            Ok(other) => object_to_text(&other).unwrap_or_default(),
+            Err(_) => String::new(),
+        })
+        .collect()
 }
 
 fn widget_on_state(doc: &Document, widget_id: ObjectId) -> Option<Vec<u8>> {
@@ -416,15 +400,94 @@ fn set_widget_as(doc: &mut Document, widget_id: ObjectId, value: Vec<u8>) -> Cor
     Ok(())
 }
 
-fn set_field_text_value(doc: &mut Document, descriptor: &FieldDescriptor, value: &str) -> CoreResult<()> {
+const FF_NO_TOGGLE_TO_OFF: i64 = 1 << 14;
+const FF_RADIO: i64 = 1 << 15;
+const FF_MULTI_SELECT: i64 = 1 << 21;
+const FF_RADIOS_IN_UNISON: i64 = 1 << 25;
+
+fn field_context(descriptor: &FieldDescriptor) -> Option<String> {
+    descriptor.full_name.clone().or(descriptor.partial_name.clone())
+}
+
+fn value_type_mismatch(descriptor: &FieldDescriptor, field_type: &str, value: &FieldValue) -> CoreError {
+    let context = field_context(descriptor);
+    core_error_with_context(
+        "BW_FILL_VALUE_TYPE_MISMATCH",
+        format!(
+            "field '{}' ({}) cannot take a {} value",
+            context.as_deref().unwrap_or("<unnamed>"),
+            field_type,
+            value.kind()
+        ),
+        context,
+    )
+}
+
+fn set_field_text_value(doc: &mut Document, descriptor: &FieldDescriptor, value: Option<&str>) -> CoreResult<()> {
+    let field = get_dict_mut(
+        doc,
+        descriptor.id,
+        "BW_FILL_FIELD_UPDATE_FAILED",
+        &format!("field {:?}", descriptor.id),
+    )?;
+    match value {
+        Some(value) => {
+            field.set(b"V", Object::string_literal(value));
+            field.set(b"DV", Object::string_literal(value));
+        }
+        None => {
+            field.remove(b"V");
+        }
+    }
+    Ok(())
+}
+
+fn set_choice_value(doc: &mut Document, descriptor: &FieldDescriptor, selected: &[String]) -> CoreResult<()> {
+    if selected.len() > 1 && descriptor.flags & FF_MULTI_SELECT == 0 {
+        let context = field_context(descriptor);
+        return Err(core_error_with_context(
+            "BW_FILL_VALUE_TYPE_MISMATCH",
+            format!(
+                "field '{}' (Ch) is not multi-select and cannot take {} values",
+                context.as_deref().unwrap_or("<unnamed>"),
+                selected.len()
+            ),
+            context,
+        ));
+    }
+
+    let exports = field_option_exports(doc, descriptor.id);
+    let indices: Option<Vec<i64>> = selected
+        .iter()
+        .map(|value| exports.iter().position(|export| export == value).map(|index| index as i64))
+        .collect();
+
     let field = get_dict_mut(
         doc,
         descriptor.id,
         "BW_FILL_FIELD_UPDATE_FAILED",
         &format!("field {:?}", descriptor.id),
     )?;
-    field.set(b"V", Object::string_literal(value));
-    field.set(b"DV", Object::string_literal(value));
+    let value = match selected {
+        [] => {
+            field.remove(b"V");
+            field.remove(b"I");
+            return Ok(());
+        }
+        [single] => Object::string_literal(single.as_str()),
+        many => Object::Array(many.iter().map(|item| Object::string_literal(item.as_str())).collect()),
+    };
+    field.set(b"V", value.clone());
+    field.set(b"DV", value);
+    match indices {
+        Some(mut indices) if descriptor.flags & FF_MULTI_SELECT != 0 => {
+            indices.sort_unstable();
+            field.set(b"I", Object::Array(indices.into_iter().map(Object::Integer).collect()));
+        }
+        _ => {
+            field.remove(b"I");
+        }
+    }
     Ok(())
 }
 
@@ -436,163 +499,307 @@ fn is_falsey(value: &str) -> bool {
     matches!(value, "" | "false" | "no" | "off" | "0" | "unchecked")
 }
 
-fn set_button_value(doc: &mut Document, descriptor: &FieldDescriptor, raw_value: &str) -> CoreResult<()> {
-    let normalized = raw_value.trim().to_ascii_lowercase();
-
-    let widget_states: Vec<(ObjectId, Vec<u8>)> = descriptor
-        .widget_ids
-        .iter()
-        .filter_map(|id| widget_on_state(doc, *id).map(|state| (*id, state)))
-        .collect();
+enum ButtonRequest<'a> {
+    On,
+    Off,
+    Text(&'a str),
+}
 
-    let mut field_value = b"Off".to_vec();
-    if descriptor.widget_ids.len() > 1 && !is_truthy(&normalized) && !is_falsey(&normalized) {
-        let requested = normalized.as_bytes();
-        let selected = widget_states
-            .iter()
-            .find(|(_, state)| state.eq_ignore_ascii_case(requested))
-            .cloned();
-
-        if let Some((selected_widget_id, selected_state)) = selected {
-            field_value = selected_state.clone();
-            for widget_id in &descriptor.widget_ids {
-                if *widget_id == selected_widget_id {
-                    set_widget_as(doc, *widget_id, selected_state.clone())?;
-                } else {
-                    set_widget_as(doc, *widget_id, b"Off".to_vec())?;
-                }
+fn set_button_value(doc: &mut Document, descriptor: &FieldDescriptor, request: ButtonRequest) -> CoreResult<()> {
+    appearance::ensure_button_appearances(doc, descriptor, &appearance::FormDefaults::from_document(doc))?;
+    let request = match request {
+        ButtonRequest::Text(raw_value) => {
+            let normalized = raw_value.trim().to_ascii_lowercase();
+            if let Some(widget_id) = requested_button_widget(doc, descriptor, &normalized) {
+                return select_button_widget(doc, descriptor, widget_id);
             }
-        } else {
-            return Err(core_error_with_context(
-                "BW_FILL_BUTTON_VALUE_INVALID",
-                format!("button value '{}' does not match available widget states", raw_value),
-                descriptor.full_name.clone().or(descriptor.partial_name.clone()),
-            ));
-        }
-    } else if is_truthy(&normalized) {
-        if descriptor.widget_ids.len() > 1 {
-            let chosen_widget = descriptor.widget_ids[0];
-            for widget_id in &descriptor.widget_ids {
-                if *widget_id == chosen_widget {
-                    let state = widget_on_state(doc, *widget_id).unwrap_or_else(|| b"Yes".to_vec());
-                    field_value = state.clone();
-                    set_widget_as(doc, *widget_id, state)?;
-                } else {
-                    set_widget_as(doc, *widget_id, b"Off".to_vec())?;
-                }
+            if descriptor.widget_ids.len() > 1 && !is_truthy(&normalized) && !is_falsey(&normalized) {
+                return Err(core_error_with_context(
+                    "BW_FILL_BUTTON_VALUE_INVALID",
+                    format!("button value '{}' does not match available widget states or export values", raw_value),
+                    field_context(descriptor),
+                ));
             }
-        } else {
-            field_value = descriptor
-                .widget_ids
-                .first()
-                .and_then(|id| widget_on_state(doc, *id))
-                .unwrap_or_else(|| b"Yes".to_vec());
-            for widget_id in &descriptor.widget_ids {
-                let widget_value = widget_on_state(doc, *widget_id).unwrap_or_else(|| field_value.clone());
-                set_widget_as(doc, *widget_id, widget_value)?;
+            if is_truthy(&normalized) {
+                ButtonRequest::On
+            } else {
+                ButtonRequest::Off
             }
         }
+        other => other,
+    };
+
+    let mut field_value = b"Off".to_vec();
+    if let ButtonRequest::On = request {
+        if descriptor.widget_ids.len() > 1 {
+            return select_button_widget(doc, descriptor, descriptor.widget_ids[0]);
+        }
+        field_value = descriptor
+            .widget_ids
+            .first()
+            .and_then(|id| widget_on_state(doc, *id))
+            .unwrap_or_else(|| b"Yes".to_vec());
+        for widget_id in &descriptor.widget_ids {
+            let widget_value = widget_on_state(doc, *widget_id).unwrap_or_else(|| field_value.clone());
+            set_widget_as(doc, *widget_id, widget_value)?;
+        }
     } else {
+        let no_toggle_to_off = FF_RADIO | FF_NO_TOGGLE_TO_OFF;
+        if descriptor.flags & no_toggle_to_off == no_toggle_to_off && button_is_on(doc, descriptor) {
+            return Err(core_error_with_context(
+                "BW_FILL_BUTTON_CLEAR_REFUSED",
+                "radio group is marked NoToggleToOff and must keep a button selected",
+                field_context(descriptor),
+            ));
+        }
         for widget_id in &descriptor.widget_ids {
             set_widget_as(doc, *widget_id, b"Off".to_vec())?;
         }
     }
 
+    set_button_field_value(doc, descriptor, field_value)
+}
+
+/// The widget a text value picks: the one whose `/Opt` export value it names
+/// (radio groups with `/Opt` use option indices as state names), else the one
+/// whose appearance state it names. A single check box only matches a state
+/// on a value that does not already read as "off".
+fn requested_button_widget(doc: &Document, descriptor: &FieldDescriptor, normalized: &str) -> Option<ObjectId> {
+    let exports = field_option_exports(doc, descriptor.id);
+    let by_export = exports
+        .iter()
+        .position(|export| export.trim().eq_ignore_ascii_case(normalized))
+        .and_then(|position| descriptor.widget_ids.get(position));
+    if let Some(widget_id) = by_export {
+        return Some(*widget_id);
+    }
+    if descriptor.widget_ids.len() == 1 && is_falsey(normalized) {
+        return None;
+    }
+    descriptor.widget_ids.iter().copied().find(|widget_id| {
+        widget_on_state(doc, *widget_id).is_some_and(|state| state.eq_ignore_ascii_case(normalized.as_bytes()))
+    })
+}
+
+/// Turns `selected` on and every other widget of the field off, except that
+/// with RadiosInUnison the widgets sharing its on-state turn on with it.
+fn select_button_widget(doc: &mut Document, descriptor: &FieldDescriptor, selected: ObjectId) -> CoreResult<()> {
+    let state = widget_on_state(doc, selected).unwrap_or_else(|| b"Yes".to_vec());
+    let unison = descriptor.flags & FF_RADIOS_IN_UNISON != 0;
+    for widget_id in &descriptor.widget_ids {
+        let on = *widget_id == selected || (unison && widget_on_state(doc, *widget_id).as_ref() == Some(&state));
+        set_widget_as(doc, *widget_id, if on { state.clone() } else { b"Off".to_vec() })?;
+    }
+    set_button_field_value(doc, descriptor, state)
+}
+
+fn button_is_on(doc: &Document, descriptor: &FieldDescriptor) -> bool {
+    descriptor.widget_ids.iter().any(|widget_id| {
+        doc.get_dictionary(*widget_id)
+            .ok()
+            .and_then(|widget| widget.get(b"AS").ok())
+            .and_then(object_to_name)
+            .is_some_and(|state| state != "Off")
+    })
+}
+
+fn set_button_field_value(doc: &mut Document, descriptor: &FieldDescriptor, value: Vec<u8>) -> CoreResult<()> {
     let field = get_dict_mut(
         doc,
         descriptor.id,
         "BW_FILL_FIELD_UPDATE_FAILED",
         &format!("field {:?}", descriptor.id),
     )?;
-    field.set(b"V", Object::Name(field_value));
+    field.set(b"V", Object::Name(value));
     Ok(())
 }
 
-fn apply_field_value(doc: &mut Document, descriptor: &FieldDescriptor, value: &str) -> CoreResult<()> {
+fn apply_field_value(doc: &mut Document, descriptor: &FieldDescriptor, value: &FieldValue) -> CoreResult<()> {
     let field_type = descriptor
         .field_type
         .clone()
         .unwrap_or_else(|| "Tx".to_string());
 
-    match field_type.as_str() {
-        "Tx" | "Ch" => set_field_text_value(doc, descriptor, value),
-        "Btn" => set_button_value(doc, descriptor, value),
-        other => Err(core_error_with_context(
+    match (field_type.as_str(), value) {
+        ("Tx", FieldValue::Clear) => set_field_text_value(doc, descriptor, None),
+        ("Ch", FieldValue::Clear) => set_choice_value(doc, descriptor, &[]),
+        ("Ch", FieldValue::List(selected)) => set_choice_value(doc, descriptor, selected),
+        ("Btn", FieldValue::Clear | FieldValue::Bool(false)) => set_button_value(doc, descriptor, ButtonRequest::Off),
+        ("Btn", FieldValue::Bool(true)) => set_button_value(doc, descriptor, ButtonRequest::On),
+        ("Tx" | "Ch" | "Btn", value) => {
+            let text = value
+                .as_text()
+                .ok_or_else(|| value_type_mismatch(descriptor, &field_type, value))?;
+            match field_type.as_str() {
+                "Tx" => set_field_text_value(doc, descriptor, Some(&text)),
+                "Ch" => set_choice_value(doc, descriptor, &[text]),
+                _ => set_button_value(doc, descriptor, ButtonRequest::Text(&text)),
+            }
+        }
+        (other, _) => Err(core_error_with_context(
             "BW_FILL_UNSUPPORTED_FIELD_TYPE",
             format!("unsupported PDF form field type '{}'", other),
-            descriptor.full_name.clone().or(descriptor.partial_name.clone()),
+            field_context(descriptor),
         )),
     }
 }
 
-fn detect_blocks_impl(pdf_data: &[u8]) -> CoreResult<Vec<Block>> {
+/// Parses `pdf_data`, decrypting it in place when it carries an `/Encrypt`
+/// dictionary. The handler is returned so the caller can re-apply it on save.
+fn load_document(
+    pdf_data: &[u8],
+    password: Option<&str>,
+    limits: &Limits,
+) -> CoreResult<(Document, Option<SecurityHandler>, Vec<CoreError>)> {
     if pdf_data.is_empty() {
         return Err(core_error("BW_PDF_EMPTY", "empty PDF payload"));
     }
 
-    let doc = Document::load_mem(pdf_data)
-        .map_err(|err| core_error_with_context("BW_PDF_INVALID", err.to_string(), Some("Document::load_mem".into())))?;
+    let mut budget = Budget::new(limits);
+    limits::check_input(pdf_data, limits)?;
+    // A failed repair keeps the original outcome unless a limit stopped it.
+    let keep_limit_errors = |error: CoreError| error.code.starts_with("BW_LIMIT_").then_some(error);
+    let (doc, handler, warnings) = match parse_pdf(pdf_data) {
+        Ok(mut doc) => {
+            let handler = open_document(&mut doc, password, limits)?;
+            match repair::needs_repair(&doc) {
+                None => (doc, handler, Vec::new()),
+                Some(reason) => match repair::load_repaired(pdf_data, &mut budget, reason) {
+                    Ok((mut repaired, warnings)) => {
+                        let handler = open_document(&mut repaired, password, limits)?;
+                        (repaired, handler, warnings)
+                    }
+                    Err(error) => match keep_limit_errors(error) {
+                        Some(error) => return Err(error),
+                        None => (doc, handler, Vec::new()),
+                    },
+                },
+            }
+        }
+        Err(err) => {
+            let (mut repaired, warnings) =
+                repair::load_repaired(pdf_data, &mut budget, err.to_string()).map_err(|error| {
+                    keep_limit_errors(error).unwrap_or_else(|| {
+                        core_error_with_context("BW_PDF_INVALID", err.to_string(), Some("Document::load_mem".into()))
+                    })
+                })?;
+            let handler = open_document(&mut repaired, password, limits)?;
+            (repaired, handler, warnings)
+        }
+    };
+    limits.check_objects(doc.objects.len(), "Document::load_mem")?;
+    budget.check_time("Document::load_mem")?;
+    Ok((doc, handler, warnings))
+}
 
-    let mut blocks = Vec::<Block>::new();
-    for (page_number, page_id) in doc.get_pages() {
-        let page_obj = doc
-            .get_object(page_id)
-            .map_err(|err| {
-                core_error_with_context(
-                    "BW_PDF_PAGE_READ_FAILED",
-                    err.to_string(),
-                    Some(format!("page {}", page_number)),
-                )
-            })?;
-        let page_dict = page_obj
-            .as_dict()
-            .map_err(|err| {
-                core_error_with_context(
-                    "BW_PDF_PAGE_INVALID",
-                    err.to_string(),
-                    Some(format!("page {}", page_number)),
-                )
-            })?;
+/// `Type` that object streams carry between parsing and [`open_document`].
+const PACKED_OBJECT_STREAM: &[u8] = b"BWPackedObjStm";
 
-        let annots_obj = match page_dict.get(b"Annots") {
-            Ok(obj) => obj,
-            Err(_) => continue,
-        };
+/// Parses `pdf_data` with object streams left packed. lopdf would otherwise
+/// unpack them while reading, before an encrypted stream can be decrypted,
+/// and silently drop every object inside.
+fn parse_pdf(pdf_data: &[u8]) -> lopdf::Result<Document> {
+    lopdf::Reader {
+        buffer: pdf_data,
+        document: Document::new(),
+    }
+    .read(Some(keep_object_streams_packed))
+}
+
+fn keep_object_streams_packed(id: ObjectId, object: &mut Object) -> Option<(ObjectId, Object)> {
+    if let Object::Stream(stream) = object {
+        if stream.dict.type_is(b"ObjStm") {
+            stream.dict.set("Type", Object::Name(PACKED_OBJECT_STREAM.to_vec()));
+        }
+    }
+    // The reader only uses the returned object for members of an object
+    // stream, and none is unpacked while parsing.
+    Some((id, Object::Null))
+}
+
+/// Decrypts a freshly parsed document when it carries an `/Encrypt`
+/// dictionary, then unpacks its object streams.
+fn open_document(doc: &mut Document, password: Option<&str>, limits: &Limits) -> CoreResult<Option<SecurityHandler>> {
+    let handler = match doc.trailer.get(b"Encrypt") {
+        Err(_) => None,
+        Ok(_) => {
+            let handler = SecurityHandler::open(doc, password.unwrap_or("")).map_err(|payload| {
+                if password.is_none() && payload.code == "BW_CRYPT_PASSWORD_INVALID" {
+                    core_error_with_context(
+                        "BW_CRYPT_PASSWORD_REQUIRED",
+                        "document is encrypted and requires a password",
+                        payload.context,
+                    )
+                } else {
+                    payload
+                }
+            })?;
+            handler.decrypt_document(doc)?;
+            Some(handler)
+        }
+    };
+    unpack_object_streams(doc, limits)?;
+    Ok(handler)
+}
 
-        let annots = match resolve_object(&doc, annots_obj) {
-            Ok(Object::Array(arr)) => arr,
-            Ok(_) | Err(_) => continue,
+/// Adds the members of every object stream to the document, never replacing
+/// an object stored directly, as lopdf does. Members are not encrypted on
+/// their own, so this runs after the streams themselves are decrypted.
+fn unpack_object_streams(doc: &mut Document, limits: &Limits) -> CoreResult<()> {
+    let mut members = Vec::new();
+    for (id, object) in doc.objects.iter_mut() {
+        let Object::Stream(stream) = object else {
+            continue;
         };
+        if !stream.dict.type_is(PACKED_OBJECT_STREAM) {
+            continue;
+        }
+        stream.dict.set("Type", Object::Name(b"ObjStm".to_vec()));
+        let content = limits::stream_content(stream, limits, &format!("object stream {} {}", id.0, id.1))?;
+        let mut decoded = lopdf::Stream::new(stream.dict.clone(), content);
+        decoded.dict.remove(b"Filter");
+        decoded.dict.remove(b"DecodeParms");
+        if let Ok(unpacked) = lopdf::ObjectStream::new(&mut decoded) {
+            members.extend(unpacked.objects);
+        }
+    }
+    for (id, object) in members {
+        doc.objects.entry(id).or_insert(object);
+    }
+    Ok(())
+}
 
-        for (annot_index, annot_ref) in annots.iter().enumerate() {
-            let annot_obj = match resolve_object(&doc, annot_ref) {
-                Ok(obj) => obj,
-                Err(_) => continue,
-            };
-            let widget = match annot_obj {
-                Object::Dictionary(dict) => dict,
-                _ => continue,
-            };
+#[cfg(any(feature = "wasm", test))]
+fn detect_blocks_impl(pdf_data: &[u8]) -> CoreResult<Vec<Block>> {
+    FormDocument::load(pdf_data)?.detect()
+}
 
-            let is_widget = is_widget_dict(&widget);
-            if !is_widget {
+fn detect_document_blocks(index: &FieldIndex) -> CoreResult<Vec<Block>> {
+    let mut blocks = Vec::<Block>::new();
+    for page in &index.pages {
+        let page = page.as_ref().map_err(Clone::clone)?;
+        for widget in &page.widgets {
+            let Some(rect) = widget.rect else {
                 continue;
-            }
-
-            let rect = match widget.get(b"Rect").ok().and_then(rect_from_object) {
-                Some(rect) => rect,
-                None => continue,
             };
-
-            let fallback = format!("field_{}_{}", page_number, annot_index + 1);
-            let label = widget_label(&doc, &widget, fallback);
-
+            let field = widget.id().and_then(|id| index.field_for_widget(id));
             blocks.push(Block {
-                label,
+                label: widget.label.clone(),
                 x: rect.0,
                 y: rect.1,
                 width: rect.2,
                 height: rect.3,
+                kind: field.and_then(|field| match field.kind() {
+                    "text" => Some(BlockKind::Text),
+                    "checkbox" => Some(BlockKind::Checkbox),
+                    "radio" => Some(BlockKind::Radio),
+                    "combo" | "list" => Some(BlockKind::Choice),
+                    _ => None,
+                }),
+                page: Some(page.number),
+                name: field.and_then(|field| field.full_name.clone()),
+                export: None,
+                options: Vec::new(),
             });
         }
     }
@@ -600,20 +807,126 @@ fn detect_blocks_impl(pdf_data: &[u8]) -> CoreResult<Vec<Block>> {
     Ok(blocks)
 }
 
-fn fill_blocks_impl(pdf_data: &[u8], field_values: HashMap<String, String>) -> CoreResult<Vec<u8>> {
-    if pdf_data.is_empty() {
-        return Err(core_error("BW_PDF_EMPTY", "empty PDF payload"));
+#[cfg(test)]
+fn fill_blocks_impl(pdf_data: &[u8], field_values: HashMap<String, FieldValue>) -> CoreResult<Vec<u8>> {
+    fill_blocks_with_options_impl(pdf_data, &[], field_values, &FillOptions::default())
+}
+
+/// Fills `pdf_data`, first creating fields for the `blocks` that carry a
+/// kind and name no existing field. Only the bytes reach the caller, so a
+/// field the fill skipped (a refused NoToggleToOff clear) fails it.
+#[cfg(any(feature = "wasm", test))]
+fn fill_blocks_with_options_impl(
+    pdf_data: &[u8],
+    blocks: &[Block],
+    field_values: HashMap<String, FieldValue>,
+    options: &FillOptions,
+) -> CoreResult<Vec<u8>> {
+    let requested_encryption = options
+        .encryption
+        .as_ref()
+        .map(SecurityHandler::aes256)
+        .transpose()?;
+
+    let mut document = FormDocument::load_with_limits(pdf_data, options.password.as_deref(), options.limits.clone())?;
+    let index = document.index()?;
+    let new_blocks: Vec<Block> = blocks
+        .iter()
+        .filter(|block| block.kind.is_some())
+        .filter(|block| !index.fields.iter().any(|field| field.full_name.as_deref() == Some(block.field_name())))
+        .cloned()
+        .collect();
+    document.create_fields(&new_blocks)?;
+    let report = document.fill_with_policy(&field_values, options.partial_names)?;
+    if let Some(error) = report.field_errors.into_iter().next() {
+        return Err(error);
+    }
+
+    match requested_encryption {
+        Some(handler) => document.output_encryption = Some(handler),
+        None if options.reapply_encryption => document.reapply_input_encryption(),
+        None => {}
+    }
+    document.save_with_code("BW_FILL_SAVE_FAILED")
+}
+
+/// Objects as they were before a fill started writing, so a failed fill can
+/// be undone without copying the whole document up front.
+struct Journal {
+    saved: HashMap<ObjectId, Option<Object>>,
+    max_id: u32,
+}
+
+impl Journal {
+    fn start(doc: &Document) -> Self {
+        Self {
+            saved: HashMap::new(),
+            max_id: doc.max_id,
+        }
+    }
+
+    /// Remembers `id` as it is now, unless it was saved before.
+    fn save(&mut self, doc: &Document, id: ObjectId) {
+        self.saved.entry(id).or_insert_with(|| doc.objects.get(&id).cloned());
+    }
+
+    /// Restores the saved objects and drops those added since the start.
+    fn roll_back(self, doc: &mut Document) {
+        for number in self.max_id + 1..=doc.max_id {
+            doc.objects.remove(&(number, 0));
+        }
+        doc.max_id = self.max_id;
+        for (id, object) in self.saved {
+            match object {
+                Some(object) => doc.objects.insert(id, object),
+                None => doc.objects.remove(&id),
+            };
+        }
     }
+}
 
-    let mut doc = Document::load_mem(pdf_data)
-        .map_err(|err| core_error_with_context("BW_PDF_INVALID", err.to_string(), Some("Document::load_mem".into())))?;
+/// Writes `field_values` into the matching fields of `doc`, resolving keys
+/// shared by several fields under `policy`. On error neither `doc` nor
+/// `index` is changed: the objects a fill writes (catalog, AcroForm, and each
+/// assigned field with its widgets) are journalled first and restored.
+fn fill_document(
+    doc: &mut Document,
+    index: &mut FieldIndex,
+    field_values: &HashMap<String, FieldValue>,
+    policy: PartialNamePolicy,
+    limits: &Limits,
+) -> CoreResult<FillReport> {
+    let mut journal = Journal::start(doc);
+    let filled = write_field_values(doc, &mut journal, index, field_values, policy, limits);
+    match filled {
+        Ok((report, touched)) => {
+            index.refresh_defaults(doc, &touched);
+            Ok(report)
+        }
+        Err(error) => {
+            journal.roll_back(doc);
+            Err(error)
+        }
+    }
+}
 
-    let catalog_id = root_catalog_id(&doc)?;
-    let acroform_id = ensure_acroform_object(&mut doc, catalog_id)?;
+fn write_field_values(
+    doc: &mut Document,
+    journal: &mut Journal,
+    index: &FieldIndex,
+    field_values: &HashMap<String, FieldValue>,
+    policy: PartialNamePolicy,
+    limits: &Limits,
+) -> CoreResult<(FillReport, HashSet<ObjectId>)> {
+    let mut budget = Budget::new(limits);
+    let catalog_id = root_catalog_id(doc)?;
+    journal.save(doc, catalog_id);
+    let acroform_id = ensure_acroform_object(doc, catalog_id)?;
+    journal.save(doc, acroform_id);
 
     {
         let acroform = get_dict_mut(
-            &mut doc,
+            doc,
             acroform_id,
             "BW_FORM_ACROFORM_INVALID",
             "AcroForm dictionary",
@@ -621,93 +934,59 @@ fn fill_blocks_impl(pdf_data: &[u8], field_values: HashMap<String, String>) -> C
         acroform.set(b"NeedAppearances", Object::Boolean(true));
     }
 
-    let field_roots = {
-        let acroform = get_dict(
-            &doc,
-            acroform_id,
-            "BW_FORM_ACROFORM_INVALID",
-            "AcroForm dictionary",
-        )?;
-        acroform.get(b"Fields").map_err(|err| {
-            core_error_with_context("BW_FORM_FIELDS_MISSING", err.to_string(), Some("AcroForm.Fields".into()))
-        })?.clone()
-    };
-
-    let mut field_ids = Vec::new();
-    let mut seen = HashSet::new();
-    collect_field_ids(&doc, &field_roots, &mut field_ids, &mut seen);
+    acroform_field_roots(doc)?;
 
-    if field_ids.is_empty() {
+    if index.fields.is_empty() {
         return Err(core_error(
             "BW_FORM_FIELDS_EMPTY",
             "AcroForm.Fields does not contain fillable fields",
         ));
     }
 
-    let descriptors: Vec<FieldDescriptor> = field_ids
-        .iter()
-        .map(|id| describe_field(&doc, *id))
-        .collect();
-
-    let mut updated_fields = 0usize;
-    for descriptor in descriptors {
-        let Some(value) = field_input_value(&descriptor, &field_values) else {
-            continue;
-        };
-        apply_field_value(&mut doc, &descriptor, &value)?;
-        updated_fields += 1;
+    let (assignments, ambiguous) = match_input_keys(index, field_values, policy)?;
+    let mut touched = HashSet::new();
+    let mut field_errors = Vec::new();
+    for (position, value) in assignments {
+        budget.tick("fill")?;
+        let descriptor = &index.fields[position];
+        journal.save(doc, descriptor.id);
+        for widget_id in &descriptor.widget_ids {
+            journal.save(doc, *widget_id);
+        }
+        match apply_field_value(doc, descriptor, &value) {
+            // A radio group that must keep its selection is left as it is,
+            // so one blank value does not sink the rest of the fill.
+            Err(error) if error.code() == "BW_FILL_BUTTON_CLEAR_REFUSED" => field_errors.push(error),
+            result => {
+                result?;
+                touched.insert(descriptor.id);
+            }
+        }
     }
 
-    if updated_fields == 0 && !field_values.is_empty() {
+    if touched.is_empty() && !field_errors.is_empty() {
+        return Err(field_errors.swap_remove(0));
+    }
+    if touched.is_empty() && !field_values.is_empty() {
         return Err(core_error(
             "BW_FILL_NO_MATCHING_FIELDS",
             "none of the provided input keys matched PDF form field names",
         ));
     }
 
-    let mut output = Vec::new();
-    doc.save_to(&mut output)
-        .map_err(|err| core_error_with_context("BW_FILL_SAVE_FAILED", err.to_string(), Some("Document::save_to".into())))?;
-
-    Ok(output)
-}
-
-#[wasm_bindgen]
-pub fn detect_blocks(pdf_data: &[u8]) -> Result<JsValue, JsValue> {
-    let blocks = detect_blocks_impl(pdf_data).map_err(core_error_to_js)?;
-    serde_wasm_bindgen::to_value(&blocks)
-        .map_err(|err| core_error_to_js(core_error_with_context("BW_SERIALIZATION_ERROR", err.to_string(), Some("detect_blocks".into()))))
-}
-
-#[wasm_bindgen]
-pub fn fill_blocks(
-    pdf_data: &[u8],
-    blocks: JsValue,
-    fields: JsValue,
-) -> Result<js_sys::Uint8Array, JsValue> {
-    let _requested_blocks: Vec<Block> = serde_wasm_bindgen::from_value(blocks).map_err(|err| {
-        core_error_to_js(core_error_with_context(
-            "BW_BLOCKS_PAYLOAD_INVALID",
-            err.to_string(),
-            Some("fill_blocks blocks argument".into()),
-        ))
-    })?;
-
-    let field_values: HashMap<String, String> = serde_wasm_bindgen::from_value(fields).map_err(|err| {
-        core_error_to_js(core_error_with_context(
-            "BW_FIELDS_PAYLOAD_INVALID",
-            err.to_string(),
-            Some("fill_blocks fields argument".into()),
-        ))
-    })?;
-    let output = fill_blocks_impl(pdf_data, field_values).map_err(core_error_to_js)?;
-    Ok(js_sys::Uint8Array::from(output.as_slice()))
+    let report = FillReport {
+        filled: touched.len(),
+        ambiguous,
+        missing_keys: Vec::new(),
+        field_errors,
+    };
+    Ok((report, touched))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use lopdf::{dictionary, Stream};
+    use lopdf::Stream;
 
     fn name(value: &str) -> Object {
         Object::Name(value.as_bytes().to_vec())
@@ -723,7 +1002,7 @@ fn assert_error_code(result: CoreResult<Vec<u8>>, expected_code: &str) {
         assert!(!payload.message.is_empty(), "error message should not be empty");
     }
 
-    fn make_fixture_pdf() -> Vec<u8> {
+    pub(crate) fn make_fixture_pdf() -> Vec<u8> {
         let mut doc = Document::with_version("1.7");
 
         let pages_id = doc.new_object_id();
@@ -867,14 +1146,14 @@ fn make_fixture_pdf() -> Vec<u8> {
 
     #[test]
     fn fill_blocks_errors_on_empty_pdf() {
-        let fields = HashMap::<String, String>::new();
+        let fields = HashMap::<String, FieldValue>::new();
         let result = fill_blocks_impl(&[], fields);
         assert_error_code(result, "BW_PDF_EMPTY");
     }
 
     #[test]
     fn fill_blocks_errors_on_invalid_pdf() {
-        let fields = HashMap::<String, String>::new();
+        let fields = HashMap::<String, FieldValue>::new();
         let result = fill_blocks_impl(&[1, 2, 3, 4], fields);
         assert_error_code(result, "BW_PDF_INVALID");
     }
@@ -911,7 +1190,7 @@ fn fill_blocks_errors_when_acroform_missing() {
     fn fill_blocks_errors_when_no_field_names_match() {
         let pdf = make_fixture_pdf();
         let mut fields = HashMap::new();
-        fields.insert("UnknownField".to_string(), "value".to_string());
+        fields.insert("UnknownField".to_string(), "value".into());
         let result = fill_blocks_impl(&pdf, fields);
         assert_error_code(result, "BW_FILL_NO_MATCHING_FIELDS");
     }
@@ -920,7 +1199,7 @@ fn fill_blocks_errors_when_no_field_names_match() {
     fn fill_blocks_errors_on_invalid_radio_value() {
         let pdf = make_fixture_pdf();
         let mut fields = HashMap::new();
-        fields.insert("Choice".to_string(), "not-a-state".to_string());
+        fields.insert("Choice".to_string(), "not-a-state".into());
         let payload = fill_blocks_impl(&pdf, fields).expect_err("invalid radio value should fail");
         assert_eq!(payload.code, "BW_FILL_BUTTON_VALUE_INVALID");
         assert_eq!(payload.context.as_deref(), Some("Choice"));
@@ -930,9 +1209,9 @@ fn fill_blocks_errors_on_invalid_radio_value() {
     fn fill_blocks_updates_fixture_pdf() {
         let input_pdf = make_fixture_pdf();
         let mut fields = HashMap::new();
-        fields.insert("Name".to_string(), "Ada Lovelace".to_string());
-        fields.insert("Consent".to_string(), "true".to_string());
-        fields.insert("Choice".to_string(), "A".to_string());
+        fields.insert("Name".to_string(), "Ada Lovelace".into());
+        fields.insert("Consent".to_string(), "true".into());
+        fields.insert("Choice".to_string(), "A".into());
 
         let output_bytes = fill_blocks_impl(&input_pdf, fields).expect("fixture fields should be fillable");
 
@@ -940,4 +1219,265 @@ fn fill_blocks_updates_fixture_pdf() {
         assert_ne!(output_bytes, input_pdf, "filled PDF should differ from input bytes");
         Document::load_mem(&output_bytes).expect("filled payload should remain a valid PDF");
     }
+
+    fn field_text_value(doc: &Document, name: &str) -> Option<Vec<u8>> {
+        doc.objects.values().find_map(|obj| {
+            let dict = obj.as_dict().ok()?;
+            if dict_text(doc, dict, b"T").as_deref() != Some(name) {
+                return None;
+            }
+            dict.get(b"V").ok()?.as_str().ok().map(<[u8]>::to_vec)
+        })
+    }
+
+    fn encrypted_fill_options() -> FillOptions {
+        FillOptions {
+            encryption: Some(EncryptionOptions {
+                user_password: "reader".to_string(),
+                owner_password: "admin".to_string(),
+                permissions: Permissions {
+                    copy: false,
+                    modify: false,
+                    ..Permissions::default()
+                },
+            }),
+            ..FillOptions::default()
+        }
+    }
+
+    #[test]
+    fn fill_blocks_encrypts_output_with_aes256() {
+        let input_pdf = make_fixture_pdf();
+        let mut fields = HashMap::new();
+        fields.insert("Name".to_string(), "Ada Lovelace".into());
+
+        let output = fill_blocks_with_options_impl(&input_pdf, &[], fields, &encrypted_fill_options())
+            .expect("encrypted fill should succeed");
+
+        let raw = Document::load_mem(&output).expect("encrypted output should parse");
+        let encrypt = raw.get_encrypted().expect("output should carry /Encrypt");
+        assert_eq!(encrypt.get(b"R").and_then(Object::as_i64).ok(), Some(6));
+        assert_eq!(encrypt.get(b"P").and_then(Object::as_i64).ok(), Some(i64::from(0xFFFF_FBC4u32 as i32)));
+        assert_ne!(field_text_value(&raw, "Name").as_deref(), Some(b"Ada Lovelace".as_slice()));
+
+        let payload = detect_blocks_impl(&output).expect_err("detect without a password should fail");
+        assert_eq!(payload.code, "BW_CRYPT_PASSWORD_REQUIRED");
+
+        let (doc, handler, _) = load_document(&output, Some("reader"), &Limits::default()).expect("user password opens output");
+        assert!(handler.is_some());
+        assert_eq!(field_text_value(&doc, "Name").as_deref(), Some(b"Ada Lovelace".as_slice()));
+    }
+
+    #[test]
+    fn fill_blocks_reapplies_input_encryption() {
+        let input_pdf = make_fixture_pdf();
+        let mut fields = HashMap::new();
+        fields.insert("Name".to_string(), "Ada Lovelace".into());
+        let encrypted_input = fill_blocks_with_options_impl(&input_pdf, &[], fields, &encrypted_fill_options())
+            .expect("encrypted fill should succeed");
+
+        let mut fields = HashMap::new();
+        fields.insert("Name".to_string(), "Grace Hopper".into());
+        let wrong_password = FillOptions {
+            password: Some("guess".to_string()),
+            ..FillOptions::default()
+        };
+        let payload = fill_blocks_with_options_impl(&encrypted_input, &[], fields.clone(), &wrong_password)
+            .expect_err("wrong password should fail");
+        assert_eq!(payload.code, "BW_CRYPT_PASSWORD_INVALID");
+
+        let reapply = FillOptions {
+            password: Some("admin".to_string()),
+            reapply_encryption: true,
+            ..FillOptions::default()
+        };
+        let output = fill_blocks_with_options_impl(&encrypted_input, &[], fields, &reapply)
+            .expect("owner password should allow refilling");
+
+        let (doc, handler, _) = load_document(&output, Some("reader"), &Limits::default()).expect("original user password still opens");
+        assert!(handler.is_some(), "output should remain encrypted");
+        assert_eq!(field_text_value(&doc, "Name").as_deref(), Some(b"Grace Hopper".as_slice()));
+    }
+
+    fn filled_field(output: &[u8], name: &str) -> Dictionary {
+        let doc = Document::load_mem(output).expect("filled payload should parse");
+        doc.objects
+            .values()
+            .filter_map(|obj| obj.as_dict().ok())
+            .find(|dict| dict_text(&doc, dict, b"T").as_deref() == Some(name))
+            .cloned()
+            .expect("field should exist")
+    }
+
+    #[test]
+    fn fill_blocks_maps_typed_values() {
+        let input_pdf = make_fixture_pdf();
+        let mut fields = HashMap::new();
+        fields.insert("Name".to_string(), FieldValue::Number(1234.0));
+        fields.insert("Consent".to_string(), FieldValue::Bool(true));
+        fields.insert("Choice".to_string(), FieldValue::Text("B".into()));
+
+        let output = fill_blocks_impl(&input_pdf, fields).expect("typed values should fill");
+        assert_eq!(filled_field(&output, "Name").get(b"V").and_then(Object::as_str).ok(), Some(b"1234".as_slice()));
+        assert_eq!(filled_field(&output, "Consent").get(b"V").and_then(Object::as_name).ok(), Some(b"Yes".as_slice()));
+        assert_eq!(filled_field(&output, "Choice").get(b"V").and_then(Object::as_name).ok(), Some(b"B".as_slice()));
+
+        let mut fields = HashMap::new();
+        fields.insert("Name".to_string(), FieldValue::Clear);
+        fields.insert("Consent".to_string(), FieldValue::Clear);
+        let cleared = fill_blocks_impl(&output, fields).expect("null should clear fields");
+        assert!(filled_field(&cleared, "Name").get(b"V").is_err());
+        assert_eq!(filled_field(&cleared, "Consent").get(b"V").and_then(Object::as_name).ok(), Some(b"Off".as_slice()));
+    }
+
+    #[test]
+    fn fill_blocks_rejects_value_type_mismatch() {
+        let input_pdf = make_fixture_pdf();
+        let mut fields = HashMap::new();
+        fields.insert("Consent".to_string(), FieldValue::List(vec!["Yes".into()]));
+        let payload = fill_blocks_impl(&input_pdf, fields).expect_err("list value for a checkbox should fail");
+        assert_eq!(payload.code, "BW_FILL_VALUE_TYPE_MISMATCH");
+        assert_eq!(payload.context.as_deref(), Some("Consent"));
+        assert!(payload.message.contains("'Consent'"), "message should name the field");
+
+        let mut fields = HashMap::new();
+        fields.insert("Name".to_string(), FieldValue::Bool(true));
+        let payload = fill_blocks_impl(&input_pdf, fields).expect_err("boolean value for a text field should fail");
+        assert_eq!(payload.code, "BW_FILL_VALUE_TYPE_MISMATCH");
+    }
+
+    #[test]
+    fn fill_blocks_writes_multi_select_choice_lists() {
+        let mut doc = Document::load_mem(&make_fixture_pdf()).expect("fixture parses");
+        let catalog_id = root_catalog_id(&doc).expect("catalog");
+        let acroform_id = ensure_acroform_object(&mut doc, catalog_id).expect("acroform");
+        let list_id = doc.add_object(dictionary! {
+            "FT" => name("Ch"),
+            "T" => Object::string_literal("Colours"),
+            "Ff" => Object::Integer(FF_MULTI_SELECT),
+            "Opt" => Object::Array(vec![
+                Object::string_literal("Red"),
+                Object::Array(vec![Object::string_literal("Grn"), Object::string_literal("Green")]),
+                Object::string_literal("Blue"),
+            ]),
+        });
+        let single_id = doc.add_object(dictionary! {
+            "FT" => name("Ch"),
+            "T" => Object::string_literal("Size"),
+        });
+        let acroform = doc.get_dictionary_mut(acroform_id).expect("acroform dictionary");
+        let mut roots = acroform.get(b"Fields").and_then(Object::as_array).expect("fields").clone();
+        roots.push(Object::Reference(list_id));
+        roots.push(Object::Reference(single_id));
+        acroform.set("Fields", Object::Array(roots));
+        let mut input_pdf = Vec::new();
+        doc.save_to(&mut input_pdf).expect("serialize choice fixture");
+
+        let mut fields = HashMap::new();
+        fields.insert("Colours".to_string(), FieldValue::List(vec!["Blue".into(), "Grn".into()]));
+        let output = fill_blocks_impl(&input_pdf, fields).expect("multi-select list should fill");
+        let colours = filled_field(&output, "Colours");
+        let values: Vec<&[u8]> = colours
+            .get(b"V")
+            .and_then(Object::as_array)
+            .expect("list value")
+            .iter()
+            .filter_map(|item| item.as_str().ok())
+            .collect();
+        assert_eq!(values, vec![b"Blue".as_slice(), b"Grn".as_slice()]);
+        let indices: Vec<i64> = colours
+            .get(b"I")
+            .and_then(Object::as_array)
+            .expect("selection indices")
+            .iter()
+            .filter_map(|item| item.as_i64().ok())
+            .collect();
+        assert_eq!(indices, vec![1, 2]);
+
+        let mut fields = HashMap::new();
+        fields.insert("Size".to_string(), FieldValue::List(vec!["S".into(), "M".into()]));
+        let payload = fill_blocks_impl(&input_pdf, fields).expect_err("single-select list should fail");
+        assert_eq!(payload.code, "BW_FILL_VALUE_TYPE_MISMATCH");
+        assert_eq!(payload.context.as_deref(), Some("Size"));
+    }
+
+    #[test]
+    fn fill_blocks_selects_radio_by_option_export_value() {
+        let radio_fixture = |flags: i64, exports: [&str; 2]| {
+            let mut doc = Document::load_mem(&make_fixture_pdf()).expect("fixture parses");
+            for dict in doc.objects.values_mut().filter_map(|obj| obj.as_dict_mut().ok()) {
+                if dict.get(b"T").ok().and_then(object_to_text).as_deref() == Some("Choice") {
+                    dict.set("Ff", Object::Integer(flags));
+                    dict.set("Opt", exports.iter().map(|export| Object::string_literal(*export)).collect::<Vec<_>>());
+                } else if let Ok(Object::Dictionary(ap)) = dict.get_mut(b"AP") {
+                    let normal = ap.get_mut(b"N").and_then(Object::as_dict_mut).expect("normal appearances");
+                    for (from, to) in [(b"A", "0"), (b"B", "1")] {
+                        if normal.remove(from).is_some() {
+                            normal.set(to, Object::Null);
+                        }
+                    }
+                }
+            }
+            let mut pdf = Vec::new();
+            doc.save_to(&mut pdf).expect("serialize radio fixture");
+            pdf
+        };
+        let states = |output: &[u8]| {
+            let doc = Document::load_mem(output).expect("filled payload should parse");
+            let mut states: Vec<String> = doc
+                .objects
+                .values()
+                .filter_map(|obj| obj.as_dict().ok())
+                .filter(|dict| dict.has(b"Parent"))
+                .filter_map(|dict| dict.get(b"AS").ok().and_then(object_to_name))
+                .collect();
+            states.sort();
+            states
+        };
+
+        let input_pdf = radio_fixture(FF_RADIO | FF_NO_TOGGLE_TO_OFF, ["Single", "Married"]);
+        let mut fields = HashMap::new();
+        fields.insert("Choice".to_string(), FieldValue::from("married"));
+        let output = fill_blocks_impl(&input_pdf, fields).expect("export value selects its widget");
+        assert_eq!(filled_field(&output, "Choice").get(b"V").ok().and_then(object_to_name).as_deref(), Some("1"));
+        assert_eq!(states(&output), vec!["1", "Off"]);
+
+        let mut fields = HashMap::new();
+        fields.insert("Choice".to_string(), FieldValue::Clear);
+        let payload = fill_blocks_impl(&output, fields.clone()).expect_err("NoToggleToOff keeps a selection");
+        assert_eq!(payload.code, "BW_FILL_BUTTON_CLEAR_REFUSED");
+        assert!(fill_blocks_impl(&input_pdf, fields).is_ok(), "an unselected group may still be cleared");
+
+        let mut form = FormDocument::load(&output).expect("filled radio loads");
+        let mut fields = HashMap::new();
+        fields.insert("Choice".to_string(), FieldValue::from(""));
+        fields.insert("Name".to_string(), FieldValue::from("Ada Lovelace"));
+        let report = form.fill_with_policy(&fields, PartialNamePolicy::default()).expect("other keys still fill");
+        assert_eq!(report.filled, 1);
+        let codes: Vec<&str> = report.field_errors.iter().map(CoreError::code).collect();
+        assert_eq!(codes, vec!["BW_FILL_BUTTON_CLEAR_REFUSED"]);
+        let output = form.save().expect("save");
+        assert_eq!(states(&output), vec!["1", "Off"], "the selection is kept");
+        let name = filled_field(&output, "Name").get(b"V").ok().and_then(object_to_text);
+        assert_eq!(name.as_deref(), Some("Ada Lovelace"));
+        let payload = fill_blocks_impl(&output, fields).expect_err("the bytes-only export cannot report it");
+        assert_eq!(payload.code, "BW_FILL_BUTTON_CLEAR_REFUSED");
+
+        let input_pdf = radio_fixture(FF_RADIO | FF_RADIOS_IN_UNISON, ["Same", "Other"]);
+        let mut doc = Document::load_mem(&input_pdf).expect("radio fixture parses");
+        for dict in doc.objects.values_mut().filter_map(|obj| obj.as_dict_mut().ok()) {
+            if let Ok(Object::Dictionary(ap)) = dict.get_mut(b"AP") {
+                let normal = ap.get_mut(b"N").and_then(Object::as_dict_mut).expect("normal appearances");
+                if normal.remove(b"1").is_some() {
+                    normal.set("0", Object::Null);
+                }
+            }
+        }
+        let mut input_pdf = Vec::new();
+        doc.save_to(&mut input_pdf).expect("serialize unison fixture");
+        let mut fields = HashMap::new();
+        fields.insert("Choice".to_string(), FieldValue::from("Same"));
+        let output = fill_blocks_impl(&input_pdf, fields).expect("unison group fills");
+        assert_eq!(states(&output), vec!["0", "0"]);
+    }
 }
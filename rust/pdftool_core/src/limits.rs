@@ -0,0 +1,563 @@
+// SPDX-License-Identifier: PMPL-1.0-or-later WITH Palimpsest
+
+//! Resource budget for untrusted input.
+//!
+//! [`Limits`] caps what a single document may cost: the object count and the
+//! decompressed size of object and cross-reference streams are checked before
+//! the parser sees the bytes, streams decoded later (object streams, page
+//! content, scripts) go through [`stream_content`] under the same size cap,
+//! the field tree walk is bounded in depth and field count, and every pass
+//! over fields or widgets draws from a step and wall-clock budget. Exceeding
+//! any limit fails with a `BW_LIMIT_*` code.
+//!
+//! The size cap applies to every stage of a filter chain, so
+//! `[/ASCII85Decode /FlateDecode]` is bounded like plain `/FlateDecode`.
+//! lopdf's own parse of the file (`Document::load_mem`) runs outside the
+//! step budget and cannot be interrupted: the pre-parse scan bounds what it
+//! inflates and materialises, and the wall clock is checked once it returns.
+
+use std::io::{self, Read};
+
+use flate2::read::ZlibDecoder;
+use lopdf::{dictionary, Object, Stream};
+use serde::{Deserialize, Serialize};
+use weezl::{decode::Decoder as LzwDecoder, BitOrder, LzwStatus};
+
+use crate::{core_error_with_context, CoreError, CoreResult};
+
+/// How often (in steps) the wall clock is consulted.
+const CLOCK_INTERVAL: u64 = 1024;
+
+/// Bytes searched after an `obj` header for the start of its stream.
+const HEADER_WINDOW: usize = 4096;
+
+/// Resource limits applied while loading and processing a document.
+///
+/// Every limit is per document; the step and time budgets restart for each
+/// operation (load, detect, fill, flatten). While loading, the object and
+/// stream limits are checked before lopdf parses the bytes; the step budget
+/// covers the repair scan, and the time limit is checked after parsing, not
+/// during it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Limits {
+    /// Indirect objects, including those packed in object streams.
+    pub max_objects: usize,
+    /// Decompressed bytes of any one stream after each of its filters:
+    /// object and cross-reference streams while loading, content and script
+    /// streams when read.
+    pub max_stream_bytes: u64,
+    /// Nesting depth of the field tree. Nesting inside objects (arrays,
+    /// dictionaries) is left to lopdf's parser.
+    pub max_depth: usize,
+    /// Field nodes in the field tree.
+    pub max_fields: usize,
+    /// Units of work (fields, widgets, annotations) per operation.
+    pub max_steps: u64,
+    /// Wall-clock milliseconds per operation; `None` disables the check.
+    pub max_millis: Option<u64>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_objects: 2_000_000,
+            max_stream_bytes: 256 * 1024 * 1024,
+            max_depth: 48,
+            max_fields: 100_000,
+            max_steps: 50_000_000,
+            max_millis: Some(30_000),
+        }
+    }
+}
+
+impl Limits {
+    /// No limits at all, for trusted input.
+    pub fn unlimited() -> Self {
+        Self {
+            max_objects: usize::MAX,
+            max_stream_bytes: u64::MAX,
+            max_depth: usize::MAX,
+            max_fields: usize::MAX,
+            max_steps: u64::MAX,
+            max_millis: None,
+        }
+    }
+
+    pub(crate) fn check_objects(&self, count: usize, context: &str) -> CoreResult<()> {
+        if count > self.max_objects {
+            return Err(exceeded(
+                "BW_LIMIT_OBJECTS",
+                format!("document has more than {} objects", self.max_objects),
+                context,
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn exceeded(code: &'static str, message: String, context: &str) -> CoreError {
+    core_error_with_context(code, message, Some(context.into()))
+}
+
+/// Step and wall-clock budget for one operation.
+pub(crate) struct Budget<'a> {
+    limits: &'a Limits,
+    steps: u64,
+    stopwatch: Stopwatch,
+}
+
+impl<'a> Budget<'a> {
+    pub(crate) fn new(limits: &'a Limits) -> Self {
+        Self {
+            limits,
+            steps: 0,
+            stopwatch: Stopwatch::start(),
+        }
+    }
+
+    pub(crate) fn limits(&self) -> &Limits {
+        self.limits
+    }
+
+    /// Spends one step; `context` names the pass for the error report.
+    pub(crate) fn tick(&mut self, context: &str) -> CoreResult<()> {
+        self.steps += 1;
+        if self.steps > self.limits.max_steps {
+            return Err(exceeded(
+                "BW_LIMIT_STEPS",
+                format!("operation exceeded {} steps", self.limits.max_steps),
+                context,
+            ));
+        }
+        if self.steps.is_multiple_of(CLOCK_INTERVAL) {
+            self.check_time(context)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn check_time(&self, context: &str) -> CoreResult<()> {
+        match (self.limits.max_millis, self.stopwatch.elapsed_millis()) {
+            (Some(max), Some(elapsed)) if elapsed > max => Err(exceeded(
+                "BW_LIMIT_TIME",
+                format!("operation exceeded {} ms", max),
+                context,
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// `std::time::Instant` panics on `wasm32-unknown-unknown`, so the browser
+/// build reads `Date.now()` instead.
+struct Stopwatch {
+    #[cfg(not(target_arch = "wasm32"))]
+    started: std::time::Instant,
+    #[cfg(all(target_arch = "wasm32", feature = "wasm"))]
+    started: f64,
+}
+
+impl Stopwatch {
+    fn start() -> Self {
+        Self {
+            #[cfg(not(target_arch = "wasm32"))]
+            started: std::time::Instant::now(),
+            #[cfg(all(target_arch = "wasm32", feature = "wasm"))]
+            started: js_sys::Date::now(),
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn elapsed_millis(&self) -> Option<u64> {
+        Some(self.started.elapsed().as_millis() as u64)
+    }
+
+    #[cfg(all(target_arch = "wasm32", feature = "wasm"))]
+    fn elapsed_millis(&self) -> Option<u64> {
+        Some((js_sys::Date::now() - self.started).max(0.0) as u64)
+    }
+
+    #[cfg(all(target_arch = "wasm32", not(feature = "wasm")))]
+    fn elapsed_millis(&self) -> Option<u64> {
+        None
+    }
+}
+
+/// Pre-parse scan of the raw bytes.
+///
+/// The parser materialises every object and inflates object and
+/// cross-reference streams while loading, so those are bounded here: `obj`
+/// headers (plus the `/N` count of each object stream) are counted against
+/// `max_objects`, and `/ObjStm` and `/XRef` streams are decoded through their
+/// `/Filter` chain under `max_stream_bytes`. `/DecodeParms` is not read here,
+/// so predictors are not undone between stages.
+pub(crate) fn check_input(pdf_data: &[u8], limits: &Limits) -> CoreResult<()> {
+    let mut objects = 0usize;
+    let mut position = 0;
+    while let Some(found) = find(&pdf_data[position..], b"obj") {
+        let at = position + found;
+        position = at + 3;
+        let Some((number, generation, _)) = object_header(pdf_data, at) else {
+            continue;
+        };
+        objects += 1;
+        let window = &pdf_data[position..pdf_data.len().min(position + HEADER_WINDOW)];
+        let dict = match (find(window, b"stream"), find(window, b"endobj")) {
+            (Some(stream), Some(end)) if end < stream => continue,
+            (Some(stream), _) => &window[..stream],
+            (None, _) => continue,
+        };
+        let object_stream = find(dict, b"/ObjStm").is_some();
+        if object_stream {
+            objects = objects.saturating_add(integer_after(dict, b"/N").unwrap_or(0));
+        }
+        let filters = filter_names(dict);
+        if !(object_stream || find(dict, b"/XRef").is_some()) || filters.is_empty() {
+            continue;
+        }
+        let data_start = stream_data_start(pdf_data, position + dict.len() + b"stream".len());
+        let data = &pdf_data[data_start..];
+        let data = match integer_after(dict, b"/Length") {
+            Some(length) if length <= data.len() && !is_reference_after(dict, b"/Length") => &data[..length],
+            _ => &data[..find(data, b"endstream").unwrap_or(data.len())],
+        };
+        decode_filters(data, &filters, None, limits, &format!("object {} {}", number, generation))?;
+        position = data_start + data.len();
+    }
+    limits.check_objects(objects, "input scan")
+}
+
+/// Decoded content of a stream read after loading, capped at
+/// `max_stream_bytes` after every filter of its chain. Data lopdf cannot
+/// decode is returned as stored, as `Document::get_page_content` does.
+pub(crate) fn stream_content(stream: &Stream, limits: &Limits, context: &str) -> CoreResult<Vec<u8>> {
+    let filters = stream.filters().unwrap_or_default();
+    let params = stream.dict.get(b"DecodeParms").ok();
+    let content = decode_filters(&stream.content, &filters, params, limits, context)?;
+    let content = content.unwrap_or_else(|| stream.content.clone());
+    if content.len() as u64 > limits.max_stream_bytes {
+        return Err(stream_too_large(limits, context));
+    }
+    Ok(content)
+}
+
+/// Decodes `data` through `filters` in order, or `None` when there are no
+/// filters or one of them is not one lopdf decodes. Flate and LZW stages are
+/// first decoded into a sink that stops one byte past `max_stream_bytes`, so
+/// a compression bomb fails before it is decoded into memory.
+fn decode_filters(
+    data: &[u8],
+    filters: &[String],
+    params: Option<&Object>,
+    limits: &Limits,
+    context: &str,
+) -> CoreResult<Option<Vec<u8>>> {
+    let max = limits.max_stream_bytes;
+    let mut decoded: Option<Vec<u8>> = None;
+    for filter in filters {
+        let input = decoded.as_deref().unwrap_or(data);
+        let bomb = match filter.as_str() {
+            "FlateDecode" => inflated_exceeds(input, max),
+            "LZWDecode" => lzw_exceeds(input, params, max),
+            _ => false,
+        };
+        if bomb {
+            return Err(stream_too_large(limits, context));
+        }
+        let mut dict = dictionary! { "Filter" => filter.as_str() };
+        if let Some(params) = params {
+            dict.set("DecodeParms", params.clone());
+        }
+        let Ok(output) = Stream::new(dict, input.to_vec()).decompressed_content() else {
+            return Ok(None);
+        };
+        if output.len() as u64 > max {
+            return Err(stream_too_large(limits, context));
+        }
+        decoded = Some(output);
+    }
+    Ok(decoded)
+}
+
+fn stream_too_large(limits: &Limits, context: &str) -> CoreError {
+    exceeded(
+        "BW_LIMIT_STREAM_SIZE",
+        format!("stream inflates past {} bytes", limits.max_stream_bytes),
+        context,
+    )
+}
+
+/// Whether `data` inflates to more than `max` bytes. Corrupt data is left for
+/// the parser to report.
+fn inflated_exceeds(data: &[u8], max: u64) -> bool {
+    let mut decoder = ZlibDecoder::new(data).take(max.saturating_add(1));
+    matches!(io::copy(&mut decoder, &mut io::sink()), Ok(inflated) if inflated > max)
+}
+
+/// Whether LZW `data` decodes to more than `max` bytes, reading
+/// `/EarlyChange` as lopdf does.
+fn lzw_exceeds(data: &[u8], params: Option<&Object>, max: u64) -> bool {
+    let early_change = params
+        .and_then(|params| params.as_dict().ok())
+        .and_then(|params| params.get(b"EarlyChange").ok())
+        .and_then(|value| value.as_i64().ok());
+    let mut decoder = match early_change {
+        Some(0) => LzwDecoder::new(BitOrder::Msb, 8),
+        _ => LzwDecoder::with_tiff_size_switch(BitOrder::Msb, 8),
+    };
+    let mut buffer = [0u8; 8192];
+    let (mut input, mut decoded) = (data, 0u64);
+    while decoded <= max {
+        let result = decoder.decode_bytes(input, &mut buffer);
+        decoded += result.consumed_out as u64;
+        input = &input[result.consumed_in..];
+        if !matches!(result.status, Ok(LzwStatus::Ok)) {
+            break;
+        }
+    }
+    decoded > max
+}
+
+/// Names in the `/Filter` entry of raw dictionary bytes, which holds a
+/// single name or an array of them.
+fn filter_names(dict: &[u8]) -> Vec<String> {
+    let mut rest = dict;
+    let value = loop {
+        let Some(found) = find(rest, b"/Filter") else {
+            return Vec::new();
+        };
+        rest = &rest[found + b"/Filter".len()..];
+        if !rest.first().is_some_and(u8::is_ascii_alphanumeric) {
+            break &rest[rest.iter().take_while(|byte| byte.is_ascii_whitespace()).count()..];
+        }
+    };
+    let names = match value.first() {
+        Some(b'[') => &value[1..value.iter().position(|&byte| byte == b']').unwrap_or(value.len())],
+        Some(b'/') => &value[..1 + value[1..].iter().take_while(|&&byte| !is_delimiter(byte)).count()],
+        _ => return Vec::new(),
+    };
+    names
+        .split(|&byte| byte == b'/')
+        .skip(1)
+        .map(|name| {
+            let length = name.iter().take_while(|&&byte| !is_delimiter(byte)).count();
+            String::from_utf8_lossy(&name[..length]).into_owned()
+        })
+        .collect()
+}
+
+fn is_delimiter(byte: u8) -> bool {
+    byte.is_ascii_whitespace() || b"()<>[]{}/%".contains(&byte)
+}
+
+/// Object number, generation and start offset of the indirect object header
+/// (`N G obj`) whose `obj` keyword is at `at`.
+pub(crate) fn object_header(data: &[u8], at: usize) -> Option<(u32, u16, usize)> {
+    if data.get(at + 3).is_some_and(|byte| byte.is_ascii_alphanumeric()) {
+        return None;
+    }
+    let mut cursor = at;
+    let mut numbers = Vec::new();
+    for _ in 0..2 {
+        let end = skip_back(data, cursor, u8::is_ascii_whitespace);
+        if end == cursor {
+            return None;
+        }
+        let start = skip_back(data, end, u8::is_ascii_digit);
+        if start == end {
+            return None;
+        }
+        numbers.push(std::str::from_utf8(&data[start..end]).ok()?);
+        cursor = start;
+    }
+    if cursor > 0 && !data[cursor - 1].is_ascii_whitespace() {
+        return None;
+    }
+    Some((numbers[1].parse().ok()?, numbers[0].parse().ok()?, cursor))
+}
+
+fn skip_back(data: &[u8], mut end: usize, accept: impl Fn(&u8) -> bool) -> usize {
+    while end > 0 && accept(&data[end - 1]) {
+        end -= 1;
+    }
+    end
+}
+
+/// Skips the end-of-line after the `stream` keyword.
+fn stream_data_start(data: &[u8], mut at: usize) -> usize {
+    if data.get(at) == Some(&b'\r') {
+        at += 1;
+    }
+    if data.get(at) == Some(&b'\n') {
+        at += 1;
+    }
+    at.min(data.len())
+}
+
+/// The integer following `key`, skipping longer keys that share its prefix.
+fn integer_after(dict: &[u8], key: &[u8]) -> Option<usize> {
+    let mut rest = dict;
+    while let Some(found) = find(rest, key) {
+        rest = &rest[found + key.len()..];
+        if rest.first().is_some_and(u8::is_ascii_alphanumeric) {
+            continue;
+        }
+        let value = &rest[rest.iter().take_while(|byte| byte.is_ascii_whitespace()).count()..];
+        let digits = value.iter().take_while(|byte| byte.is_ascii_digit()).count();
+        if let Some(parsed) = std::str::from_utf8(&value[..digits]).ok().and_then(|digits| digits.parse().ok()) {
+            return Some(parsed);
+        }
+    }
+    None
+}
+
+/// Whether `key` is followed by an indirect reference (`N G R`) rather than
+/// a direct integer.
+fn is_reference_after(dict: &[u8], key: &[u8]) -> bool {
+    let Some(found) = find(dict, key) else {
+        return false;
+    };
+    let rest = &dict[found + key.len()..];
+    let tokens: Vec<&[u8]> = rest
+        .split(|byte| byte.is_ascii_whitespace())
+        .filter(|token| !token.is_empty())
+        .take(3)
+        .collect();
+    tokens.len() == 3 && tokens[2].starts_with(b"R")
+}
+
+pub(crate) fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    fn deflated_spaces(inflated: usize) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(&vec![b' '; inflated]).expect("compress");
+        encoder.finish().expect("compress")
+    }
+
+    fn object_stream_pdf(inflated: usize) -> Vec<u8> {
+        encoded_object_stream_pdf("/FlateDecode", deflated_spaces(inflated))
+    }
+
+    fn encoded_object_stream_pdf(filter: &str, packed: Vec<u8>) -> Vec<u8> {
+        let mut pdf = b"%PDF-1.7\n".to_vec();
+        pdf.extend_from_slice(
+            format!("1 0 obj\n<< /Type /ObjStm /N 1 /First 4 /Filter {} /Length {} >>\nstream\n", filter, packed.len())
+                .as_bytes(),
+        );
+        pdf.extend_from_slice(&packed);
+        pdf.extend_from_slice(b"\nendstream\nendobj\n%%EOF\n");
+        pdf
+    }
+
+    #[test]
+    fn object_stream_inflation_is_bounded() {
+        let limits = Limits {
+            max_stream_bytes: 64 * 1024,
+            ..Limits::default()
+        };
+        check_input(&object_stream_pdf(1024), &limits).expect("small stream passes");
+        let error = check_input(&object_stream_pdf(1024 * 1024), &limits).expect_err("bomb rejected");
+        assert_eq!(error.code(), "BW_LIMIT_STREAM_SIZE");
+        assert_eq!(error.context(), Some("object 1 0"));
+    }
+
+    #[test]
+    fn streams_read_later_are_capped() {
+        let limits = Limits {
+            max_stream_bytes: 64 * 1024,
+            ..Limits::default()
+        };
+        let flate = |inflated| Stream::new(lopdf::dictionary! { "Filter" => "FlateDecode" }, deflated_spaces(inflated));
+        assert_eq!(stream_content(&flate(1024), &limits, "page 1").expect("small stream decodes"), vec![b' '; 1024]);
+        let error = stream_content(&flate(1024 * 1024), &limits, "page 1").expect_err("bomb rejected");
+        assert_eq!(error.code(), "BW_LIMIT_STREAM_SIZE");
+        assert_eq!(error.context(), Some("page 1"));
+
+        let plain = Stream::new(lopdf::Dictionary::new(), vec![b' '; 64 * 1024 + 1]);
+        let error = stream_content(&plain, &limits, "page 1").expect_err("raw size counts");
+        assert_eq!(error.code(), "BW_LIMIT_STREAM_SIZE");
+    }
+
+    fn ascii85(data: &[u8]) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        for chunk in data.chunks(4) {
+            let mut word = [0u8; 4];
+            word[..chunk.len()].copy_from_slice(chunk);
+            let mut value = u32::from_be_bytes(word);
+            let mut digits = [0u8; 5];
+            for digit in digits.iter_mut().rev() {
+                *digit = b'!' + (value % 85) as u8;
+                value /= 85;
+            }
+            encoded.extend_from_slice(&digits[..chunk.len() + 1]);
+        }
+        encoded.extend_from_slice(b"~>");
+        encoded
+    }
+
+    #[test]
+    fn filter_chains_are_capped_at_every_stage() {
+        let limits = Limits {
+            max_stream_bytes: 64 * 1024,
+            ..Limits::default()
+        };
+        let chain = || vec![Object::from("ASCII85Decode"), Object::from("FlateDecode")];
+        let wrapped = |inflated| Stream::new(dictionary! { "Filter" => chain() }, ascii85(&deflated_spaces(inflated)));
+        assert_eq!(stream_content(&wrapped(1024), &limits, "page 1").expect("small chain decodes"), vec![b' '; 1024]);
+        let error = stream_content(&wrapped(1024 * 1024), &limits, "page 1").expect_err("wrapped bomb rejected");
+        assert_eq!(error.code(), "BW_LIMIT_STREAM_SIZE");
+
+        let lzw = |inflated| {
+            let encoded = weezl::encode::Encoder::with_tiff_size_switch(BitOrder::Msb, 8).encode(&vec![b' '; inflated]);
+            Stream::new(dictionary! { "Filter" => "LZWDecode" }, encoded.expect("compress"))
+        };
+        assert_eq!(stream_content(&lzw(1024), &limits, "page 1").expect("small LZW decodes"), vec![b' '; 1024]);
+        let error = stream_content(&lzw(1024 * 1024), &limits, "page 1").expect_err("LZW bomb rejected");
+        assert_eq!(error.code(), "BW_LIMIT_STREAM_SIZE");
+
+        let filter = "[ /ASCII85Decode/FlateDecode ]";
+        assert_eq!(filter_names(format!("<< /Filter{} >>", filter).as_bytes()), ["ASCII85Decode", "FlateDecode"]);
+        check_input(&encoded_object_stream_pdf(filter, ascii85(&deflated_spaces(1024))), &limits).expect("passes");
+        let bomb = encoded_object_stream_pdf(filter, ascii85(&deflated_spaces(1024 * 1024)));
+        assert_eq!(check_input(&bomb, &limits).expect_err("wrapped bomb rejected").code(), "BW_LIMIT_STREAM_SIZE");
+    }
+
+    #[test]
+    fn object_headers_count_against_the_object_limit() {
+        let limits = Limits {
+            max_objects: 2,
+            ..Limits::default()
+        };
+        let pdf = b"%PDF-1.7\n1 0 obj\n<<>>\nendobj\n2 0 obj\n<<>>\nendobj\n3 0 obj\n1\nendobj\n";
+        assert_eq!(check_input(pdf, &limits).expect_err("too many").code(), "BW_LIMIT_OBJECTS");
+        check_input(&pdf[..49], &limits).expect("two objects pass");
+        // The object stream's /N counts as well.
+        assert_eq!(check_input(&object_stream_pdf(16), &Limits { max_objects: 1, ..Limits::default() })
+            .expect_err("packed objects count")
+            .code(), "BW_LIMIT_OBJECTS");
+    }
+
+    #[test]
+    fn step_budget_fails_with_a_limit_code() {
+        let limits = Limits {
+            max_steps: 3,
+            ..Limits::default()
+        };
+        let mut budget = Budget::new(&limits);
+        for _ in 0..3 {
+            budget.tick("test").expect("within budget");
+        }
+        let error = budget.tick("test").expect_err("over budget");
+        assert_eq!(error.code(), "BW_LIMIT_STEPS");
+        assert_eq!(error.context(), Some("test"));
+    }
+}
@@ -0,0 +1,309 @@
+// SPDX-License-Identifier: PMPL-1.0-or-later WITH Palimpsest
+
+//! Template mappings from semantic keys to field names.
+//!
+//! Integrations send data under their own keys (`applicant.first_name`); a
+//! [`Mapping`] binds those keys to one form's field names and is stored with
+//! that form's fingerprint hash, so it can be picked automatically for any
+//! copy of the template. A mapped fill resolves the data through the
+//! bindings into ordinary field values and then goes through the usual key
+//! matching, so bindings may name fields by full name, partial name or path
+//! expression (`Items[*].Qty`). Each binding may reshape its value with
+//! [`Transform`] steps first.
+//!
+//! Mappings match forms by exact hash, so a mapping whose `fingerprint` was
+//! computed under an earlier [`FINGERPRINT_VERSION`](crate::FINGERPRINT_VERSION)
+//! no longer matches and needs the hash regenerated from the template.
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "name": "Passport renewal",
+//!   "fingerprint": "3f1c…",
+//!   "bindings": [
+//!     { "field": "f1_01[0]", "key": "applicant.first_name", "transform": ["trim", "upper"] },
+//!     { "field": "Items[*].Qty", "key": "items[*].quantity" }
+//!   ]
+//! }
+//! ```
+
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::value::format_number;
+use crate::transform::{self, Transform};
+use crate::{core_error_with_context, CoreError, CoreResult, FieldValue, FormFingerprint};
+
+/// The mapping format version this build reads and writes.
+pub const MAPPING_VERSION: u32 = 1;
+
+/// Semantic data as sent by an integration: nested objects and lists, read
+/// with dotted keys (`applicant.first_name`, `items[2].quantity`).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum DataValue {
+    Null,
+    Bool(bool),
+    Number(f64),
+    Text(String),
+    List(Vec<DataValue>),
+    Object(BTreeMap<String, DataValue>),
+}
+
+/// Binds one semantic key to one field.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Binding {
+    /// Field name, partial name or path expression.
+    pub field: String,
+    /// Dotted key into the semantic data; `[*]` collects every list item.
+    /// A binding without one builds its value with `concat` or `default`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    /// [`Transform`] steps applied in order.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub transform: Vec<Transform>,
+}
+
+/// Semantic keys bound to the fields of one form template.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Mapping {
+    #[serde(default = "default_version")]
+    pub version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Hash of the fingerprint of the form the mapping was written for.
+    pub fingerprint: String,
+    pub bindings: Vec<Binding>,
+}
+
+fn default_version() -> u32 {
+    MAPPING_VERSION
+}
+
+impl Mapping {
+    /// Rejects mapping versions this build cannot read.
+    pub fn check_version(&self) -> CoreResult<()> {
+        if self.version == 0 || self.version > MAPPING_VERSION {
+            return Err(core_error_with_context(
+                "BW_MAPPING_VERSION_UNSUPPORTED",
+                format!("mapping version {} is not supported (latest is {})", self.version, MAPPING_VERSION),
+                self.name.clone(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Checks the version and every binding's transform definitions;
+    /// `BW_MAPPING_TRANSFORM_INVALID` names the first bad binding's field.
+    pub fn validate(&self) -> CoreResult<()> {
+        self.check_version()?;
+        for binding in &self.bindings {
+            transform::validate(&binding.field, binding.key.as_deref(), &binding.transform)?;
+        }
+        Ok(())
+    }
+}
+
+/// Semantic data resolved through a mapping.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Resolution {
+    /// Value for each bound field.
+    pub values: HashMap<String, FieldValue>,
+    /// Keys the bindings read that the data does not contain, for bindings
+    /// left without a value.
+    pub missing_keys: Vec<String>,
+    /// One `BW_TRANSFORM_FAILED` error per field whose transforms could not
+    /// handle its data; those fields are left out of `values`.
+    pub field_errors: Vec<CoreError>,
+}
+
+/// The mapping in `mappings` written for the form with `fingerprint`.
+pub fn find_mapping<'a>(mappings: &'a [Mapping], fingerprint: &FormFingerprint) -> Option<&'a Mapping> {
+    mappings.iter().find(|mapping| mapping.fingerprint == fingerprint.hash)
+}
+
+/// Flattens nested data into dotted keys; list items are addressed as
+/// `key[n]`, and lists of scalars are also kept whole under `key`.
+fn flatten(prefix: &str, value: &DataValue, out: &mut HashMap<String, FieldValue>) {
+    match value {
+        DataValue::Object(entries) => {
+            for (key, value) in entries {
+                let path = if prefix.is_empty() { key.clone() } else { format!("{}.{}", prefix, key) };
+                flatten(&path, value, out);
+            }
+        }
+        DataValue::List(items) => {
+            let texts: Option<Vec<String>> = items.iter().map(scalar_text).collect();
+            if let Some(texts) = texts {
+                out.insert(prefix.to_owned(), FieldValue::List(texts));
+            }
+            for (position, item) in items.iter().enumerate() {
+                flatten(&format!("{}[{}]", prefix, position), item, out);
+            }
+        }
+        DataValue::Null => {
+            out.insert(prefix.to_owned(), FieldValue::Clear);
+        }
+        DataValue::Bool(flag) => {
+            out.insert(prefix.to_owned(), FieldValue::Bool(*flag));
+        }
+        DataValue::Number(number) => {
+            out.insert(prefix.to_owned(), FieldValue::Number(*number));
+        }
+        DataValue::Text(text) => {
+            out.insert(prefix.to_owned(), FieldValue::Text(text.clone()));
+        }
+    }
+}
+
+fn scalar_text(value: &DataValue) -> Option<String> {
+    match value {
+        DataValue::Text(text) => Some(text.clone()),
+        DataValue::Number(number) => format_number(*number),
+        _ => None,
+    }
+}
+
+/// The value under `key`; a `[*]` in the key collects the matching item of
+/// every list entry, in order, as a list.
+fn lookup(flat: &HashMap<String, FieldValue>, key: &str) -> Option<FieldValue> {
+    let Some((head, tail)) = key.split_once("[*]") else {
+        return flat.get(key).cloned();
+    };
+    let mut items = Vec::new();
+    for position in 0.. {
+        match flat.get(&format!("{}[{}]{}", head, position, tail)) {
+            Some(value) => items.push(value.as_text().unwrap_or_default()),
+            None if flat.contains_key(&format!("{}[{}]", head, position)) => items.push(String::new()),
+            None => break,
+        }
+    }
+    (!items.is_empty()).then_some(FieldValue::List(items))
+}
+
+/// Field values for `data` under `mapping`. The mapping is validated first;
+/// a binding whose transforms fail on its data is reported and skipped.
+pub fn resolve_mapping(mapping: &Mapping, data: &BTreeMap<String, DataValue>) -> CoreResult<Resolution> {
+    mapping.validate()?;
+    let mut flat = HashMap::new();
+    flatten("", &DataValue::Object(data.clone()), &mut flat);
+
+    let mut resolution = Resolution::default();
+    for binding in &mapping.bindings {
+        let mut absent = Vec::new();
+        let mut read = |key: &str| {
+            let value = lookup(&flat, key);
+            if value.is_none() {
+                absent.push(key.to_owned());
+            }
+            value
+        };
+        let value = binding.key.as_deref().and_then(&mut read);
+        match transform::apply(&binding.field, value, &binding.transform, &mut read) {
+            Ok(Some(value)) => {
+                resolution.values.insert(binding.field.clone(), value);
+            }
+            Ok(None) => {
+                for key in absent {
+                    if !resolution.missing_keys.contains(&key) {
+                        resolution.missing_keys.push(key);
+                    }
+                }
+            }
+            Err(error) => resolution.field_errors.push(error),
+        }
+    }
+    Ok(resolution)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::make_fixture_pdf;
+    use crate::FormDocument;
+
+    fn data(json: &str) -> BTreeMap<String, DataValue> {
+        serde_json::from_str(json).expect("data JSON")
+    }
+
+    #[test]
+    fn nested_keys_and_list_items_resolve() {
+        let mapping: Mapping = serde_json::from_str(
+            r#"{
+                "fingerprint": "abc",
+                "bindings": [
+                    { "field": "f1_01[0]", "key": "applicant.first_name", "transform": ["trim", "upper"] },
+                    { "field": "Items[*].Qty", "key": "items[*].quantity" },
+                    { "field": "Tags", "key": "tags" },
+                    { "field": "Fax", "key": "applicant.fax" },
+                    { "field": "Sex", "key": "applicant.sex", "transform": [{ "lookup": { "table": { "M": "Male" } } }] }
+                ]
+            }"#,
+        )
+        .expect("mapping JSON");
+        assert_eq!(mapping.version, MAPPING_VERSION);
+        let Resolution {
+            values,
+            missing_keys,
+            field_errors,
+        } = resolve_mapping(
+            &mapping,
+            &data(r#"{ "applicant": { "first_name": " ada ", "sex": "X" }, "items": [{ "quantity": 4 }, { "quantity": 2 }], "tags": ["a", "b"] }"#),
+        )
+        .expect("resolves");
+        assert_eq!(values["f1_01[0]"], FieldValue::from("ADA"));
+        assert_eq!(values["Items[*].Qty"], FieldValue::List(vec!["4".into(), "2".into()]));
+        assert_eq!(values["Tags"], FieldValue::List(vec!["a".into(), "b".into()]));
+        assert_eq!(missing_keys, ["applicant.fax"]);
+        assert!(!values.contains_key("Sex"));
+        assert_eq!(field_errors.len(), 1);
+        assert_eq!((field_errors[0].code(), field_errors[0].context()), ("BW_TRANSFORM_FAILED", Some("Sex")));
+
+        let future = Mapping {
+            version: MAPPING_VERSION + 1,
+            ..mapping
+        };
+        let error = resolve_mapping(&future, &BTreeMap::new()).expect_err("newer format");
+        assert_eq!(error.code(), "BW_MAPPING_VERSION_UNSUPPORTED");
+    }
+
+    #[test]
+    fn mapped_fill_checks_the_fingerprint() {
+        let mut form = FormDocument::load(&make_fixture_pdf()).expect("loads");
+        let fingerprint = form.fingerprint().expect("fingerprint");
+        let mut mapping = Mapping {
+            version: MAPPING_VERSION,
+            name: Some("fixture".into()),
+            fingerprint: fingerprint.hash.clone(),
+            bindings: vec![
+                Binding {
+                    field: "Name".into(),
+                    key: Some("person.name".into()),
+                    transform: Vec::new(),
+                },
+                Binding {
+                    field: "Consent".into(),
+                    key: Some("person.agrees".into()),
+                    transform: Vec::new(),
+                },
+            ],
+        };
+        let others = [Mapping {
+            fingerprint: "other".into(),
+            ..mapping.clone()
+        }];
+        assert!(find_mapping(&others, &fingerprint).is_none());
+        assert_eq!(find_mapping(std::slice::from_ref(&mapping), &fingerprint), Some(&mapping));
+
+        let person = data(r#"{ "person": { "name": "Ada Lovelace" } }"#);
+        let report = form.fill_mapped(&person, &mapping, Default::default()).expect("mapped fill");
+        assert_eq!(report.filled, 1);
+        assert_eq!(report.missing_keys, ["person.agrees"]);
+
+        mapping.fingerprint = "stale".into();
+        let error = form.fill_mapped(&person, &mapping, Default::default()).expect_err("other template");
+        assert_eq!(error.code(), "BW_MAPPING_FINGERPRINT_MISMATCH");
+        assert_eq!(error.context(), Some("fixture"));
+    }
+}
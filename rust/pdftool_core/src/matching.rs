@@ -0,0 +1,280 @@
+// SPDX-License-Identifier: PMPL-1.0-or-later WITH Palimpsest
+
+//! Resolution of fill keys to fields.
+//!
+//! A key names a field by its full name (`Applicant.Date`) or, as a fallback,
+//! by its partial name (`Date`). A partial name shared by several fields is
+//! ambiguous; [`PartialNamePolicy`] decides whether such a key fills the
+//! first candidate, all of them, or fails with `BW_FILL_AMBIGUOUS_KEY`.
+//!
+//! Keys containing `[` are path expressions (see [`crate::paths`]): a list
+//! value given for a `[*]` path is spread over the matched rows in order, one
+//! item per row; any other value is written to every matched field. A path
+//! that matches no terminal field is looked up as a partial name instead, so
+//! XFA names such as `f1_01[0]` still work.
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::paths::match_path;
+use crate::{core_error_with_context, CoreError, CoreResult, FieldIndex, FieldValue};
+
+/// What a key that matches several fields by partial name fills.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PartialNamePolicy {
+    /// Fail with `BW_FILL_AMBIGUOUS_KEY`, listing the candidates.
+    #[default]
+    Error,
+    /// Fill the first candidate in field tree order.
+    First,
+    /// Fill every candidate.
+    All,
+}
+
+impl PartialNamePolicy {
+    /// Parses `error`, `first` or `all`.
+    pub fn parse(text: &str) -> Option<Self> {
+        match text {
+            "error" => Some(Self::Error),
+            "first" => Some(Self::First),
+            "all" => Some(Self::All),
+            _ => None,
+        }
+    }
+}
+
+/// A key that matched several fields by partial name.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AmbiguousKey {
+    pub key: String,
+    /// Full names of every field the key matched.
+    pub candidates: Vec<String>,
+    /// Full names of the fields the policy filled.
+    pub applied: Vec<String>,
+}
+
+/// Outcome of a fill.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FillReport {
+    /// Number of fields updated.
+    pub filled: usize,
+    /// Keys resolved under the partial-name policy rather than uniquely.
+    pub ambiguous: Vec<AmbiguousKey>,
+    /// Keys a mapping binds that the semantic data did not contain.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub missing_keys: Vec<String>,
+    /// Fields left unfilled: a mapping's failed transforms, and clears
+    /// refused by NoToggleToOff radio groups (`BW_FILL_BUTTON_CLEAR_REFUSED`).
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub field_errors: Vec<CoreError>,
+}
+
+/// A field's position in the index and the value it receives.
+type Assignment<'a> = (usize, Cow<'a, FieldValue>);
+
+/// The value each field receives, as field positions in tree order, plus the
+/// ambiguous keys that were resolved by `policy`. A full-name match always
+/// takes precedence over a partial-name match for the same field.
+pub(crate) fn match_input_keys<'a>(
+    index: &FieldIndex,
+    values: &'a HashMap<String, FieldValue>,
+    policy: PartialNamePolicy,
+) -> CoreResult<(Vec<Assignment<'a>>, Vec<AmbiguousKey>)> {
+    let mut by_full: HashMap<&str, Vec<usize>> = HashMap::new();
+    let mut by_partial: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (position, field) in index.fields.iter().enumerate() {
+        if let Some(full_name) = &field.full_name {
+            by_full.entry(full_name).or_default().push(position);
+        }
+        if let Some(partial_name) = &field.partial_name {
+            if field.full_name.as_ref() != Some(partial_name) {
+                by_partial.entry(partial_name).or_default().push(position);
+            }
+        }
+    }
+    let full_name = |position: usize| index.fields[position].full_name.clone().unwrap_or_default();
+
+    let mut assigned: BTreeMap<usize, Cow<'a, FieldValue>> = BTreeMap::new();
+    let mut ambiguous = Vec::new();
+    let mut keys: Vec<&String> = values.keys().collect();
+    keys.sort();
+    for key in keys {
+        let value = &values[key];
+        let full = by_full.get(key.as_str()).map(Vec::as_slice).unwrap_or_default();
+        let partial = by_partial.get(key.as_str()).map(Vec::as_slice).unwrap_or_default();
+        for position in full {
+            assigned.insert(*position, Cow::Borrowed(value));
+        }
+        if full.is_empty() {
+            if let Some(matches) = match_path(index, key) {
+                for (position, value) in spread_over_rows(key, value, &matches)? {
+                    assigned.insert(position, value);
+                }
+                continue;
+            }
+        }
+        if !full.is_empty() && policy != PartialNamePolicy::All {
+            continue;
+        }
+        let applied = match (partial.len(), policy) {
+            (0 | 1, _) | (_, PartialNamePolicy::All) => partial,
+            (_, PartialNamePolicy::First) => &partial[..1],
+            (_, PartialNamePolicy::Error) => {
+                let candidates: Vec<String> = partial.iter().map(|position| full_name(*position)).collect();
+                return Err(core_error_with_context(
+                    "BW_FILL_AMBIGUOUS_KEY",
+                    format!("'{}' matches {} fields by partial name", key, candidates.len()),
+                    Some(candidates.join(", ")),
+                ));
+            }
+        };
+        for position in applied {
+            assigned.entry(*position).or_insert(Cow::Borrowed(value));
+        }
+        if partial.len() > 1 {
+            ambiguous.push(AmbiguousKey {
+                key: key.clone(),
+                candidates: partial.iter().map(|position| full_name(*position)).collect(),
+                applied: applied.iter().map(|position| full_name(*position)).collect(),
+            });
+        }
+    }
+
+    Ok((assigned.into_iter().collect(), ambiguous))
+}
+
+/// Values for the fields a path key matched, as `(position, row)`. A list
+/// given for a wildcard path supplies one item per row.
+fn spread_over_rows<'a>(
+    key: &str,
+    value: &'a FieldValue,
+    matches: &[(usize, usize)],
+) -> CoreResult<Vec<Assignment<'a>>> {
+    let FieldValue::List(items) = value else {
+        return Ok(matches.iter().map(|(position, _)| (*position, Cow::Borrowed(value))).collect());
+    };
+    if !key.contains("[*]") {
+        return Ok(matches.iter().map(|(position, _)| (*position, Cow::Borrowed(value))).collect());
+    }
+    let rows = matches.iter().map(|(_, row)| row + 1).max().unwrap_or(0);
+    if items.len() > rows {
+        return Err(core_error_with_context(
+            "BW_FILL_PATH_ROWS_EXCEEDED",
+            format!("{} values given for {} matching rows", items.len(), rows),
+            Some(key.to_owned()),
+        ));
+    }
+    Ok(matches
+        .iter()
+        .filter_map(|(position, row)| Some((*position, Cow::Owned(FieldValue::Text(items.get(*row)?.clone())))))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FormDocument;
+    use lopdf::{dictionary, Document, Object};
+
+    /// Text fields `Applicant.Date`, `Spouse.Date` and `Signed`.
+    fn sections_pdf() -> Vec<u8> {
+        let mut doc = Document::with_version("1.7");
+        let pages_id = doc.new_object_id();
+        let page_id = doc.new_object_id();
+        let mut annots = Vec::new();
+        let mut roots = Vec::new();
+        for (row, section) in ["Applicant", "Spouse"].into_iter().enumerate() {
+            let section_id = doc.new_object_id();
+            let date_id = doc.add_object(dictionary! {
+                "Type" => "Annot",
+                "Subtype" => "Widget",
+                "T" => Object::string_literal("Date"),
+                "Parent" => Object::Reference(section_id),
+                "Rect" => vec![10.into(), (100 * row as i64).into(), 110.into(), (100 * row as i64 + 20).into()],
+            });
+            doc.objects.insert(section_id, Object::Dictionary(dictionary! {
+                "T" => Object::string_literal(section),
+                "FT" => "Tx",
+                "Kids" => vec![Object::Reference(date_id)],
+            }));
+            annots.push(Object::Reference(date_id));
+            roots.push(Object::Reference(section_id));
+        }
+        let signed_id = doc.add_object(dictionary! {
+            "Type" => "Annot",
+            "Subtype" => "Widget",
+            "T" => Object::string_literal("Signed"),
+            "FT" => "Tx",
+            "Rect" => vec![10.into(), 300.into(), 110.into(), 320.into()],
+        });
+        annots.push(Object::Reference(signed_id));
+        roots.push(Object::Reference(signed_id));
+        doc.objects.insert(page_id, Object::Dictionary(dictionary! {
+            "Type" => "Page",
+            "Parent" => Object::Reference(pages_id),
+            "MediaBox" => vec![0.into(), 0.into(), 612.into(), 792.into()],
+            "Annots" => annots,
+        }));
+        doc.objects.insert(pages_id, Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Kids" => vec![Object::Reference(page_id)],
+            "Count" => 1,
+        }));
+        let acroform_id = doc.add_object(dictionary! { "Fields" => roots });
+        let catalog_id = doc.add_object(dictionary! {
+            "Type" => "Catalog",
+            "Pages" => Object::Reference(pages_id),
+            "AcroForm" => Object::Reference(acroform_id),
+        });
+        doc.trailer.set("Root", Object::Reference(catalog_id));
+        let mut pdf = Vec::new();
+        doc.save_to(&mut pdf).expect("sections save");
+        pdf
+    }
+
+    fn values(pairs: &[(&str, &str)]) -> HashMap<String, FieldValue> {
+        pairs.iter().map(|(key, value)| (key.to_string(), FieldValue::from(*value))).collect()
+    }
+
+    #[test]
+    fn shared_partial_names_fail_by_default_and_list_candidates() {
+        let mut form = FormDocument::load(&sections_pdf()).expect("loads");
+        let error = form.fill(&values(&[("Date", "2024-01-01")])).expect_err("ambiguous");
+        assert_eq!(error.code(), "BW_FILL_AMBIGUOUS_KEY");
+        assert_eq!(error.context(), Some("Applicant.Date, Spouse.Date"));
+
+        // Full names and unique partial names are unaffected.
+        assert_eq!(form.fill(&values(&[("Spouse.Date", "2024-01-02"), ("Signed", "yes")])).expect("fill"), 2);
+    }
+
+    #[test]
+    fn policies_are_reported_in_the_fill_result() {
+        let mut form = FormDocument::load(&sections_pdf()).expect("loads");
+        let report = form
+            .fill_with_policy(&values(&[("Date", "2024-01-01")]), PartialNamePolicy::First)
+            .expect("first");
+        assert_eq!(report.filled, 1);
+        assert_eq!(
+            report.ambiguous,
+            vec![AmbiguousKey {
+                key: "Date".into(),
+                candidates: vec!["Applicant.Date".into(), "Spouse.Date".into()],
+                applied: vec!["Applicant.Date".into()],
+            }]
+        );
+
+        let report = form
+            .fill_with_policy(&values(&[("Date", "2024-01-03"), ("Spouse.Date", "2024-01-04")]), PartialNamePolicy::All)
+            .expect("all");
+        assert_eq!(report.filled, 2);
+        assert_eq!(report.ambiguous[0].applied.len(), 2);
+        let extracted = form.extract().expect("extract");
+        let value_of = |name: &str| extracted.iter().find(|field| field.name == name).map(|field| field.value.clone());
+        assert_eq!(value_of("Applicant.Date"), Some(FieldValue::Text("2024-01-03".into())));
+        assert_eq!(value_of("Spouse.Date"), Some(FieldValue::Text("2024-01-04".into())), "full name wins");
+    }
+}
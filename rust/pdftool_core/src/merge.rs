@@ -0,0 +1,416 @@
+// SPDX-License-Identifier: PMPL-1.0-or-later WITH Palimpsest
+
+//! Combining forms into one document.
+//!
+//! Full field names must stay unique in the combined form, so a source's
+//! field tree can first be moved under a new non-terminal field whose `/T`
+//! is a prefix: `Name` becomes `record2.Name` while the original field
+//! dictionaries, values and appearances are left as they are. Appending then
+//! renumbers the source's objects past the target's, hangs its pages off the
+//! target's page tree (copying inherited page attributes onto each page) and
+//! its field roots off the target's `AcroForm.Fields`, and carries over the
+//! default resources (`/DR`) and appearance (`/DA`) the fields rely on and
+//! the calculation order (`/CO`). A `/DR` font whose name the target already
+//! uses for another font is renamed (`F1` becomes `F1_1`) in the appended
+//! fields' `/DA`.
+//!
+//! [`FormDocument::append`] prefixes a copy only when its top-level field
+//! names collide with the target's, choosing the first free `copy<n>`.
+
+use std::collections::{HashMap, HashSet};
+
+use lopdf::{dictionary, Dictionary, Document, Object, ObjectId};
+use serde::Serialize;
+
+use crate::{
+    acroform_field_roots, append_root_fields, core_error_with_context, ensure_acroform_object, get_dict, get_dict_mut,
+    object_as_reference, resolve_object, root_catalog_id, CoreResult, FieldIndex, FormDocument,
+};
+
+/// Page attributes a page may inherit from its ancestors in the page tree.
+const INHERITABLE: [&[u8]; 4] = [b"Resources", b"MediaBox", b"CropBox", b"Rotate"];
+
+/// What [`FormDocument::append`] added.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MergeReport {
+    /// Pages appended.
+    pub pages: usize,
+    /// Terminal fields appended.
+    pub fields: usize,
+    /// Prefix given to the appended fields because their names collided.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<String>,
+}
+
+/// First segments of every full field name (`Applicant` for
+/// `Applicant.Name`).
+fn top_names(index: &FieldIndex) -> HashSet<&str> {
+    index
+        .fields
+        .iter()
+        .filter_map(|field| field.full_name.as_deref())
+        .map(|name| name.split('.').next().unwrap_or(name))
+        .collect()
+}
+
+pub(crate) fn append_form(base: &mut FormDocument, other: &FormDocument) -> CoreResult<MergeReport> {
+    let (fields, prefix) = {
+        let ours = top_names(base.index()?);
+        let other_index = other.index()?;
+        let theirs = top_names(other_index);
+        let prefix = (!ours.is_disjoint(&theirs)).then(|| {
+            (1..)
+                .map(|n| format!("copy{}", n))
+                .find(|name| !ours.contains(name.as_str()) && !theirs.contains(name.as_str()))
+                .expect("some copy<n> is free")
+        });
+        (other_index.terminal_fields().count(), prefix)
+    };
+    let mut doc = other.doc.clone();
+    if let Some(prefix) = &prefix {
+        prefix_fields(&mut doc, prefix)?;
+    }
+    let pages = doc.get_pages().len();
+    append_document(&mut base.doc, doc)?;
+    base.invalidate_index();
+    Ok(MergeReport { pages, fields, prefix })
+}
+
+/// Moves every field root of `doc` under a new field titled `prefix`, which
+/// carries the form's `/DA` so the fields keep their default appearance
+/// wherever they end up.
+pub(crate) fn prefix_fields(doc: &mut Document, prefix: &str) -> CoreResult<()> {
+    let roots = match acroform_field_roots(doc)? {
+        Object::Array(items) => items,
+        reference => resolve_object(doc, &reference)
+            .ok()
+            .and_then(|object| object.as_array().ok().cloned())
+            .unwrap_or_default(),
+    };
+    let acroform_id = ensure_acroform_object(doc, root_catalog_id(doc)?)?;
+    let default_appearance = doc.get_dictionary(acroform_id).ok().and_then(|form| form.get(b"DA").ok()).cloned();
+
+    let (moved, kept): (Vec<Object>, Vec<Object>) = roots.into_iter().partition(|root| object_as_reference(root).is_some());
+    let mut parent = dictionary! {
+        "T" => Object::string_literal(prefix),
+        "Kids" => moved.clone(),
+    };
+    if let Some(appearance) = default_appearance {
+        parent.set("DA", appearance);
+    }
+    let parent_id = doc.add_object(parent);
+    for root in moved.iter().filter_map(object_as_reference) {
+        if let Ok(field) = doc.get_dictionary_mut(root) {
+            field.set("Parent", Object::Reference(parent_id));
+        }
+    }
+    let mut fields = kept;
+    fields.insert(0, Object::Reference(parent_id));
+    get_dict_mut(doc, acroform_id, "BW_FORM_ACROFORM_INVALID", "AcroForm dictionary")?.set("Fields", fields);
+    Ok(())
+}
+
+/// Appends the pages and form fields of `other` to `base`. Field names are
+/// taken as they are; callers prefix them first when they may collide.
+pub(crate) fn append_document(base: &mut Document, mut other: Document) -> CoreResult<()> {
+    other.renumber_objects_with(base.max_id + 1);
+    let pages: Vec<ObjectId> = other.get_pages().into_values().collect();
+    for page in &pages {
+        inherit_page_attributes(&mut other, *page);
+    }
+    let form = other
+        .catalog()
+        .ok()
+        .and_then(|catalog| catalog.get(b"AcroForm").ok())
+        .and_then(|form| resolve_object(&other, form).ok())
+        .and_then(|form| form.as_dict().ok().cloned());
+    let roots: Vec<ObjectId> = match form.as_ref().and_then(|form| form.get(b"Fields").ok()) {
+        Some(fields) => resolve_object(&other, fields)
+            .ok()
+            .and_then(|fields| fields.as_array().ok().map(|items| items.iter().filter_map(object_as_reference).collect()))
+            .unwrap_or_default(),
+        None => Vec::new(),
+    };
+    base.max_id = base.max_id.max(other.max_id);
+    base.objects.extend(other.objects);
+
+    let pages_id = get_dict(base, root_catalog_id(base)?, "BW_FORM_CATALOG_INVALID", "catalog dictionary")?
+        .get(b"Pages")
+        .ok()
+        .and_then(object_as_reference)
+        .ok_or_else(|| core_error_with_context("BW_PDF_PAGES_INVALID", "catalog has no page tree", Some("catalog.Pages".into())))?;
+    for page in &pages {
+        get_dict_mut(base, *page, "BW_PDF_PAGES_INVALID", "page dictionary")?.set("Parent", Object::Reference(pages_id));
+    }
+    let tree = get_dict_mut(base, pages_id, "BW_PDF_PAGES_INVALID", "page tree root")?;
+    let count = tree.get(b"Count").and_then(Object::as_i64).unwrap_or(0);
+    tree.set("Count", count + pages.len() as i64);
+    match tree.get_mut(b"Kids") {
+        Ok(Object::Array(kids)) => kids.extend(pages.iter().map(|page| Object::Reference(*page))),
+        _ => tree.set("Kids", pages.iter().map(|page| Object::Reference(*page)).collect::<Vec<_>>()),
+    }
+
+    if let Some(form) = form {
+        if !roots.is_empty() {
+            let acroform_id = append_root_fields(base, &roots)?;
+            merge_form_defaults(base, acroform_id, &form, &roots)?;
+        }
+    }
+    base.prune_objects();
+    Ok(())
+}
+
+/// Copies the attributes `page` inherits from its ancestors onto the page
+/// itself, so it keeps them under a new parent.
+fn inherit_page_attributes(doc: &mut Document, page: ObjectId) {
+    let mut inherited: Vec<(&[u8], Object)> = Vec::new();
+    let mut seen = HashSet::new();
+    let mut parent = doc.get_dictionary(page).ok().and_then(|dict| dict.get(b"Parent").ok()).and_then(object_as_reference);
+    while let Some(id) = parent.filter(|id| seen.insert(*id)) {
+        let Ok(node) = doc.get_dictionary(id) else {
+            break;
+        };
+        for key in INHERITABLE {
+            if inherited.iter().all(|(name, _)| *name != key) {
+                if let Ok(value) = node.get(key) {
+                    inherited.push((key, value.clone()));
+                }
+            }
+        }
+        parent = node.get(b"Parent").ok().and_then(object_as_reference);
+    }
+    if let Ok(dict) = doc.get_dictionary_mut(page) {
+        for (key, value) in inherited {
+            if !dict.has(key) {
+                dict.set(key, value);
+            }
+        }
+    }
+}
+
+/// Adds the `/DR` entries of `form` to the target's form, renaming those
+/// whose name the target already uses for a different resource and rewriting
+/// the appended fields' `/DA` to match. Gives `roots` the source's `/DA`
+/// when the two forms' defaults differ, appends the source's calculation
+/// order (`/CO`) and keeps `/NeedAppearances` if either form asks for it.
+fn merge_form_defaults(base: &mut Document, acroform_id: ObjectId, form: &Dictionary, roots: &[ObjectId]) -> CoreResult<()> {
+    let target = get_dict(base, acroform_id, "BW_FORM_ACROFORM_INVALID", "AcroForm dictionary")?;
+    let resolved_dict = |object: Option<&Object>| {
+        object
+            .and_then(|object| resolve_object(base, object).ok())
+            .and_then(|object| object.as_dict().ok().cloned())
+            .unwrap_or_default()
+    };
+    let resolved_array = |object: Option<&Object>| {
+        object
+            .and_then(|object| resolve_object(base, object).ok())
+            .and_then(|object| object.as_array().ok().cloned())
+            .unwrap_or_default()
+    };
+    let mut resources = resolved_dict(target.get(b"DR").ok());
+    // Font renames, the only resources a `/DA` names.
+    let mut renamed_fonts: HashMap<Vec<u8>, Vec<u8>> = HashMap::new();
+    for (category, entries) in resolved_dict(form.get(b"DR").ok()).iter() {
+        let mut merged = resolved_dict(resources.get(category).ok());
+        let entries = resolved_dict(Some(entries));
+        for (name, value) in entries.iter() {
+            match merged.get(name) {
+                Err(_) => merged.set(name.clone(), value.clone()),
+                Ok(existing) if existing == value => {}
+                Ok(_) => {
+                    let free = (1..)
+                        .map(|n| [name.as_slice(), format!("_{}", n).as_bytes()].concat())
+                        .find(|candidate| !merged.has(candidate) && !entries.has(candidate))
+                        .unwrap_or_default();
+                    merged.set(free.clone(), value.clone());
+                    if category.as_slice() == b"Font" {
+                        renamed_fonts.insert(name.clone(), free);
+                    }
+                }
+            }
+        }
+        resources.set(category.clone(), merged);
+    }
+    let mut order = resolved_array(target.get(b"CO").ok());
+    let source_order = resolved_array(form.get(b"CO").ok());
+    let target_appearance = target.get(b"DA").ok().cloned();
+    let source_appearance = form.get(b"DA").ok().map(|appearance| rename_fonts(appearance, &renamed_fonts));
+    let need_appearances = form.get(b"NeedAppearances").and_then(Object::as_bool).unwrap_or(false);
+
+    if !renamed_fonts.is_empty() {
+        rename_subtree_fonts(base, roots, &renamed_fonts);
+    }
+    let target = get_dict_mut(base, acroform_id, "BW_FORM_ACROFORM_INVALID", "AcroForm dictionary")?;
+    if !resources.is_empty() {
+        target.set("DR", resources);
+    }
+    if !source_order.is_empty() {
+        order.extend(source_order);
+        target.set("CO", order);
+    }
+    if need_appearances {
+        target.set("NeedAppearances", true);
+    }
+    match (target_appearance, source_appearance) {
+        (None, Some(appearance)) => target.set("DA", appearance),
+        (Some(ours), Some(theirs)) if ours != theirs => {
+            for root in roots {
+                if let Ok(field) = base.get_dictionary_mut(*root) {
+                    if !field.has(b"DA") {
+                        field.set("DA", theirs.clone());
+                    }
+                }
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Rewrites `/DA` on every field and widget below `roots` for renamed fonts.
+fn rename_subtree_fonts(doc: &mut Document, roots: &[ObjectId], renamed: &HashMap<Vec<u8>, Vec<u8>>) {
+    let mut pending = roots.to_vec();
+    let mut seen = HashSet::new();
+    while let Some(id) = pending.pop() {
+        if !seen.insert(id) {
+            continue;
+        }
+        let Ok(dict) = doc.get_dictionary_mut(id) else {
+            continue;
+        };
+        if let Ok(appearance) = dict.get(b"DA") {
+            let appearance = rename_fonts(appearance, renamed);
+            dict.set("DA", appearance);
+        }
+        if let Ok(Object::Array(kids)) = dict.get(b"Kids") {
+            pending.extend(kids.iter().filter_map(object_as_reference));
+        }
+    }
+}
+
+/// The `/DA` string with each renamed font operand replaced.
+fn rename_fonts(appearance: &Object, renamed: &HashMap<Vec<u8>, Vec<u8>>) -> Object {
+    let Ok(text) = appearance.as_str() else {
+        return appearance.clone();
+    };
+    let tokens: Vec<Vec<u8>> = text
+        .split(|byte| byte.is_ascii_whitespace())
+        .filter(|token| !token.is_empty())
+        .map(|token| match token.strip_prefix(b"/").and_then(|name| renamed.get(name)) {
+            Some(name) => [b"/".as_slice(), name].concat(),
+            None => token.to_vec(),
+        })
+        .collect();
+    Object::string_literal(tokens.join(&b' '))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::make_fixture_pdf;
+    use crate::index::FieldDescriptor;
+    use crate::FieldValue;
+    use std::collections::HashMap;
+
+    fn filled(name: &str) -> FormDocument {
+        let mut form = FormDocument::load(&make_fixture_pdf()).expect("loads");
+        form.fill(&HashMap::from([("Name".to_string(), FieldValue::from(name))])).expect("fill");
+        form
+    }
+
+    fn values(form: &FormDocument) -> HashMap<String, FieldValue> {
+        form.extract().expect("extract").into_iter().map(|field| (field.name, field.value)).collect()
+    }
+
+    #[test]
+    fn colliding_copies_are_prefixed_and_keep_their_values() {
+        let mut merged = filled("Ada");
+        let mut second = filled("Grace");
+        let catalog_id = root_catalog_id(&second.doc).expect("catalog");
+        let acroform_id = ensure_acroform_object(&mut second.doc, catalog_id).expect("form");
+        let form = second.doc.get_dictionary_mut(acroform_id).expect("form dictionary");
+        form.set("DA", Object::string_literal("/ZaDb 0 Tf 0 g"));
+        let font = dictionary! { "Type" => "Font", "Subtype" => "Type1", "BaseFont" => "ZapfDingbats" };
+        form.set("DR", dictionary! { "Font" => dictionary! { "ZaDb" => font } });
+
+        let report = merged.append(&second).expect("append");
+        assert_eq!(
+            report,
+            MergeReport {
+                pages: 1,
+                fields: 3,
+                prefix: Some("copy1".into()),
+            }
+        );
+        assert_eq!(merged.append(&filled("Edsger")).expect("append").prefix.as_deref(), Some("copy2"));
+
+        let merged = FormDocument::load(&merged.save().expect("save")).expect("merged loads");
+        assert_eq!(merged.doc.get_pages().len(), 3);
+        let values = values(&merged);
+        assert_eq!(values.len(), 9);
+        assert_eq!(values["Name"], FieldValue::from("Ada"));
+        assert_eq!(values["copy1.Name"], FieldValue::from("Grace"));
+        assert_eq!(values["copy2.Name"], FieldValue::from("Edsger"));
+
+        let lookup = |dict: &Object, key: &[u8]| resolve_object(&merged.doc, dict.as_dict().unwrap().get(key).unwrap()).unwrap();
+        let catalog = Object::Dictionary(merged.doc.catalog().expect("catalog").clone());
+        let fonts = lookup(&lookup(&lookup(&catalog, b"AcroForm"), b"DR"), b"Font");
+        assert!(fonts.as_dict().unwrap().has(b"ZaDb"), "the copy's /DR fonts are carried over");
+    }
+
+    #[test]
+    fn colliding_fonts_are_renamed_and_calculation_orders_joined() {
+        let with_font = |base_font: &str| {
+            let mut form = filled("Ada");
+            let index = form.index().expect("index");
+            let named = |field: &&FieldDescriptor| field.full_name.as_deref() == Some("Name");
+            let name_id = index.terminal_fields().find(named).expect("Name").id;
+            form.doc.get_dictionary_mut(name_id).expect("field").set("DA", Object::string_literal("/F1 12 Tf 0 g"));
+            let catalog_id = root_catalog_id(&form.doc).expect("catalog");
+            let acroform_id = ensure_acroform_object(&mut form.doc, catalog_id).expect("form");
+            let acroform = form.doc.get_dictionary_mut(acroform_id).expect("form dictionary");
+            let font = dictionary! { "Type" => "Font", "Subtype" => "Type1", "BaseFont" => base_font };
+            acroform.set("DR", dictionary! { "Font" => dictionary! { "F1" => font } });
+            acroform.set("DA", Object::string_literal(format!("/F1 {} Tf 0 g", base_font.len())));
+            acroform.set("CO", vec![Object::Reference(name_id)]);
+            form.invalidate_index();
+            form
+        };
+        let mut merged = with_font("Helvetica");
+        merged.append(&with_font("Courier")).expect("append");
+
+        let acroform = merged.doc.catalog().expect("catalog").get(b"AcroForm").expect("AcroForm").clone();
+        let acroform = resolve_object(&merged.doc, &acroform).expect("AcroForm").as_dict().expect("dict").clone();
+        let fonts = acroform.get(b"DR").and_then(|dr| dr.as_dict()).and_then(|dr| dr.get(b"Font")).expect("fonts");
+        let base_font = |name: &[u8]| {
+            let font = fonts.as_dict().unwrap().get(name).unwrap().as_dict().unwrap();
+            font.get(b"BaseFont").cloned()
+        };
+        assert_eq!(base_font(b"F1").unwrap(), Object::Name(b"Helvetica".to_vec()));
+        assert_eq!(base_font(b"F1_1").unwrap(), Object::Name(b"Courier".to_vec()));
+
+        let index = merged.index().expect("index");
+        let field = |name: &str| {
+            let mut terminals = index.terminal_fields();
+            terminals.find(|field| field.full_name.as_deref() == Some(name)).unwrap()
+        };
+        assert_eq!(field("Name").da.as_deref(), Some("/F1 12 Tf 0 g"));
+        assert_eq!(field("copy1.Name").da.as_deref(), Some("/F1_1 12 Tf 0 g"));
+        let copy = index.fields.iter().find(|field| field.full_name.as_deref() == Some("copy1")).expect("prefix field");
+        assert_eq!(copy.da.as_deref(), Some("/F1_1 7 Tf 0 g"), "the copy keeps its own renamed default");
+        let order = acroform.get(b"CO").and_then(Object::as_array).expect("calculation order");
+        assert_eq!(order, &vec![Object::Reference(field("Name").id), Object::Reference(field("copy1.Name").id)]);
+    }
+
+    #[test]
+    fn distinct_field_names_are_not_renamed() {
+        let mut merged = filled("Ada");
+        let mut other = filled("Grace");
+        prefix_fields(&mut other.doc, "Applicant").expect("prefix");
+        other.invalidate_index();
+        let report = merged.append(&other).expect("append");
+        assert_eq!(report.prefix, None);
+        assert_eq!(values(&merged)["Applicant.Name"], FieldValue::from("Grace"));
+    }
+}
@@ -0,0 +1,350 @@
+// SPDX-License-Identifier: PMPL-1.0-or-later WITH Palimpsest
+
+//! Indexed field paths and repeating rows.
+//!
+//! Forms repeat a row of fields either with XFA-style suffixes
+//! (`Items[0].Qty`, `form1[0].Items[2].Qty[0]`) or with numeric partial names
+//! (`Items.2.Qty`). Both read as a path of named segments with an optional
+//! row index, where a missing index means row 0. Fill keys containing `[`
+//! are matched as paths, with `[*]` standing for any row; detection groups
+//! the fields that differ only in one row index.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::Serialize;
+
+use crate::FieldIndex;
+
+/// A run of fields that repeat per row, such as `Items[*]` with columns
+/// `Description` and `Qty`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RepeatingGroup {
+    /// The row path, with `[*]` at the repeating index.
+    pub path: String,
+    /// Number of distinct rows present.
+    pub rows: usize,
+    /// Column paths below a row, in field tree order.
+    pub columns: Vec<String>,
+}
+
+/// A field name segment and its row index (explicit or numeric).
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Segment {
+    name: String,
+    index: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum KeyIndex {
+    /// `[*]`
+    Any,
+    /// `[n]`, or row 0 when omitted.
+    At(usize),
+}
+
+/// Splits a full field name into segments. `Qty[2]` carries its own index; a
+/// purely numeric segment (`Items.2`) is the index of the segment before it.
+fn field_segments(full_name: &str) -> Vec<Segment> {
+    let mut segments: Vec<Segment> = Vec::new();
+    for part in full_name.split('.') {
+        if let (Ok(index), Some(last)) = (part.parse::<usize>(), segments.last_mut()) {
+            if last.index.is_none() {
+                last.index = Some(index);
+                continue;
+            }
+        }
+        segments.push(match split_index(part) {
+            Some((name, index)) => Segment {
+                name: name.to_owned(),
+                index: index.parse().ok(),
+            },
+            None => Segment {
+                name: part.to_owned(),
+                index: None,
+            },
+        });
+    }
+    segments
+}
+
+/// `name[inner]` as `(name, inner)`.
+fn split_index(part: &str) -> Option<(&str, &str)> {
+    let (name, rest) = part.split_once('[')?;
+    Some((name, rest.strip_suffix(']')?))
+}
+
+/// Parses a fill key such as `Items[*].Qty`. Keys without `[` are plain
+/// names and yield `None`.
+fn key_segments(key: &str) -> Option<Vec<(String, KeyIndex)>> {
+    if !key.contains('[') {
+        return None;
+    }
+    key.split('.')
+        .map(|part| match split_index(part) {
+            Some((name, "*")) => Some((name.to_owned(), KeyIndex::Any)),
+            Some((name, index)) => index.parse().ok().map(|index| (name.to_owned(), KeyIndex::At(index))),
+            None => Some((part.to_owned(), KeyIndex::At(0))),
+        })
+        .collect()
+}
+
+/// The row indices captured by the key's wildcards when `field` matches.
+fn capture(key: &[(String, KeyIndex)], field: &[Segment]) -> Option<Vec<usize>> {
+    if key.len() != field.len() {
+        return None;
+    }
+    let mut captured = Vec::new();
+    for ((name, wanted), segment) in key.iter().zip(field) {
+        if *name != segment.name {
+            return None;
+        }
+        let index = segment.index.unwrap_or(0);
+        match wanted {
+            KeyIndex::Any => captured.push(index),
+            KeyIndex::At(wanted) if *wanted == index => {}
+            KeyIndex::At(_) => return None,
+        }
+    }
+    Some(captured)
+}
+
+/// Fields matched by the path `key` as `(position, row)`, where `row` is the
+/// ordinal of the field's wildcard captures among all matches (0 for keys
+/// without wildcards). Only terminal fields match, so a value never lands on
+/// a row's group node. `None` when `key` is not a path expression or matches
+/// nothing, leaving it to the partial-name lookup (`f1_01[0]`).
+pub(crate) fn match_path(index: &FieldIndex, key: &str) -> Option<Vec<(usize, usize)>> {
+    let key = key_segments(key)?;
+    let matches: Vec<(usize, Vec<usize>)> = index
+        .fields
+        .iter()
+        .enumerate()
+        .filter(|(_, field)| field.terminal)
+        .filter_map(|(position, field)| {
+            let captured = capture(&key, &field_segments(field.full_name.as_deref()?))?;
+            Some((position, captured))
+        })
+        .collect();
+    if matches.is_empty() {
+        return None;
+    }
+    let rows: Vec<&Vec<usize>> = matches.iter().map(|(_, captured)| captured).collect::<BTreeSet<_>>().into_iter().collect();
+    Some(
+        matches
+            .iter()
+            .map(|(position, captured)| (*position, rows.binary_search(&captured).unwrap_or(0)))
+            .collect(),
+    )
+}
+
+fn render(segments: &[Segment], wildcard: Option<usize>) -> String {
+    segments
+        .iter()
+        .enumerate()
+        .map(|(position, segment)| match (Some(position) == wildcard, segment.index) {
+            (true, _) => format!("{}[*]", segment.name),
+            (false, Some(index)) if index > 0 => format!("{}[{}]", segment.name, index),
+            _ => segment.name.clone(),
+        })
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// Groups terminal fields that differ only in one row index. A group needs
+/// at least two distinct rows; columns keep the order they first appear in.
+pub(crate) fn repeating_groups(index: &FieldIndex) -> Vec<RepeatingGroup> {
+    // path -> (rows, columns)
+    let mut groups: BTreeMap<String, (BTreeSet<usize>, Vec<String>)> = BTreeMap::new();
+    let mut order: Vec<String> = Vec::new();
+    for field in index.terminal_fields() {
+        let Some(full_name) = &field.full_name else {
+            continue;
+        };
+        let segments = field_segments(full_name);
+        for (position, segment) in segments.iter().enumerate() {
+            if segment.index.is_none() {
+                continue;
+            }
+            let path = render(&segments[..=position], Some(position));
+            let column = render(&segments[position + 1..], None);
+            let (rows, columns) = groups.entry(path.clone()).or_insert_with(|| {
+                order.push(path.clone());
+                Default::default()
+            });
+            rows.insert(segment.index.unwrap_or(0));
+            if !column.is_empty() && !columns.contains(&column) {
+                columns.push(column);
+            }
+        }
+    }
+    order
+        .into_iter()
+        .filter_map(|path| {
+            let (rows, columns) = groups.remove(&path)?;
+            (rows.len() > 1).then_some(RepeatingGroup {
+                path,
+                rows: rows.len(),
+                columns,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FieldValue, FormDocument};
+    use lopdf::{dictionary, Document, Object};
+    use std::collections::HashMap;
+
+    /// Rows `Items[0..3]`, each with `Description[0]` and `Qty[0]` text
+    /// fields, plus `Total` and `topmostSubform[0].f1_01[0]`.
+    fn invoice_pdf() -> Vec<u8> {
+        let mut doc = Document::with_version("1.7");
+        let pages_id = doc.new_object_id();
+        let page_id = doc.new_object_id();
+        let mut annots = Vec::new();
+        let mut roots = Vec::new();
+        for row in 0..3i64 {
+            let row_id = doc.new_object_id();
+            let mut kids = Vec::new();
+            for (column, name) in ["Description[0]", "Qty[0]"].into_iter().enumerate() {
+                let x = 10 + 200 * column as i64;
+                let widget_id = doc.add_object(dictionary! {
+                    "Type" => "Annot",
+                    "Subtype" => "Widget",
+                    "T" => Object::string_literal(name),
+                    "FT" => "Tx",
+                    "Parent" => Object::Reference(row_id),
+                    "Rect" => vec![x.into(), (700 - 30 * row).into(), (x + 150).into(), (720 - 30 * row).into()],
+                });
+                kids.push(Object::Reference(widget_id));
+                annots.push(Object::Reference(widget_id));
+            }
+            doc.objects.insert(row_id, Object::Dictionary(dictionary! {
+                "T" => Object::string_literal(format!("Items[{}]", row)),
+                "Kids" => kids,
+            }));
+            roots.push(Object::Reference(row_id));
+        }
+        let total_id = doc.add_object(dictionary! {
+            "Type" => "Annot",
+            "Subtype" => "Widget",
+            "T" => Object::string_literal("Total"),
+            "FT" => "Tx",
+            "Rect" => vec![210.into(), 500.into(), 360.into(), 520.into()],
+        });
+        annots.push(Object::Reference(total_id));
+        roots.push(Object::Reference(total_id));
+        let subform_id = doc.new_object_id();
+        let name_id = doc.add_object(dictionary! {
+            "Type" => "Annot",
+            "Subtype" => "Widget",
+            "T" => Object::string_literal("f1_01[0]"),
+            "FT" => "Tx",
+            "Parent" => Object::Reference(subform_id),
+            "Rect" => vec![10.into(), 760.into(), 160.into(), 780.into()],
+        });
+        doc.objects.insert(subform_id, Object::Dictionary(dictionary! {
+            "T" => Object::string_literal("topmostSubform[0]"),
+            "Kids" => vec![Object::Reference(name_id)],
+        }));
+        annots.push(Object::Reference(name_id));
+        roots.push(Object::Reference(subform_id));
+        doc.objects.insert(page_id, Object::Dictionary(dictionary! {
+            "Type" => "Page",
+            "Parent" => Object::Reference(pages_id),
+            "MediaBox" => vec![0.into(), 0.into(), 612.into(), 792.into()],
+            "Annots" => annots,
+        }));
+        doc.objects.insert(pages_id, Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Kids" => vec![Object::Reference(page_id)],
+            "Count" => 1,
+        }));
+        let acroform_id = doc.add_object(dictionary! { "Fields" => roots });
+        let catalog_id = doc.add_object(dictionary! {
+            "Type" => "Catalog",
+            "Pages" => Object::Reference(pages_id),
+            "AcroForm" => Object::Reference(acroform_id),
+        });
+        doc.trailer.set("Root", Object::Reference(catalog_id));
+        let mut pdf = Vec::new();
+        doc.save_to(&mut pdf).expect("invoice save");
+        pdf
+    }
+
+    fn value_of(form: &FormDocument, name: &str) -> Option<FieldValue> {
+        form.extract().expect("extract").into_iter().find(|field| field.name == name).map(|field| field.value)
+    }
+
+    #[test]
+    fn wildcard_lists_spread_over_rows_in_order() {
+        let mut form = FormDocument::load(&invoice_pdf()).expect("loads");
+        let values = HashMap::from([
+            ("Items[*].Qty".to_string(), FieldValue::List(vec!["4".into(), "2".into()])),
+            ("Items[2].Description".to_string(), FieldValue::from("Nails")),
+            ("Total".to_string(), FieldValue::from("6")),
+        ]);
+        assert_eq!(form.fill(&values).expect("fill"), 4);
+        assert_eq!(value_of(&form, "Items[0].Qty[0]"), Some(FieldValue::Text("4".into())));
+        assert_eq!(value_of(&form, "Items[1].Qty[0]"), Some(FieldValue::Text("2".into())));
+        assert_eq!(value_of(&form, "Items[2].Qty[0]"), Some(FieldValue::Clear), "row beyond the list is untouched");
+        assert_eq!(value_of(&form, "Items[2].Description[0]"), Some(FieldValue::Text("Nails".into())));
+
+        let too_many = HashMap::from([(
+            "Items[*].Qty".to_string(),
+            FieldValue::List(vec!["1".into(), "2".into(), "3".into(), "4".into()]),
+        )]);
+        let error = form.fill(&too_many).expect_err("four values for three rows");
+        assert_eq!(error.code(), "BW_FILL_PATH_ROWS_EXCEEDED");
+        assert_eq!(error.context(), Some("Items[*].Qty"));
+    }
+
+    #[test]
+    fn unmatched_paths_fall_back_to_partial_names() {
+        let mut form = FormDocument::load(&invoice_pdf()).expect("loads");
+        let values = HashMap::from([("f1_01[0]".to_string(), FieldValue::from("Ada"))]);
+        assert_eq!(form.fill(&values).expect("partial XFA name"), 1);
+        assert_eq!(value_of(&form, "topmostSubform[0].f1_01[0]"), Some(FieldValue::Text("Ada".into())));
+
+        let ambiguous = HashMap::from([("Qty[0]".to_string(), FieldValue::from("1"))]);
+        assert_eq!(form.fill(&ambiguous).expect_err("three rows").code(), "BW_FILL_AMBIGUOUS_KEY");
+    }
+
+    #[test]
+    fn wildcard_paths_skip_row_group_nodes() {
+        let mut form = FormDocument::load(&invoice_pdf()).expect("loads");
+        let values = HashMap::from([("Items[*]".to_string(), FieldValue::from("x"))]);
+        assert_eq!(form.fill(&values).expect_err("no terminal row").code(), "BW_FILL_NO_MATCHING_FIELDS");
+        let index = form.index().expect("index");
+        assert!(index.fields.iter().filter(|field| !field.terminal).all(|field| {
+            form.doc.get_dictionary(field.id).is_ok_and(|dict| !dict.has(b"V"))
+        }));
+    }
+
+    #[test]
+    fn repeating_rows_are_grouped_with_their_columns() {
+        let form = FormDocument::load(&invoice_pdf()).expect("loads");
+        assert_eq!(
+            form.repeating_rows().expect("rows"),
+            vec![RepeatingGroup {
+                path: "Items[*]".into(),
+                rows: 3,
+                columns: vec!["Description".into(), "Qty".into()],
+            }]
+        );
+    }
+
+    #[test]
+    fn xfa_and_numeric_segments_parse_alike() {
+        let xfa = field_segments("form1[0].Items[2].Qty[0]");
+        let numeric = field_segments("form1.Items.2.Qty");
+        let key = key_segments("form1.Items[*].Qty").expect("path key");
+        assert_eq!(capture(&key, &xfa), Some(vec![2]));
+        assert_eq!(capture(&key, &numeric), Some(vec![2]));
+        assert_eq!(capture(&key_segments("form1.Items[1].Qty").expect("path key"), &xfa), None);
+        assert_eq!(key_segments("Items.Qty"), None);
+        assert_eq!(render(&xfa, Some(1)), "form1.Items[*].Qty");
+    }
+}
@@ -0,0 +1,650 @@
+// SPDX-License-Identifier: PMPL-1.0-or-later WITH Palimpsest
+
+//! Recovery for files whose cross-reference data cannot be trusted.
+//!
+//! When the parser rejects a file, or loads it with its catalog or some
+//! objects missing (corrupt xref offsets), the raw bytes are rescanned for
+//! `N G obj` headers. A fresh cross-reference table and trailer pointing at
+//! those headers are appended to a copy of the input, the catalog is located
+//! by `/Type /Catalog`, and the copy is parsed again.
+//!
+//! Field and widget dictionaries written inline (directly inside `/Fields`,
+//! `/Kids` or `/Annots`) are promoted to indirect objects, so every widget can
+//! be addressed for fill, appearance generation and flattening. Widgets that
+//! pages list in `/Annots` but no `AcroForm.Fields` entry reaches are then
+//! linked into the field tree, so detection and fill agree on which fields
+//! exist. Every repair is reported as a warning on the loaded document.
+
+use std::collections::{BTreeMap, HashSet};
+
+use lopdf::xref::XrefEntry;
+use lopdf::{Dictionary, Document, Object, ObjectId};
+
+use crate::limits::{find, object_header};
+use crate::{
+    append_root_fields, core_error_with_context, ensure_acroform_object, get_dict_mut, is_widget_dict, object_as_reference,
+    parse_pdf, root_catalog_id, Budget, CoreError, CoreResult, FieldIndex, Limits,
+};
+
+/// Bytes of an object body searched for `/Type /Catalog`.
+const BODY_WINDOW: usize = 4096;
+
+/// Why an otherwise parsed document should be rebuilt, if it should.
+pub(crate) fn needs_repair(doc: &Document) -> Option<String> {
+    if doc.catalog().is_err() {
+        return Some("trailer has no usable /Root catalog".into());
+    }
+    let unreadable = doc
+        .reference_table
+        .entries
+        .iter()
+        .filter(|(number, entry)| {
+            matches!(entry, XrefEntry::Normal { generation, .. } if !doc.objects.contains_key(&(**number, *generation)))
+        })
+        .count();
+    (unreadable > 0).then(|| format!("{} objects unreadable at their cross-reference offsets", unreadable))
+}
+
+/// Rebuilds the cross-reference table of `pdf_data` and parses the result.
+/// `reason` (the parse error, or what [`needs_repair`] found) becomes the
+/// context of the `BW_PDF_XREF_REBUILT` warning.
+pub(crate) fn load_repaired(
+    pdf_data: &[u8],
+    budget: &mut Budget,
+    reason: String,
+) -> CoreResult<(Document, Vec<CoreError>)> {
+    let mut offsets: BTreeMap<u32, (u16, usize)> = BTreeMap::new();
+    let mut catalog = None;
+    let mut position = 0;
+    while let Some(found) = find(&pdf_data[position..], b"obj") {
+        let at = position + found;
+        position = at + 3;
+        let Some((number, generation, start)) = object_header(pdf_data, at) else {
+            continue;
+        };
+        budget.tick("xref rebuild")?;
+        // Later definitions (incremental updates) replace earlier ones.
+        offsets.insert(number, (generation, start));
+        let window = &pdf_data[position..pdf_data.len().min(position + BODY_WINDOW)];
+        let body_end = [find(window, b"endobj"), find(window, b"stream")]
+            .into_iter()
+            .flatten()
+            .min()
+            .unwrap_or(window.len());
+        if is_catalog(&window[..body_end]) {
+            catalog = Some((number, generation));
+        }
+    }
+    offsets.remove(&0);
+
+    let mut warnings = vec![core_error_with_context(
+        "BW_PDF_XREF_REBUILT",
+        format!("cross-reference table rebuilt from {} object headers", offsets.len()),
+        Some(reason),
+    )];
+    let trailer_root = reference_after_last(pdf_data, b"/Root").filter(|(number, _)| offsets.contains_key(number));
+    let root = match (catalog, trailer_root) {
+        (Some(catalog), root) => {
+            if root != Some(catalog) {
+                warnings.push(core_error_with_context(
+                    "BW_PDF_CATALOG_RECOVERED",
+                    "document catalog located by /Type /Catalog",
+                    Some(format!("object {} {}", catalog.0, catalog.1)),
+                ));
+            }
+            catalog
+        }
+        (None, Some(root)) => root,
+        (None, None) => {
+            return Err(core_error_with_context(
+                "BW_PDF_INVALID",
+                "no /Type /Catalog object found while rebuilding the cross-reference table",
+                Some("xref rebuild".into()),
+            ))
+        }
+    };
+
+    let size = offsets.last_key_value().map_or(1, |(number, _)| number + 1);
+    budget.limits().check_objects(size as usize, "xref rebuild")?;
+
+    let mut repaired = pdf_data.to_vec();
+    if !repaired.ends_with(b"\n") {
+        repaired.push(b'\n');
+    }
+    let xref_offset = repaired.len();
+    repaired.extend_from_slice(format!("xref\n0 {}\n", size).as_bytes());
+    for number in 0..size {
+        let entry = match offsets.get(&number) {
+            Some((generation, offset)) => format!("{:010} {:05} n \n", offset, generation),
+            None => "0000000000 65535 f \n".to_owned(),
+        };
+        repaired.extend_from_slice(entry.as_bytes());
+    }
+    let mut trailer = format!("trailer\n<< /Size {} /Root {} {} R", size, root.0, root.1);
+    for key in [&b"/Info"[..], b"/Encrypt"] {
+        if let Some((number, generation)) = reference_after_last(pdf_data, key) {
+            trailer.push_str(&format!(" {} {} {} R", String::from_utf8_lossy(key), number, generation));
+        }
+    }
+    if let Some(id) = id_array_after_last(pdf_data) {
+        trailer.push_str(" /ID ");
+        trailer.push_str(&id);
+    }
+    trailer.push_str(&format!(" >>\nstartxref\n{}\n%%EOF\n", xref_offset));
+    repaired.extend_from_slice(trailer.as_bytes());
+
+    let doc = parse_pdf(&repaired)
+        .map_err(|err| core_error_with_context("BW_PDF_INVALID", err.to_string(), Some("xref rebuild".into())))?;
+    budget.check_time("xref rebuild")?;
+    Ok((doc, warnings))
+}
+
+/// Where an array of field or annotation references lives.
+#[derive(Clone, Copy)]
+enum ArraySlot {
+    /// An indirect array object.
+    Object(ObjectId),
+    /// A direct array under a key of a dictionary object.
+    Entry(ObjectId, &'static [u8]),
+}
+
+impl ArraySlot {
+    fn locate(doc: &Document, holder: ObjectId, key: &'static [u8]) -> Option<Self> {
+        match doc.get_dictionary(holder).ok()?.get(key).ok()? {
+            Object::Reference(id) => matches!(doc.get_object(*id), Ok(Object::Array(_))).then_some(ArraySlot::Object(*id)),
+            Object::Array(_) => Some(ArraySlot::Entry(holder, key)),
+            _ => None,
+        }
+    }
+
+    fn array_mut(self, doc: &mut Document) -> Option<&mut Vec<Object>> {
+        match self {
+            ArraySlot::Object(id) => doc.get_object_mut(id).and_then(Object::as_array_mut).ok(),
+            ArraySlot::Entry(holder, key) => doc
+                .get_object_mut(holder)
+                .and_then(Object::as_dict_mut)
+                .and_then(|dict| dict.get_mut(key))
+                .and_then(Object::as_array_mut)
+                .ok(),
+        }
+    }
+}
+
+/// The dictionary without its back-links, for matching an inline widget in
+/// `/Annots` against the same widget written inline under `/Kids`.
+fn without_links(dict: &Dictionary) -> Dictionary {
+    let mut dict = dict.clone();
+    dict.remove(b"P");
+    dict.remove(b"Parent");
+    dict
+}
+
+/// Replaces the inline dictionaries in `slot` accepted by `accept` with
+/// references to new objects, after `link` has added their back-link. Returns
+/// every reference the array then holds.
+fn promote_in_array(
+    doc: &mut Document,
+    slot: ArraySlot,
+    accept: impl Fn(&Dictionary) -> bool,
+    mut link: impl FnMut(&mut Dictionary) -> Option<ObjectId>,
+    promoted: &mut Vec<ObjectId>,
+) -> Vec<ObjectId> {
+    let Some(mut items) = slot.array_mut(doc).map(std::mem::take) else {
+        return Vec::new();
+    };
+    for item in &mut items {
+        let Object::Dictionary(dict) = item else {
+            continue;
+        };
+        if !accept(dict) {
+            continue;
+        }
+        let mut dict = std::mem::take(dict);
+        let id = match link(&mut dict) {
+            Some(existing) => existing,
+            None => {
+                let id = doc.add_object(dict);
+                promoted.push(id);
+                id
+            }
+        };
+        *item = Object::Reference(id);
+    }
+    let references = items.iter().filter_map(object_as_reference).collect();
+    if let Some(array) = slot.array_mut(doc) {
+        *array = items;
+    }
+    references
+}
+
+/// Promotes inline field dictionaries under `AcroForm.Fields` and `/Kids`,
+/// and inline widgets in page `/Annots`, to indirect objects. An inline
+/// `/Annots` widget equal to an inline kid widget is pointed at that kid.
+pub(crate) fn promote_inline_dictionaries(doc: &mut Document, limits: &Limits) -> Option<CoreError> {
+    let mut promoted = Vec::new();
+
+    let acroform_id = root_catalog_id(doc)
+        .ok()
+        .filter(|catalog_id| doc.get_dictionary(*catalog_id).is_ok_and(|catalog| catalog.has(b"AcroForm")))
+        .and_then(|catalog_id| ensure_acroform_object(doc, catalog_id).ok());
+    if let Some(acroform_id) = acroform_id {
+        let mut pending: Vec<(ArraySlot, Option<ObjectId>, usize)> = ArraySlot::locate(doc, acroform_id, b"Fields")
+            .map(|slot| (slot, None, 0))
+            .into_iter()
+            .collect();
+        let mut seen = HashSet::new();
+        while let Some((slot, parent, depth)) = pending.pop() {
+            let kids = promote_in_array(
+                doc,
+                slot,
+                |_| true,
+                |dict| {
+                    if let (Some(parent), false) = (parent, dict.has(b"Parent")) {
+                        dict.set("Parent", Object::Reference(parent));
+                    }
+                    None
+                },
+                &mut promoted,
+            );
+            if depth >= limits.max_depth {
+                continue;
+            }
+            for kid in kids.into_iter().filter(|kid| seen.insert(*kid)) {
+                if let Some(slot) = ArraySlot::locate(doc, kid, b"Kids") {
+                    pending.push((slot, Some(kid), depth + 1));
+                }
+            }
+        }
+    }
+
+    let promoted_widgets: Vec<(ObjectId, Dictionary)> = promoted
+        .iter()
+        .filter_map(|id| doc.get_dictionary(*id).ok().map(|dict| (*id, dict)))
+        .filter(|(_, dict)| is_widget_dict(dict))
+        .map(|(id, dict)| (id, without_links(dict)))
+        .collect();
+    for page_id in doc.get_pages().into_values() {
+        let Some(slot) = ArraySlot::locate(doc, page_id, b"Annots") else {
+            continue;
+        };
+        promote_in_array(
+            doc,
+            slot,
+            is_widget_dict,
+            |dict| {
+                let stripped = without_links(dict);
+                if let Some((id, _)) = promoted_widgets.iter().find(|(_, kid)| *kid == stripped) {
+                    return Some(*id);
+                }
+                if !dict.has(b"P") {
+                    dict.set("P", Object::Reference(page_id));
+                }
+                None
+            },
+            &mut promoted,
+        );
+    }
+
+    (!promoted.is_empty()).then(|| {
+        core_error_with_context(
+            "BW_PDF_INLINE_PROMOTED",
+            format!("{} inline field or widget dictionaries promoted to indirect objects", promoted.len()),
+            None,
+        )
+    })
+}
+
+/// Links every page widget that `index` could not reach from
+/// `AcroForm.Fields` into the field tree: a missing `/Kids` entry on its
+/// parent is restored, or its top-most ancestor is appended to
+/// `AcroForm.Fields` (creating the form if needed). A chain without `/T` is
+/// named after the label detection reports, made unique, and one without
+/// `/FT` becomes a text field, or a button when the widget has `/AS`.
+pub(crate) fn adopt_orphan_widgets(
+    doc: &mut Document,
+    index: &FieldIndex,
+    limits: &Limits,
+) -> CoreResult<Option<CoreError>> {
+    let mut seen = HashSet::new();
+    let orphans: Vec<(ObjectId, String)> = index
+        .pages
+        .iter()
+        .filter_map(|page| page.as_ref().ok())
+        .flat_map(|page| &page.widgets)
+        .filter_map(|widget| Some((widget.id()?, widget.label.clone())))
+        .filter(|(id, _)| index.field_for_widget(*id).is_none() && seen.insert(*id))
+        .collect();
+    if orphans.is_empty() {
+        return Ok(None);
+    }
+
+    let field_ids: HashSet<ObjectId> = index.fields.iter().map(|field| field.id).collect();
+    let mut names: HashSet<String> = index
+        .fields
+        .iter()
+        .filter(|field| field.parent.is_none())
+        .filter_map(|field| field.partial_name.clone())
+        .collect();
+    let mut new_roots: Vec<ObjectId> = Vec::new();
+    for (widget_id, label) in &orphans {
+        let has_state = doc.get_dictionary(*widget_id).is_ok_and(|widget| widget.has(b"AS"));
+        let (mut node, mut named, mut typed) = (*widget_id, false, false);
+        let mut chain = HashSet::from([node]);
+        let mut attached = false;
+        while let Ok(dict) = doc.get_dictionary(node) {
+            named |= dict.has(b"T");
+            typed |= dict.has(b"FT");
+            let Some(parent) = dict.get(b"Parent").ok().and_then(object_as_reference) else {
+                break;
+            };
+            if chain.len() > limits.max_depth || !chain.insert(parent) {
+                break;
+            }
+            let Ok(parent_dict) = doc.get_object_mut(parent).and_then(Object::as_dict_mut) else {
+                break;
+            };
+            let listed = matches!(parent_dict.get(b"Kids"), Ok(Object::Array(kids)) if kids.contains(&Object::Reference(node)));
+            if !listed {
+                match parent_dict.get_mut(b"Kids") {
+                    Ok(Object::Array(kids)) => kids.push(Object::Reference(node)),
+                    _ => parent_dict.set("Kids", vec![Object::Reference(node)]),
+                }
+            }
+            if field_ids.contains(&parent) {
+                attached = true;
+                break;
+            }
+            node = parent;
+        }
+        if attached || new_roots.contains(&node) {
+            continue;
+        }
+        let root = get_dict_mut(doc, node, "BW_FORM_FIELD_INVALID", "orphan widget")?;
+        if !named {
+            let mut name = label.clone();
+            let mut suffix = 1;
+            while names.contains(&name) {
+                suffix += 1;
+                name = format!("{}_{}", label, suffix);
+            }
+            root.set("T", Object::string_literal(name.clone()));
+            names.insert(name);
+        }
+        if !typed {
+            root.set("FT", if has_state { "Btn" } else { "Tx" });
+        }
+        new_roots.push(node);
+    }
+
+    if !new_roots.is_empty() {
+        append_root_fields(doc, &new_roots)?;
+    }
+
+    Ok(Some(core_error_with_context(
+        "BW_FORM_ORPHANS_ADOPTED",
+        format!("{} widget annotations outside AcroForm.Fields linked into the field tree", orphans.len()),
+        Some("AcroForm.Fields".into()),
+    )))
+}
+
+/// Whether an object body declares `/Type /Catalog`.
+fn is_catalog(body: &[u8]) -> bool {
+    let mut rest = body;
+    while let Some(found) = find(rest, b"/Catalog") {
+        let before = &rest[..found];
+        let before = &before[..before.iter().rposition(|byte| !byte.is_ascii_whitespace()).map_or(0, |last| last + 1)];
+        let delimited = !rest.get(found + 8).is_some_and(u8::is_ascii_alphanumeric);
+        if before.ends_with(b"/Type") && delimited {
+            return true;
+        }
+        rest = &rest[found + 8..];
+    }
+    false
+}
+
+fn rfind(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).rposition(|window| window == needle)
+}
+
+/// The last `key N G R` in the file, as found in a trailer or xref stream
+/// dictionary.
+fn reference_after_last(data: &[u8], key: &[u8]) -> Option<(u32, u16)> {
+    let mut end = data.len();
+    while let Some(found) = rfind(&data[..end], key) {
+        end = found;
+        let tokens: Vec<&[u8]> = data[found + key.len()..]
+            .split(|byte| byte.is_ascii_whitespace())
+            .filter(|token| !token.is_empty())
+            .take(3)
+            .collect();
+        if let [number, generation, reference] = tokens.as_slice() {
+            let parse = |token: &[u8]| std::str::from_utf8(token).ok().and_then(|text| text.parse::<u64>().ok());
+            if reference.starts_with(b"R") {
+                if let (Some(number), Some(generation)) = (parse(number), parse(generation)) {
+                    if let (Ok(number), Ok(generation)) = (u32::try_from(number), u16::try_from(generation)) {
+                        return Some((number, generation));
+                    }
+                }
+            }
+        }
+    }
+    None
+}
+
+/// The last `/ID [<..> <..>]` array, copied verbatim.
+fn id_array_after_last(data: &[u8]) -> Option<String> {
+    let found = rfind(data, b"/ID")?;
+    let rest = &data[found + 3..];
+    let open = rest.iter().position(|byte| !byte.is_ascii_whitespace())?;
+    if rest[open] != b'[' {
+        return None;
+    }
+    let close = open + rest[open..].iter().position(|byte| *byte == b']')?;
+    let array = &rest[open..=close];
+    array
+        .iter()
+        .all(|byte| byte.is_ascii_hexdigit() || byte.is_ascii_whitespace() || b"[]<>".contains(byte))
+        .then(|| String::from_utf8_lossy(array).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::make_fixture_pdf;
+    use crate::FormDocument;
+    use lopdf::dictionary;
+    use lopdf::xref::XrefType;
+
+    /// The fixture written with a classic `xref` table and `trailer`.
+    fn classic_fixture() -> Vec<u8> {
+        let mut doc = Document::load_mem(&make_fixture_pdf()).expect("fixture parses");
+        doc.reference_table.cross_reference_type = XrefType::CrossReferenceTable;
+        let mut pdf = Vec::new();
+        doc.save_to(&mut pdf).expect("fixture saves");
+        pdf
+    }
+
+    /// The fixture with every xref offset shifted so no object can be read.
+    fn corrupt_offsets(pdf: &[u8]) -> Vec<u8> {
+        let xref = rfind(pdf, b"\nxref\n").expect("classic xref table") + 1;
+        let mut broken = pdf.to_vec();
+        let mut line_start = xref;
+        for line in pdf[xref..].split(|byte| *byte == b'\n') {
+            if line.len() >= 18 && (line.ends_with(b" n ") || line.ends_with(b" n\r")) {
+                broken[line_start..line_start + 10].copy_from_slice(b"0000000001");
+            }
+            line_start += line.len() + 1;
+        }
+        broken
+    }
+
+    #[test]
+    fn corrupt_offsets_are_rebuilt_and_reported() {
+        let pdf = corrupt_offsets(&classic_fixture());
+        let form = FormDocument::load(&pdf).expect("recovered");
+        let codes: Vec<&str> = form.warnings().iter().map(|warning| warning.code()).collect();
+        assert_eq!(codes[0], "BW_PDF_XREF_REBUILT");
+        assert_eq!(form.detect().expect("detect after repair").len(), 4);
+        assert_eq!(form.extract().expect("extract after repair").len(), 3);
+    }
+
+    #[test]
+    fn truncated_trailer_recovers_the_catalog_and_fills() {
+        let pdf = classic_fixture();
+        let cut = rfind(&pdf, b"trailer").expect("trailer");
+        let mut form = FormDocument::load(&pdf[..cut]).expect("recovered");
+        let codes: Vec<&str> = form.warnings().iter().map(|warning| warning.code()).collect();
+        assert_eq!(codes, vec!["BW_PDF_XREF_REBUILT", "BW_PDF_CATALOG_RECOVERED"]);
+
+        let mut values = std::collections::HashMap::new();
+        values.insert("Name".to_string(), crate::FieldValue::from("Recovered"));
+        assert_eq!(form.fill(&values).expect("fill after repair"), 1);
+        let reloaded = FormDocument::load(&form.save().expect("save")).expect("saved output is sound");
+        assert!(reloaded.warnings().is_empty());
+    }
+
+    #[test]
+    fn unrecoverable_input_keeps_the_parse_error() {
+        let error = FormDocument::load(b"%PDF-1.7\n1 0 obj\n<< /Kind /Other >>\nendobj\n").expect_err("no catalog");
+        assert_eq!(error.code(), "BW_PDF_INVALID");
+        assert_eq!(error.context(), Some("Document::load_mem"));
+    }
+
+    /// The fixture with two widgets on the page that no field lists: one named
+    /// text widget and one bare widget with neither `/T` nor `/FT`.
+    fn fixture_with_orphans(drop_acroform: bool) -> Vec<u8> {
+        let mut doc = Document::load_mem(&make_fixture_pdf()).expect("fixture parses");
+        let page_id = doc.get_pages()[&1];
+        let named = doc.add_object(dictionary! {
+            "Type" => "Annot",
+            "Subtype" => "Widget",
+            "T" => Object::string_literal("Loose"),
+            "FT" => "Tx",
+            "Rect" => vec![10.into(), 10.into(), 110.into(), 30.into()],
+            "P" => Object::Reference(page_id),
+        });
+        let bare = doc.add_object(dictionary! {
+            "Type" => "Annot",
+            "Subtype" => "Widget",
+            "Rect" => vec![10.into(), 40.into(), 110.into(), 60.into()],
+        });
+        let page = doc.get_object_mut(page_id).and_then(Object::as_dict_mut).expect("page");
+        let annots = page.get_mut(b"Annots").and_then(Object::as_array_mut).expect("annots");
+        annots.push(Object::Reference(named));
+        annots.push(Object::Reference(bare));
+        if drop_acroform {
+            doc.catalog_mut().expect("catalog").remove(b"AcroForm");
+        }
+        let mut pdf = Vec::new();
+        doc.save_to(&mut pdf).expect("fixture saves");
+        pdf
+    }
+
+    #[test]
+    fn orphan_widgets_are_detected_and_filled_alike() {
+        let mut form = FormDocument::load(&fixture_with_orphans(false)).expect("loads");
+        assert!(form.warnings().iter().any(|warning| warning.code() == "BW_FORM_ORPHANS_ADOPTED"));
+        let labels: Vec<String> = form.detect().expect("detect").into_iter().map(|block| block.label).collect();
+        assert_eq!(labels, vec!["Name", "Consent", "Choice", "Choice", "Loose", "field_1_6"]);
+
+        let mut values = std::collections::HashMap::new();
+        values.insert("Loose".to_string(), crate::FieldValue::from("adopted"));
+        values.insert("field_1_6".to_string(), crate::FieldValue::from("named by detection"));
+        assert_eq!(form.fill(&values).expect("fill reaches orphans"), 2);
+
+        let reloaded = FormDocument::load(&form.save().expect("save")).expect("reloads");
+        assert!(reloaded.warnings().is_empty(), "adoption is persisted");
+        let value_of = |name: &str| reloaded.extract().expect("extract").into_iter().find(|field| field.name == name);
+        assert_eq!(value_of("field_1_6").map(|field| field.field_type), Some("Tx".to_string()));
+        assert_eq!(value_of("Loose").map(|field| field.value), Some(crate::FieldValue::Text("adopted".into())));
+    }
+
+    #[test]
+    fn a_form_without_acroform_is_rebuilt_from_page_widgets() {
+        let mut form = FormDocument::load(&fixture_with_orphans(true)).expect("loads");
+        let labels: Vec<String> = form.detect().expect("detect").into_iter().map(|block| block.label).collect();
+        assert_eq!(labels, vec!["Name", "Consent", "Choice", "Choice", "Loose", "field_1_6"]);
+        let choice = form.index().expect("index").fields.iter().find(|field| field.full_name.as_deref() == Some("Choice"));
+        assert_eq!(choice.map(|field| field.widget_ids.len()), Some(2), "radio kids stay under their parent");
+
+        let mut values = std::collections::HashMap::new();
+        values.insert("Name".to_string(), crate::FieldValue::from("Ada"));
+        values.insert("Choice".to_string(), crate::FieldValue::from("B"));
+        assert_eq!(form.fill(&values).expect("fill"), 2);
+    }
+
+    /// A page whose only fields are written inline: a merged text field
+    /// directly in `/Fields`, and a radio group whose kid widgets are inline in
+    /// `/Kids`. `/Annots` repeats each widget as an equal inline copy.
+    fn inline_form() -> Vec<u8> {
+        let mut doc = Document::with_version("1.7");
+        let pages_id = doc.new_object_id();
+        let page_id = doc.new_object_id();
+        let on = doc.add_object(lopdf::Stream::new(dictionary! {}, b"0 g".to_vec()));
+        let off = doc.add_object(lopdf::Stream::new(dictionary! {}, Vec::new()));
+        let text = dictionary! {
+            "Type" => "Annot",
+            "Subtype" => "Widget",
+            "T" => Object::string_literal("Inline"),
+            "FT" => "Tx",
+            "Rect" => vec![10.into(), 100.into(), 200.into(), 120.into()],
+        };
+        let radio_id = doc.new_object_id();
+        let kid = |state: &str, y: i64| {
+            dictionary! {
+                "Type" => "Annot",
+                "Subtype" => "Widget",
+                "Rect" => vec![10.into(), y.into(), 20.into(), (y + 10).into()],
+                "AS" => "Off",
+                "AP" => dictionary! { "N" => dictionary! { state => Object::Reference(on), "Off" => Object::Reference(off) } },
+            }
+        };
+        let (x, y) = (kid("X", 40), kid("Y", 60));
+        doc.objects.insert(radio_id, Object::Dictionary(dictionary! {
+            "T" => Object::string_literal("Pick"),
+            "FT" => "Btn",
+            "Ff" => 1 << 15,
+            "Kids" => vec![x.clone().into(), y.clone().into()],
+        }));
+        doc.objects.insert(page_id, Object::Dictionary(dictionary! {
+            "Type" => "Page",
+            "Parent" => Object::Reference(pages_id),
+            "MediaBox" => vec![0.into(), 0.into(), 612.into(), 792.into()],
+            "Annots" => vec![text.clone().into(), x.into(), y.into()],
+        }));
+        doc.objects.insert(pages_id, Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Kids" => vec![Object::Reference(page_id)],
+            "Count" => 1,
+        }));
+        let catalog_id = doc.add_object(dictionary! {
+            "Type" => "Catalog",
+            "Pages" => Object::Reference(pages_id),
+            "AcroForm" => dictionary! { "Fields" => vec![text.into(), Object::Reference(radio_id)] },
+        });
+        doc.trailer.set("Root", Object::Reference(catalog_id));
+        let mut pdf = Vec::new();
+        doc.save_to(&mut pdf).expect("inline form saves");
+        pdf
+    }
+
+    #[test]
+    fn inline_widgets_are_promoted_and_shared_with_annots() {
+        let mut form = FormDocument::load(&inline_form()).expect("loads");
+        let codes: Vec<&str> = form.warnings().iter().map(|warning| warning.code()).collect();
+        assert_eq!(codes, vec!["BW_PDF_INLINE_PROMOTED"], "annots copies resolve to the promoted kids");
+        let labels: Vec<String> = form.detect().expect("detect").into_iter().map(|block| block.label).collect();
+        assert_eq!(labels, vec!["Inline", "Pick", "Pick"]);
+
+        let mut values = std::collections::HashMap::new();
+        values.insert("Inline".to_string(), crate::FieldValue::from("written"));
+        values.insert("Pick".to_string(), crate::FieldValue::from("Y"));
+        assert_eq!(form.fill(&values).expect("fill inline fields"), 2);
+        let reloaded = FormDocument::load(&form.save().expect("save")).expect("reloads");
+        assert!(reloaded.warnings().is_empty());
+        let value_of = |name: &str| reloaded.extract().expect("extract").into_iter().find(|field| field.name == name);
+        assert_eq!(value_of("Pick").map(|field| field.value), Some(crate::FieldValue::Text("Y".into())));
+
+        assert_eq!(form.flatten().expect("flatten"), 3);
+    }
+}
@@ -0,0 +1,421 @@
+// SPDX-License-Identifier: PMPL-1.0-or-later WITH Palimpsest
+
+//! Value transforms for mapped fills.
+//!
+//! A [`Binding`](crate::Binding) may list steps that turn the bound data into
+//! what the form expects. Steps run in order on the value of the binding's
+//! key (or on nothing, when the binding has no key), and text steps apply to
+//! every item of a list. Plain steps are written as strings, the others as a
+//! one-entry object:
+//!
+//! ```json
+//! "transform": [
+//!   { "concat": { "keys": ["applicant.last_name"], "separator": ", " } },
+//!   "trim", "upper", "digits",
+//!   { "split": { "separator": " ", "index": 0 } },
+//!   { "date": { "from": "yyyy-mm-dd", "to": "mm/dd/yyyy" } },
+//!   { "currency": { "symbol": "$", "decimals": 2 } },
+//!   { "lookup": { "table": { "M": "Male", "F": "Female" }, "default": "Other" } },
+//!   { "default": "N/A" }
+//! ]
+//! ```
+//!
+//! Definitions are checked by [`validate`] before any data is read; a step
+//! that cannot handle a value (a date in the wrong format, a code missing
+//! from a lookup table) fails that one field only.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::autofill::{format_date, MONTHS};
+use crate::value::format_number;
+use crate::{core_error_with_context, CoreResult, FieldValue};
+
+/// Most decimals a currency step may ask for.
+const MAX_DECIMALS: u8 = 6;
+
+/// One step of a binding's transform pipeline.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Transform {
+    Trim,
+    Upper,
+    Lower,
+    /// Keeps only the ASCII digits.
+    Digits,
+    /// Appends the values of `keys`, skipping empty ones.
+    Concat {
+        keys: Vec<String>,
+        #[serde(default = "default_separator")]
+        separator: String,
+    },
+    /// Splits at `separator`; `index` picks one part (negative counts from
+    /// the end), otherwise every part becomes a list item.
+    Split {
+        separator: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        index: Option<i64>,
+    },
+    /// Rewrites a date from one `AFDate_FormatEx` pattern to another.
+    Date { from: String, to: String },
+    /// Formats a number as an amount: `1234.5` -> `$1,234.50`.
+    Currency {
+        #[serde(default)]
+        symbol: String,
+        #[serde(default = "default_decimals")]
+        decimals: u8,
+        #[serde(default = "default_thousands")]
+        thousands: String,
+        #[serde(default = "default_decimal")]
+        decimal: String,
+    },
+    /// Replaces the value by its entry in `table`, or by `default` when it
+    /// has none.
+    Lookup {
+        table: BTreeMap<String, String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        default: Option<String>,
+    },
+    /// The value to use when there is none or it is empty.
+    Default(String),
+}
+
+fn default_separator() -> String {
+    " ".into()
+}
+
+fn default_decimals() -> u8 {
+    2
+}
+
+fn default_thousands() -> String {
+    ",".into()
+}
+
+fn default_decimal() -> String {
+    ".".into()
+}
+
+impl Transform {
+    fn name(&self) -> &'static str {
+        match self {
+            Transform::Trim => "trim",
+            Transform::Upper => "upper",
+            Transform::Lower => "lower",
+            Transform::Digits => "digits",
+            Transform::Concat { .. } => "concat",
+            Transform::Split { .. } => "split",
+            Transform::Date { .. } => "date",
+            Transform::Currency { .. } => "currency",
+            Transform::Lookup { .. } => "lookup",
+            Transform::Default(_) => "default",
+        }
+    }
+
+    /// Why the step can never work, if it cannot.
+    fn problem(&self) -> Option<String> {
+        match self {
+            Transform::Concat { keys, .. } if keys.is_empty() => Some("concat needs at least one key".into()),
+            Transform::Split { separator, .. } if separator.is_empty() => Some("split needs a separator".into()),
+            Transform::Date { from, to } => [from, to]
+                .into_iter()
+                .find(|pattern| !['y', 'm', 'd'].iter().all(|token| pattern.contains(*token)))
+                .map(|pattern| format!("date pattern '{}' needs a year, a month and a day", pattern)),
+            Transform::Currency { decimals, .. } if *decimals > MAX_DECIMALS => {
+                Some(format!("currency allows at most {} decimals", MAX_DECIMALS))
+            }
+            Transform::Currency { thousands, decimal, .. } if decimal.is_empty() || thousands == decimal => {
+                Some("currency needs a decimal separator distinct from the thousands separator".into())
+            }
+            Transform::Lookup { table, default } if table.is_empty() && default.is_none() => {
+                Some("lookup needs a table or a default".into())
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Checks the steps of the binding for `field`: each must be well formed,
+/// and a binding without a key must produce its value with `concat` or
+/// `default`.
+pub(crate) fn validate(field: &str, key: Option<&str>, steps: &[Transform]) -> CoreResult<()> {
+    let invalid = |message: String| core_error_with_context("BW_MAPPING_TRANSFORM_INVALID", message, Some(field.to_owned()));
+    if field.is_empty() {
+        return Err(invalid("binding has no field".into()));
+    }
+    if let Some(problem) = steps.iter().find_map(Transform::problem) {
+        return Err(invalid(problem));
+    }
+    let produces = steps.iter().any(|step| matches!(step, Transform::Concat { .. } | Transform::Default(_)));
+    if key.is_none() && !produces {
+        return Err(invalid("binding has no key and no concat or default step".into()));
+    }
+    Ok(())
+}
+
+/// Runs `steps` over `value` for `field`; `lookup` reads the keys `concat`
+/// names and records the ones that are absent.
+pub(crate) fn apply(
+    field: &str,
+    value: Option<FieldValue>,
+    steps: &[Transform],
+    lookup: &mut dyn FnMut(&str) -> Option<FieldValue>,
+) -> CoreResult<Option<FieldValue>> {
+    let mut value = value;
+    for step in steps {
+        let failed =
+            |reason: String| core_error_with_context("BW_TRANSFORM_FAILED", format!("{}: {}", step.name(), reason), Some(field.to_owned()));
+        value = match step {
+            Transform::Trim => map_text(value, |text| Ok(text.trim().to_owned())),
+            Transform::Upper => map_text(value, |text| Ok(text.to_uppercase())),
+            Transform::Lower => map_text(value, |text| Ok(text.to_lowercase())),
+            Transform::Digits => map_text(value, |text| Ok(text.chars().filter(char::is_ascii_digit).collect())),
+            Transform::Concat { keys, separator } => {
+                let mut parts: Vec<String> = value.as_ref().map(|value| text_of(value, separator)).into_iter().collect();
+                parts.extend(keys.iter().filter_map(|key| lookup(key)).map(|value| text_of(&value, separator)));
+                parts.retain(|part| !part.is_empty());
+                Ok((!parts.is_empty()).then(|| FieldValue::Text(parts.join(separator.as_str()))))
+            }
+            Transform::Split { separator, index: None } => Ok(value.map(|value| match value.as_text() {
+                Some(text) => FieldValue::List(text.split(separator.as_str()).map(str::to_owned).collect()),
+                None => value,
+            })),
+            Transform::Split {
+                separator,
+                index: Some(index),
+            } => map_text(value, |text| {
+                let parts: Vec<&str> = text.split(separator.as_str()).collect();
+                let position = if *index < 0 { parts.len() as i64 + index } else { *index };
+                Ok(usize::try_from(position).ok().and_then(|position| parts.get(position)).unwrap_or(&"").to_string())
+            }),
+            Transform::Date { from, to } => map_text(value, |text| {
+                if text.trim().is_empty() {
+                    return Ok(text);
+                }
+                parse_date(&text, from)
+                    .and_then(|iso| format_date(&iso, to))
+                    .ok_or_else(|| format!("'{}' is not a date in the form {}", text, from))
+            }),
+            Transform::Currency {
+                symbol,
+                decimals,
+                thousands,
+                decimal,
+            } => match value {
+                Some(FieldValue::Number(number)) => {
+                    Ok(Some(FieldValue::Text(format_currency(number, symbol, *decimals, thousands, decimal))))
+                }
+                other => map_text(other, |text| {
+                    if text.trim().is_empty() {
+                        return Ok(text);
+                    }
+                    parse_amount(&text, symbol, thousands, decimal)
+                        .map(|number| format_currency(number, symbol, *decimals, thousands, decimal))
+                        .ok_or_else(|| format!("'{}' is not an amount", text))
+                }),
+            },
+            Transform::Lookup { table, default } => map_text(value, |text| {
+                table
+                    .get(&text)
+                    .or(default.as_ref())
+                    .cloned()
+                    .ok_or_else(|| format!("'{}' is not in the lookup table", text))
+            }),
+            Transform::Default(fallback) => Ok(match value {
+                Some(value) if !is_empty(&value) => Some(value),
+                _ => Some(FieldValue::Text(fallback.clone())),
+            }),
+        }
+        .map_err(failed)?;
+    }
+    Ok(value)
+}
+
+/// Applies `step` to text values and list items; numbers are read as their
+/// text, and other values pass unchanged.
+fn map_text(
+    value: Option<FieldValue>,
+    step: impl Fn(String) -> Result<String, String>,
+) -> Result<Option<FieldValue>, String> {
+    Ok(match value {
+        Some(FieldValue::Text(text)) => Some(FieldValue::Text(step(text)?)),
+        Some(FieldValue::Number(number)) => match format_number(number) {
+            Some(text) => Some(FieldValue::Text(step(text)?)),
+            None => Some(FieldValue::Number(number)),
+        },
+        Some(FieldValue::List(items)) => Some(FieldValue::List(items.into_iter().map(step).collect::<Result<_, _>>()?)),
+        other => other,
+    })
+}
+
+fn text_of(value: &FieldValue, separator: &str) -> String {
+    match value {
+        FieldValue::List(items) => items.iter().filter(|item| !item.is_empty()).cloned().collect::<Vec<_>>().join(separator),
+        other => other.as_text().unwrap_or_default(),
+    }
+}
+
+fn is_empty(value: &FieldValue) -> bool {
+    match value {
+        FieldValue::Clear => true,
+        FieldValue::Text(text) => text.trim().is_empty(),
+        FieldValue::List(items) => items.iter().all(|item| item.trim().is_empty()),
+        _ => false,
+    }
+}
+
+/// Reads `text` written in an `AFDate_FormatEx` pattern as ISO `yyyy-mm-dd`.
+/// Numeric parts may drop a leading zero, month names may be abbreviated,
+/// and two-digit years fall in 1950..=2049.
+pub(crate) fn parse_date(text: &str, pattern: &str) -> Option<String> {
+    let (mut year, mut month, mut day) = (None, None, None);
+    let mut text = text.trim();
+    let mut rest = pattern;
+    while let Some(ch) = rest.chars().next() {
+        let run = rest.chars().take_while(|next| *next == ch).count();
+        let used = match (ch, run) {
+            ('y', 4..) => {
+                year = Some(take_digits(&mut text, 4, 4)?);
+                4
+            }
+            ('y', 2..) => {
+                let short = take_digits(&mut text, 2, 2)?;
+                year = Some(if short < 50 { 2000 + short } else { 1900 + short });
+                2
+            }
+            ('m', 3..) => {
+                let word: String = text.chars().take_while(|next| next.is_alphabetic()).collect();
+                let lower = word.to_lowercase();
+                let position = MONTHS
+                    .iter()
+                    .position(|name| lower.len() >= 3 && name.to_lowercase().starts_with(&lower))?;
+                month = Some(position as u32 + 1);
+                text = &text[word.len()..];
+                run.min(4)
+            }
+            ('m', _) => {
+                month = Some(take_digits(&mut text, 1, 2)?);
+                run.min(2)
+            }
+            ('d', _) => {
+                day = Some(take_digits(&mut text, 1, 2)?);
+                run.min(2)
+            }
+            _ => {
+                text = text.strip_prefix(ch)?;
+                1
+            }
+        };
+        rest = &rest[used * ch.len_utf8()..];
+    }
+    let (year, month, day) = (year?, month?, day?);
+    (text.is_empty() && (1..=12).contains(&month) && (1..=31).contains(&day))
+        .then(|| format!("{:04}-{:02}-{:02}", year, month, day))
+}
+
+/// Takes `fewest..=most` leading ASCII digits off `text`.
+fn take_digits(text: &mut &str, fewest: usize, most: usize) -> Option<u32> {
+    let count = text.bytes().take(most).take_while(u8::is_ascii_digit).count();
+    if count < fewest {
+        return None;
+    }
+    let number = text[..count].parse().ok()?;
+    *text = &text[count..];
+    Some(number)
+}
+
+/// The number in `text` once the symbol and thousands separators are gone.
+fn parse_amount(text: &str, symbol: &str, thousands: &str, decimal: &str) -> Option<f64> {
+    let mut text = text.trim().to_owned();
+    for noise in [symbol, thousands] {
+        if !noise.is_empty() {
+            text = text.replace(noise, "");
+        }
+    }
+    let text: String = text.replace(decimal, ".").chars().filter(|ch| !ch.is_whitespace()).collect();
+    text.parse().ok().filter(|number: &f64| number.is_finite())
+}
+
+fn format_currency(number: f64, symbol: &str, decimals: u8, thousands: &str, decimal: &str) -> String {
+    let fixed = format!("{:.*}", usize::from(decimals), number.abs());
+    let (whole, fraction) = fixed.split_once('.').unwrap_or((&fixed, ""));
+    let mut grouped = String::new();
+    for (position, digit) in whole.chars().enumerate() {
+        if position > 0 && (whole.len() - position) % 3 == 0 {
+            grouped.push_str(thousands);
+        }
+        grouped.push(digit);
+    }
+    let negative = number < 0.0 && fixed.bytes().any(|byte| matches!(byte, b'1'..=b'9'));
+    let sign = if negative { "-" } else { "" };
+    if fraction.is_empty() {
+        format!("{}{}{}", sign, symbol, grouped)
+    } else {
+        format!("{}{}{}{}{}", sign, symbol, grouped, decimal, fraction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn run(steps: &str, value: Option<FieldValue>, data: &[(&str, &str)]) -> CoreResult<Option<FieldValue>> {
+        let steps: Vec<Transform> = serde_json::from_str(steps).expect("steps JSON");
+        let data: HashMap<&str, FieldValue> = data.iter().map(|(key, value)| (*key, FieldValue::from(*value))).collect();
+        validate("Field", Some("key"), &steps)?;
+        apply("Field", value, &steps, &mut |key| data.get(key).cloned())
+    }
+
+    fn text(value: &str) -> Option<FieldValue> {
+        Some(FieldValue::from(value))
+    }
+
+    #[test]
+    fn steps_reshape_values() {
+        let cases: [(&str, Option<FieldValue>, Option<FieldValue>); 9] = [
+            (r#"[{ "concat": { "keys": ["last"], "separator": ", " } }, "upper"]"#, text("Ada"), text("ADA, LOVELACE")),
+            (r#"[{ "split": { "separator": " ", "index": -1 } }]"#, text("Ada King Lovelace"), text("Lovelace")),
+            (r#"[{ "split": { "separator": "," } }, "trim"]"#, text("a, b"), Some(FieldValue::List(vec!["a".into(), "b".into()]))),
+            (r#"["digits"]"#, text("(555) 010-4477"), text("5550104477")),
+            (r#"[{ "date": { "from": "d mmm yyyy", "to": "mm/dd/yy" } }]"#, text("9 Dec 1906"), text("12/09/06")),
+            (r#"[{ "currency": { "symbol": "$" } }]"#, Some(FieldValue::Number(-1234567.456)), text("-$1,234,567.46")),
+            (
+                r#"[{ "currency": { "symbol": "€", "thousands": ".", "decimal": ",", "decimals": 0 } }]"#,
+                text("€ 12.500,4"),
+                text("€12.500"),
+            ),
+            (r#"[{ "lookup": { "table": { "M": "Male" }, "default": "Other" } }]"#, text("X"), text("Other")),
+            (r#"[{ "default": "N/A" }]"#, None, text("N/A")),
+        ];
+        for (steps, value, expected) in cases {
+            assert_eq!(run(steps, value, &[("last", "Lovelace")]).expect(steps), expected, "{}", steps);
+        }
+        assert_eq!(parse_date("09/12/06", "dd/mm/yy").as_deref(), Some("2006-12-09"));
+        assert_eq!(parse_date("2006-12-09x", "yyyy-mm-dd"), None);
+    }
+
+    #[test]
+    fn bad_definitions_and_bad_values_are_reported() {
+        for (steps, key) in [
+            (r#"[{ "date": { "from": "yyyy-mm", "to": "dd" } }]"#, Some("key")),
+            (r#"[{ "split": { "separator": "" } }]"#, Some("key")),
+            (r#"[{ "currency": { "decimals": 9 } }]"#, Some("key")),
+            (r#"[{ "lookup": { "table": {} } }]"#, Some("key")),
+            (r#"["upper"]"#, None),
+        ] {
+            let steps: Vec<Transform> = serde_json::from_str(steps).expect("steps JSON");
+            let error = validate("Field", key, &steps).expect_err("invalid definition");
+            assert_eq!(error.code(), "BW_MAPPING_TRANSFORM_INVALID");
+            assert_eq!(error.context(), Some("Field"));
+        }
+
+        let error = run(r#"[{ "date": { "from": "yyyy-mm-dd", "to": "dd/mm/yyyy" } }]"#, text("12/09/1906"), &[])
+            .expect_err("not ISO");
+        assert_eq!(error.code(), "BW_TRANSFORM_FAILED");
+        assert!(error.message().starts_with("date:"), "{}", error.message());
+        let error = run(r#"[{ "lookup": { "table": { "M": "Male" } } }]"#, text("X"), &[]).expect_err("unknown code");
+        assert_eq!(error.code(), "BW_TRANSFORM_FAILED");
+    }
+}
@@ -0,0 +1,113 @@
+// SPDX-License-Identifier: PMPL-1.0-or-later WITH Palimpsest
+
+//! Typed field values accepted by the fill pipeline.
+//!
+//! Values arrive from JavaScript as plain JSON-like data, so the enum is
+//! untagged: `null` clears a field, booleans toggle buttons, numbers and
+//! strings are written as text or matched against button states, and string
+//! arrays select several options of a multi-select choice field.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum FieldValue {
+    Clear,
+    Bool(bool),
+    Number(f64),
+    Text(String),
+    List(Vec<String>),
+}
+
+impl FieldValue {
+    /// JSON-style name of the value kind, used in mismatch diagnostics.
+    pub(crate) fn kind(&self) -> &'static str {
+        match self {
+            FieldValue::Clear => "null",
+            FieldValue::Bool(_) => "boolean",
+            FieldValue::Number(_) => "number",
+            FieldValue::Text(_) => "string",
+            FieldValue::List(_) => "list",
+        }
+    }
+
+    /// Scalar text representation (strings and numbers only).
+    pub(crate) fn as_text(&self) -> Option<String> {
+        match self {
+            FieldValue::Text(text) => Some(text.clone()),
+            FieldValue::Number(number) => format_number(*number),
+            _ => None,
+        }
+    }
+}
+
+impl From<&str> for FieldValue {
+    fn from(value: &str) -> Self {
+        FieldValue::Text(value.to_owned())
+    }
+}
+
+impl From<String> for FieldValue {
+    fn from(value: String) -> Self {
+        FieldValue::Text(value)
+    }
+}
+
+impl From<bool> for FieldValue {
+    fn from(value: bool) -> Self {
+        FieldValue::Bool(value)
+    }
+}
+
+impl From<f64> for FieldValue {
+    fn from(value: f64) -> Self {
+        FieldValue::Number(value)
+    }
+}
+
+impl From<Vec<String>> for FieldValue {
+    fn from(value: Vec<String>) -> Self {
+        FieldValue::List(value)
+    }
+}
+
+/// Formats whole numbers without a fractional part; `None` for NaN/infinity.
+pub(crate) fn format_number(value: f64) -> Option<String> {
+    if !value.is_finite() {
+        return None;
+    }
+    if value.fract() == 0.0 && value.abs() < 1e15 {
+        Some(format!("{}", value as i64))
+    } else {
+        Some(format!("{}", value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn untagged_payload_maps_to_variants() {
+        let parsed: Vec<FieldValue> =
+            serde_json::from_str(r#"[null, true, 3, 2.5, "text", ["a", "b"]]"#).expect("parse values");
+        assert_eq!(
+            parsed,
+            vec![
+                FieldValue::Clear,
+                FieldValue::Bool(true),
+                FieldValue::Number(3.0),
+                FieldValue::Number(2.5),
+                FieldValue::Text("text".into()),
+                FieldValue::List(vec!["a".into(), "b".into()]),
+            ]
+        );
+    }
+
+    #[test]
+    fn numbers_format_without_trailing_zeroes() {
+        assert_eq!(format_number(42.0).as_deref(), Some("42"));
+        assert_eq!(format_number(-0.25).as_deref(), Some("-0.25"));
+        assert_eq!(format_number(f64::NAN), None);
+    }
+}
@@ -0,0 +1,411 @@
+// SPDX-License-Identifier: PMPL-1.0-or-later WITH Palimpsest
+
+//! `wasm-bindgen` exports for the browser build (`wasm` feature, on by
+//! default). Errors are thrown as `{ code, message, context }` objects.
+
+use std::collections::{BTreeMap, HashMap};
+
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    core_error_with_context, csv_records, detect_blocks_impl, diff_fingerprints, extract, fill_blocks_with_options_impl,
+    flatten, Block, CoreError, CoreResult, DataValue, EncryptionOptions, FieldEdit, FieldValue, FillOptions, FormDocument,
+    FormFingerprint, Limits, Mapping, PartialNamePolicy, Profile,
+};
+
+fn core_error_to_js(payload: CoreError) -> JsValue {
+    serde_wasm_bindgen::to_value(&payload)
+        .unwrap_or_else(|_| JsValue::from_str(&format!("{}: {}", payload.code, payload.message)))
+}
+
+fn to_js<T: serde::Serialize>(value: &T, context: &str) -> Result<JsValue, JsValue> {
+    serde_wasm_bindgen::to_value(value)
+        .map_err(|err| core_error_to_js(core_error_with_context("BW_SERIALIZATION_ERROR", err.to_string(), Some(context.into()))))
+}
+
+fn from_js<T: serde::de::DeserializeOwned>(value: JsValue, code: &'static str, context: &str) -> CoreResult<T> {
+    serde_wasm_bindgen::from_value(value).map_err(|err| core_error_with_context(code, err.to_string(), Some(context.into())))
+}
+
+/// Batch records given as CSV text or as an array of field objects.
+fn batch_records(records: JsValue, context: &str) -> CoreResult<Vec<HashMap<String, FieldValue>>> {
+    match records.as_string() {
+        Some(text) => csv_records(&text),
+        None => from_js(records, "BW_FIELDS_PAYLOAD_INVALID", context),
+    }
+}
+
+/// Bulk extraction inputs: each document's bytes with its row label.
+fn bulk_inputs(documents: &js_sys::Array, names: JsValue, context: &str) -> CoreResult<Vec<(String, Vec<u8>)>> {
+    let names: Option<Vec<String>> = from_js(names, "BW_OPTIONS_PAYLOAD_INVALID", context)?;
+    Ok(documents
+        .iter()
+        .enumerate()
+        .map(|(position, document)| {
+            let name = names.as_ref().and_then(|names| names.get(position).cloned());
+            (name.unwrap_or_else(|| format!("document {}", position + 1)), js_sys::Uint8Array::new(&document).to_vec())
+        })
+        .collect())
+}
+
+#[wasm_bindgen]
+pub fn detect_blocks(pdf_data: &[u8]) -> Result<JsValue, JsValue> {
+    let blocks = detect_blocks_impl(pdf_data).map_err(core_error_to_js)?;
+    serde_wasm_bindgen::to_value(&blocks)
+        .map_err(|err| core_error_to_js(core_error_with_context("BW_SERIALIZATION_ERROR", err.to_string(), Some("detect_blocks".into()))))
+}
+
+#[wasm_bindgen]
+pub fn fill_blocks(
+    pdf_data: &[u8],
+    blocks: JsValue,
+    fields: JsValue,
+) -> Result<js_sys::Uint8Array, JsValue> {
+    let blocks: Vec<Block> = serde_wasm_bindgen::from_value(blocks).map_err(|err| {
+        core_error_to_js(core_error_with_context(
+            "BW_BLOCKS_PAYLOAD_INVALID",
+            err.to_string(),
+            Some("fill_blocks blocks argument".into()),
+        ))
+    })?;
+
+    let field_values: HashMap<String, FieldValue> = serde_wasm_bindgen::from_value(fields).map_err(|err| {
+        core_error_to_js(core_error_with_context(
+            "BW_FIELDS_PAYLOAD_INVALID",
+            err.to_string(),
+            Some("fill_blocks fields argument".into()),
+        ))
+    })?;
+    let output = fill_blocks_with_options_impl(pdf_data, &blocks, field_values, &FillOptions::default())
+        .map_err(core_error_to_js)?;
+    Ok(js_sys::Uint8Array::from(output.as_slice()))
+}
+
+#[wasm_bindgen]
+pub fn fill_blocks_with_options(
+    pdf_data: &[u8],
+    blocks: JsValue,
+    fields: JsValue,
+    options: JsValue,
+) -> Result<js_sys::Uint8Array, JsValue> {
+    let blocks: Vec<Block> = serde_wasm_bindgen::from_value(blocks).map_err(|err| {
+        core_error_to_js(core_error_with_context(
+            "BW_BLOCKS_PAYLOAD_INVALID",
+            err.to_string(),
+            Some("fill_blocks_with_options blocks argument".into()),
+        ))
+    })?;
+
+    let field_values: HashMap<String, FieldValue> = serde_wasm_bindgen::from_value(fields).map_err(|err| {
+        core_error_to_js(core_error_with_context(
+            "BW_FIELDS_PAYLOAD_INVALID",
+            err.to_string(),
+            Some("fill_blocks_with_options fields argument".into()),
+        ))
+    })?;
+
+    let options: FillOptions = serde_wasm_bindgen::from_value(options).map_err(|err| {
+        core_error_to_js(core_error_with_context(
+            "BW_OPTIONS_PAYLOAD_INVALID",
+            err.to_string(),
+            Some("fill_blocks_with_options options argument".into()),
+        ))
+    })?;
+    let output = fill_blocks_with_options_impl(pdf_data, &blocks, field_values, &options).map_err(core_error_to_js)?;
+    Ok(js_sys::Uint8Array::from(output.as_slice()))
+}
+
+#[wasm_bindgen]
+pub fn extract_fields(pdf_data: &[u8]) -> Result<JsValue, JsValue> {
+    let fields = extract::extract_fields_impl(pdf_data, None).map_err(core_error_to_js)?;
+    serde_wasm_bindgen::to_value(&fields)
+        .map_err(|err| core_error_to_js(core_error_with_context("BW_SERIALIZATION_ERROR", err.to_string(), Some("extract_fields".into()))))
+}
+
+#[wasm_bindgen]
+pub fn flatten_form(pdf_data: &[u8]) -> Result<js_sys::Uint8Array, JsValue> {
+    let output = flatten::flatten_impl(pdf_data, None).map_err(core_error_to_js)?;
+    Ok(js_sys::Uint8Array::from(output.as_slice()))
+}
+
+/// Similarity in `0..1` of two fingerprints from `PdfSession.fingerprint()`.
+#[wasm_bindgen]
+pub fn fingerprint_similarity(a: JsValue, b: JsValue) -> Result<f64, JsValue> {
+    let a: FormFingerprint =
+        from_js(a, "BW_FINGERPRINT_PAYLOAD_INVALID", "fingerprint_similarity a argument").map_err(core_error_to_js)?;
+    let b: FormFingerprint =
+        from_js(b, "BW_FINGERPRINT_PAYLOAD_INVALID", "fingerprint_similarity b argument").map_err(core_error_to_js)?;
+    Ok(a.similarity(&b))
+}
+
+/// Field changes between two fingerprints (`old` first), as `{ identical,
+/// changes }`.
+#[wasm_bindgen]
+pub fn diff_form_fingerprints(old: JsValue, new: JsValue) -> Result<JsValue, JsValue> {
+    let old: FormFingerprint =
+        from_js(old, "BW_FINGERPRINT_PAYLOAD_INVALID", "diff_form_fingerprints old argument").map_err(core_error_to_js)?;
+    let new: FormFingerprint =
+        from_js(new, "BW_FINGERPRINT_PAYLOAD_INVALID", "diff_form_fingerprints new argument").map_err(core_error_to_js)?;
+    to_js(&diff_fingerprints(&old, &new), "diff_form_fingerprints")
+}
+
+/// A parsed document kept alive across calls so the extension does not
+/// re-run `Document::load_mem` for every detect/fill round trip. Bytes are
+/// only produced by `preview` and `save`.
+#[wasm_bindgen]
+pub struct PdfSession {
+    document: FormDocument,
+    dirty: bool,
+}
+
+#[wasm_bindgen]
+impl PdfSession {
+    /// `limits` is an optional `Limits` object (`{ maxObjects, maxStreamBytes,
+    /// maxDepth, maxFields, maxSteps, maxMillis }`); missing keys keep their
+    /// defaults.
+    #[wasm_bindgen(constructor)]
+    pub fn new(pdf_data: &[u8], password: Option<String>, limits: JsValue) -> Result<PdfSession, JsValue> {
+        let limits: Limits = if limits.is_undefined() || limits.is_null() {
+            Limits::default()
+        } else {
+            from_js(limits, "BW_OPTIONS_PAYLOAD_INVALID", "PdfSession limits argument").map_err(core_error_to_js)?
+        };
+        let document =
+            FormDocument::load_with_limits(pdf_data, password.as_deref(), limits).map_err(core_error_to_js)?;
+        Ok(PdfSession {
+            document,
+            dirty: false,
+        })
+    }
+
+    /// Widget blocks, read from the document's cached field index.
+    pub fn detect(&self) -> Result<JsValue, JsValue> {
+        let blocks = self.document.detect().map_err(core_error_to_js)?;
+        to_js(&blocks, "PdfSession.detect")
+    }
+
+    /// Creates a field for each block (`{ kind, page, name, x, y, width,
+    /// height, export?, options? }`); returns the number of fields created.
+    pub fn create_fields(&mut self, blocks: JsValue) -> Result<usize, JsValue> {
+        let blocks: Vec<Block> = from_js(blocks, "BW_BLOCKS_PAYLOAD_INVALID", "PdfSession.create_fields blocks argument")
+            .map_err(core_error_to_js)?;
+        let created = self.document.create_fields(&blocks).map_err(core_error_to_js)?;
+        self.dirty |= created > 0;
+        Ok(created)
+    }
+
+    /// Applies field edits (`[{ delete: { field } }, { rename: { field, to } },
+    /// { move: { field, parent } }, { split: { field, names } }]`) all or
+    /// nothing; returns `{ removed, renamed: [{ from, to }] }`.
+    pub fn edit_fields(&mut self, edits: JsValue) -> Result<JsValue, JsValue> {
+        let edits: Vec<FieldEdit> = from_js(edits, "BW_EDITS_PAYLOAD_INVALID", "PdfSession.edit_fields edits argument")
+            .map_err(core_error_to_js)?;
+        let report = self.document.edit_fields(&edits).map_err(core_error_to_js)?;
+        self.dirty = true;
+        to_js(&report, "PdfSession.edit_fields")
+    }
+
+    /// Fields repeated per row, as `{ path, rows, columns }` groups.
+    pub fn repeating_rows(&self) -> Result<JsValue, JsValue> {
+        let groups = self.document.repeating_rows().map_err(core_error_to_js)?;
+        to_js(&groups, "PdfSession.repeating_rows")
+    }
+
+    /// Structural fingerprint (`{ hash, pages, fields }`), unaffected by
+    /// field values.
+    pub fn fingerprint(&self) -> Result<JsValue, JsValue> {
+        let fingerprint = self.document.fingerprint().map_err(core_error_to_js)?;
+        to_js(&fingerprint, "PdfSession.fingerprint")
+    }
+
+    /// Field changes from this session's form to `revised`, a later edition.
+    pub fn diff(&self, revised: &PdfSession) -> Result<JsValue, JsValue> {
+        let diff = self.document.diff(&revised.document).map_err(core_error_to_js)?;
+        to_js(&diff, "PdfSession.diff")
+    }
+
+    /// Semantic tags per field (`{ name, kind, nearbyLabel, tags: [{ tag,
+    /// confidence }] }`), most likely tag first.
+    pub fn classify(&self) -> Result<JsValue, JsValue> {
+        let classified = self.document.classify().map_err(core_error_to_js)?;
+        to_js(&classified, "PdfSession.classify")
+    }
+
+    /// Values proposed from a `Profile` object (`{ givenName, familyName,
+    /// fullName, email, phone, dateOfBirth, street, city, region, postcode,
+    /// country, ids }`), each with its `sources`, `derivation` and
+    /// `confidence`. Nothing is written; pass the accepted values to `fill`.
+    pub fn propose_autofill(&self, profile: JsValue) -> Result<JsValue, JsValue> {
+        let profile: Profile =
+            from_js(profile, "BW_PROFILE_PAYLOAD_INVALID", "PdfSession.propose_autofill profile argument")
+                .map_err(core_error_to_js)?;
+        let proposals = self.document.propose_autofill(&profile).map_err(core_error_to_js)?;
+        to_js(&proposals, "PdfSession.propose_autofill")
+    }
+
+    pub fn extract(&self) -> Result<JsValue, JsValue> {
+        let fields = self.document.extract().map_err(core_error_to_js)?;
+        to_js(&fields, "PdfSession.extract")
+    }
+
+    /// Values of many completed copies of this form (`documents`, an array
+    /// of PDF bytes; `names` optionally labels each row, `document <n>`
+    /// otherwise) as `{ columns, rows: [{ source, values, error }] }`.
+    pub fn extract_bulk(&self, documents: js_sys::Array, names: JsValue) -> Result<JsValue, JsValue> {
+        let inputs = bulk_inputs(&documents, names, "PdfSession.extract_bulk names argument").map_err(core_error_to_js)?;
+        let table = self
+            .document
+            .extract_bulk(inputs.iter().map(|(name, data)| (name.as_str(), data.as_slice())), None)
+            .map_err(core_error_to_js)?;
+        to_js(&table, "PdfSession.extract_bulk")
+    }
+
+    /// Like `extract_bulk`, as CSV text with `source` and `error` columns.
+    pub fn extract_bulk_csv(&self, documents: js_sys::Array, names: JsValue) -> Result<String, JsValue> {
+        let inputs =
+            bulk_inputs(&documents, names, "PdfSession.extract_bulk_csv names argument").map_err(core_error_to_js)?;
+        let table = self
+            .document
+            .extract_bulk(inputs.iter().map(|(name, data)| (name.as_str(), data.as_slice())), None)
+            .map_err(core_error_to_js)?;
+        Ok(table.to_csv())
+    }
+
+    /// Applies `fields` to the session; returns the `{ filled, ambiguous,
+    /// fieldErrors }` report, where `fieldErrors` lists the fields left as
+    /// they were (refused NoToggleToOff clears).
+    pub fn fill(&mut self, fields: JsValue) -> Result<JsValue, JsValue> {
+        let values: HashMap<String, FieldValue> =
+            from_js(fields, "BW_FIELDS_PAYLOAD_INVALID", "PdfSession.fill fields argument").map_err(core_error_to_js)?;
+        let report = self.document.fill_with_policy(&values, PartialNamePolicy::default()).map_err(core_error_to_js)?;
+        self.dirty = true;
+        to_js(&report, "PdfSession.fill")
+    }
+
+    /// Applies `fields`, resolving keys that match several fields by partial
+    /// name under `policy` (`"error"`, `"first"` or `"all"`); returns the
+    /// `{ filled, ambiguous, fieldErrors }` report.
+    pub fn fill_with_policy(&mut self, fields: JsValue, policy: JsValue) -> Result<JsValue, JsValue> {
+        let values: HashMap<String, FieldValue> =
+            from_js(fields, "BW_FIELDS_PAYLOAD_INVALID", "PdfSession.fill_with_policy fields argument")
+                .map_err(core_error_to_js)?;
+        let policy: PartialNamePolicy =
+            from_js(policy, "BW_OPTIONS_PAYLOAD_INVALID", "PdfSession.fill_with_policy policy argument")
+                .map_err(core_error_to_js)?;
+        let report = self.document.fill_with_policy(&values, policy).map_err(core_error_to_js)?;
+        self.dirty = true;
+        to_js(&report, "PdfSession.fill_with_policy")
+    }
+
+    /// Applies semantic `data` (nested objects keyed like
+    /// `applicant.first_name`) through a `Mapping` written for this form;
+    /// returns the `{ filled, ambiguous, missingKeys, fieldErrors }` report.
+    pub fn fill_mapped(&mut self, data: JsValue, mapping: JsValue) -> Result<JsValue, JsValue> {
+        let data: BTreeMap<String, DataValue> =
+            from_js(data, "BW_FIELDS_PAYLOAD_INVALID", "PdfSession.fill_mapped data argument").map_err(core_error_to_js)?;
+        let mapping: Mapping = from_js(mapping, "BW_MAPPING_PAYLOAD_INVALID", "PdfSession.fill_mapped mapping argument")
+            .map_err(core_error_to_js)?;
+        let report = self.document.fill_mapped(&data, &mapping, PartialNamePolicy::default()).map_err(core_error_to_js)?;
+        self.dirty = true;
+        to_js(&report, "PdfSession.fill_mapped")
+    }
+
+    /// Sets a single field, as an interactive edit; returns the fill report,
+    /// as `fill` does.
+    pub fn set_field(&mut self, name: String, value: JsValue) -> Result<JsValue, JsValue> {
+        let value: FieldValue =
+            from_js(value, "BW_FIELDS_PAYLOAD_INVALID", "PdfSession.set_field value argument").map_err(core_error_to_js)?;
+        let values = HashMap::from([(name, value)]);
+        let report = self.document.fill_with_policy(&values, PartialNamePolicy::default()).map_err(core_error_to_js)?;
+        self.dirty = true;
+        to_js(&report, "PdfSession.set_field")
+    }
+
+    /// Bytes of the session with `fields` applied, without committing them.
+    pub fn preview(&self, fields: JsValue) -> Result<js_sys::Uint8Array, JsValue> {
+        let values: HashMap<String, FieldValue> =
+            from_js(fields, "BW_FIELDS_PAYLOAD_INVALID", "PdfSession.preview fields argument").map_err(core_error_to_js)?;
+        let output = self.document.preview(&values).map_err(core_error_to_js)?;
+        Ok(js_sys::Uint8Array::from(output.as_slice()))
+    }
+
+    /// Fills a copy of the session's form per record, without changing the
+    /// session. `records` is CSV text (header row of field keys) or an array
+    /// of field objects; returns one `{ record, report, error, output }`
+    /// entry per record, `output` being the record's PDF bytes.
+    pub fn fill_batch(&self, records: JsValue) -> Result<js_sys::Array, JsValue> {
+        let records = batch_records(records, "PdfSession.fill_batch records argument").map_err(core_error_to_js)?;
+        let outcomes = self.document.fill_batch(&records, PartialNamePolicy::default()).map_err(core_error_to_js)?;
+        let entries = js_sys::Array::new();
+        for outcome in outcomes {
+            let entry = to_js(&outcome, "PdfSession.fill_batch")?;
+            if let Some(output) = &outcome.output {
+                js_sys::Reflect::set(&entry, &"output".into(), &js_sys::Uint8Array::from(output.as_slice()))?;
+            }
+            entries.push(&entry);
+        }
+        Ok(entries)
+    }
+
+    /// Like `fill_batch`, combining the records into one PDF whose fields are
+    /// prefixed `record<n>.`; returns `{ output, records }`.
+    pub fn fill_batch_combined(&self, records: JsValue) -> Result<JsValue, JsValue> {
+        let records = batch_records(records, "PdfSession.fill_batch_combined records argument").map_err(core_error_to_js)?;
+        let (output, outcomes) =
+            self.document.fill_batch_combined(&records, PartialNamePolicy::default()).map_err(core_error_to_js)?;
+        let result = js_sys::Object::new();
+        js_sys::Reflect::set(&result, &"output".into(), &js_sys::Uint8Array::from(output.as_slice()))?;
+        js_sys::Reflect::set(&result, &"records".into(), &to_js(&outcomes, "PdfSession.fill_batch_combined")?)?;
+        Ok(result.into())
+    }
+
+    /// Appends the pages and fields of `other`; fields whose names collide
+    /// with this form's are prefixed `copy<n>.`. Returns `{ pages, fields,
+    /// prefix }`.
+    pub fn append(&mut self, other: &PdfSession) -> Result<JsValue, JsValue> {
+        let report = self.document.append(&other.document).map_err(core_error_to_js)?;
+        self.dirty = true;
+        to_js(&report, "PdfSession.append")
+    }
+
+    /// Flattens the form in place; returns the number of widgets flattened.
+    pub fn flatten(&mut self) -> Result<usize, JsValue> {
+        let flattened = self.document.flatten().map_err(core_error_to_js)?;
+        self.dirty = true;
+        Ok(flattened)
+    }
+
+    /// Encrypts subsequent saves with AES-256 (`{ userPassword, ownerPassword, permissions }`).
+    pub fn set_encryption(&mut self, options: JsValue) -> Result<(), JsValue> {
+        let options: EncryptionOptions =
+            from_js(options, "BW_OPTIONS_PAYLOAD_INVALID", "PdfSession.set_encryption options argument")
+                .map_err(core_error_to_js)?;
+        self.document.encrypt(&options).map_err(core_error_to_js)?;
+        self.dirty = true;
+        Ok(())
+    }
+
+    /// Re-applies the input's original encryption on save.
+    pub fn reapply_input_encryption(&mut self) {
+        self.document.reapply_input_encryption();
+        self.dirty = true;
+    }
+
+    /// Repairs made while loading (for example `BW_PDF_XREF_REBUILT`), as
+    /// `{ code, message, context }` objects.
+    pub fn warnings(&self) -> Result<JsValue, JsValue> {
+        to_js(&self.document.warnings(), "PdfSession.warnings")
+    }
+
+    /// Whether the session has changes not yet returned by `save`.
+    #[wasm_bindgen(getter)]
+    pub fn dirty(&self) -> bool {
+        self.dirty
+    }
+
+    pub fn save(&mut self) -> Result<js_sys::Uint8Array, JsValue> {
+        let output = self.document.save().map_err(core_error_to_js)?;
+        self.dirty = false;
+        Ok(js_sys::Uint8Array::from(output.as_slice()))
+    }
+}
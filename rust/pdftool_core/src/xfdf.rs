@@ -0,0 +1,217 @@
+// SPDX-License-Identifier: PMPL-1.0-or-later WITH Palimpsest
+
+//! Reader for XFDF field values (`<fields><field name=".."><value>..`).
+//!
+//! Nested `<field>` elements are joined into dotted full names; a field with
+//! several `<value>` elements becomes a list value.
+
+use crate::{core_error_with_context, CoreError, CoreResult, FieldValue};
+
+pub(crate) fn parse_xfdf(text: &str) -> CoreResult<Vec<(String, FieldValue)>> {
+    let mut names: Vec<String> = Vec::new();
+    let mut values: Vec<(String, Vec<String>)> = Vec::new();
+    let mut rest = text;
+
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start..];
+        if let Some(after) = rest.strip_prefix("<?") {
+            rest = skip_past(after, "?>")?;
+        } else if let Some(after) = rest.strip_prefix("<!--") {
+            rest = skip_past(after, "-->")?;
+        } else if let Some(after) = rest.strip_prefix("<!") {
+            rest = skip_past(after, ">")?;
+        } else if let Some(after) = rest.strip_prefix("</") {
+            let end = after.find('>').ok_or_else(|| invalid("unterminated closing tag"))?;
+            if local_name(after[..end].trim()) == "field" && names.pop().is_none() {
+                return Err(invalid("unbalanced </field>"));
+            }
+            rest = &after[end + 1..];
+        } else {
+            let end = tag_end(rest).ok_or_else(|| invalid("unterminated tag"))?;
+            let tag = &rest[1..end];
+            let self_closing = tag.ends_with('/');
+            let tag = tag.trim_end_matches('/');
+            let tag_name = local_name(tag.split_whitespace().next().unwrap_or(""));
+            rest = &rest[end + 1..];
+
+            match tag_name {
+                "field" => {
+                    let name = attribute(tag, "name").ok_or_else(|| invalid("<field> without a name attribute"))?;
+                    if !self_closing {
+                        names.push(name);
+                    }
+                }
+                "value" => {
+                    if names.is_empty() {
+                        return Err(invalid("<value> outside of a <field>"));
+                    }
+                    let value = if self_closing {
+                        String::new()
+                    } else {
+                        let close = value_end(rest).ok_or_else(|| invalid("unterminated <value>"))?;
+                        let raw = &rest[..close];
+                        let close_end = rest[close..].find('>').ok_or_else(|| invalid("unterminated </value>"))?;
+                        rest = &rest[close + close_end + 1..];
+                        decode_text(raw)?
+                    };
+                    let full_name = names.join(".");
+                    match values.iter_mut().find(|(name, _)| *name == full_name) {
+                        Some((_, items)) => items.push(value),
+                        None => values.push((full_name, vec![value])),
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    if !names.is_empty() {
+        return Err(invalid("unclosed <field> element"));
+    }
+
+    Ok(values
+        .into_iter()
+        .map(|(name, mut items)| {
+            let value = if items.len() == 1 {
+                FieldValue::Text(items.remove(0))
+            } else {
+                FieldValue::List(items)
+            };
+            (name, value)
+        })
+        .collect())
+}
+
+fn invalid(message: &str) -> CoreError {
+    core_error_with_context("BW_INPUT_XFDF_INVALID", message, Some("xfdf".into()))
+}
+
+fn skip_past<'a>(text: &'a str, marker: &str) -> CoreResult<&'a str> {
+    text.find(marker)
+        .map(|index| &text[index + marker.len()..])
+        .ok_or_else(|| invalid("unterminated markup declaration"))
+}
+
+/// Index of the `>` closing the tag at the start of `text`, skipping quoted
+/// attribute values.
+fn tag_end(text: &str) -> Option<usize> {
+    let mut quote = None;
+    for (index, ch) in text.char_indices() {
+        match (quote, ch) {
+            (None, '"' | '\'') => quote = Some(ch),
+            (Some(open), ch) if ch == open => quote = None,
+            (None, '>') => return Some(index),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Offset of the closing tag that ends a `<value>` body, stepping over CDATA
+/// sections whose content may itself look like markup.
+fn value_end(text: &str) -> Option<usize> {
+    let mut offset = 0;
+    loop {
+        let rest = &text[offset..];
+        let close = rest.find("</")?;
+        match rest.find("<![CDATA[") {
+            Some(cdata) if cdata < close => {
+                offset += cdata + rest[cdata..].find("]]>")? + 3;
+            }
+            _ => return Some(offset + close),
+        }
+    }
+}
+
+fn local_name(name: &str) -> &str {
+    name.rsplit(':').next().unwrap_or(name)
+}
+
+fn attribute(tag: &str, wanted: &str) -> Option<String> {
+    let mut rest = tag.split_once(char::is_whitespace)?.1;
+    loop {
+        rest = rest.trim_start();
+        let (key, after) = rest.split_once('=')?;
+        let after = after.trim_start();
+        let quote = after.chars().next()?;
+        if quote != '"' && quote != '\'' {
+            return None;
+        }
+        let close = after[1..].find(quote)? + 1;
+        if key.trim() == wanted {
+            return decode_text(&after[1..close]).ok();
+        }
+        rest = &after[close + 1..];
+    }
+}
+
+fn decode_text(raw: &str) -> CoreResult<String> {
+    let mut out = String::with_capacity(raw.len());
+    let mut rest = raw;
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix("<![CDATA[") {
+            let end = after.find("]]>").ok_or_else(|| invalid("unterminated CDATA section"))?;
+            out.push_str(&after[..end]);
+            rest = &after[end + 3..];
+        } else if rest.starts_with('&') {
+            let end = rest.find(';').ok_or_else(|| invalid("unterminated entity reference"))?;
+            let entity = &rest[1..end];
+            let decoded = match entity {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                _ => entity
+                    .strip_prefix("#x")
+                    .map(|hex| u32::from_str_radix(hex, 16))
+                    .or_else(|| entity.strip_prefix('#').map(str::parse::<u32>))
+                    .and_then(Result::ok)
+                    .and_then(char::from_u32),
+            };
+            out.push(decoded.ok_or_else(|| invalid("unknown entity reference"))?);
+            rest = &rest[end + 1..];
+        } else {
+            let ch = rest.chars().next().unwrap_or_default();
+            out.push(ch);
+            rest = &rest[ch.len_utf8()..];
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nested_fields_join_into_full_names() {
+        let xfdf = r#"<?xml version="1.0" encoding="UTF-8"?>
+<xfdf xmlns="http://ns.adobe.com/xfdf/" xml:space="preserve">
+  <fields>
+    <field name="Applicant">
+      <field name="Name"><value>Ada &amp; Co</value></field>
+    </field>
+    <field name="Colours"><value>Red</value><value>Blue</value></field>
+    <field name="Notes"><value><![CDATA[<b>bold</b>]]></value></field>
+    <field name="Empty"><value/></field>
+  </fields>
+</xfdf>"#;
+        let values = parse_xfdf(xfdf).expect("valid XFDF");
+        assert_eq!(
+            values,
+            vec![
+                ("Applicant.Name".to_string(), FieldValue::Text("Ada & Co".into())),
+                ("Colours".to_string(), FieldValue::List(vec!["Red".into(), "Blue".into()])),
+                ("Notes".to_string(), FieldValue::Text("<b>bold</b>".into())),
+                ("Empty".to_string(), FieldValue::Text(String::new())),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_unbalanced_fields() {
+        let payload = parse_xfdf("<fields><field name=\"a\"><value>1</value></fields>").expect_err("unclosed field");
+        assert_eq!(payload.code, "BW_INPUT_XFDF_INVALID");
+    }
+}